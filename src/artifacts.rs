@@ -0,0 +1,186 @@
+use crate::claim::CLAIM_SUFFIX;
+use crate::config::Config;
+use crate::marker::IGNORE_FILE_NAME;
+use std::path::{Path, PathBuf};
+
+/// Infix of every temporary or status file sstc writes next to media, e.g. `clip.sstc.tmp.mp4`
+pub const ARTIFACT_INFIX: &str = ".sstc.";
+
+/// File name suffixes of files sstc writes or reads for its own bookkeeping.
+///
+/// Every feature that creates a file which could land in a watched directory must
+/// register its suffix here so sstc never ingests anything it wrote itself. Files named by
+/// the config instead, its state files and posters, are told apart by [`is_state_file`] and
+/// [`crate::thumbnail::is_poster`].
+pub const ARTIFACT_SUFFIXES: &[&str] = &[IGNORE_FILE_NAME, CLAIM_SUFFIX];
+
+/// Whether the path is one of sstc's own artifacts and must never be queued
+pub fn is_sstc_artifact(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+
+    name.contains(ARTIFACT_INFIX) || ARTIFACT_SUFFIXES.iter().any(|s| name.ends_with(s))
+}
+
+/// Suffixes SQLite adds to the database's name for its journal and write-ahead log
+const SQLITE_SIDECAR_SUFFIXES: &[&str] = &["-journal", "-wal", "-shm"];
+
+/// Whether `path` is one of the files the config keeps sstc's state in, like the queue file or
+/// the history, which a watched tree may well hold. Their names are the user's to pick, so
+/// unlike [`is_sstc_artifact`] this goes by the config.
+pub fn is_state_file(config: &Config, path: &Path) -> bool {
+    let mut state_files: Vec<PathBuf> = [
+        &config.history_file,
+        &config.history_db,
+        &config.metrics_file,
+        &config.queue_file,
+        &config.control_socket,
+    ]
+    .into_iter()
+    .flatten()
+    .cloned()
+    .collect();
+    state_files.extend(config.hash_store.as_ref().map(|store| store.path.clone()));
+    if let Some(history_db) = &config.history_db {
+        state_files.extend(SQLITE_SIDECAR_SUFFIXES.iter().map(|suffix| {
+            let mut name = history_db.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        }));
+    }
+
+    state_files.iter().any(|state_file| {
+        state_file == path || std::fs::canonicalize(state_file).is_ok_and(|real| real == path)
+    })
+}
+
+/// Temporary sibling an in-progress encode of `path` is written to, e.g. `clip.sstc.tmp.mp4`
+pub fn temp_path_for(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}{}tmp.{}", stem, ARTIFACT_INFIX, ext.to_string_lossy()),
+        None => format!("{}{}tmp", stem, ARTIFACT_INFIX),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job_log;
+    use crate::test_support::{Sandbox, BASIC_CONFIG};
+    use crate::transcoder::Transcoder;
+
+    #[tokio::test]
+    async fn no_artifact_matches_an_input_taking_every_extension() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "extensions: [mp4]",
+            "extensions: ['*']\n    source_action: {suffix: .done}",
+        ));
+        let transcoder = Transcoder::new(config);
+        let input = sandbox.path().join("in");
+        let media = sandbox.file("in/clip.mp4");
+        assert!(transcoder.matches_input(&media));
+        assert!(transcoder.matches_input(&sandbox.file("in/notes.txt")));
+
+        let written = [
+            // An encode, or a replacement of a source in place, still in progress
+            temp_path_for(&media),
+            // The queue file or hash store being written, or a job log
+            temp_path_for(&input.join("queue.json")),
+            job_log::create(&input.join("logs"), &media).unwrap(),
+            input.join(format!("clip.mp4{}", CLAIM_SUFFIX)),
+            input.join(IGNORE_FILE_NAME),
+            input.join(format!("clip.mp4{}", IGNORE_FILE_NAME)),
+        ];
+        for artifact in &written {
+            if !artifact.exists() {
+                std::fs::write(artifact, b"").unwrap();
+            }
+            assert!(is_sstc_artifact(artifact), "{}", artifact.display());
+            assert!(
+                !transcoder.matches_input(artifact),
+                "{}",
+                artifact.display()
+            );
+        }
+
+        // The source_action rename is told apart by the input's own suffix
+        let done = sandbox.file("in/old.mp4.done");
+        assert!(!transcoder.matches_input(&done));
+    }
+
+    #[tokio::test]
+    async fn state_files_and_posters_in_a_watched_tree_match_no_input() {
+        const STATE_FILES: &str = "
+history_file: {dir}/in/history.jsonl
+history_db: {dir}/in/history.db
+metrics_file: {dir}/in/metrics.prom
+queue_file: {dir}/in/queue.json
+hash_store: {path: {dir}/in/hashes.json}
+";
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&format!(
+            "{}{}",
+            STATE_FILES,
+            BASIC_CONFIG
+                .replace("extensions: [mp4]", "extensions: ['*']")
+                .replace("container: mkv", "container: mkv\n    thumbnail: {at: 10%}")
+        ));
+        let transcoder = Transcoder::new(config);
+        sandbox.file("in/clip.mkv");
+
+        for name in [
+            "history.jsonl",
+            "history.db",
+            "history.db-wal",
+            "history.db-journal",
+            "metrics.prom",
+            "queue.json",
+            "hashes.json",
+            // The poster of an output written into the watched tree
+            "clip.jpg",
+        ] {
+            let path = sandbox.file(&format!("in/{}", name));
+            assert!(!transcoder.matches_input(&path), "{}", name);
+        }
+
+        // A jpg with no video of its name beside it is the user's own
+        assert!(transcoder.matches_input(&sandbox.file("in/photo.jpg")));
+    }
+
+    #[tokio::test]
+    async fn jpg_next_to_a_video_is_media_when_no_output_writes_posters() {
+        let sandbox = Sandbox::new();
+        let config =
+            sandbox.config(&BASIC_CONFIG.replace("extensions: [mp4]", "extensions: ['*']"));
+        let transcoder = Transcoder::new(config);
+        sandbox.file("in/clip.mkv");
+
+        assert!(transcoder.matches_input(&sandbox.file("in/clip.jpg")));
+    }
+
+    #[test]
+    fn media_is_not_an_artifact() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["clip.mp4", "clip.sstc", "sstc.mkv", "photo.jpg"] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, b"").unwrap();
+            assert!(!is_sstc_artifact(&path), "{}", name);
+        }
+    }
+
+    #[test]
+    fn temp_path_keeps_the_extension() {
+        assert_eq!(
+            temp_path_for(Path::new("/out/clip.mkv")),
+            Path::new("/out/clip.sstc.tmp.mkv")
+        );
+        assert_eq!(
+            temp_path_for(Path::new("/out/clip")),
+            Path::new("/out/clip.sstc.tmp")
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::artifacts;
+use crate::compliance;
+use crate::config::{Config, InputConfig};
+use crate::diagnostic;
+use crate::ffprobe;
+use crate::summary::SummaryOptions;
+use crate::transcoder::Transcoder;
+use anyhow::{anyhow, Context, Result};
+use owo_colors::{OwoColorize, Style};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+pub struct ScanOptions {
+    /// List file to read paths from, `-` for stdin; scans the inputs when absent
+    pub from_list: Option<String>,
+    /// List entries are separated by NUL instead of newlines
+    pub null_separated: bool,
+    /// Preset and output for listed files that don't belong to a configured input
+    pub explicit_target: Option<(String, String)>,
+    pub summary: SummaryOptions,
+    /// Print the normalize compliance of every file instead of transcoding
+    pub report_only: bool,
+    /// Scan only the configured input with this path
+    pub input: Option<PathBuf>,
+    /// Transcode files again whose outputs already exist: every scanned file when empty,
+    /// otherwise those at or under one of the paths
+    pub force: Option<Vec<PathBuf>>,
+    /// Print the ffmpeg commands instead of running them
+    pub dry_run: bool,
+}
+
+/// Queue every file once, process them to completion and report the outcome.
+pub async fn run_scan(config: Arc<Config>, options: ScanOptions) -> Result<()> {
+    if let Some((preset, output)) = &options.explicit_target {
+        if !config.presets.contains_key(preset) {
+            return Err(anyhow!(
+                "Preset '{}' does not exist{}",
+                preset,
+                diagnostic::did_you_mean(preset, config.presets.keys())
+            ));
+        }
+        if !config.outputs.contains_key(output) {
+            return Err(anyhow!(
+                "Output '{}' does not exist{}",
+                output,
+                diagnostic::did_you_mean(output, config.outputs.keys())
+            ));
+        }
+    }
+
+    let inputs: Vec<&InputConfig> = match &options.input {
+        Some(path) => {
+            let wanted = path.canonicalize().unwrap_or_else(|_| path.clone());
+            let inputs: Vec<_> = config
+                .inputs
+                .iter()
+                .filter(|input| {
+                    input
+                        .path
+                        .canonicalize()
+                        .unwrap_or_else(|_| input.path.clone())
+                        == wanted
+                })
+                .collect();
+            if inputs.is_empty() {
+                return Err(anyhow!("No input is configured for {}", path.display()));
+            }
+            inputs
+        }
+        None => config.inputs.iter().collect(),
+    };
+
+    let paths = match &options.from_list {
+        Some(list) => read_path_list(list, options.null_separated)?,
+        None => {
+            let mut paths = Vec::new();
+            for input in inputs {
+                let mut files = Vec::new();
+                collect_files(&input.path, &mut files)?;
+                // Files the input doesn't transcode, like camera proxies, aren't problems here
+                files.retain(|file| {
+                    file.strip_prefix(&input.path)
+                        .is_ok_and(|relative| input.matches_file(relative))
+                });
+                paths.extend(files);
+            }
+            paths
+        }
+    };
+
+    info!("Scanning {} file(s)", paths.len().magenta());
+
+    let forced: Option<Vec<PathBuf>> = options.force.as_ref().map(|paths| {
+        paths
+            .iter()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+            .collect()
+    });
+    match &forced {
+        Some(paths) if paths.is_empty() => {
+            info!("Forcing files to be transcoded again even when their outputs exist")
+        }
+        Some(paths) => info!(
+            "Forcing files under {} to be transcoded again even when their outputs exist",
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None => {}
+    }
+    let is_forced = |path: &Path| {
+        forced.as_ref().is_some_and(|paths| {
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            paths.is_empty() || paths.iter().any(|forced| path.starts_with(forced))
+        })
+    };
+
+    let transcoder = Transcoder::new(config);
+    if options.dry_run {
+        info!("Dry run: printing the ffmpeg commands instead of running them");
+        transcoder.enable_dry_run();
+    }
+    if options.report_only {
+        report_compliance(&transcoder, paths, &options).await;
+        return Ok(());
+    }
+
+    let mut missing = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for path in paths {
+        if !path.is_file() {
+            missing.push(path);
+            continue;
+        }
+
+        match &options.explicit_target {
+            Some((preset, output)) => {
+                let input = InputConfig {
+                    path: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+                    extensions: Vec::new(),
+                    preset: preset.clone(),
+                    output: output.clone(),
+                    ..Default::default()
+                };
+                match is_forced(&path) {
+                    true => transcoder.force_file(&path, Some(input)).await?,
+                    false => transcoder.process_file_with_input(&path, input).await?,
+                }
+            }
+            None if transcoder.matches_input(&path) => match is_forced(&path) {
+                true => transcoder.force_file(&path, None).await?,
+                false => transcoder.process_file(&path).await?,
+            },
+            None => unmatched.push(path),
+        }
+    }
+
+    transcoder.wait_until_idle().await;
+    if options.dry_run {
+        transcoder.stats().log_dry_run_summary();
+    } else {
+        transcoder.stats().log_summary();
+        transcoder.stats().print_table(options.summary);
+    }
+
+    for path in &missing {
+        warn!("Listed file does not exist: {}", path.display().yellow());
+    }
+    for path in &unmatched {
+        warn!(
+            "File does not belong to any configured input: {}",
+            path.display().yellow()
+        );
+    }
+
+    let failed = transcoder.stats().failed();
+    if failed > 0 || !missing.is_empty() || !unmatched.is_empty() {
+        error!(
+            "{} failed, {} missing, {} not matching any input",
+            failed,
+            missing.len(),
+            unmatched.len()
+        );
+        return Err(anyhow!("Scan finished with problems"));
+    }
+
+    Ok(())
+}
+
+/// Probe every file and print whether it meets its input's normalize rules
+async fn report_compliance(transcoder: &Transcoder, paths: Vec<PathBuf>, options: &ScanOptions) {
+    let paint = |text: &str, style: Style| {
+        if options.summary.color {
+            text.style(style).to_string()
+        } else {
+            text.to_string()
+        }
+    };
+    let (mut compliant, mut violating, mut unknown) = (0, 0, 0);
+
+    for path in paths {
+        let input = match &options.explicit_target {
+            Some((preset, output)) => Some(InputConfig {
+                path: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+                preset: preset.clone(),
+                output: output.clone(),
+                ..Default::default()
+            }),
+            None => transcoder.matching_input(&path),
+        };
+        let Some(input) = input else {
+            warn!(
+                "File does not belong to any configured input: {}",
+                path.display().yellow()
+            );
+            continue;
+        };
+
+        let probe = match ffprobe::probe(&path).await {
+            Ok(probe) => probe,
+            Err(e) => {
+                unknown += 1;
+                println!(
+                    "{} {}: {:#}",
+                    paint("?", Style::new().yellow()),
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let Some(rules) = &input.normalize else {
+            violating += 1;
+            println!(
+                "{} {}: no normalize rules, would transcode",
+                paint("x", Style::new().red()),
+                path.display()
+            );
+            continue;
+        };
+
+        let violations = compliance::violations(rules, &probe);
+        if violations.is_empty() {
+            compliant += 1;
+            println!("{} {}", paint("+", Style::new().green()), path.display());
+        } else {
+            violating += 1;
+            println!(
+                "{} {}: {}",
+                paint("x", Style::new().red()),
+                path.display(),
+                violations.join("; ")
+            );
+        }
+    }
+
+    println!(
+        "{} compliant, {} to transcode, {} unreadable",
+        paint(&compliant.to_string(), Style::new().green()),
+        paint(&violating.to_string(), Style::new().red()),
+        paint(&unknown.to_string(), Style::new().yellow())
+    );
+}
+
+/// Read a newline- or NUL-separated list of paths from a file, or stdin for `-`
+fn read_path_list(source: &str, null_separated: bool) -> Result<Vec<PathBuf>> {
+    let mut content = Vec::new();
+    if source == "-" {
+        std::io::stdin()
+            .read_to_end(&mut content)
+            .context("Failed to read file list from stdin")?;
+    } else {
+        content = std::fs::read(source).context(format!("Failed to read file list: {}", source))?;
+    }
+
+    Ok(parse_path_list(&content, null_separated))
+}
+
+fn parse_path_list(content: &[u8], null_separated: bool) -> Vec<PathBuf> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+
+    content
+        .split(|b| *b == separator)
+        .map(|entry| {
+            if null_separated {
+                entry
+            } else {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+/// Paths are bytes on unix, and `find -print0` lists them as they are
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).context(format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() && !artifacts::is_sstc_artifact(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newline_list_skips_blank_lines_and_carriage_returns() {
+        let list = b"/in/a.mp4\r\n\n/in/with space.mp4\n/in/last.mp4";
+        assert_eq!(
+            parse_path_list(list, false),
+            [
+                PathBuf::from("/in/a.mp4"),
+                PathBuf::from("/in/with space.mp4"),
+                PathBuf::from("/in/last.mp4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nul_list_keeps_names_as_they_are() {
+        let list = b"/in/line\nbreak.mp4\0/in/trailing\r\0\0/in/b.mp4\0";
+        assert_eq!(
+            parse_path_list(list, true),
+            [
+                PathBuf::from("/in/line\nbreak.mp4"),
+                PathBuf::from("/in/trailing\r"),
+                PathBuf::from("/in/b.mp4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nul_list_keeps_names_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let paths = parse_path_list(b"/in/caf\xe9.mp4\0", true);
+        assert_eq!(paths[0].as_os_str().as_bytes(), b"/in/caf\xe9.mp4");
+    }
+
+    #[test]
+    fn empty_list_has_no_paths() {
+        assert!(parse_path_list(b"", false).is_empty());
+        assert!(parse_path_list(b"\n\n", false).is_empty());
+        assert!(parse_path_list(b"\0", true).is_empty());
+    }
+
+    #[test]
+    fn reads_list_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("list.txt");
+        std::fs::write(&list, "/in/a.mp4\n/in/b.mp4\n").unwrap();
+
+        let paths = read_path_list(&list.to_string_lossy(), false).unwrap();
+        assert_eq!(
+            paths,
+            [PathBuf::from("/in/a.mp4"), PathBuf::from("/in/b.mp4")]
+        );
+        assert!(read_path_list(&dir.path().join("missing").to_string_lossy(), false).is_err());
+    }
+}
@@ -0,0 +1,571 @@
+use crate::artifacts;
+use crate::config::DistributedConfig;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::hash::BuildHasher;
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Suffix of the files instances use to claim a source, e.g. `clip.mp4.sstc.claim`
+pub const CLAIM_SUFFIX: &str = ".sstc.claim";
+/// Extension of the lock taken to remove a stale claim, e.g. `clip.mp4.sstc.claim.takeover`
+const TAKEOVER_EXTENSION: &str = "claim.takeover";
+
+/// Contents of a claim file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimInfo {
+    pub host: String,
+    pub pid: u32,
+    /// Unix time of the last renewal by the owner
+    pub renewed_at: u64,
+    /// Tells claims of the same process apart, as jobs of one instance claim sources too
+    #[serde(default)]
+    pub nonce: String,
+}
+
+impl ClaimInfo {
+    /// A new claim of this process
+    fn new() -> Self {
+        static CLAIMS: AtomicU64 = AtomicU64::new(0);
+        let nonce = std::collections::hash_map::RandomState::new()
+            .hash_one((SystemTime::now(), CLAIMS.fetch_add(1, Ordering::Relaxed)));
+        Self {
+            host: hostname(),
+            pid: std::process::id(),
+            renewed_at: unix_now(),
+            nonce: format!("{:016x}", nonce),
+        }
+    }
+
+    /// The same claim, renewed now
+    fn renewed(&self) -> Self {
+        Self {
+            renewed_at: unix_now(),
+            ..self.clone()
+        }
+    }
+
+    /// Whether both are the same claim, whenever renewed
+    fn is_same_claim(&self, other: &ClaimInfo) -> bool {
+        self.host == other.host && self.pid == other.pid && self.nonce == other.nonce
+    }
+
+    /// Seconds since the owner last renewed the claim
+    pub fn age_secs(&self) -> u64 {
+        unix_now().saturating_sub(self.renewed_at)
+    }
+
+    pub fn is_stale(&self, lease_secs: u64) -> bool {
+        self.age_secs() > lease_secs
+    }
+}
+
+/// Outcome of trying to claim a source
+pub enum ClaimAttempt {
+    Claimed(SourceClaim),
+    /// Another live instance owns the source
+    Taken(ClaimInfo),
+}
+
+/// A claim on a source held by this instance, renewed in the background and released when dropped
+pub struct SourceClaim {
+    path: PathBuf,
+    info: ClaimInfo,
+    renewal: JoinHandle<()>,
+}
+
+impl SourceClaim {
+    /// Atomically claim `source`, taking over claims whose owner stopped renewing them
+    pub fn acquire(
+        settings: &DistributedConfig,
+        source: &Path,
+        input_root: &Path,
+    ) -> Result<ClaimAttempt> {
+        let path = claim_path(settings, source, input_root);
+
+        for _ in 0..2 {
+            let info = ClaimInfo::new();
+            match create_claim(&path, &info) {
+                Ok(()) => {
+                    // Whoever removed a claim they took for stale must not have removed ours
+                    match read_claim(&path) {
+                        Some(owner) if owner.is_same_claim(&info) => {}
+                        Some(owner) => return Ok(ClaimAttempt::Taken(owner)),
+                        None => continue,
+                    }
+                    debug!("Claimed {} via {}", source.display(), path.display());
+                    let renewal =
+                        spawn_renewal(path.clone(), info.clone(), settings.lease.as_secs());
+                    return Ok(ClaimAttempt::Claimed(Self {
+                        path,
+                        info,
+                        renewal,
+                    }));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context(format!("Failed to create claim {}", path.display())))
+                }
+            }
+
+            let Some(owner) = read_claim(&path) else {
+                // Released in the meantime; try again
+                continue;
+            };
+            if !owner.is_stale(settings.lease.as_secs()) {
+                return Ok(ClaimAttempt::Taken(owner));
+            }
+
+            info!(
+                "Taking over stale claim on {} from {} (pid {}, {}s old)",
+                source.display(),
+                owner.host,
+                owner.pid,
+                owner.age_secs()
+            );
+            take_over_stale(&path, &owner, settings.lease.as_secs())?;
+        }
+
+        match read_claim(&path) {
+            Some(owner) => Ok(ClaimAttempt::Taken(owner)),
+            None => Err(anyhow::anyhow!(
+                "Could not claim {}: claim file keeps changing",
+                source.display()
+            )),
+        }
+    }
+}
+
+impl Drop for SourceClaim {
+    fn drop(&mut self) {
+        self.renewal.abort();
+        // Only remove the claim if nobody took it over in the meantime
+        if read_claim(&self.path).is_some_and(|info| info.is_same_claim(&self.info)) {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                warn!("Failed to release claim {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Where the claim for `source` lives: next to it, or in the shared claims directory keyed by
+/// its path relative to the input so instances with different mount points agree
+pub fn claim_path(settings: &DistributedConfig, source: &Path, input_root: &Path) -> PathBuf {
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+
+    match &settings.claims_dir {
+        None => source.with_file_name(format!("{}{}", name, CLAIM_SUFFIX)),
+        Some(dir) => {
+            let source = std::fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf());
+            let input_root =
+                std::fs::canonicalize(input_root).unwrap_or_else(|_| input_root.to_path_buf());
+            let relative = source
+                .strip_prefix(&input_root)
+                .map(flatten)
+                .unwrap_or_else(|_| escape(&name));
+            dir.join(format!("{}{}", relative, CLAIM_SUFFIX))
+        }
+    }
+}
+
+/// One file name for a relative path: `%` is escaped as `%25` and separators as `%2F`, so
+/// `a/b%c` and `a%b/c` never share a claim
+fn flatten(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(escape(&name.to_string_lossy())),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("%2F")
+}
+
+fn escape(name: &str) -> String {
+    name.replace('%', "%25")
+}
+
+/// Every claim file in the claims directory, or next to sources in the given inputs
+pub fn list_claims(settings: &DistributedConfig, inputs: &[PathBuf]) -> Vec<(PathBuf, ClaimInfo)> {
+    let mut files = Vec::new();
+    match &settings.claims_dir {
+        Some(dir) => collect_claim_files(dir, false, &mut files),
+        None => {
+            for input in inputs {
+                collect_claim_files(input, true, &mut files);
+            }
+        }
+    }
+    files.sort();
+
+    files
+        .into_iter()
+        .filter_map(|path| read_claim(&path).map(|info| (path, info)))
+        .collect()
+}
+
+fn collect_claim_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if recursive {
+                collect_claim_files(&path, recursive, files);
+            }
+        } else if path.to_string_lossy().ends_with(CLAIM_SUFFIX) {
+            files.push(path);
+        }
+    }
+}
+
+/// Read a claim; one caught mid-write is dated by its modification time instead
+pub fn read_claim(path: &Path) -> Option<ClaimInfo> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok().or_else(|| {
+        let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+        Some(ClaimInfo {
+            host: "unknown".to_string(),
+            pid: 0,
+            renewed_at: modified.duration_since(UNIX_EPOCH).ok()?.as_secs(),
+            nonce: String::new(),
+        })
+    })
+}
+
+fn create_claim(path: &Path, info: &ClaimInfo) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    file.write_all(serde_json::to_string(info)?.as_bytes())
+}
+
+/// Remove the claim `stale` was read from, so the next attempt to create one can win.
+///
+/// Only one instance at a time takes a claim over, holding a `.takeover` lock next to it, and
+/// it removes the claim only while it is still the one found stale. A claim another instance
+/// created meanwhile is left alone, as is one its owner renewed after all.
+fn take_over_stale(path: &Path, stale: &ClaimInfo, lease_secs: u64) -> Result<()> {
+    let lock = path.with_extension(TAKEOVER_EXTENSION);
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+    {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            // A lock older than a lease was left behind by an instance that died taking over
+            let abandoned = std::fs::metadata(&lock)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    modified.elapsed().unwrap_or_default() > Duration::from_secs(lease_secs)
+                });
+            if abandoned {
+                let _ = std::fs::remove_file(&lock);
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context(format!("Failed to take over claim {}", path.display())))
+        }
+    }
+    let _lock = TakeoverLock(lock);
+
+    let unchanged = read_claim(path)
+        .is_some_and(|current| current.is_same_claim(stale) && current.is_stale(lease_secs));
+    if unchanged {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Failed to take over claim {}", path.display())))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The lock of a claim being taken over, removed when dropped
+struct TakeoverLock(PathBuf);
+
+impl Drop for TakeoverLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Rewrite the claim well within the lease so other instances never consider it stale. Each
+/// renewal is written aside and renamed over the claim, so readers never see half of one.
+fn spawn_renewal(path: PathBuf, info: ClaimInfo, lease_secs: u64) -> JoinHandle<()> {
+    let period = Duration::from_secs((lease_secs / 3).max(1));
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if !read_claim(&path).is_some_and(|current| current.is_same_claim(&info)) {
+                warn!(
+                    "Claim {} is no longer ours, stopping renewal",
+                    path.display()
+                );
+                return;
+            }
+            if let Err(e) = write_renewal(&path, &info.renewed()) {
+                warn!("Failed to renew claim {}: {}", path.display(), e);
+            }
+        }
+    })
+}
+
+fn write_renewal(path: &Path, info: &ClaimInfo) -> std::io::Result<()> {
+    let temp = artifacts::temp_path_for(path);
+    std::fs::write(&temp, serde_json::to_string(info)?)?;
+    std::fs::rename(&temp, path)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its full length and gethostname NUL-terminates within it
+    let ok = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0;
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    if ok && len > 0 {
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    } else {
+        "unknown".to_string()
+    }
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    fn settings(yaml: &str) -> DistributedConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn write_claim(path: &Path, host: &str, renewed_at: u64) {
+        let info = ClaimInfo {
+            host: host.to_string(),
+            pid: 4242,
+            renewed_at,
+            nonce: "0123456789abcdef".to_string(),
+        };
+        std::fs::write(path, serde_json::to_string(&info).unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn claim_is_exclusive_and_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mp4");
+        let settings = settings("lease: 1m");
+
+        let ClaimAttempt::Claimed(claim) =
+            SourceClaim::acquire(&settings, &source, dir.path()).unwrap()
+        else {
+            panic!("expected to claim a free source");
+        };
+        let path = dir.path().join("clip.mp4.sstc.claim");
+        let owner = read_claim(&path).unwrap();
+        assert_eq!((owner.host, owner.pid), (hostname(), std::process::id()));
+        assert!(matches!(
+            SourceClaim::acquire(&settings, &source, dir.path()).unwrap(),
+            ClaimAttempt::Taken(_)
+        ));
+
+        drop(claim);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn live_claim_of_another_host_is_respected() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mp4");
+        let path = dir.path().join("clip.mp4.sstc.claim");
+        write_claim(&path, "other-host", unix_now());
+
+        match SourceClaim::acquire(&settings("lease: 1m"), &source, dir.path()).unwrap() {
+            ClaimAttempt::Taken(owner) => {
+                assert_eq!((owner.host.as_str(), owner.pid), ("other-host", 4242))
+            }
+            ClaimAttempt::Claimed(_) => panic!("took a live claim"),
+        }
+        assert_eq!(read_claim(&path).unwrap().host, "other-host");
+    }
+
+    #[tokio::test]
+    async fn stale_claim_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mp4");
+        let path = dir.path().join("clip.mp4.sstc.claim");
+        write_claim(&path, "crashed-host", unix_now() - 120);
+
+        let attempt = SourceClaim::acquire(&settings("lease: 1m"), &source, dir.path()).unwrap();
+        assert!(matches!(attempt, ClaimAttempt::Claimed(_)));
+        assert_eq!(read_claim(&path).unwrap().host, hostname());
+        // Nothing is left over from the takeover
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn stale_claim_is_taken_over_by_exactly_one_contender() {
+        const CONTENDERS: usize = 8;
+        for _ in 0..20 {
+            let dir = tempfile::tempdir().unwrap();
+            let source = dir.path().join("clip.mp4");
+            let path = dir.path().join("clip.mp4.sstc.claim");
+            write_claim(&path, "crashed-host", unix_now() - 120);
+
+            let start = Arc::new(Barrier::new(CONTENDERS));
+            let contenders: Vec<_> = (0..CONTENDERS)
+                .map(|_| {
+                    let (start, source, root) =
+                        (start.clone(), source.clone(), dir.path().to_path_buf());
+                    tokio::task::spawn_blocking(move || {
+                        start.wait();
+                        SourceClaim::acquire(&settings("lease: 1m"), &source, &root)
+                    })
+                })
+                .collect();
+            let mut claims = Vec::new();
+            for contender in contenders {
+                if let ClaimAttempt::Claimed(claim) = contender.await.unwrap().unwrap() {
+                    claims.push(claim);
+                }
+            }
+
+            // Jobs of one process contend too, told apart by the claim's nonce alone
+            assert_eq!(claims.len(), 1);
+            assert!(read_claim(&path).unwrap().is_same_claim(&claims[0].info));
+            drop(claims);
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_claim_replaced_meanwhile_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4.sstc.claim");
+        write_claim(&path, "crashed-host", unix_now() - 120);
+        let stale = read_claim(&path).unwrap();
+
+        // Another instance took it over and claimed the source before this one got to it
+        std::fs::remove_file(&path).unwrap();
+        let fresh = ClaimInfo::new();
+        create_claim(&path, &fresh).unwrap();
+        take_over_stale(&path, &stale, 60).unwrap();
+        assert!(read_claim(&path).unwrap().is_same_claim(&fresh));
+
+        // The takeover lock of one that died halfway only holds up others for a lease
+        write_claim(&path, "crashed-host", unix_now() - 120);
+        let lock = path.with_extension(TAKEOVER_EXTENSION);
+        std::fs::write(&lock, b"").unwrap();
+        take_over_stale(&path, &stale, 60).unwrap();
+        assert!(path.exists());
+        take_over_stale(&path, &stale, 0).unwrap();
+        take_over_stale(&path, &stale, 60).unwrap();
+        assert!(!path.exists());
+        assert!(!lock.exists());
+    }
+
+    #[tokio::test]
+    async fn renewal_replaces_the_claim_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4.sstc.claim");
+        let info = ClaimInfo {
+            renewed_at: unix_now() - 30,
+            ..ClaimInfo::new()
+        };
+        create_claim(&path, &info).unwrap();
+
+        write_renewal(&path, &info.renewed()).unwrap();
+
+        let renewed = read_claim(&path).unwrap();
+        assert!(renewed.is_same_claim(&info));
+        assert!(renewed.age_secs() < 5);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn half_written_claim_is_dated_by_its_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4.sstc.claim");
+        std::fs::write(&path, "{\"host\":").unwrap();
+
+        let info = read_claim(&path).unwrap();
+        assert_eq!((info.host.as_str(), info.pid), ("unknown", 0));
+        assert!(!info.is_stale(60));
+    }
+
+    #[test]
+    fn shared_claims_are_keyed_by_the_path_within_the_input() {
+        let claims = Path::new("/claims");
+        let settings = DistributedConfig {
+            claims_dir: Some(claims.to_path_buf()),
+            ..settings("{}")
+        };
+        // Instances mounting the share in different places agree on the claim
+        for root in ["/mnt/nas/inbox", "/Volumes/inbox"] {
+            let source = Path::new(root).join("cams/front/clip.mp4");
+            assert_eq!(
+                claim_path(&settings, &source, Path::new(root)),
+                claims.join("cams%2Ffront%2Fclip.mp4.sstc.claim")
+            );
+        }
+
+        // A `%` in a name doesn't make two paths share a claim
+        let root = Path::new("/inbox");
+        let a = claim_path(&settings, &root.join("a/b%c.mp4"), root);
+        let b = claim_path(&settings, &root.join("a%b/c.mp4"), root);
+        assert_eq!(a, claims.join("a%2Fb%25c.mp4.sstc.claim"));
+        assert_eq!(b, claims.join("a%25b%2Fc.mp4.sstc.claim"));
+    }
+
+    #[tokio::test]
+    async fn claims_are_listed_with_their_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        write_claim(&dir.path().join("a.mp4.sstc.claim"), "box-a", unix_now());
+        write_claim(
+            &dir.path().join("sub/b.mp4.sstc.claim"),
+            "box-b",
+            unix_now(),
+        );
+        std::fs::write(dir.path().join("c.mp4"), b"").unwrap();
+
+        let listed: Vec<_> = list_claims(&settings("{}"), &[dir.path().to_path_buf()])
+            .into_iter()
+            .map(|(path, info)| (path, info.host))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (dir.path().join("a.mp4.sstc.claim"), "box-a".to_string()),
+                (dir.path().join("sub/b.mp4.sstc.claim"), "box-b".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1390 @@
+use crate::config::{
+    container_holds_attachments, container_holds_chapters, quality_option, DeinterlaceMode,
+    HwAccelConfig, HwAccelKind, PresetConfig, Rotation, StreamsConfig, SubtitlePolicy, TonemapMode,
+};
+use crate::crop::Crop;
+use crate::ffprobe::{ProbeResult, Stream};
+use crate::scaling::ScaleDecision;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings of a single ffmpeg invocation that don't come from the preset
+#[derive(Debug, Clone)]
+pub struct CommandOptions {
+    /// Report machine-readable progress on stdout this often
+    pub progress: Option<Duration>,
+    /// Replace an existing output (`-y`) instead of refusing to (`-n`)
+    pub overwrite: bool,
+    /// Global `input_options` of the config, placed before the preset's own
+    pub input_options: Vec<String>,
+    /// The pass this invocation runs of a two-pass encode
+    pub pass: Option<Pass>,
+    /// Black bars `auto_crop` found, cut away ahead of any scaling
+    pub crop: Option<Crop>,
+    /// Audio filter chain, like the loudnorm of `normalize_audio` with its measured values
+    pub audio_filter: Option<String>,
+    /// Threads of the video encoder, the preset's `threads` worked out for this machine
+    pub threads: Option<u32>,
+}
+
+/// One pass of a two-pass encode
+#[derive(Debug, Clone)]
+pub struct Pass {
+    /// 1 only gathers statistics and writes no output, 2 encodes using them
+    pub number: u8,
+    /// Prefix of the statistics files both passes share, unique per job
+    pub log_file: PathBuf,
+}
+
+/// Where the first pass writes its output, which nobody reads
+const NULL_SINK: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        Self {
+            progress: Some(Duration::from_secs(1)),
+            overwrite: false,
+            input_options: Vec::new(),
+            pass: None,
+            crop: None,
+            audio_filter: None,
+            threads: None,
+        }
+    }
+}
+
+/// Containers that take text subtitles only as mov_text
+pub const MOV_TEXT_CONTAINERS: &[&str] = &["mp4", "m4v", "mov"];
+
+/// The subtitle stream `subtitles: burn` renders, when the source has it
+pub fn burned_subtitle<'a>(preset: &PresetConfig, probe: &'a ProbeResult) -> Option<&'a Stream> {
+    if preset.subtitles != Some(SubtitlePolicy::Burn) || preset.is_audio_only() {
+        return None;
+    }
+    probe
+        .subtitle_streams()
+        .get(preset.subtitle_stream.unwrap_or(0))
+        .copied()
+}
+
+/// `-map` targets of the streams the preset keeps, or none to leave the choice to ffmpeg.
+/// A language in `audio_languages` the source has no stream in is skipped, so it can't leave
+/// the output without audio.
+fn stream_maps(
+    preset: &PresetConfig,
+    probe: &ProbeResult,
+    container: &str,
+    overlay: bool,
+    first_pass: bool,
+) -> Vec<String> {
+    let audio_only = preset.is_audio_only();
+    let copy_subtitles =
+        preset.subtitle_policy() == SubtitlePolicy::Copy && !audio_only && !first_pass;
+    // The overlay's output has to be mapped regardless; otherwise -map in extra_options wins
+    if !overlay
+        && (preset.extra_options.contains("-map")
+            || (preset.streams.is_none() && !copy_subtitles && !preset.is_remux()))
+    {
+        return Vec::new();
+    }
+    // A remux keeps everything, as map_all does
+    let default_streams = StreamsConfig {
+        map_all: preset.is_remux(),
+        ..StreamsConfig::default()
+    };
+    let streams = preset.streams.as_ref().unwrap_or(&default_streams);
+    let attachments = streams.keeps_attachments() && container_holds_attachments(container);
+
+    if streams.map_all && !overlay {
+        // What the rest of the preset leaves out is taken back out of `-map 0`, so the maps
+        // say the same as -vn, -an and -sn rather than lean on them
+        return std::iter::once("0".to_string())
+            .chain(
+                [
+                    ("v", audio_only),
+                    ("a", first_pass),
+                    ("s", !copy_subtitles),
+                    ("t", !attachments),
+                ]
+                .into_iter()
+                .filter(|(_, drop)| *drop)
+                .map(|(stream_type, _)| format!("-0:{}", stream_type)),
+            )
+            .collect();
+    }
+
+    let mut maps = Vec::new();
+    if overlay {
+        maps.push("[v]".to_string());
+    } else if !audio_only {
+        maps.push("0:v:0?".to_string());
+    }
+    if !first_pass {
+        let languages: Vec<&String> = streams
+            .audio_languages
+            .iter()
+            .filter(|language| probe.has_audio_language(language))
+            .collect();
+        if streams.map_all {
+            maps.push("0:a?".to_string());
+        } else if languages.is_empty() {
+            maps.push("0:a:0?".to_string());
+        } else {
+            maps.extend(
+                languages
+                    .into_iter()
+                    .map(|language| format!("0:a:m:language:{}", language)),
+            );
+        }
+    }
+    if copy_subtitles {
+        maps.push("0:s?".to_string());
+    }
+    if attachments {
+        maps.push("0:t?".to_string());
+    }
+    maps
+}
+
+/// The deinterlacing filter the preset's `deinterlace` applies to this source, if any
+pub fn deinterlace_filter(preset: &PresetConfig, probe: &ProbeResult) -> Option<&'static str> {
+    if preset.is_audio_only() {
+        return None;
+    }
+    let filter = match preset.deinterlace.unwrap_or_default() {
+        DeinterlaceMode::Off => None,
+        DeinterlaceMode::Yadif => Some("yadif"),
+        DeinterlaceMode::Bwdif => Some("bwdif"),
+        DeinterlaceMode::Auto => probe
+            .video_stream()
+            .is_some_and(Stream::is_interlaced)
+            .then_some("bwdif"),
+    };
+    // vaapi frames are deinterlaced where they are, on the GPU
+    match preset.hwaccel.as_ref().map(|h| h.kind) {
+        Some(HwAccelKind::Vaapi) => filter.map(|_| "deinterlace_vaapi"),
+        _ => filter,
+    }
+}
+
+/// The filter turning the picture for an explicit `rotation`, if any. `auto` leaves turning to
+/// ffmpeg's autorotate, and `keep_metadata` to the player.
+pub fn rotation_filter(preset: &PresetConfig) -> Option<&'static str> {
+    if preset.is_audio_only() {
+        return None;
+    }
+    let vaapi = preset.hwaccel.as_ref().map(|h| h.kind) == Some(HwAccelKind::Vaapi);
+    match (preset.rotation?, vaapi) {
+        (Rotation::Degrees(90), false) => Some("transpose=clock"),
+        (Rotation::Degrees(180), false) => Some("hflip,vflip"),
+        (Rotation::Degrees(270), false) => Some("transpose=cclock"),
+        (Rotation::Degrees(90), true) => Some("transpose_vaapi=dir=clock"),
+        (Rotation::Degrees(180), true) => Some("transpose_vaapi=dir=reversal"),
+        (Rotation::Degrees(270), true) => Some("transpose_vaapi=dir=cclock"),
+        _ => None,
+    }
+}
+
+/// The HDR to SDR chain the preset's `tonemap` applies to this source, if any. zscale turns
+/// the picture into linear light with BT.709 primaries, tonemap squeezes its brightness into
+/// SDR range, and zscale brings back the BT.709 transfer.
+pub fn tonemap_filter(preset: &PresetConfig, probe: &ProbeResult) -> Option<String> {
+    if preset.is_audio_only() {
+        return None;
+    }
+    let mode = preset.tonemap.unwrap_or_default();
+    if mode == TonemapMode::Auto && !probe.video_stream().is_some_and(Stream::is_hdr) {
+        return None;
+    }
+    Some(format!(
+        "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap={}:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p",
+        mode.algorithm()?
+    ))
+}
+
+/// Quote `value` for a filter option inside a filter graph, which takes one level of escaping
+/// for the option and another for the graph
+fn filter_escape(value: &str) -> String {
+    let option = value
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace(':', "\\:");
+    let mut graph = String::with_capacity(option.len());
+    for c in option.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph.push('\\');
+        }
+        graph.push(c);
+    }
+    graph
+}
+
+/// Options before `-i` that decode on the GPU. nvenc frames stay in GPU memory unless
+/// `software_filters` need them back in system memory
+fn hwaccel_input_args(hwaccel: &HwAccelConfig, software_filters: bool) -> Vec<String> {
+    let device = hwaccel.device.clone();
+    match hwaccel.kind {
+        HwAccelKind::Nvenc => {
+            let mut args = vec!["-hwaccel".to_string(), "cuda".to_string()];
+            if let Some(gpu) = device {
+                args.extend(["-hwaccel_device".to_string(), gpu]);
+            }
+            if !software_filters {
+                args.extend(["-hwaccel_output_format".to_string(), "cuda".to_string()]);
+            }
+            args
+        }
+        HwAccelKind::Vaapi => vec![
+            "-vaapi_device".to_string(),
+            device.unwrap_or_else(|| HwAccelConfig::DEFAULT_VAAPI_DEVICE.to_string()),
+            "-hwaccel".to_string(),
+            "vaapi".to_string(),
+            "-hwaccel_output_format".to_string(),
+            "vaapi".to_string(),
+        ],
+        HwAccelKind::Qsv => {
+            let mut args = vec!["-hwaccel".to_string(), "qsv".to_string()];
+            if let Some(device) = device {
+                args.extend(["-qsv_device".to_string(), device]);
+            }
+            args
+        }
+        HwAccelKind::Videotoolbox => vec!["-hwaccel".to_string(), "videotoolbox".to_string()],
+    }
+}
+
+/// Every argument sstc passes to ffmpeg for one encode, without the program name.
+///
+/// Pure: it only looks at its arguments, so the exact command line for a preset and a
+/// probed source can be predicted without running anything.
+pub fn build_ffmpeg_command(
+    input: &Path,
+    output: &Path,
+    preset: &PresetConfig,
+    probe: &ProbeResult,
+    options: &CommandOptions,
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = Vec::new();
+
+    // Warnings too, each tagged with its level so harmless ones aren't logged as errors
+    args.extend(["-loglevel".into(), "level+warning".into()]);
+    args.push("-nostats".into());
+    // Only overwrite when on_existing says so: the exists check and the output claim decide
+    // what gets written
+    args.push(if options.overwrite { "-y" } else { "-n" }.into());
+    if let Some(period) = options.progress {
+        args.extend(["-progress".into(), "pipe:1".into()]);
+        args.extend([
+            "-stats_period".into(),
+            format!("{:?}", period.as_secs_f64()).into(),
+        ]);
+    }
+
+    let audio_only = preset.is_audio_only();
+    let subtitle_index = preset.subtitle_stream.unwrap_or(0);
+    let burned = burned_subtitle(preset, probe);
+    // Bitmap subtitles are pictures to overlay, which takes a filter graph of two inputs
+    let overlay = burned.is_some_and(Stream::is_bitmap_subtitle);
+    let scales: Vec<String> = preset
+        .scale
+        .iter()
+        .filter(|_| !audio_only)
+        .map(|scale| format!("scale={}", scale))
+        .chain(
+            ScaleDecision::new(preset, probe)
+                .filter()
+                .map(str::to_string),
+        )
+        .collect();
+    let crop = options
+        .crop
+        .filter(|_| !audio_only)
+        .map(|crop| crop.filter());
+    let deinterlace = deinterlace_filter(preset, probe);
+    let tonemap = tonemap_filter(preset, probe);
+    let rotate = rotation_filter(preset);
+    let fps = preset
+        .fps
+        .filter(|_| !audio_only)
+        .map(|fps| format!("fps={}", fps));
+    let user_filters: &[String] = if audio_only {
+        &[]
+    } else {
+        &preset.video_filters
+    };
+
+    // Input options only apply to the input that follows them
+    if let Some(hwaccel) = preset.hwaccel.as_ref().filter(|_| !audio_only) {
+        let software_filters = !scales.is_empty()
+            || crop.is_some()
+            || deinterlace.is_some()
+            || tonemap.is_some()
+            || rotate.is_some()
+            || burned.is_some()
+            || !user_filters.is_empty()
+            || preset.pixel_format.is_some()
+            || preset.extra_options.contains("-filter_complex");
+        args.extend(
+            hwaccel_input_args(hwaccel, software_filters)
+                .into_iter()
+                .map(Into::into),
+        );
+    }
+    args.extend(options.input_options.iter().map(Into::into));
+    args.extend(preset.input_options.iter().map(Into::into));
+    // Without autorotate the picture is encoded as stored, and ffmpeg carries the display
+    // matrix over to the output
+    if preset.rotation == Some(Rotation::KeepMetadata)
+        && !audio_only
+        && !preset.input_options.contains(&"-noautorotate".to_string())
+    {
+        args.push("-noautorotate".into());
+    }
+    args.extend(["-i".into(), input.into()]);
+
+    let container = output
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let first_pass = options.pass.as_ref().is_some_and(|pass| pass.number == 1);
+    for map in stream_maps(preset, probe, &container, overlay, first_pass) {
+        args.extend(["-map".into(), map.into()]);
+    }
+    // Global tags and chapters come from the source, unless the preset opts out with -1
+    if !first_pass && !preset.extra_options.contains("-map_metadata") {
+        let source = if preset.preserve_metadata.unwrap_or(true) {
+            "0"
+        } else {
+            "-1"
+        };
+        args.extend(["-map_metadata".into(), source.into()]);
+    }
+    if !first_pass && !preset.extra_options.contains("-map_chapters") {
+        let source =
+            if preset.preserve_chapters.unwrap_or(true) && container_holds_chapters(&container) {
+                "0"
+            } else {
+                "-1"
+            };
+        args.extend(["-map_chapters".into(), source.into()]);
+    }
+    if audio_only {
+        args.push("-vn".into());
+    }
+    if preset.is_remux() {
+        args.extend(["-c".into(), "copy".into()]);
+    }
+    if let Some(video_encoder) = preset.video_encoder() {
+        args.extend(["-c:v".into(), video_encoder.into()]);
+    }
+    if let Some(HwAccelConfig {
+        kind: HwAccelKind::Nvenc,
+        device: Some(gpu),
+    }) = preset.hwaccel.as_ref().filter(|_| !audio_only)
+    {
+        args.extend(["-gpu".into(), gpu.into()]);
+    }
+    if first_pass {
+        args.push("-an".into());
+    } else if let Some(audio_codec) = &preset.audio_codec {
+        args.extend(["-c:a".into(), audio_codec.into()]);
+    }
+
+    if let Some(video_bitrate) = preset.video_bitrate.as_ref().filter(|_| !audio_only) {
+        args.extend(["-b:v".into(), video_bitrate.into()]);
+    }
+    // Settings given again in extra_options are left to them, which validation warns about
+    if let Some(encoder) = preset.video_encoder().filter(|_| !preset.is_remux()) {
+        if let (Some(crf), Some((flag, _))) = (preset.crf, quality_option(&encoder)) {
+            if !preset.extra_options.contains(flag) {
+                args.extend([flag.into(), crf.to_string().into()]);
+                // libvpx only goes by quality alone with its bitrate cap lifted
+                if encoder.starts_with("libvpx") && preset.video_bitrate.is_none() {
+                    args.extend(["-b:v".into(), "0".into()]);
+                }
+            }
+        }
+        if let Some(speed) = preset
+            .encoder_preset
+            .as_ref()
+            .filter(|_| !preset.extra_options.contains("-preset"))
+        {
+            args.extend(["-preset".into(), speed.into()]);
+        }
+        if let Some(threads) = options
+            .threads
+            .filter(|_| !preset.extra_options.contains("-threads"))
+        {
+            args.extend(["-threads".into(), threads.to_string().into()]);
+        }
+    }
+    if let Some(audio_bitrate) = preset.audio_bitrate.as_ref().filter(|_| !first_pass) {
+        args.extend(["-b:a".into(), audio_bitrate.into()]);
+    }
+    // The preset's own filters come first, so normalize_audio measures what they leave
+    let audio_filters: Vec<&str> = preset
+        .audio_filters
+        .iter()
+        .map(String::as_str)
+        .chain(options.audio_filter.as_deref())
+        .collect();
+    if !first_pass && !audio_filters.is_empty() {
+        args.extend(["-af".into(), audio_filters.join(",").into()]);
+    }
+    // libx265 ignores -pass and takes its pass settings in -x265-params instead
+    let x265_pass = options
+        .pass
+        .as_ref()
+        .filter(|_| preset.video_encoder().as_deref() == Some("libx265"))
+        .map(|pass| {
+            format!(
+                "pass={}:stats={}-0.log",
+                pass.number,
+                pass.log_file.display()
+            )
+        });
+    if let Some(pass) = &options.pass {
+        args.extend([
+            "-pass".into(),
+            pass.number.to_string().into(),
+            "-passlogfile".into(),
+            pass.log_file.clone().into(),
+        ]);
+    }
+
+    if let Some(pixel_format) = preset.pixel_format.as_ref().filter(|_| !audio_only) {
+        args.extend(["-pix_fmt".into(), pixel_format.into()]);
+    }
+    // Tagged as what it now is, or players would still treat the output as HDR
+    if tonemap.is_some() {
+        for flag in ["-color_primaries", "-color_trc", "-colorspace"] {
+            args.extend([flag.into(), "bt709".into()]);
+        }
+    }
+
+    if !audio_only && preset.hwaccel.as_ref().map(|h| h.kind) == Some(HwAccelKind::Vaapi) {
+        // Frames decoded in software are uploaded, hardware-decoded ones pass straight through
+        let chain: Vec<String> = ["format=nv12|vaapi".to_string(), "hwupload".to_string()]
+            .into_iter()
+            .chain(deinterlace.map(str::to_string))
+            .chain(fps)
+            .chain(rotate.map(str::to_string))
+            .chain(user_filters.iter().cloned())
+            .chain(
+                scales
+                    .iter()
+                    .map(|s| s.replacen("scale=", "scale_vaapi=", 1)),
+            )
+            .collect();
+        args.extend(["-vf".into(), chain.join(",").into()]);
+    } else if overlay {
+        // Pictures placed in the bars are cut away with them, overlay can't move them
+        let chain: Vec<String> = std::iter::once("overlay".to_string())
+            .chain(crop)
+            .chain(rotate.map(str::to_string))
+            .chain(user_filters.iter().cloned())
+            .chain(scales)
+            .collect();
+        // Only the video is deinterlaced and tone mapped; subtitle pictures have no fields and
+        // are already SDR
+        let source: Vec<String> = deinterlace
+            .map(str::to_string)
+            .into_iter()
+            .chain(fps)
+            .chain(tonemap)
+            .collect();
+        let video = if source.is_empty() {
+            "[0:v:0]".to_string()
+        } else {
+            format!("[0:v:0]{}[src];[src]", source.join(","))
+        };
+        args.extend([
+            "-filter_complex".into(),
+            format!("{}[0:s:{}]{}[v]", video, subtitle_index, chain.join(",")).into(),
+        ]);
+    } else {
+        // Fields are woven back together before anything looks at the picture, and frames are
+        // left out before anything else works on them. Crops are found on the picture as
+        // stored. Subtitles go on after tone mapping and turning and before scaling, so they
+        // keep their colors, stand upright and are sized for the picture
+        let chain: Vec<String> = deinterlace
+            .map(str::to_string)
+            .into_iter()
+            .chain(fps)
+            .chain(crop)
+            .chain(tonemap)
+            .chain(rotate.map(str::to_string))
+            .chain(burned.map(|_| {
+                format!(
+                    "subtitles=filename={}:si={}",
+                    filter_escape(&input.to_string_lossy()),
+                    subtitle_index
+                )
+            }))
+            .chain(user_filters.iter().cloned())
+            .chain(scales)
+            .collect();
+        if !chain.is_empty() {
+            args.extend(["-vf".into(), chain.join(",").into()]);
+        }
+    }
+
+    match preset.subtitle_policy() {
+        SubtitlePolicy::Copy if !audio_only && !first_pass => {
+            let codec = if MOV_TEXT_CONTAINERS.contains(&container.as_str()) {
+                "mov_text"
+            } else {
+                "copy"
+            };
+            args.extend(["-c:s".into(), codec.into()]);
+        }
+        _ => args.push("-sn".into()),
+    }
+
+    // x265 sizes its thread pools by `pools` and not by -threads, so the count goes into
+    // the x265 params of the encode too, unless they set their own
+    let x265_pools = options
+        .threads
+        .filter(|_| preset.video_encoder().as_deref() == Some("libx265"))
+        .map(|threads| format!("pools={}", threads));
+    for (flag, value) in preset.extra_options.iter() {
+        args.push(flag.into());
+        // Flags like `-an` stand on their own
+        match value {
+            Some(value) if flag == "-x265-params" => {
+                let params: Vec<&str> = std::iter::once(value)
+                    .chain(x265_pools.as_deref().filter(|_| !value.contains("pools=")))
+                    .chain(x265_pass.as_deref())
+                    .collect();
+                args.push(params.join(":").into());
+            }
+            Some(value) => args.push(value.into()),
+            None => {}
+        }
+    }
+    if let Some(pass) = x265_pass.filter(|_| !preset.extra_options.contains("-x265-params")) {
+        let params: Vec<String> = x265_pools.into_iter().chain([pass]).collect();
+        args.extend(["-x265-params".into(), params.join(":").into()]);
+    }
+
+    if first_pass {
+        args.extend(["-f".into(), "null".into(), NULL_SINK.into()]);
+    } else {
+        args.push(output.into());
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{preset, probe, video_probe};
+    use serde_json::{json, Value};
+
+    /// Arguments every encode starts with when progress is off and outputs aren't replaced
+    const PREFIX: &str = "-loglevel level+warning -nostats -n";
+
+    /// Options without progress reporting, which every case would repeat otherwise
+    fn quiet() -> CommandOptions {
+        CommandOptions {
+            progress: None,
+            ..CommandOptions::default()
+        }
+    }
+
+    /// A source with a 1080p h264 video stream with `video` fields set, and `others` after it
+    fn source_with(video: Value, others: Vec<Value>) -> ProbeResult {
+        let mut stream = json!({
+            "index": 0,
+            "codec_type": "video",
+            "codec_name": "h264",
+            "width": 1920,
+            "height": 1080,
+            "r_frame_rate": "25/1",
+        });
+        for (key, value) in video.as_object().unwrap() {
+            stream[key] = value.clone();
+        }
+        let mut streams = vec![stream];
+        streams.extend(others);
+        probe(json!({
+            "format": {
+                "filename": "/in/clip.mp4",
+                "nb_streams": streams.len(),
+                "format_name": "mov",
+                "duration": "10.0",
+            },
+            "streams": streams,
+        }))
+    }
+
+    fn audio(index: u32, language: &str) -> Value {
+        json!({"index": index, "codec_type": "audio", "codec_name": "aac", "tags": {"language": language}})
+    }
+
+    fn subtitle(index: u32, codec: &str) -> Value {
+        json!({"index": index, "codec_type": "subtitle", "codec_name": codec})
+    }
+
+    fn args_of(
+        preset_yaml: &str,
+        output: &str,
+        probe: &ProbeResult,
+        options: &CommandOptions,
+    ) -> Vec<String> {
+        build_ffmpeg_command(
+            Path::new("/in/clip.mp4"),
+            Path::new(output),
+            &preset(preset_yaml),
+            probe,
+            options,
+        )
+        .into_iter()
+        .map(|arg| arg.into_string().unwrap())
+        .collect()
+    }
+
+    /// Check each `(preset, output, expected)` against `probe`; `expected` is everything after
+    /// the prefix, split on spaces
+    fn check_table(probe: &ProbeResult, options: &CommandOptions, cases: &[(&str, &str, &str)]) {
+        for (preset_yaml, output, expected) in cases {
+            let expected: Vec<String> = format!("{} {}", PREFIX, expected)
+                .split(' ')
+                .map(str::to_string)
+                .collect();
+            assert_eq!(
+                args_of(preset_yaml, output, probe, options),
+                expected,
+                "preset:\n{}",
+                preset_yaml
+            );
+        }
+    }
+
+    #[test]
+    fn progress_and_overwrite_lead_the_command() {
+        let args = args_of(
+            "video_codec: libx264",
+            "/out/clip.mkv",
+            &video_probe(10.0),
+            &CommandOptions {
+                overwrite: true,
+                ..CommandOptions::default()
+            },
+        );
+        assert_eq!(
+            args[..8],
+            [
+                "-loglevel",
+                "level+warning",
+                "-nostats",
+                "-y",
+                "-progress",
+                "pipe:1",
+                "-stats_period",
+                "1.0"
+            ]
+        );
+    }
+
+    #[test]
+    fn codecs_quality_and_bitrates() {
+        check_table(
+            &video_probe(10.0),
+            &quiet(),
+            &[
+                (
+                    "video_codec: libx264",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx265\ncrf: 24\nencoder_preset: slow\naudio_codec: aac\naudio_bitrate: 128k",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -c:a aac -crf 24 -preset slow -b:a 128k -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nvideo_bitrate: 4M\npixel_format: yuv420p",
+                    "/out/clip.mp4",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -b:v 4M -pix_fmt yuv420p -sn /out/clip.mp4",
+                ),
+                // libvpx needs its bitrate cap lifted to go by quality, and webm has no chapters
+                (
+                    "video_codec: libvpx-vp9\ncrf: 31\naudio_codec: libopus",
+                    "/out/clip.webm",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters -1 -c:v libvpx-vp9 -c:a libopus -crf 31 -b:v 0 -sn /out/clip.webm",
+                ),
+                (
+                    "video_codec: libvpx-vp9\ncrf: 31\nvideo_bitrate: 2M",
+                    "/out/clip.webm",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters -1 -c:v libvpx-vp9 -b:v 2M -crf 31 -sn /out/clip.webm",
+                ),
+                // Settings given again in extra_options are left to them
+                (
+                    "video_codec: libx264\ncrf: 20\nencoder_preset: fast\nextra_options:\n  - -crf: '18'\n  - -preset: veryslow\n  - -an:",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn -crf 18 -preset veryslow -an /out/clip.mkv",
+                ),
+                // Encoders without a known quality option get no -crf at all
+                (
+                    "video_codec: mpeg4\ncrf: 20",
+                    "/out/clip.avi",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters -1 -c:v mpeg4 -sn /out/clip.avi",
+                ),
+                (
+                    "preserve_metadata: false\npreserve_chapters: false\nvideo_codec: libx264",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata -1 -map_chapters -1 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nextra_options:\n  - -map_metadata: '-1'\n  - -map_chapters: '-1'",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -c:v libx264 -sn -map_metadata -1 -map_chapters -1 /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn remux_and_audio_only() {
+        check_table(
+            &video_probe(10.0),
+            &quiet(),
+            &[
+                (
+                    "mode: remux",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0 -map_metadata 0 -map_chapters 0 -c copy -c:s copy /out/clip.mkv",
+                ),
+                // mp4 takes text subtitles as mov_text, and no attachments at all
+                (
+                    "mode: remux",
+                    "/out/clip.mp4",
+                    "-i /in/clip.mp4 -map 0 -map -0:t -map_metadata 0 -map_chapters 0 -c copy -c:s mov_text /out/clip.mp4",
+                ),
+                (
+                    "audio_only: true\nvideo_codec: libx264\naudio_codec: aac\naudio_bitrate: 192k\nscale: 1280:-2\nfps: 30\npixel_format: yuv420p",
+                    "/out/clip.m4a",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -vn -c:a aac -b:a 192k -sn /out/clip.m4a",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn filters_in_order() {
+        check_table(
+            &video_probe(10.0),
+            &quiet(),
+            &[
+                (
+                    "video_codec: libx264\ndeinterlace: yadif\nfps: 30\nrotation: 90\nvideo_filters: [hqdn3d, 'eq=gamma=1.1']\nscale: -2:720",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf yadif,fps=30,transpose=clock,hqdn3d,eq=gamma=1.1,scale=-2:720 -sn /out/clip.mkv",
+                ),
+                // The source is progressive, so auto leaves it alone
+                (
+                    "video_codec: libx264\ndeinterlace: auto\nmax_height: 720",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf scale=iw:'min(ih,720)':force_original_aspect_ratio=decrease:force_divisible_by=2 -sn /out/clip.mkv",
+                ),
+                // Already small enough
+                (
+                    "video_codec: libx264\nmax_width: 1920\nmax_height: 1080",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nrotation: 90",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf transpose=clock -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nrotation: 180\naudio_filters: [highpass=f=80]",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -af highpass=f=80 -vf hflip,vflip -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nrotation: keep_metadata",
+                    "/out/clip.mkv",
+                    "-noautorotate -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                // Fits as stored, but not once turned upright
+                (
+                    "video_codec: libx264\nrotation: 90\nmax_width: 1920\nmax_height: 1080",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf transpose=clock,scale='min(iw,1920)':'min(ih,1080)':force_original_aspect_ratio=decrease:force_divisible_by=2 -sn /out/clip.mkv",
+                ),
+            ],
+        );
+
+        // A portrait phone clip, stored landscape with a display matrix turning it 90°
+        let portrait = source_with(
+            json!({"side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]}),
+            vec![],
+        );
+        check_table(
+            &portrait,
+            &quiet(),
+            &[
+                (
+                    "video_codec: libx264\nmax_width: 1920\nmax_height: 1080",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf scale='min(iw,1920)':'min(ih,1080)':force_original_aspect_ratio=decrease:force_divisible_by=2 -sn /out/clip.mkv",
+                ),
+                // Turned back to landscape by the explicit rotation, it fits
+                (
+                    "video_codec: libx264\nrotation: 270\nmax_width: 1920\nmax_height: 1080",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf transpose=cclock -sn /out/clip.mkv",
+                ),
+                // Encoded as stored, it fits
+                (
+                    "video_codec: libx264\nrotation: keep_metadata\nmax_width: 1920\nmax_height: 1080",
+                    "/out/clip.mkv",
+                    "-noautorotate -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn filters_for_interlaced_and_hdr_sources() {
+        let interlaced = source_with(json!({"field_order": "tt"}), vec![]);
+        check_table(
+            &interlaced,
+            &quiet(),
+            &[(
+                "video_codec: libx264\ndeinterlace: auto",
+                "/out/clip.mkv",
+                "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf bwdif -sn /out/clip.mkv",
+            )],
+        );
+
+        let hdr = source_with(json!({"color_transfer": "smpte2084"}), vec![]);
+        check_table(
+            &hdr,
+            &quiet(),
+            &[(
+                "video_codec: libx264\ntonemap: auto\nmax_height: 720",
+                "/out/clip.mkv",
+                "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -color_primaries bt709 -color_trc bt709 -colorspace bt709 -vf zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p,scale=iw:'min(ih,720)':force_original_aspect_ratio=decrease:force_divisible_by=2 -sn /out/clip.mkv",
+            )],
+        );
+        // SDR sources aren't tone mapped by auto, but are by a named curve
+        check_table(
+            &video_probe(10.0),
+            &quiet(),
+            &[
+                (
+                    "video_codec: libx264\ntonemap: auto",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\ntonemap: mobius",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -color_primaries bt709 -color_trc bt709 -colorspace bt709 -vf zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=mobius:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p -sn /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn crop_comes_before_scaling() {
+        let options = CommandOptions {
+            crop: Some(Crop {
+                width: 1920,
+                height: 800,
+                x: 0,
+                y: 140,
+            }),
+            ..quiet()
+        };
+        check_table(
+            &video_probe(10.0),
+            &options,
+            &[
+                (
+                    "video_codec: libx264\ndeinterlace: yadif\nscale: -2:720",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf yadif,crop=1920:800:0:140,scale=-2:720 -sn /out/clip.mkv",
+                ),
+                (
+                    "audio_only: true\naudio_codec: aac",
+                    "/out/clip.m4a",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -vn -c:a aac -sn /out/clip.m4a",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn hardware_backends() {
+        check_table(
+            &video_probe(10.0),
+            &quiet(),
+            &[
+                // Frames stay on the GPU without software filters
+                (
+                    "video_codec: hevc\ncrf: 28\nencoder_preset: p5\nhwaccel: {type: nvenc, device: '1'}",
+                    "/out/clip.mkv",
+                    "-hwaccel cuda -hwaccel_device 1 -hwaccel_output_format cuda -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v hevc_nvenc -gpu 1 -cq 28 -preset p5 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: h264\nhwaccel: {type: nvenc}\nscale: -2:720",
+                    "/out/clip.mkv",
+                    "-hwaccel cuda -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v h264_nvenc -vf scale=-2:720 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: hevc\ncrf: 25\nhwaccel: {type: vaapi}\ndeinterlace: yadif\nfps: 30\nmax_height: 720",
+                    "/out/clip.mkv",
+                    "-vaapi_device /dev/dri/renderD128 -hwaccel vaapi -hwaccel_output_format vaapi -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v hevc_vaapi -qp 25 -vf format=nv12|vaapi,hwupload,deinterlace_vaapi,fps=30,scale_vaapi=iw:'min(ih,720)':force_original_aspect_ratio=decrease:force_divisible_by=2 -sn /out/clip.mkv",
+                ),
+                (
+                    "hwaccel: {type: vaapi, device: /dev/dri/renderD129}\nrotation: 270",
+                    "/out/clip.mkv",
+                    "-vaapi_device /dev/dri/renderD129 -hwaccel vaapi -hwaccel_output_format vaapi -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v h264_vaapi -vf format=nv12|vaapi,hwupload,transpose_vaapi=dir=cclock -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: av1\ncrf: 30\nhwaccel: {type: qsv, device: /dev/dri/renderD128}",
+                    "/out/clip.mkv",
+                    "-hwaccel qsv -qsv_device /dev/dri/renderD128 -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v av1_qsv -global_quality 30 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: hevc\nvideo_bitrate: 6M\nhwaccel: {type: videotoolbox}",
+                    "/out/clip.mp4",
+                    "-hwaccel videotoolbox -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v hevc_videotoolbox -b:v 6M -sn /out/clip.mp4",
+                ),
+                // Audio-only presets leave the GPU alone
+                (
+                    "audio_only: true\naudio_codec: aac\nhwaccel: {type: nvenc, device: '0'}",
+                    "/out/clip.m4a",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -vn -c:a aac -sn /out/clip.m4a",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn subtitles_copied_and_burned() {
+        let source = source_with(
+            json!({}),
+            vec![
+                audio(1, "eng"),
+                subtitle(2, "subrip"),
+                subtitle(3, "hdmv_pgs_subtitle"),
+            ],
+        );
+        check_table(
+            &source,
+            &quiet(),
+            &[
+                (
+                    "video_codec: libx264\nsubtitles: copy",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0:v:0? -map 0:a:0? -map 0:s? -map_metadata 0 -map_chapters 0 -c:v libx264 -c:s copy /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nsubtitles: copy",
+                    "/out/clip.mp4",
+                    "-i /in/clip.mp4 -map 0:v:0? -map 0:a:0? -map 0:s? -map_metadata 0 -map_chapters 0 -c:v libx264 -c:s mov_text /out/clip.mp4",
+                ),
+                (
+                    "video_codec: libx264\nsubtitles: burn\nscale: -2:720",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -vf subtitles=filename=/in/clip.mp4:si=0,scale=-2:720 -sn /out/clip.mkv",
+                ),
+                // Bitmap subtitles are overlaid, which needs the video mapped from the graph
+                (
+                    "video_codec: libx264\nsubtitles: burn\nsubtitle_stream: 1\ndeinterlace: yadif\nscale: -2:720",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map [v] -map 0:a:0? -map_metadata 0 -map_chapters 0 -c:v libx264 -filter_complex [0:v:0]yadif[src];[src][0:s:1]overlay,scale=-2:720[v] -sn /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn burned_subtitle_paths_are_escaped() {
+        let source = source_with(json!({}), vec![subtitle(1, "ass")]);
+        let args: Vec<String> = build_ffmpeg_command(
+            Path::new("/in/it's [1:2], really.mkv"),
+            Path::new("/out/clip.mkv"),
+            &preset("video_codec: libx264\nsubtitles: burn"),
+            &source,
+            &quiet(),
+        )
+        .into_iter()
+        .map(|arg| arg.into_string().unwrap())
+        .collect();
+        let vf = args.iter().position(|arg| arg == "-vf").unwrap();
+        assert_eq!(
+            args[vf + 1],
+            r"subtitles=filename=/in/it\\\'s \[1\\:2\]\, really.mkv:si=0"
+        );
+    }
+
+    #[test]
+    fn two_pass() {
+        let pass = |number| CommandOptions {
+            pass: Some(Pass {
+                number,
+                log_file: PathBuf::from("/tmp/sstc-2pass-7"),
+            }),
+            ..quiet()
+        };
+        let x264 = "video_codec: libx264\nvideo_bitrate: 3M\naudio_codec: aac\naudio_bitrate: 128k\ntwo_pass: true\nsubtitles: copy";
+        check_table(
+            &video_probe(10.0),
+            &pass(1),
+            &[(
+                x264,
+                "/out/clip.mkv",
+                &format!("-i /in/clip.mp4 -c:v libx264 -an -b:v 3M -pass 1 -passlogfile /tmp/sstc-2pass-7 -sn -f null {}", NULL_SINK),
+            )],
+        );
+        check_table(
+            &video_probe(10.0),
+            &pass(2),
+            &[(
+                x264,
+                "/out/clip.mkv",
+                "-i /in/clip.mp4 -map 0:v:0? -map 0:a:0? -map 0:s? -map_metadata 0 -map_chapters 0 -c:v libx264 -c:a aac -b:v 3M -b:a 128k -pass 2 -passlogfile /tmp/sstc-2pass-7 -c:s copy /out/clip.mkv",
+            )],
+        );
+        // libx265 takes its pass in -x265-params, joined to any the preset has
+        check_table(
+            &video_probe(10.0),
+            &pass(2),
+            &[
+                (
+                    "video_codec: libx265\nvideo_bitrate: 3M\ntwo_pass: true",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -b:v 3M -pass 2 -passlogfile /tmp/sstc-2pass-7 -sn -x265-params pass=2:stats=/tmp/sstc-2pass-7-0.log /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx265\nvideo_bitrate: 3M\ntwo_pass: true\nextra_options:\n  - -x265-params: aq-mode=3",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -b:v 3M -pass 2 -passlogfile /tmp/sstc-2pass-7 -sn -x265-params aq-mode=3:pass=2:stats=/tmp/sstc-2pass-7-0.log /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn audio_filters() {
+        let options = CommandOptions {
+            audio_filter: Some("loudnorm=I=-16:TP=-1.5:LRA=11".to_string()),
+            ..quiet()
+        };
+        check_table(
+            &video_probe(10.0),
+            &options,
+            &[
+                (
+                    "video_codec: libx264\naudio_codec: aac\naudio_filters: [highpass=f=80]",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -c:a aac -af highpass=f=80,loudnorm=I=-16:TP=-1.5:LRA=11 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx265",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -af loudnorm=I=-16:TP=-1.5:LRA=11 -sn /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn thread_counts() {
+        let options = CommandOptions {
+            threads: Some(8),
+            ..quiet()
+        };
+        check_table(
+            &video_probe(10.0),
+            &options,
+            &[
+                (
+                    "video_codec: libx264\nencoder_preset: slow",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -preset slow -threads 8 -sn /out/clip.mkv",
+                ),
+                // x265 sizes its pools from its own params, which only get the count when present
+                (
+                    "video_codec: libx265",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -threads 8 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx265\nextra_options:\n  - -x265-params: aq-mode=3",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -threads 8 -sn -x265-params aq-mode=3:pools=8 /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx265\nextra_options:\n  - -x265-params: pools=4",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -threads 8 -sn -x265-params pools=4 /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nextra_options:\n  - -threads: \"2\"",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn -threads 2 /out/clip.mkv",
+                ),
+            ],
+        );
+        let pass = CommandOptions {
+            pass: Some(Pass {
+                number: 2,
+                log_file: PathBuf::from("/tmp/sstc-2pass-7"),
+            }),
+            ..options
+        };
+        check_table(
+            &video_probe(10.0),
+            &pass,
+            &[(
+                "video_codec: libx265\nvideo_bitrate: 3M\ntwo_pass: true",
+                "/out/clip.mkv",
+                "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx265 -b:v 3M -threads 8 -pass 2 -passlogfile /tmp/sstc-2pass-7 -sn -x265-params pools=8:pass=2:stats=/tmp/sstc-2pass-7-0.log /out/clip.mkv",
+            )],
+        );
+    }
+
+    #[test]
+    fn input_options_go_before_the_input() {
+        let options = CommandOptions {
+            input_options: vec!["-fflags".to_string(), "+genpts".to_string()],
+            ..quiet()
+        };
+        check_table(
+            &video_probe(10.0),
+            &options,
+            &[(
+                "video_codec: h264\nhwaccel: {type: nvenc}\ninput_options: [-ss, '30']",
+                "/out/clip.mkv",
+                "-hwaccel cuda -hwaccel_output_format cuda -fflags +genpts -ss 30 -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v h264_nvenc -sn /out/clip.mkv",
+            )],
+        );
+    }
+
+    #[test]
+    fn input_options_keep_their_order() {
+        let options = CommandOptions {
+            input_options: vec![
+                "-probesize".to_string(),
+                "50M".to_string(),
+                "-analyzeduration".to_string(),
+                "100M".to_string(),
+            ],
+            ..quiet()
+        };
+        check_table(
+            &video_probe(10.0),
+            &options,
+            &[
+                (
+                    "video_codec: libx264\ninput_options: [-ss, '5', -t, '2', -ss, '1']",
+                    "/out/clip.mkv",
+                    "-probesize 50M -analyzeduration 100M -ss 5 -t 2 -ss 1 -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                // The preset's rotation flag follows everything configured
+                (
+                    "video_codec: libx264\nrotation: keep_metadata\ninput_options: [-ss, '5']",
+                    "/out/clip.mkv",
+                    "-probesize 50M -analyzeduration 100M -ss 5 -noautorotate -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nrotation: keep_metadata\ninput_options: [-noautorotate]",
+                    "/out/clip.mkv",
+                    "-probesize 50M -analyzeduration 100M -noautorotate -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn example_hardware_presets_decode_on_the_device() {
+        let mut config = crate::config::Config::default();
+        crate::presets::PresetGenerator::generate_example_presets(&mut config).unwrap();
+        for (name, expected) in [
+            (
+                "vaapi_hevc",
+                "-vaapi_device /dev/dri/renderD128 -hwaccel vaapi -hwaccel_output_format vaapi -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v hevc_vaapi -c:a copy -qp 24 -vf format=nv12|vaapi,hwupload -sn -tag:v hvc1 /out/clip.mkv",
+            ),
+            (
+                "nvenc_hevc",
+                "-hwaccel cuda -hwaccel_output_format cuda -i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v hevc_nvenc -c:a copy -cq 24 -preset p5 -sn -tag:v hvc1 /out/clip.mkv",
+            ),
+        ] {
+            let args: Vec<String> = build_ffmpeg_command(
+                Path::new("/in/clip.mp4"),
+                Path::new("/out/clip.mkv"),
+                &config.presets[name],
+                &video_probe(10.0),
+                &quiet(),
+            )
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect();
+            assert_eq!(args.join(" "), format!("{} {}", PREFIX, expected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn stream_maps_in_order() {
+        let source = source_with(
+            json!({}),
+            vec![
+                audio(1, "eng"),
+                audio(2, "jpn"),
+                audio(3, "fre"),
+                subtitle(4, "subrip"),
+                json!({"index": 5, "codec_type": "attachment", "codec_name": "ttf"}),
+            ],
+        );
+        check_table(
+            &source,
+            &quiet(),
+            &[
+                // Languages in the order asked for, not the order of the source
+                (
+                    "video_codec: libx264\nstreams: {audio_languages: [jpn, eng]}",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0:v:0? -map 0:a:m:language:jpn -map 0:a:m:language:eng -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                // Languages the source lacks are left out, and with none left the first track
+                (
+                    "video_codec: libx264\nstreams: {audio_languages: [ger, eng]}",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0:v:0? -map 0:a:m:language:eng -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nstreams: {audio_languages: [ger]}",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0:v:0? -map 0:a:0? -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nsubtitles: copy\nstreams: {audio_languages: [fre], keep_attachments: true}",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0:v:0? -map 0:a:m:language:fre -map 0:s? -map 0:t? -map_metadata 0 -map_chapters 0 -c:v libx264 -c:s copy /out/clip.mkv",
+                ),
+                // Everything, minus what the rest of the preset or the container leaves out
+                (
+                    "video_codec: libx264\nstreams: {map_all: true}",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0 -map -0:s -map_metadata 0 -map_chapters 0 -c:v libx264 -sn /out/clip.mkv",
+                ),
+                (
+                    "video_codec: libx264\nsubtitles: copy\nstreams: {map_all: true}",
+                    "/out/clip.mp4",
+                    "-i /in/clip.mp4 -map 0 -map -0:t -map_metadata 0 -map_chapters 0 -c:v libx264 -c:s mov_text /out/clip.mp4",
+                ),
+                (
+                    "video_codec: libx264\nsubtitles: copy\nstreams: {map_all: true, keep_attachments: false}",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map 0 -map -0:t -map_metadata 0 -map_chapters 0 -c:v libx264 -c:s copy /out/clip.mkv",
+                ),
+                (
+                    "audio_only: true\naudio_codec: aac\nstreams: {map_all: true}",
+                    "/out/clip.m4a",
+                    "-i /in/clip.mp4 -map 0 -map -0:v -map -0:s -map -0:t -map_metadata 0 -map_chapters 0 -vn -c:a aac -sn /out/clip.m4a",
+                ),
+                // Maps in extra_options replace the generated ones, in the order given
+                (
+                    "video_codec: libx264\nstreams: {audio_languages: [jpn]}\nextra_options:\n  - -map: '0:v'\n  - -map: '0:a:2'\n  - -map: '0:a:0'",
+                    "/out/clip.mkv",
+                    "-i /in/clip.mp4 -map_metadata 0 -map_chapters 0 -c:v libx264 -sn -map 0:v -map 0:a:2 -map 0:a:0 /out/clip.mkv",
+                ),
+            ],
+        );
+    }
+
+    /// Real ffprobe's report on `path`, or `None` when ffprobe isn't installed
+    fn real_probe(path: &Path) -> Option<ProbeResult> {
+        let output = std::process::Command::new("ffprobe")
+            .args(["-v", "quiet", "-print_format", "json"])
+            .args(["-show_format", "-show_streams", "-show_chapters"])
+            .arg(path)
+            .output()
+            .ok()?;
+        assert!(
+            output.status.success(),
+            "ffprobe failed on {}",
+            path.display()
+        );
+        Some(serde_json::from_slice(&output.stdout).unwrap())
+    }
+
+    /// Run the real ffmpeg, returning `false` when it isn't installed
+    fn real_ffmpeg(args: &[OsString]) -> bool {
+        let Ok(output) = std::process::Command::new("ffmpeg").args(args).output() else {
+            return false;
+        };
+        assert!(
+            output.status.success(),
+            "ffmpeg {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        true
+    }
+
+    #[test]
+    fn chapters_survive_a_real_encode() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = dir.path().join("chapters.txt");
+        std::fs::write(
+            &metadata,
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=1000\ntitle=One\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=1000\nEND=2000\ntitle=Two\n",
+        )
+        .unwrap();
+        let source = dir.path().join("clip.mkv");
+        let made = real_ffmpeg(&[
+            "-v".into(),
+            "error".into(),
+            "-f".into(),
+            "lavfi".into(),
+            "-i".into(),
+            "testsrc=duration=2:size=64x64:rate=10".into(),
+            "-i".into(),
+            metadata.into(),
+            "-map_metadata".into(),
+            "1".into(),
+            "-map_chapters".into(),
+            "1".into(),
+            "-c:v".into(),
+            "mpeg4".into(),
+            source.clone().into(),
+        ]);
+        if !made {
+            eprintln!("ffmpeg is not installed, skipping");
+            return;
+        }
+        let probed = real_probe(&source).unwrap();
+        assert_eq!(probed.chapters.len(), 2);
+
+        let output = dir.path().join("out.mkv");
+        let args = build_ffmpeg_command(
+            &source,
+            &output,
+            &preset("video_codec: mpeg4"),
+            &probed,
+            &quiet(),
+        );
+        assert!(real_ffmpeg(&args));
+
+        let transcoded = real_probe(&output).unwrap();
+        let titles: Vec<_> = transcoded
+            .chapters
+            .iter()
+            .map(|chapter| chapter.tags.get("title").map(String::as_str))
+            .collect();
+        assert_eq!(titles, [Some("One"), Some("Two")]);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::file_check::{self, Stability};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// How often a waiting job looks for its companions again
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default time a job waits for its companions before going ahead without them
+pub const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+/// The file next to `source` with the same stem and the given extension, in any letter case
+pub fn find(source: &Path, extension: &str) -> Option<PathBuf> {
+    let extension = extension.trim_start_matches('*').trim_start_matches('.');
+    let stem = source.file_stem()?;
+    let dir = source.parent().filter(|p| !p.as_os_str().is_empty())?;
+
+    std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let path = entry.path();
+        let matches = path.file_stem() == Some(stem)
+            && path
+                .extension()
+                .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case(extension));
+        (matches && path.is_file()).then_some(path)
+    })
+}
+
+/// Wait until a companion exists and has stopped growing, as `stability` defines it, for every
+/// extension, or `timeout`. `on_missing` hears which extensions are still missing each time
+/// they are looked for and some are.
+///
+/// Returns the extensions still missing when the wait ended.
+pub async fn wait_for(
+    source: &Path,
+    extensions: &[String],
+    timeout: Duration,
+    stability: Stability,
+    mut on_missing: impl FnMut(&[String]),
+) -> Vec<String> {
+    let deadline = Instant::now() + timeout;
+    let mut logged = false;
+
+    loop {
+        let missing: Vec<String> = extensions
+            .iter()
+            .filter(|ext| find(source, ext).is_none())
+            .cloned()
+            .collect();
+
+        if missing.is_empty() {
+            break;
+        }
+        on_missing(&missing);
+        if Instant::now() >= deadline {
+            return missing;
+        }
+        if !logged {
+            info!(
+                "Waiting up to {}s for {} companion(s) of {}",
+                timeout.as_secs(),
+                missing.join(", "),
+                source.display()
+            );
+            logged = true;
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    for ext in extensions {
+        if let Some(companion) = find(source, ext) {
+            if let Err(e) = file_check::wait_for_stable_size(&companion, stability).await {
+                debug!("Companion {} is not stable: {}", companion.display(), e);
+            }
+        }
+    }
+
+    Vec::new()
+}
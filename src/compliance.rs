@@ -0,0 +1,201 @@
+use crate::config::NormalizeConfig;
+use crate::ffprobe::ProbeResult;
+use crate::units::{Bitrate, ResolutionTier};
+
+/// Every way the probed file falls outside the normalize envelope; empty when it complies
+pub fn violations(rules: &NormalizeConfig, probe: &ProbeResult) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    let video = probe.video_stream();
+    let video_codec = video.and_then(|s| s.codec_name.as_deref());
+    if !rules.video_codecs.is_empty() {
+        match video_codec {
+            Some(codec) if allowed(&rules.video_codecs, codec) => {}
+            Some(codec) => violations.push(format!(
+                "video codec {} is not one of {}",
+                codec,
+                rules.video_codecs.join(", ")
+            )),
+            None => violations.push("no video stream".to_string()),
+        }
+    }
+
+    if !rules.audio_codecs.is_empty() {
+        for codec in probe.audio_codecs() {
+            if !allowed(&rules.audio_codecs, codec) {
+                violations.push(format!(
+                    "audio codec {} is not one of {}",
+                    codec,
+                    rules.audio_codecs.join(", ")
+                ));
+            }
+        }
+    }
+
+    let short_side = video.and_then(|s| Some(s.width?.min(s.height?)));
+    if let (Some(short_side), Some(bitrate)) = (short_side, probe.video_bitrate()) {
+        if let Some((tier, max)) = bitrate_tier(rules, short_side) {
+            if bitrate > max.bits_per_sec() {
+                violations.push(format!(
+                    "video bitrate {:.1} Mbit/s exceeds {:.1} Mbit/s for the {} tier",
+                    bitrate as f64 / 1e6,
+                    max.bits_per_sec() as f64 / 1e6,
+                    tier
+                ));
+            }
+        }
+    }
+
+    violations
+}
+
+/// The smallest tier the resolution fits in, or the largest one for bigger files
+fn bitrate_tier(rules: &NormalizeConfig, short_side: u32) -> Option<(ResolutionTier, Bitrate)> {
+    rules
+        .max_video_bitrate
+        .range(ResolutionTier(short_side)..)
+        .next()
+        .or_else(|| rules.max_video_bitrate.iter().next_back())
+        .map(|(tier, max)| (*tier, *max))
+}
+
+fn allowed(codecs: &[String], codec: &str) -> bool {
+    codecs.iter().any(|c| c.eq_ignore_ascii_case(codec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::probe;
+    use serde_json::json;
+
+    fn rules(yaml: &str) -> NormalizeConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// A `width`x`height` video of `codec` at `bit_rate` bits per second with the given audio
+    fn source(codec: &str, width: u32, height: u32, bit_rate: u64, audio: &[&str]) -> ProbeResult {
+        let mut streams = vec![json!({
+            "index": 0,
+            "codec_type": "video",
+            "codec_name": codec,
+            "width": width,
+            "height": height,
+            "bit_rate": bit_rate.to_string(),
+        })];
+        for (index, codec) in audio.iter().enumerate() {
+            streams.push(json!({"index": index + 1, "codec_type": "audio", "codec_name": codec}));
+        }
+        probe(json!({
+            "format": {"filename": "in.mp4", "nb_streams": streams.len(), "format_name": "mov"},
+            "streams": streams,
+        }))
+    }
+
+    #[test]
+    fn codecs_outside_the_envelope_are_named() {
+        let audio_rules = rules("audio_codecs: [aac]\n");
+        let rules = rules("video_codecs: [hevc, av1]\naudio_codecs: [aac, opus]\n");
+        for (probe, expected) in [
+            (source("hevc", 1920, 1080, 8_000_000, &["aac"]), vec![]),
+            // ffprobe names are matched regardless of case
+            (source("HEVC", 1920, 1080, 8_000_000, &["Opus"]), vec![]),
+            (
+                source("h264", 1920, 1080, 8_000_000, &["aac"]),
+                vec!["video codec h264 is not one of hevc, av1"],
+            ),
+            (
+                source("av1", 1920, 1080, 8_000_000, &["aac", "ac3", "mp3"]),
+                vec![
+                    "audio codec ac3 is not one of aac, opus",
+                    "audio codec mp3 is not one of aac, opus",
+                ],
+            ),
+        ] {
+            assert_eq!(violations(&rules, &probe), expected);
+        }
+
+        let audio_only = probe(json!({
+            "format": {"filename": "in.m4a", "nb_streams": 1, "format_name": "mov"},
+            "streams": [{"index": 0, "codec_type": "audio", "codec_name": "aac"}],
+        }));
+        assert_eq!(violations(&rules, &audio_only), ["no video stream"]);
+        // Without video codec rules a missing video stream is nothing to fix
+        assert!(violations(&audio_rules, &audio_only).is_empty());
+    }
+
+    #[test]
+    fn bitrate_is_held_to_the_tier_of_the_resolution() {
+        let rules = rules("max_video_bitrate: {720p: 6M, 1080p: 12M, 2160: 40M}\n");
+        for (width, height, bit_rate, expected) in [
+            (1920, 1080, 12_000_000, None),
+            (
+                1920,
+                1080,
+                15_000_000,
+                Some("video bitrate 15.0 Mbit/s exceeds 12.0 Mbit/s for the 1080p tier"),
+            ),
+            // The short side decides, so portrait video falls in the same tier
+            (
+                1080,
+                1920,
+                15_000_000,
+                Some("video bitrate 15.0 Mbit/s exceeds 12.0 Mbit/s for the 1080p tier"),
+            ),
+            (
+                1280,
+                720,
+                7_000_000,
+                Some("video bitrate 7.0 Mbit/s exceeds 6.0 Mbit/s for the 720p tier"),
+            ),
+            // Below every tier is held to the smallest
+            (
+                640,
+                360,
+                7_000_000,
+                Some("video bitrate 7.0 Mbit/s exceeds 6.0 Mbit/s for the 720p tier"),
+            ),
+            // Between tiers is held to the next one up
+            (1440, 900, 10_000_000, None),
+            (
+                1440,
+                900,
+                13_000_000,
+                Some("video bitrate 13.0 Mbit/s exceeds 12.0 Mbit/s for the 1080p tier"),
+            ),
+            // Above every tier is held to the largest
+            (7680, 4320, 40_000_000, None),
+            (
+                7680,
+                4320,
+                50_000_000,
+                Some("video bitrate 50.0 Mbit/s exceeds 40.0 Mbit/s for the 2160p tier"),
+            ),
+        ] {
+            let found = violations(&rules, &source("hevc", width, height, bit_rate, &[]));
+            assert_eq!(
+                found,
+                expected.into_iter().collect::<Vec<_>>(),
+                "{}x{}",
+                width,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn tiers_are_picked_by_the_short_side() {
+        assert_eq!(bitrate_tier(&rules("{}"), 1080), None);
+        let rules = rules("max_video_bitrate: {720p: 6M, 1080p: 12M}\n");
+        for (short_side, expected) in [
+            (360, Some((720, 6_000_000))),
+            (720, Some((720, 6_000_000))),
+            (721, Some((1080, 12_000_000))),
+            (1080, Some((1080, 12_000_000))),
+            (2160, Some((1080, 12_000_000))),
+        ] {
+            let tier = bitrate_tier(&rules, short_side).map(|(tier, max)| (tier.0, max.0));
+            assert_eq!(tier, expected, "{}", short_side);
+        }
+    }
+}
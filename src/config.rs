@@ -1,97 +1,3993 @@
-use anyhow::{Context, Result};
+use crate::diagnostic::{self, ParseError};
+use crate::expand::expand_path;
+use crate::file_check::Stability;
+use crate::template::{self, TemplateVars};
+use crate::timestamp::TimeWindow;
+use crate::units::{
+    Bitrate, FrameRate, HumanDuration, HumanSize, ProgressInterval, ResolutionTier, Threads,
+    VideoPosition,
+};
+use anyhow::{anyhow, Context, Result};
+use bytesize::ByteSize;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, NaiveDateTime, Weekday};
+use glob::{MatchOptions, Pattern};
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{error, warn};
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     pub inputs: Vec<InputConfig>,
     pub outputs: HashMap<String, OutputConfig>,
+    #[serde(default)]
     pub presets: HashMap<String, PresetConfig>,
+    /// Preset of inputs that set neither `preset` nor `targets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_preset: Option<String>,
+    /// Output of inputs that set neither `output` nor `targets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_output: Option<String>,
+    /// Shared presets file(s) merged into `presets`, relative to this config; inline presets win
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presets_file: Option<PathList>,
+    /// Names of the presets merged in from `presets_file`
+    #[serde(skip)]
+    pub shared_presets: HashSet<String>,
     pub max_parallel_jobs: Option<usize>,
+    /// Most files waiting in the queue at once; unbounded when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queue_size: Option<usize>,
+    /// What the watcher does with new files while the queue is at `max_queue_size`; scans
+    /// always wait for room
+    #[serde(default, skip_serializing_if = "QueueFullPolicy::is_default")]
+    pub on_queue_full: QueueFullPolicy,
+    /// Which queued file of the highest priority starts next, `fifo` when unset
+    #[serde(default, skip_serializing_if = "QueueOrder::is_default")]
+    pub queue_order: QueueOrder,
+    /// Treat every validation warning as an error
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict: bool,
+    /// Command run when work appears in an idle queue
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_queue_active: Option<String>,
+    /// Command run once the queue has been empty for `drain_settle`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_queue_drained: Option<String>,
+    #[serde(
+        default,
+        alias = "drain_settle_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub drain_settle: Option<HumanDuration>,
+    #[serde(default, skip_serializing_if = "HookFailurePolicy::is_default")]
+    pub on_hook_failure: HookFailurePolicy,
+    /// How often a source that isn't ready, like one still being copied, is tried again before
+    /// it is given up on, `5` when unset; starts over when the source changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    /// Wait before the first retry, doubling for each one after, `5s` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<HumanDuration>,
+    /// Move sources given up on into this directory instead of leaving them in place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine_dir: Option<PathBuf>,
+    /// How long running jobs get to finish on Ctrl-C before their ffmpeg is killed, `5m` when
+    /// unset; a second Ctrl-C kills them right away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutdown_grace: Option<HumanDuration>,
+    /// Claim sources before working on them so several instances can share the inputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distributed: Option<DistributedConfig>,
+    /// JSON Lines file every finished job is appended to, for `sstc history`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_file: Option<PathBuf>,
+    /// SQLite database every finished job is recorded in, to look jobs up long after
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_db: Option<PathBuf>,
+    /// Prometheus text file rewritten after every job with the CPU time spent per preset
+    /// (`sstc_cpu_seconds_total`), for node_exporter's textfile collector
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_file: Option<PathBuf>,
+    /// Directory each job writes its complete ffmpeg output to, one
+    /// `<timestamp>-<source>.sstc.log` per job, so a failure can be read on its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_logs_dir: Option<PathBuf>,
+    /// Most logs kept in `job_logs_dir`, the oldest removed first; 1000 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_logs_max_count: Option<usize>,
+    /// Remove logs in `job_logs_dir` older than this, e.g. `30d`; kept until
+    /// `job_logs_max_count` pushes them out when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_logs_max_age: Option<HumanDuration>,
+    /// Skip targets `history_db` has a successful transcode of for the same source, path
+    /// and size, even when the output has been moved away since
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_if_in_history: bool,
+    /// JSON file the pending queue is kept in, so files still queued when the service stops
+    /// or crashes are queued again on the next start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_file: Option<PathBuf>,
+    /// Skip sources with the same content as one transcoded before, under whatever name,
+    /// going by the hashes kept in `hash_store`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dedup_by_hash: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_store: Option<HashStoreConfig>,
+    /// Kill jobs whose output stops growing on disk while ffmpeg still reports progress
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verify_output_growth: bool,
+    /// How often ffmpeg reports progress and the progress bar redraws, `1s` when unset;
+    /// `off` runs ffmpeg without progress reporting, and without the checks that rely on it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_interval: Option<ProgressInterval>,
+    /// Kill ffmpeg when a job runs longer than this, e.g. `4h`; no limit when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_job_duration: Option<HumanDuration>,
+    /// Kill ffmpeg when a job runs longer than the source duration times this; no limit when
+    /// unset. With `max_job_duration` as well, whichever is reached first applies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_speed_ratio: Option<f64>,
+    /// Reload the config whenever the file changes, as on SIGHUP
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub watch_config: bool,
+    /// Unix socket the running service takes commands like `sstc cancel` and `sstc pause` on;
+    /// none when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_socket: Option<PathBuf>,
+    /// Also stop the ffmpeg of running jobs in place while paused, instead of letting them
+    /// finish; Unix only. Time spent stopped counts toward `max_job_duration`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suspend_running: bool,
+    /// Export a trace per job to an OpenTelemetry collector
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otel: Option<OtelConfig>,
+    /// ffmpeg binary to run instead of the one found in PATH
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ffmpeg_path: Option<PathBuf>,
+    /// ffprobe binary to run instead of the one found in PATH
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ffprobe_path: Option<PathBuf>,
+    /// ffmpeg options placed before `-i` in every job, ahead of the preset's `input_options`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_options: Vec<String>,
+    /// CPU and I/O priority ffmpeg and ffprobe run at, e.g. `{nice: 19, ionice_class: idle}`
+    /// to keep the machine usable while encoding; normal priority when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_priority: Option<ProcessPriority>,
+    /// Hours queued jobs may start in; files are still found and queued outside of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleConfig>,
+    /// How long sources must stop growing before they are transcoded, for inputs that don't
+    /// set their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stability: Option<StabilityConfig>,
+    /// Globs of temporary and hidden files never queued, for inputs that don't set their own;
+    /// hidden files, `*.part`, `*.tmp` and `*.~*` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Files partial downloads and copies are written as before they get their final name
+pub const DEFAULT_IGNORE: &[&str] = &[".*", "*.part", "*.tmp", "*.~*"];
+
+/// A single path or a list of them
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum PathList {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl PathList {
+    pub fn paths(&self) -> &[PathBuf] {
+        match self {
+            PathList::One(path) => std::slice::from_ref(path),
+            PathList::Many(paths) => paths,
+        }
+    }
+}
+
+/// The part of a shared presets file sstc reads. Everything else is ignored, so the output of
+/// `sstc config presets generate` can be used as is.
+#[derive(Debug, Deserialize)]
+struct PresetsFile {
+    #[serde(default)]
+    presets: HashMap<String, PresetConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DistributedConfig {
+    /// Directory shared by all instances for claim files; claims sit next to sources when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claims_dir: Option<PathBuf>,
+    /// How long a claim stays valid without being renewed by its owner
+    #[serde(default = "DistributedConfig::default_lease", alias = "lease_secs")]
+    pub lease: HumanDuration,
+}
+
+/// Where `dedup_by_hash` remembers the sources it transcoded, and for how long
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HashStoreConfig {
+    /// JSON file the hashes are kept in
+    pub path: PathBuf,
+    /// Most hashes kept, the oldest dropped first
+    #[serde(default = "HashStoreConfig::default_max_entries")]
+    pub max_entries: usize,
+    /// Forget hashes older than this, e.g. `90d`; kept until `max_entries` pushes them out
+    /// when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<HumanDuration>,
+}
+
+impl HashStoreConfig {
+    fn default_max_entries() -> usize {
+        10_000
+    }
+}
+
+/// Scheduling priority of the processes sstc starts. On Windows any positive `nice` runs them
+/// in the below-normal priority class, and the I/O class is left to the system.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessPriority {
+    /// Niceness from -20 to 19, higher yielding more CPU to other processes; 0 when unset.
+    /// Below 0 needs root, without it the processes keep normal priority
+    #[serde(default)]
+    pub nice: i32,
+    /// I/O scheduling class on Linux; the system default when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ionice_class: Option<IoniceClass>,
+    /// Level within `best_effort`, from 0 (highest) to 7; 4 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ionice_level: Option<u8>,
+}
+
+impl ProcessPriority {
+    /// Whether processes run as if no priority was configured
+    pub fn is_normal(&self) -> bool {
+        self.nice == 0 && self.ionice_class.is_none()
+    }
+}
+
+impl std::fmt::Display for ProcessPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "nice {}", self.nice)?;
+        match (self.ionice_class, self.ionice_level) {
+            (Some(IoniceClass::BestEffort), Some(level)) => {
+                write!(f, ", ionice best_effort {}", level)
+            }
+            (Some(IoniceClass::BestEffort), None) => write!(f, ", ionice best_effort"),
+            (Some(IoniceClass::Idle), _) => write!(f, ", ionice idle"),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+/// Linux I/O scheduling class of `process_priority`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IoniceClass {
+    /// Shares the disk by `ionice_level`
+    BestEffort,
+    /// Only gets the disk when nothing else wants it
+    Idle,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Local time the window opens, `HH:MM`
+    pub start: String,
+    /// Local time the window closes, `HH:MM`; before `start` for a window across midnight
+    pub end: String,
+    /// Days the window opens on, like `[mon, tue]`; every day when empty. A window across
+    /// midnight belongs to the day it opens on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// Kill transcodes still running when the window closes and requeue their sources,
+    /// instead of letting them finish
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hard_stop: bool,
+}
+
+impl ScheduleConfig {
+    pub fn window(&self) -> Result<TimeWindow> {
+        TimeWindow::parse(&format!("{}-{}", self.start, self.end))
+    }
+
+    pub fn weekdays(&self) -> Result<Vec<Weekday>> {
+        self.days
+            .iter()
+            .map(|day| {
+                day.parse()
+                    .map_err(|_| anyhow!("Invalid day '{}', expected mon, tue, ... sun", day))
+            })
+            .collect()
+    }
+
+    /// Whether jobs may start at `now`; a schedule that doesn't parse never holds them
+    pub fn is_open(&self, now: NaiveDateTime) -> bool {
+        let (Ok(window), Ok(days)) = (self.window(), self.weekdays()) else {
+            return true;
+        };
+        if !window.contains(now.time()) {
+            return false;
+        }
+        // Past midnight, the window that's open is the one of the day before
+        let opened = if window.start > window.end && now.time() < window.end {
+            now.date() - chrono::Days::new(1)
+        } else {
+            now.date()
+        };
+        days.is_empty() || days.contains(&opened.weekday())
+    }
+}
+
+impl fmt::Display for ScheduleConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)?;
+        if !self.days.is_empty() {
+            write!(f, " on {}", self.days.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OtelConfig {
+    /// OTLP/gRPC endpoint of the collector, e.g. `http://localhost:4317`; tracing is off when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// `service.name` reported with every span, `sstc` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+}
+
+impl DistributedConfig {
+    fn default_lease() -> HumanDuration {
+        HumanDuration::from_secs(300)
+    }
+}
+
+/// One output a file is transcoded to
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    pub preset: String,
+    pub output: String,
+    /// Preset to retry with when the primary preset is rejected by the encoder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_preset: Option<String>,
+}
+
+impl TargetConfig {
+    /// `preset -> output`, to tell the targets of one file apart in logs
+    pub fn label(&self) -> String {
+        format!("{} -> {}", self.preset, self.output)
+    }
+}
+
+impl InputConfig {
+    /// The targets of this input, with the single `preset`/`output` form as a one-element list
+    pub fn targets(&self) -> Vec<TargetConfig> {
+        if !self.targets.is_empty() {
+            return self.targets.clone();
+        }
+        vec![TargetConfig {
+            preset: self.preset.clone(),
+            output: self.output.clone(),
+            fallback_preset: self.fallback_preset.clone(),
+        }]
+    }
+
+    /// Whether a file at `relative` below the input path is one this input transcodes
+    pub fn matches_file(&self, relative: &Path) -> bool {
+        if self.ignored_by(relative).is_some() || self.excluded_by(relative).is_some() {
+            return false;
+        }
+        // A source this input already renamed once it was done
+        if let SourceAction::Suffix(suffix) = &self.source_action {
+            if relative.to_string_lossy().ends_with(suffix.as_str()) {
+                return false;
+            }
+        }
+
+        if self.patterns.is_empty() {
+            let Some(extension) = relative.extension().and_then(|e| e.to_str()) else {
+                return false;
+            };
+            return self
+                .extensions
+                .iter()
+                .any(|ext| ext == "*" || ext.eq_ignore_ascii_case(extension));
+        }
+
+        self.patterns
+            .iter()
+            .any(|pattern| glob_matches(pattern, relative))
+    }
+
+    /// Why a file of `size` bytes falls outside `min_size`/`max_size`, if it does
+    pub fn outside_size_limits(&self, size: u64) -> Option<String> {
+        if let Some(min) = self.min_size.filter(|min| size < min.bytes()) {
+            return Some(format!(
+                "{} is below min_size {}",
+                ByteSize::b(size).display().si(),
+                ByteSize::b(min.bytes()).display().si()
+            ));
+        }
+        if let Some(max) = self.max_size.filter(|max| size > max.bytes()) {
+            return Some(format!(
+                "{} is above max_size {}",
+                ByteSize::b(size).display().si(),
+                ByteSize::b(max.bytes()).display().si()
+            ));
+        }
+        None
+    }
+
+    /// How a file last modified at `modified` compares to `min_age`/`max_age` at `now`
+    pub fn file_age(&self, modified: SystemTime, now: SystemTime) -> FileAge {
+        // Modification times in the future count as just now
+        let age = now.duration_since(modified).unwrap_or_default();
+        if let Some(max) = self.max_age.filter(|max| age > Duration::from(*max)) {
+            return FileAge::TooOld(format!("last modified more than max_age {} ago", max));
+        }
+        if let Some(min) = self.min_age.filter(|min| age < Duration::from(*min)) {
+            return FileAge::TooNew(Duration::from(min) - age);
+        }
+        FileAge::Ready
+    }
+
+    /// Whether sources in this ffprobe video codec are left out of re-encoding
+    pub fn skips_video_codec(&self, codec: &str) -> bool {
+        self.skip_if_video_codec
+            .iter()
+            .any(|skip| skip.eq_ignore_ascii_case(codec))
+    }
+
+    /// The `exclude` pattern that rules the file out, if any
+    pub fn excluded_by(&self, relative: &Path) -> Option<&str> {
+        self.exclude
+            .iter()
+            .find(|pattern| glob_matches(pattern, relative))
+            .map(String::as_str)
+    }
+
+    /// The `ignore` pattern that marks the file, or a directory it is in, as temporary or
+    /// hidden, if any
+    pub fn ignored_by(&self, relative: &Path) -> Option<&str> {
+        let matches = |pattern: &str| {
+            if pattern.contains('/') {
+                glob_matches(pattern, relative)
+            } else {
+                relative
+                    .components()
+                    .any(|component| glob_matches(pattern, Path::new(component.as_os_str())))
+            }
+        };
+        match &self.ignore {
+            Some(patterns) => patterns
+                .iter()
+                .map(String::as_str)
+                .find(|pattern| matches(pattern)),
+            None => DEFAULT_IGNORE
+                .iter()
+                .copied()
+                .find(|pattern| matches(pattern)),
+        }
+    }
+}
+
+/// Match a glob against the file name, or against the whole relative path when it has a `/`
+fn glob_matches(pattern: &str, relative: &Path) -> bool {
+    let options = MatchOptions {
+        case_sensitive: !cfg!(windows),
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    let Ok(glob) = Pattern::new(pattern) else {
+        return false;
+    };
+
+    if pattern.contains('/') {
+        glob.matches_path_with(relative, options)
+    } else {
+        relative
+            .file_name()
+            .is_some_and(|name| glob.matches_path_with(Path::new(name), options))
+    }
+}
+
+/// What to do with the queue when the `on_queue_active` hook fails
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HookFailurePolicy {
+    /// Keep queueing but don't start jobs until the hook succeeds
+    Hold,
+    #[default]
+    Continue,
+}
+
+impl HookFailurePolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What to do with a file found while the queue is full
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFullPolicy {
+    /// Wait for a queued file to start
+    #[default]
+    Wait,
+    /// Leave the file for a rescan of the inputs once there is room again
+    Drop,
+}
+
+impl QueueFullPolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Order in which queued files of the same priority start
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOrder {
+    /// In the order they were queued
+    #[default]
+    Fifo,
+    /// Smallest file first, so short clips aren't stuck behind a long recording
+    SmallestFirst,
+    LargestFirst,
+    /// Least recently modified file first
+    OldestFirst,
+}
+
+impl QueueOrder {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Where a file stands against the `min_age`/`max_age` of its input
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileAge {
+    Ready,
+    /// Modified too recently; old enough after this long
+    TooNew(Duration),
+    /// Modified too long ago, with the reason
+    TooOld(String),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct InputConfig {
     pub path: PathBuf,
+    /// Extensions of the files to transcode, case-insensitively; `*` takes any extension
+    #[serde(default)]
     pub extensions: Vec<String>,
+    /// Globs like `GX*.MP4` matched against the file name, or against the path relative to
+    /// `path` when they contain a `/`; replace `extensions` when given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+    /// Globs of files to leave alone even when they match, e.g. `*_proxy.mp4`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Replaces the global `ignore` list for this input; names without a `/` are matched
+    /// against every directory below `path` too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore: Option<Vec<String>>,
+    /// Preset of the input's only target, `default_preset` when unset; use `targets` to
+    /// transcode to several outputs
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub preset: String,
+    /// Output of the input's only target, `default_output` when unset
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub output: String,
+    /// Preset to retry with when the primary preset is rejected by the encoder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_preset: Option<String>,
+    /// Every output each file is transcoded to, with its own preset; replaces `preset`,
+    /// `output` and `fallback_preset`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TargetConfig>,
+    /// Files of inputs with a higher priority are taken from the queue first; 0 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// Leave files smaller than this alone, e.g. `10MB`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<HumanSize>,
+    /// Leave files larger than this alone, e.g. `50GB`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<HumanSize>,
+    /// Hold back files modified more recently than this, e.g. `10m` for uploads that come
+    /// in pieces
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<HumanDuration>,
+    /// Leave files last modified longer ago than this alone, e.g. `7d` to skip an archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<HumanDuration>,
+    /// Leave clips shorter than this alone, e.g. `5s` for accidental recordings
+    #[serde(
+        default,
+        alias = "min_duration_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_duration: Option<HumanDuration>,
+    /// Only transcode clips recorded within this daily window, e.g. `21:00-06:00`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_between: Option<String>,
+    /// strftime-style pattern locating the recording time in file names, e.g. `%Y%m%d%H%M%S`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_pattern: Option<String>,
+    /// Extensions of files with the same stem to wait for before transcoding, e.g. `[srt, xml]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wait_for_companion: Vec<String>,
+    /// How long to wait for companions before going ahead without them
+    #[serde(
+        default,
+        alias = "companion_timeout_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub companion_timeout: Option<HumanDuration>,
+    /// Only transcode files outside this envelope, leaving compliant ones alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalize: Option<NormalizeConfig>,
+    /// ffprobe video codec names, e.g. `[hevc, av1]`, of sources that shouldn't be re-encoded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_if_video_codec: Vec<String>,
+    /// What happens to a source whose codec is in `skip_if_video_codec`
+    #[serde(default, skip_serializing_if = "CodecMatchAction::is_default")]
+    pub on_match: CodecMatchAction,
+    /// What happens to the source once every target was transcoded
+    #[serde(default, skip_serializing_if = "SourceAction::is_default")]
+    pub source_action: SourceAction,
+    /// Overrides the global `stability` field by field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stability: Option<StabilityConfig>,
+}
+
+/// Tuning of the wait for a source's size to settle; see
+/// [`crate::file_check::wait_for_stable_size`]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StabilityConfig {
+    /// How often the size is checked, 1s by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_interval: Option<HumanDuration>,
+    /// How long the size must stay the same, 3s by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stable_for: Option<HumanDuration>,
+    /// How long to wait before requeueing a file that keeps growing, 60s by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<HumanDuration>,
+}
+
+impl StabilityConfig {
+    /// The settings of an input, with what it leaves unset taken from the global ones
+    pub fn resolve(input: Option<&Self>, global: Option<&Self>) -> Stability {
+        let pick = |field: fn(&Self) -> Option<HumanDuration>| {
+            input
+                .and_then(field)
+                .or_else(|| global.and_then(field))
+                .map(Into::into)
+        };
+        let defaults = Stability::default();
+
+        Stability {
+            check_interval: pick(|s| s.check_interval).unwrap_or(defaults.check_interval),
+            stable_for: pick(|s| s.stable_for).unwrap_or(defaults.stable_for),
+            timeout: pick(|s| s.timeout).unwrap_or(defaults.timeout),
+        }
+    }
+}
+
+/// What to do with a source after a successful transcode, e.g. `delete`, `{move: /done}` or
+/// `{suffix: .done}`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default, JsonSchema)]
+#[serde(try_from = "SourceActionSpec", into = "SourceActionSpec")]
+pub enum SourceAction {
+    #[default]
+    Keep,
+    Delete,
+    /// Move the source into this directory, creating it if needed
+    Move(PathBuf),
+    /// Rename the source by appending this to its file name
+    Suffix(String),
+}
+
+impl SourceAction {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How `source_action` is written: a bare `keep`/`delete`, or a one-key map for the actions
+/// that take a value
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+enum SourceActionSpec {
+    Name(String),
+    Move {
+        #[serde(rename = "move")]
+        dir: PathBuf,
+    },
+    Suffix {
+        suffix: String,
+    },
+}
+
+impl TryFrom<SourceActionSpec> for SourceAction {
+    type Error = String;
+
+    fn try_from(spec: SourceActionSpec) -> Result<Self, Self::Error> {
+        match spec {
+            SourceActionSpec::Name(name) => match name.as_str() {
+                "keep" => Ok(SourceAction::Keep),
+                "delete" => Ok(SourceAction::Delete),
+                _ => Err(format!(
+                    "unknown source_action '{}', expected keep, delete, {{move: <dir>}} or {{suffix: <str>}}",
+                    name
+                )),
+            },
+            SourceActionSpec::Move { dir } => Ok(SourceAction::Move(dir)),
+            SourceActionSpec::Suffix { suffix } => Ok(SourceAction::Suffix(suffix)),
+        }
+    }
+}
+
+impl From<SourceAction> for SourceActionSpec {
+    fn from(action: SourceAction) -> Self {
+        match action {
+            SourceAction::Keep => SourceActionSpec::Name("keep".to_string()),
+            SourceAction::Delete => SourceActionSpec::Name("delete".to_string()),
+            SourceAction::Move(dir) => SourceActionSpec::Move { dir },
+            SourceAction::Suffix(suffix) => SourceActionSpec::Suffix { suffix },
+        }
+    }
+}
+
+/// Handling of sources that are already in one of the `skip_if_video_codec` codecs
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecMatchAction {
+    /// Leave the source alone
+    #[default]
+    Skip,
+    /// Copy the streams into the output container without re-encoding
+    Remux,
+}
+
+impl CodecMatchAction {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What a file in a normalized library may look like; an empty list allows anything
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizeConfig {
+    /// Accepted ffprobe video codec names, e.g. `[hevc, av1]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub video_codecs: Vec<String>,
+    /// Accepted ffprobe audio codec names, e.g. `[aac, opus]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audio_codecs: Vec<String>,
+    /// Highest video bitrate per resolution tier, keyed by the tier's short side, e.g.
+    /// `{720p: 6M, 1080p: 12M}`. A file falls into the smallest tier it fits in; files above
+    /// every tier use the largest.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_video_bitrate: BTreeMap<ResolutionTier, Bitrate>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct OutputConfig {
     pub path: PathBuf,
     pub filename_template: String,
+    /// Directory below `path` for each file, e.g. `{year}/{month}/{day}`; same tokens as the
+    /// filename template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir_template: Option<String>,
     pub container: String,
+    /// What to do when a job's output path is its own input file
+    #[serde(default, skip_serializing_if = "SameFilePolicy::is_default")]
+    pub on_same_file: SameFilePolicy,
+    /// What to do when a job's output path is already taken by another file
+    #[serde(default, skip_serializing_if = "ExistingOutputPolicy::is_default")]
+    pub on_existing: ExistingOutputPolicy,
+    /// What `on_existing: skip` does when the source was modified after its output was
+    /// written, like when it was replaced with a fixed version
+    #[serde(default, skip_serializing_if = "SourceNewerPolicy::is_default")]
+    pub if_source_newer: SourceNewerPolicy,
+    /// Where `replace` moves originals before overwriting them; deleted when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_dir: Option<PathBuf>,
+    /// Create missing directories below `path` that the filename template points into
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub create_subdirs: bool,
+    /// Write a `.jpg` poster next to each output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<ThumbnailConfig>,
+}
+
+/// A poster frame taken from each finished output, for media libraries
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ThumbnailConfig {
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub enabled: bool,
+    /// Where in the output the frame is taken, e.g. `30s` or `10%`
+    #[serde(default = "ThumbnailConfig::default_at")]
+    pub at: VideoPosition,
+    /// Width of the image, keeping the aspect ratio; the output's own width when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+}
+
+impl ThumbnailConfig {
+    fn default_at() -> VideoPosition {
+        VideoPosition::Percent(10.0)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+/// Handling of in-place jobs whose output would overwrite the source
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SameFilePolicy {
+    /// Fail the job and leave the source alone
+    #[default]
+    Error,
+    /// Encode to a temp file and swap it in once it has been verified
+    Replace,
+}
+
+impl SameFilePolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Handling of outputs that already exist, e.g. left behind by an earlier run
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingOutputPolicy {
+    /// Leave the existing file alone and skip the job
+    #[default]
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Write to `name (1).ext`, `name (2).ext`, ... instead
+    Rename,
+    /// Fail the job
+    Error,
+}
+
+impl ExistingOutputPolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Handling of existing outputs older than their source
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceNewerPolicy {
+    /// Keep the output as it is
+    #[default]
+    Skip,
+    /// Transcode the source again, replacing the output once the new one is verified
+    Retranscode,
+}
+
+impl SourceNewerPolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct PresetConfig {
+    /// Base preset this one inherits every field from, overriding only what it sets itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// `remux` rewraps every stream into the output container without re-encoding; `encode`
+    /// when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<PresetMode>,
     pub video_codec: Option<String>,
     pub pixel_format: Option<String>,
     pub audio_codec: Option<String>,
     pub video_bitrate: Option<String>,
     pub audio_bitrate: Option<String>,
+    /// Constant quality level, lower is better. Passed as `-crf` to software encoders, `-cq` to
+    /// nvenc, `-qp` to vaapi and `-global_quality` to qsv
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u8>,
+    /// Speed preset of the encoder, like `medium` for x264/x265 or `p5` for nvenc
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder_preset: Option<String>,
+    /// Threads the video encoder may use, passed as `-threads` and as `pools` to an
+    /// `-x265-params` the encode has; `auto` shares the cores out over `max_parallel_jobs`.
+    /// Left to ffmpeg when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<Threads>,
+    /// Bring the audio to a set loudness (EBU R128), measured in a pass of its own before encoding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalize_audio: Option<NormalizeAudioConfig>,
+    /// Encode twice, the first pass only gathering statistics, to hit `video_bitrate` at better
+    /// quality; ignored without `video_bitrate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_pass: Option<bool>,
     pub scale: Option<String>,
-    pub extra_options: HashMap<String, String>,
+    /// ffmpeg video filters, in order, e.g. `[hqdn3d, "eq=gamma=1.1"]`. They run after sstc's
+    /// own (deinterlacing, cropping, tone mapping, rotation, burned subtitles) and before
+    /// `scale`, all in the one `-vf` of the encode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub video_filters: Vec<String>,
+    /// ffmpeg audio filters, in order, joined into the one `-af` of the encode ahead of
+    /// `normalize_audio`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audio_filters: Vec<String>,
+    /// `off` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deinterlace: Option<DeinterlaceMode>,
+    /// Map HDR to SDR; `off` when unset. Needs an ffmpeg built with zimg for `zscale`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tonemap: Option<TonemapMode>,
+    /// `auto` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Rotation>,
+    /// Output frame rate, like 30 or `30000/1001`. Applied with the fps filter, so the frames
+    /// it leaves out don't count toward `max_dropped_frames_pct`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fps: Option<FrameRate>,
+    /// Detect black bars before encoding and crop them away, ahead of any scaling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_crop: Option<bool>,
+    /// Brightness up to which `auto_crop` takes a pixel for black, out of 255; 24 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_limit: Option<u32>,
+    /// How much of the source `auto_crop` analyses, spread across it; 2m30s when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_sample_duration: Option<HumanDuration>,
+    /// `drop` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitles: Option<SubtitlePolicy>,
+    /// Which subtitle stream `subtitles: burn` renders, counting subtitle streams from 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle_stream: Option<usize>,
+    /// Which streams of the source to keep; without it ffmpeg picks one video and one audio stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streams: Option<StreamsConfig>,
+    /// Copy the source's global tags, like title and creation time; on when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_metadata: Option<bool>,
+    /// Copy the source's chapters, where the container holds them; on when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_chapters: Option<bool>,
+    /// Write only the audio, e.g. with `container: m4a`; every video setting is ignored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_only: Option<bool>,
+    /// Container to write instead of the output's, e.g. `mp4` for HEVC tagged `hvc1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Downscale sources wider than this, keeping the aspect ratio; smaller sources are untouched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    /// Downscale sources taller than this, keeping the aspect ratio; smaller sources are untouched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    /// ffmpeg options placed before `-i`, in order, e.g. `[-hwaccel, vaapi]` for hardware decoding
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_options: Vec<String>,
+    /// Decode and encode on the GPU; `video_codec` then names the codec, like `hevc`, and the
+    /// backend's encoder for it is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hwaccel: Option<HwAccelConfig>,
+    #[serde(default)]
+    pub extra_options: ExtraOptions,
+    /// Maximum share of dropped frames, in percent of the expected frame count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dropped_frames_pct: Option<f64>,
+    /// `warn` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_dropped_frames: Option<DroppedFramesAction>,
+    /// Overrides the global progress_interval for jobs of this preset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_interval: Option<ProgressInterval>,
+    /// Overrides the global max_job_duration for jobs of this preset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_job_duration: Option<HumanDuration>,
+    /// Overrides the global max_speed_ratio for jobs of this preset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_speed_ratio: Option<f64>,
+    /// Replaces the global process_priority for jobs of this preset as a whole, e.g.
+    /// `{nice: 0}` for normal priority
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_priority: Option<ProcessPriority>,
+    /// Fail jobs whose output duration is off from the expected one; `false` for presets that
+    /// trim in ways sstc can't predict. On when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<bool>,
+    /// Allowed output duration difference in percent of the expected duration, 2 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_tolerance_pct: Option<f64>,
+    /// Allowed output duration difference regardless of length, 2s when unset; the larger of
+    /// this and `duration_tolerance_pct` applies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_tolerance: Option<HumanDuration>,
+    /// Stop encodes whose output is projected to end up larger than `max_size_ratio` times the
+    /// source, once a fifth of it is done, and keep the original or remux it instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub if_larger_than_source: Option<LargerOutputAction>,
+    /// Largest projected output size for `if_larger_than_source`, as a multiple of the
+    /// source's size; 1 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_ratio: Option<f64>,
 }
 
-pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
-    let file = std::fs::File::open(path).context("Failed to open config file")?;
-    let config: Config = serde_yaml::from_reader(file).context("Failed to parse YAML config")?;
+impl PresetConfig {
+    /// Stream copy into the output container, for sources that need no re-encoding
+    pub fn remux() -> PresetConfig {
+        PresetConfig {
+            video_codec: Some("copy".to_string()),
+            audio_codec: Some("copy".to_string()),
+            ..PresetConfig::default()
+        }
+    }
 
-    for input in &config.inputs {
-        if !input.path.exists() {
-            std::fs::create_dir_all(&input.path).context(format!(
-                "Failed to create input directory: {}",
-                input.path.display()
-            ))?;
+    /// Encoder passed to `-c:v`: the hardware backend's encoder for `video_codec` with
+    /// `hwaccel`, otherwise `video_codec` as is
+    pub fn video_encoder(&self) -> Option<String> {
+        if self.is_audio_only() {
+            return None;
+        }
+        match &self.hwaccel {
+            Some(hwaccel) => Some(hwaccel.encoder(self.video_codec.as_deref())),
+            None => self.video_codec.clone(),
         }
     }
 
-    validate_config(&config)?;
-    Ok(config)
+    /// Whether jobs of this preset copy streams instead of encoding them
+    pub fn is_remux(&self) -> bool {
+        self.mode == Some(PresetMode::Remux)
+    }
+
+    /// What happens to subtitles: remuxes keep them unless told otherwise
+    pub fn subtitle_policy(&self) -> SubtitlePolicy {
+        match self.subtitles {
+            Some(policy) => policy,
+            None if self.is_remux() => SubtitlePolicy::Copy,
+            None => SubtitlePolicy::Drop,
+        }
+    }
+
+    /// Whether jobs of this preset encode in two passes
+    pub fn is_two_pass(&self) -> bool {
+        self.two_pass == Some(true) && self.video_bitrate.is_some() && !self.is_audio_only()
+    }
+
+    pub fn is_audio_only(&self) -> bool {
+        self.audio_only == Some(true)
+    }
+
+    /// Extension of the files this preset writes into `output`
+    pub fn container_in<'a>(&'a self, output: &'a OutputConfig) -> &'a str {
+        self.container.as_deref().unwrap_or(&output.container)
+    }
+
+    /// Longest a job of this preset may run on a source of `source_secs` under `config`, with
+    /// the setting that limits it
+    pub fn time_limit_in(
+        &self,
+        config: &Config,
+        source_secs: f64,
+    ) -> Option<(Duration, &'static str)> {
+        let absolute = self
+            .max_job_duration
+            .or(config.max_job_duration)
+            .map(|limit| (limit.0, "max_job_duration"));
+        let relative = self
+            .max_speed_ratio
+            .or(config.max_speed_ratio)
+            .filter(|_| source_secs > 0.0)
+            .map(|ratio| {
+                (
+                    Duration::from_secs_f64(source_secs * ratio),
+                    "max_speed_ratio",
+                )
+            });
+        match (absolute, relative) {
+            (Some(a), Some(r)) => Some(if r.0 < a.0 { r } else { a }),
+            (a, r) => a.or(r),
+        }
+    }
+
+    /// How often jobs of this preset report progress under `config`
+    pub fn progress_interval_in(&self, config: &Config) -> ProgressInterval {
+        self.progress_interval
+            .or(config.progress_interval)
+            .unwrap_or_default()
+    }
+
+    /// Threads of the video encoder of this preset's jobs under `config`, if it says
+    pub fn threads_in(&self, config: &Config) -> Option<u32> {
+        self.threads
+            .filter(|_| !self.is_remux() && !self.is_audio_only())
+            .map(|threads| threads.per_job(config.max_parallel_jobs.unwrap_or(1)))
+    }
+
+    /// Priority the processes of this preset's jobs run at under `config`
+    pub fn process_priority_in(&self, config: &Config) -> ProcessPriority {
+        self.process_priority
+            .or(config.process_priority)
+            .unwrap_or_default()
+    }
+
+    /// This preset with every field it leaves unset taken from `base`; `extra_options` are
+    /// merged, with this preset's flags replacing every occurrence of the same flag in `base`
+    fn inherit(self, base: &PresetConfig) -> PresetConfig {
+        let extra_options = self.extra_options.merged_over(&base.extra_options);
+
+        PresetConfig {
+            extends: None,
+            mode: self.mode.or(base.mode),
+            video_codec: self.video_codec.or_else(|| base.video_codec.clone()),
+            pixel_format: self.pixel_format.or_else(|| base.pixel_format.clone()),
+            audio_codec: self.audio_codec.or_else(|| base.audio_codec.clone()),
+            video_bitrate: self.video_bitrate.or_else(|| base.video_bitrate.clone()),
+            audio_bitrate: self.audio_bitrate.or_else(|| base.audio_bitrate.clone()),
+            crf: self.crf.or(base.crf),
+            encoder_preset: self.encoder_preset.or_else(|| base.encoder_preset.clone()),
+            threads: self.threads.or(base.threads),
+            normalize_audio: self.normalize_audio.or(base.normalize_audio),
+            two_pass: self.two_pass.or(base.two_pass),
+            scale: self.scale.or_else(|| base.scale.clone()),
+            video_filters: if self.video_filters.is_empty() {
+                base.video_filters.clone()
+            } else {
+                self.video_filters
+            },
+            audio_filters: if self.audio_filters.is_empty() {
+                base.audio_filters.clone()
+            } else {
+                self.audio_filters
+            },
+            deinterlace: self.deinterlace.or(base.deinterlace),
+            tonemap: self.tonemap.or(base.tonemap),
+            rotation: self.rotation.or(base.rotation),
+            fps: self.fps.or(base.fps),
+            auto_crop: self.auto_crop.or(base.auto_crop),
+            crop_limit: self.crop_limit.or(base.crop_limit),
+            crop_sample_duration: self.crop_sample_duration.or(base.crop_sample_duration),
+            subtitles: self.subtitles.or(base.subtitles),
+            subtitle_stream: self.subtitle_stream.or(base.subtitle_stream),
+            streams: self.streams.or_else(|| base.streams.clone()),
+            preserve_metadata: self.preserve_metadata.or(base.preserve_metadata),
+            preserve_chapters: self.preserve_chapters.or(base.preserve_chapters),
+            audio_only: self.audio_only.or(base.audio_only),
+            container: self.container.or_else(|| base.container.clone()),
+            max_width: self.max_width.or(base.max_width),
+            max_height: self.max_height.or(base.max_height),
+            input_options: if self.input_options.is_empty() {
+                base.input_options.clone()
+            } else {
+                self.input_options
+            },
+            hwaccel: self.hwaccel.or_else(|| base.hwaccel.clone()),
+            extra_options,
+            max_dropped_frames_pct: self.max_dropped_frames_pct.or(base.max_dropped_frames_pct),
+            on_dropped_frames: self.on_dropped_frames.or(base.on_dropped_frames),
+            progress_interval: self.progress_interval.or(base.progress_interval),
+            max_job_duration: self.max_job_duration.or(base.max_job_duration),
+            max_speed_ratio: self.max_speed_ratio.or(base.max_speed_ratio),
+            process_priority: self.process_priority.or(base.process_priority),
+            verify: self.verify.or(base.verify),
+            duration_tolerance_pct: self.duration_tolerance_pct.or(base.duration_tolerance_pct),
+            duration_tolerance: self.duration_tolerance.or(base.duration_tolerance),
+            if_larger_than_source: self.if_larger_than_source.or(base.if_larger_than_source),
+            max_size_ratio: self.max_size_ratio.or(base.max_size_ratio),
+        }
+    }
 }
 
-fn validate_config(config: &Config) -> Result<()> {
-    for input in &config.inputs {
-        if !input.path.exists() {
-            return Err(anyhow::anyhow!(
-                "Input path does not exist: {}",
-                input.path.display()
-            ));
+/// ffmpeg output options in the order they are passed, written as a list of `flag: value`
+/// pairs so a flag like `-map` can be given more than once. Flags without a value, like `-an`,
+/// are written on their own or with a null value.
+///
+/// The older `{flag: value}` map form is still read, in file order, but can't repeat a flag;
+/// it is always written back as a list.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtraOptions {
+    options: Vec<(String, Option<String>)>,
+    /// Read from the deprecated map form
+    legacy_map: bool,
+}
+
+impl ExtraOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, flag: impl Into<String>, value: impl Into<String>) {
+        self.options.push((flag.into(), Some(value.into())));
+    }
+
+    /// Add a flag that takes no value, like `-an`
+    pub fn push_flag(&mut self, flag: impl Into<String>) {
+        self.options.push((flag.into(), None));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.options
+            .iter()
+            .map(|(flag, value)| (flag.as_str(), value.as_deref()))
+    }
+
+    /// Value of the last occurrence of `flag`, the one ffmpeg goes by
+    pub fn get(&self, flag: &str) -> Option<&str> {
+        self.iter()
+            .filter(|(f, _)| *f == flag)
+            .filter_map(|(_, value)| value)
+            .last()
+    }
+
+    pub fn contains(&self, flag: &str) -> bool {
+        self.iter().any(|(f, _)| f == flag)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    /// Whether these came from the deprecated map form
+    pub fn is_legacy_map(&self) -> bool {
+        self.legacy_map
+    }
+
+    /// `base`'s options for flags this doesn't set, followed by this one's own
+    fn merged_over(self, base: &ExtraOptions) -> ExtraOptions {
+        let mut options: Vec<_> = base
+            .options
+            .iter()
+            .filter(|(flag, _)| !self.contains(flag))
+            .cloned()
+            .collect();
+        options.extend(self.options);
+        ExtraOptions {
+            options,
+            legacy_map: self.legacy_map,
         }
+    }
+}
 
-        if !config.outputs.contains_key(&input.output) {
-            return Err(anyhow::anyhow!(
-                "Output '{}' referenced by input '{}' does not exist",
-                input.output,
-                input.path.display()
-            ));
+/// One entry of the list form: a bare flag or a single `flag: value` pair
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExtraOption {
+    Flag(String),
+    Pair(BTreeMap<String, Option<String>>),
+}
+
+impl Serialize for ExtraOptions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        struct Pair<'a>(&'a str, &'a str);
+        impl Serialize for Pair<'_> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(self.0, self.1)?;
+                map.end()
+            }
         }
 
-        if !config.presets.contains_key(&input.preset) {
-            return Err(anyhow::anyhow!(
-                "Preset '{}' referenced by input '{}' does not exist",
-                input.preset,
-                input.path.display()
-            ));
+        let mut seq = serializer.serialize_seq(Some(self.options.len()))?;
+        for (flag, value) in self.iter() {
+            match value {
+                Some(value) => seq.serialize_element(&Pair(flag, value))?,
+                None => seq.serialize_element(flag)?,
+            }
         }
+        seq.end()
     }
+}
 
-    for (_, output) in &config.outputs {
-        if !output.path.exists() {
-            std::fs::create_dir_all(&output.path).context(format!(
-                "Failed to create output directory: {}",
-                output.path.display()
-            ))?;
+impl<'de> Deserialize<'de> for ExtraOptions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{self, MapAccess, SeqAccess, Visitor};
+
+        struct ExtraOptionsVisitor;
+
+        impl<'de> Visitor<'de> for ExtraOptionsVisitor {
+            type Value = ExtraOptions;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of flags and `flag: value` pairs")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut options = ExtraOptions::new();
+                while let Some(entry) = seq.next_element::<ExtraOption>()? {
+                    match entry {
+                        ExtraOption::Flag(flag) => options.push_flag(flag),
+                        ExtraOption::Pair(pair) if pair.len() == 1 => options.options.extend(pair),
+                        ExtraOption::Pair(pair) => {
+                            return Err(de::Error::custom(format!(
+                                "each extra_options entry must be a flag or a single `flag: value` pair, got {} keys",
+                                pair.len()
+                            )))
+                        }
+                    }
+                }
+                Ok(options)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut options = ExtraOptions::new();
+                while let Some(entry) = map.next_entry::<String, Option<String>>()? {
+                    options.options.push(entry);
+                }
+                options.legacy_map = true;
+                Ok(options)
+            }
         }
+
+        deserializer.deserialize_any(ExtraOptionsVisitor)
     }
+}
 
-    Ok(())
+impl JsonSchema for ExtraOptions {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "ExtraOptions".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "anyOf": [
+                {
+                    "type": "array",
+                    "items": {
+                        "anyOf": [
+                            { "type": "string" },
+                            {
+                                "type": "object",
+                                "minProperties": 1,
+                                "maxProperties": 1,
+                                "additionalProperties": { "type": ["string", "null"] },
+                            },
+                        ],
+                    },
+                },
+                {
+                    "type": "object",
+                    "additionalProperties": { "type": ["string", "null"] },
+                    "deprecated": true,
+                },
+            ],
+        })
+    }
+}
+
+/// What happens to a job that exceeds `max_dropped_frames_pct`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DroppedFramesAction {
+    #[default]
+    Warn,
+    Fail,
+}
+
+/// What happens to a job whose output is projected to outgrow its source
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LargerOutputAction {
+    /// Stop and write no output, leaving the source as the only copy
+    KeepOriginal,
+    /// Stop and copy the source's streams into the output container instead
+    Remux,
+}
+
+/// Loudness targets of `normalize_audio`. The first audio stream is measured, and the same
+/// correction applies to every audio stream the output keeps.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizeAudioConfig {
+    /// Integrated loudness to reach, -23 (EBU R128) when unset; -16 suits podcasts
+    #[serde(default = "NormalizeAudioConfig::default_target_lufs")]
+    pub target_lufs: f64,
+    /// Highest true peak allowed, in dBTP; -1 when unset
+    #[serde(default = "NormalizeAudioConfig::default_true_peak")]
+    pub true_peak: f64,
+}
+
+impl NormalizeAudioConfig {
+    fn default_target_lufs() -> f64 {
+        -23.0
+    }
+
+    fn default_true_peak() -> f64 {
+        -1.0
+    }
+}
+
+/// Deinterlacing filter of a preset
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeinterlaceMode {
+    #[default]
+    Off,
+    /// Always deinterlace, with the fast yadif filter
+    Yadif,
+    /// Always deinterlace, with bwdif, sharper than yadif at a little more CPU
+    Bwdif,
+    /// bwdif on sources whose field order says they are interlaced, nothing on the rest
+    Auto,
+}
+
+/// HDR to SDR tone mapping of a preset
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TonemapMode {
+    #[default]
+    Off,
+    /// Always tone map with hable, which keeps detail in highlights and shadows
+    Hable,
+    /// Always tone map with mobius, which keeps in-range colors closer to the source
+    Mobius,
+    /// Always tone map with reinhard, the simplest curve
+    Reinhard,
+    /// hable on sources whose transfer characteristics are HDR (PQ or HLG), nothing on the rest
+    Auto,
+}
+
+impl TonemapMode {
+    /// Name of the curve in ffmpeg's `tonemap` filter
+    pub fn algorithm(self) -> Option<&'static str> {
+        match self {
+            TonemapMode::Off => None,
+            TonemapMode::Hable | TonemapMode::Auto => Some("hable"),
+            TonemapMode::Mobius => Some("mobius"),
+            TonemapMode::Reinhard => Some("reinhard"),
+        }
+    }
+}
+
+/// How a preset treats the rotation phones record in a display matrix instead of turning the
+/// picture: `auto`, `keep_metadata`, or 90, 180 or 270 degrees clockwise
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(try_from = "RotationSpec", into = "RotationSpec")]
+pub enum Rotation {
+    /// Let ffmpeg turn the picture upright and drop the display matrix
+    #[default]
+    Auto,
+    /// Encode the picture as stored and carry the display matrix over for players to apply
+    KeepMetadata,
+    /// Turn the picture this many degrees clockwise, after the turn any display matrix asks for
+    Degrees(u32),
+}
+
+/// How `rotation` is written: a name, or a bare number of degrees
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+enum RotationSpec {
+    Degrees(u32),
+    Name(String),
+}
+
+impl TryFrom<RotationSpec> for Rotation {
+    type Error = String;
+
+    fn try_from(spec: RotationSpec) -> Result<Self, Self::Error> {
+        match spec {
+            RotationSpec::Degrees(degrees @ (90 | 180 | 270)) => Ok(Rotation::Degrees(degrees)),
+            RotationSpec::Name(name) if name == "auto" => Ok(Rotation::Auto),
+            RotationSpec::Name(name) if name == "keep_metadata" => Ok(Rotation::KeepMetadata),
+            RotationSpec::Degrees(degrees) => Err(format!(
+                "unsupported rotation {}, expected 90, 180 or 270",
+                degrees
+            )),
+            RotationSpec::Name(name) => Err(format!(
+                "unknown rotation '{}', expected auto, keep_metadata, 90, 180 or 270",
+                name
+            )),
+        }
+    }
+}
+
+impl From<Rotation> for RotationSpec {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Auto => RotationSpec::Name("auto".to_string()),
+            Rotation::KeepMetadata => RotationSpec::Name("keep_metadata".to_string()),
+            Rotation::Degrees(degrees) => RotationSpec::Degrees(degrees),
+        }
+    }
+}
+
+/// How a preset turns a source into its output
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PresetMode {
+    /// Encode with the preset's codecs
+    #[default]
+    Encode,
+    /// Copy every stream as is into the output container (`-map 0 -c copy`)
+    Remux,
+}
+
+/// What happens to the subtitle streams of a source
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitlePolicy {
+    /// Leave them out of the output
+    #[default]
+    Drop,
+    /// Keep all of them; mp4 and mov get text subtitles as mov_text and can't hold bitmap ones
+    Copy,
+    /// Render `subtitle_stream`, the first one when unset, into the picture
+    Burn,
+}
+
+/// Stream selection of a preset, turned into `-map` arguments
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StreamsConfig {
+    /// Keep every stream of the source (`-map 0`), minus what the preset leaves out otherwise
+    #[serde(default)]
+    pub map_all: bool,
+    /// Keep the audio streams in these languages, as tagged in the source, e.g. `[eng, jpn]`;
+    /// the first audio stream when none of them is there
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audio_languages: Vec<String>,
+    /// Keep attachments like the fonts of mkv subtitles; kept with `map_all` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_attachments: Option<bool>,
+}
+
+impl StreamsConfig {
+    pub fn keeps_attachments(&self) -> bool {
+        self.keep_attachments.unwrap_or(self.map_all)
+    }
+}
+
+/// GPU backend of a preset's `hwaccel`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HwAccelKind {
+    /// NVIDIA: NVDEC decoding, NVENC encoding
+    Nvenc,
+    /// Intel and AMD on Linux
+    Vaapi,
+    /// Intel Quick Sync
+    Qsv,
+    /// Apple
+    Videotoolbox,
+}
+
+impl HwAccelKind {
+    /// Method name `-hwaccel` takes and `ffmpeg -hwaccels` lists
+    pub fn method(self) -> &'static str {
+        match self {
+            HwAccelKind::Nvenc => "cuda",
+            HwAccelKind::Vaapi => "vaapi",
+            HwAccelKind::Qsv => "qsv",
+            HwAccelKind::Videotoolbox => "videotoolbox",
+        }
+    }
+
+    /// Suffix of the backend's encoders, as in `hevc_nvenc`
+    fn encoder_suffix(self) -> &'static str {
+        match self {
+            HwAccelKind::Nvenc => "nvenc",
+            HwAccelKind::Vaapi => "vaapi",
+            HwAccelKind::Qsv => "qsv",
+            HwAccelKind::Videotoolbox => "videotoolbox",
+        }
+    }
+}
+
+impl fmt::Display for HwAccelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.encoder_suffix())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HwAccelConfig {
+    #[serde(rename = "type")]
+    pub kind: HwAccelKind,
+    /// Render node for `vaapi` and `qsv`, `/dev/dri/renderD128` for `vaapi` when unset; the GPU
+    /// index for `nvenc`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+impl HwAccelConfig {
+    pub const DEFAULT_VAAPI_DEVICE: &'static str = "/dev/dri/renderD128";
+
+    /// This backend's encoder for the codec `video_codec` names, h264 when unset; `copy` and
+    /// encoders sstc doesn't know a hardware variant of are kept as they are
+    pub fn encoder(&self, video_codec: Option<&str>) -> String {
+        let suffix = self.kind.encoder_suffix();
+        let Some(codec) = video_codec else {
+            return format!("h264_{}", suffix);
+        };
+        match codec_family(codec) {
+            _ if codec == "copy" || codec.ends_with(suffix) => codec.to_string(),
+            Some(family @ ("h264" | "hevc" | "av1" | "vp9")) => format!("{}_{}", family, suffix),
+            _ => codec.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// All findings produced by a validation run, collected instead of failing on the first one.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    pub strict: bool,
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            findings: Vec::new(),
+        }
+    }
+
+    fn warning(&mut self, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    fn error(&mut self, message: String) {
+        self.findings.push(Finding {
+            severity: Severity::Error,
+            message,
+        });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.findings.iter().any(|f| f.severity == Severity::Error)
+    }
+
+    pub fn has_warnings(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|f| f.severity == Severity::Warning)
+    }
+
+    /// Whether the report rejects the config, taking strictness into account
+    pub fn is_failure(&self) -> bool {
+        self.has_errors() || (self.strict && self.has_warnings())
+    }
+
+    /// Log every finding, marking warnings that strict mode escalated to errors
+    pub fn log(&self) {
+        for finding in &self.findings {
+            match finding.severity {
+                Severity::Error => error!("{}", finding.message.red()),
+                Severity::Warning if self.strict => {
+                    error!("{} {}", "(strict)".red(), finding.message.red())
+                }
+                Severity::Warning => warn!("{}", finding.message.yellow()),
+            }
+        }
+    }
+}
+
+/// Returned when validation rejects a config; carries the full report.
+#[derive(Debug)]
+pub struct ValidationFailed {
+    pub report: ValidationReport,
+}
+
+impl ValidationFailed {
+    /// Exit code 2 for hard errors, 3 when only strict mode caused the failure
+    pub fn exit_code(&self) -> i32 {
+        if self.report.has_errors() {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+impl fmt::Display for ValidationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors = self
+            .report
+            .findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count();
+        let warnings = self.report.findings.len() - errors;
+
+        if errors == 0 {
+            write!(
+                f,
+                "Config validation failed due to strict mode: {} warning(s) treated as errors",
+                warnings
+            )
+        } else {
+            write!(
+                f,
+                "Config validation failed with {} error(s) and {} warning(s)",
+                errors, warnings
+            )
+        }
+    }
+}
+
+impl std::error::Error for ValidationFailed {}
+
+pub fn load_config<P: AsRef<Path>>(path: P, strict: bool) -> Result<Config> {
+    let config = prepare_config(path.as_ref())?;
+
+    for input in &config.inputs {
+        if !input.path.exists() {
+            std::fs::create_dir_all(&input.path).context(format!(
+                "Failed to create input directory: {}",
+                input.path.display()
+            ))?;
+        }
+    }
+    for output in config.outputs.values() {
+        if !output.path.exists() {
+            std::fs::create_dir_all(&output.path).context(format!(
+                "Failed to create output directory: {}",
+                output.path.display()
+            ))?;
+        }
+    }
+
+    let report = validate_config(&config, strict || config.strict);
+    report.log();
+
+    if report.is_failure() {
+        return Err(ValidationFailed { report }.into());
+    }
+
+    Ok(config)
+}
+
+/// Validate a config file without creating anything, for checking it before deployment.
+///
+/// Problems that stop the config from loading at all are reported as errors too.
+pub fn check_config<P: AsRef<Path>>(path: P, strict: bool) -> ValidationReport {
+    let config = match prepare_config(path.as_ref()) {
+        Ok(config) => config,
+        Err(e) => {
+            let mut report = ValidationReport::new(strict);
+            report.error(format!("{:#}", e));
+            return report;
+        }
+    };
+
+    let mut report = validate_config(&config, strict || config.strict);
+    // `load_config` creates these; checking on another machine shouldn't
+    for input in config.inputs.iter().filter(|input| !input.path.exists()) {
+        report.warning(format!(
+            "Input path does not exist and will be created on start: {}",
+            input.path.display()
+        ));
+    }
+    for output in config
+        .outputs
+        .values()
+        .filter(|output| !output.path.exists())
+    {
+        report.warning(format!(
+            "Output path does not exist and will be created on start: {}",
+            output.path.display()
+        ));
+    }
+    report
+}
+
+/// Read a config and resolve everything it refers to, before validation
+fn prepare_config(path: &Path) -> Result<Config> {
+    let mut config = read_config(path)?;
+    expand_paths(&mut config)?;
+    merge_shared_presets(&mut config, path)?;
+    resolve_preset_inheritance(&mut config.presets)?;
+    apply_default_target(&mut config);
+    apply_default_ignore(&mut config);
+    Ok(config)
+}
+
+/// Give inputs without their own `ignore` list the global one
+fn apply_default_ignore(config: &mut Config) {
+    let Some(ignore) = &config.ignore else {
+        return;
+    };
+    for input in config.inputs.iter_mut().filter(|i| i.ignore.is_none()) {
+        input.ignore = Some(ignore.clone());
+    }
+}
+
+/// Give single-target inputs without their own preset or output the configured defaults
+fn apply_default_target(config: &mut Config) {
+    for input in config
+        .inputs
+        .iter_mut()
+        .filter(|input| input.targets.is_empty())
+    {
+        if let (true, Some(preset)) = (input.preset.is_empty(), &config.default_preset) {
+            input.preset = preset.clone();
+        }
+        if let (true, Some(output)) = (input.output.is_empty(), &config.default_output) {
+            input.output = output.clone();
+        }
+    }
+}
+
+/// Settings a reload can't apply to the running service, by name, that differ between configs
+pub fn restart_required_changes(old: &Config, new: &Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if old.max_parallel_jobs != new.max_parallel_jobs {
+        changed.push("max_parallel_jobs");
+    }
+    if old.on_queue_active != new.on_queue_active
+        || old.on_queue_drained != new.on_queue_drained
+        || old.drain_settle != new.drain_settle
+        || old.on_hook_failure != new.on_hook_failure
+    {
+        changed.push("queue hooks");
+    }
+    if old.distributed != new.distributed {
+        changed.push("distributed");
+    }
+    if old.history_file != new.history_file {
+        changed.push("history_file");
+    }
+    if old.history_db != new.history_db {
+        changed.push("history_db");
+    }
+    if old.queue_file != new.queue_file {
+        changed.push("queue_file");
+    }
+    if old.dedup_by_hash != new.dedup_by_hash || old.hash_store != new.hash_store {
+        changed.push("dedup_by_hash/hash_store");
+    }
+    if old.watch_config != new.watch_config {
+        changed.push("watch_config");
+    }
+    if old.control_socket != new.control_socket {
+        changed.push("control_socket");
+    }
+    if old.otel != new.otel {
+        changed.push("otel");
+    }
+    if old.ffmpeg_path != new.ffmpeg_path || old.ffprobe_path != new.ffprobe_path {
+        changed.push("ffmpeg_path/ffprobe_path");
+    }
+    changed
+}
+
+/// Syntax of a config file, picked by its extension; YAML unless it says otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    /// Parse a config, reporting where and in which section it went wrong
+    pub fn parse<T: serde::de::DeserializeOwned>(&self, text: &str) -> Result<T> {
+        match self {
+            ConfigFormat::Yaml => {
+                serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(text))
+                    .map_err(|e| {
+                        let location = e.inner().location().map(|l| (l.line(), l.column()));
+                        ParseError::new(
+                            text,
+                            e.path().to_string(),
+                            location,
+                            diagnostic::strip_location(&e.inner().to_string()),
+                        )
+                    })
+                    .context("Failed to parse YAML config")
+            }
+            ConfigFormat::Toml => serde_path_to_error::deserialize(toml::Deserializer::new(text))
+                .map_err(|e| {
+                    let location = e
+                        .inner()
+                        .span()
+                        .map(|span| line_and_column(text, span.start));
+                    ParseError::new(text, e.path().to_string(), location, e.inner().message())
+                })
+                .context("Failed to parse TOML config"),
+            ConfigFormat::Json => {
+                serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(text))
+                    .map_err(|e| {
+                        let location =
+                            (e.inner().line() > 0).then(|| (e.inner().line(), e.inner().column()));
+                        ParseError::new(
+                            text,
+                            e.path().to_string(),
+                            location,
+                            diagnostic::strip_location(&e.inner().to_string()),
+                        )
+                    })
+                    .context("Failed to parse JSON config")
+            }
+        }
+    }
+
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<String> {
+        Ok(match self {
+            ConfigFormat::Yaml => serde_yaml::to_string(value)?,
+            ConfigFormat::Toml => toml::to_string_pretty(value)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+}
+
+/// 1-based line and column of a byte offset into `text`
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Parse a config file as written, without expanding paths or validating it
+pub fn read_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).context("Failed to open config file")?;
+    ConfigFormat::from_path(path).parse(&text)
+}
+
+/// Write a config in the format its file extension asks for
+pub fn write_config<P: AsRef<Path>, T: Serialize>(path: P, value: &T) -> Result<()> {
+    let path = path.as_ref();
+    let text = ConfigFormat::from_path(path).serialize(value)?;
+    std::fs::write(path, text).context(format!("Failed to write {}", path.display()))
+}
+
+/// JSON Schema of the config file, for editors and external validators
+pub fn json_schema() -> Result<String> {
+    serde_json::to_string_pretty(&schemars::schema_for!(Config))
+        .context("Failed to serialize the config schema")
+}
+
+/// Add the presets of every `presets_file` to the config. Inline presets take precedence over
+/// shared ones, and later files over earlier ones.
+fn merge_shared_presets(config: &mut Config, config_path: &Path) -> Result<()> {
+    let Some(files) = &config.presets_file else {
+        return Ok(());
+    };
+    let base = config_path.parent().unwrap_or(Path::new(""));
+
+    let mut shared: HashMap<String, (PresetConfig, PathBuf)> = HashMap::new();
+    for file in files.paths() {
+        let file = base.join(expand_path(file)?);
+        let text = std::fs::read_to_string(&file)
+            .context(format!("Failed to open presets file {}", file.display()))?;
+        let presets: PresetsFile = ConfigFormat::from_path(&file)
+            .parse(&text)
+            .context(format!("Failed to load presets file {}", file.display()))?;
+
+        for (name, preset) in presets.presets {
+            if let Some((_, earlier)) = shared.insert(name.clone(), (preset, file.clone())) {
+                warn!(
+                    "Preset {} from {} is shadowed by the one in {}",
+                    name.yellow(),
+                    earlier.display(),
+                    file.display()
+                );
+            }
+        }
+    }
+
+    for (name, (preset, file)) in shared {
+        match config.presets.entry(name) {
+            Entry::Occupied(inline) => warn!(
+                "Preset {} from {} is shadowed by the inline preset",
+                inline.key().yellow(),
+                file.display()
+            ),
+            Entry::Vacant(slot) => {
+                config.shared_presets.insert(slot.key().clone());
+                slot.insert(preset);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Flatten every `extends` chain so each preset carries all of its settings
+fn resolve_preset_inheritance(presets: &mut HashMap<String, PresetConfig>) -> Result<()> {
+    let mut names: Vec<String> = presets.keys().cloned().collect();
+    names.sort();
+
+    let mut resolved = HashMap::new();
+    for name in names {
+        resolve_preset(&name, presets, &mut resolved, &mut Vec::new())?;
+    }
+    *presets = resolved;
+    Ok(())
+}
+
+fn resolve_preset(
+    name: &str,
+    presets: &HashMap<String, PresetConfig>,
+    resolved: &mut HashMap<String, PresetConfig>,
+    chain: &mut Vec<String>,
+) -> Result<PresetConfig> {
+    if let Some(preset) = resolved.get(name) {
+        return Ok(preset.clone());
+    }
+    if chain.iter().any(|link| link == name) {
+        chain.push(name.to_string());
+        return Err(anyhow!("Preset inheritance cycle: {}", chain.join(" -> ")));
+    }
+
+    let preset = presets[name].clone();
+    let flattened = match &preset.extends {
+        Some(base_name) => {
+            if !presets.contains_key(base_name) {
+                return Err(anyhow!(
+                    "Preset '{}' extends '{}', which does not exist{}",
+                    name,
+                    base_name,
+                    diagnostic::did_you_mean(base_name, presets.keys())
+                ));
+            }
+            chain.push(name.to_string());
+            let base = resolve_preset(base_name, presets, resolved, chain)?;
+            chain.pop();
+            preset.inherit(&base)
+        }
+        None => preset,
+    };
+
+    resolved.insert(name.to_string(), flattened.clone());
+    Ok(flattened)
+}
+
+/// Expand environment variables and `~` in every path of the config
+fn expand_paths(config: &mut Config) -> Result<()> {
+    for input in &mut config.inputs {
+        input.path = expand_path(&input.path)?;
+    }
+    for output in config.outputs.values_mut() {
+        output.path = expand_path(&output.path)?;
+        if let Some(trash_dir) = &output.trash_dir {
+            output.trash_dir = Some(expand_path(trash_dir)?);
+        }
+    }
+    if let Some(claims_dir) = config
+        .distributed
+        .as_mut()
+        .and_then(|d| d.claims_dir.as_mut())
+    {
+        *claims_dir = expand_path(claims_dir)?;
+    }
+    if let Some(history_file) = &mut config.history_file {
+        *history_file = expand_path(history_file)?;
+    }
+    if let Some(history_db) = &mut config.history_db {
+        *history_db = expand_path(history_db)?;
+    }
+    if let Some(metrics_file) = &mut config.metrics_file {
+        *metrics_file = expand_path(metrics_file)?;
+    }
+    if let Some(queue_file) = &mut config.queue_file {
+        *queue_file = expand_path(queue_file)?;
+    }
+    if let Some(job_logs_dir) = &mut config.job_logs_dir {
+        *job_logs_dir = expand_path(job_logs_dir)?;
+    }
+    if let Some(control_socket) = &mut config.control_socket {
+        *control_socket = expand_path(control_socket)?;
+    }
+    if let Some(hash_store) = &mut config.hash_store {
+        hash_store.path = expand_path(&hash_store.path)?;
+    }
+    if let Some(quarantine_dir) = &mut config.quarantine_dir {
+        *quarantine_dir = expand_path(quarantine_dir)?;
+    }
+    for binary in [&mut config.ffmpeg_path, &mut config.ffprobe_path]
+        .into_iter()
+        .flatten()
+    {
+        *binary = expand_path(binary)?;
+    }
+    Ok(())
+}
+
+fn validate_config(config: &Config, strict: bool) -> ValidationReport {
+    let mut report = ValidationReport::new(strict);
+
+    for input in &config.inputs {
+        let single_target =
+            !input.preset.is_empty() || !input.output.is_empty() || input.fallback_preset.is_some();
+        if !input.targets.is_empty() && single_target {
+            report.error(format!(
+                "Input '{}' sets targets together with preset/output/fallback_preset; use one or the other",
+                input.path.display()
+            ));
+        } else if input.targets.is_empty() && (input.preset.is_empty() || input.output.is_empty()) {
+            report.error(format!(
+                "Input '{}' needs a preset and an output, set on it or as default_preset/default_output, or targets",
+                input.path.display()
+            ));
+        }
+
+        let mut seen_outputs = HashSet::new();
+        for target in input.targets() {
+            check_target(config, input, &target, &mut report);
+
+            // Without {preset} in the path, later targets only find the first one's file
+            let by_preset = config.outputs.get(&target.output).is_some_and(|output| {
+                output.filename_template.contains("{preset}")
+                    || output
+                        .subdir_template
+                        .as_ref()
+                        .is_some_and(|subdir| subdir.contains("{preset}"))
+            });
+            if !seen_outputs.insert(target.output.clone()) && !by_preset {
+                report.warning(format!(
+                    "Targets of input '{}' share output '{}', whose templates don't use {{preset}}; only the first of them will be written",
+                    input.path.display(),
+                    target.output
+                ));
+            }
+        }
+
+        if input.extensions.is_empty() && input.patterns.is_empty() {
+            report.error(format!(
+                "Input '{}' needs extensions or patterns to match files",
+                input.path.display()
+            ));
+        }
+
+        for pattern in input
+            .patterns
+            .iter()
+            .chain(&input.exclude)
+            .chain(input.ignore.iter().flatten())
+        {
+            if let Err(e) = Pattern::new(pattern) {
+                report.error(format!(
+                    "Invalid pattern '{}' of input '{}': {}",
+                    pattern,
+                    input.path.display(),
+                    e
+                ));
+            }
+        }
+
+        match &input.source_action {
+            SourceAction::Suffix(suffix) if suffix.is_empty() || suffix.contains('/') => {
+                report.error(format!(
+                    "source_action suffix '{}' of input '{}' must be non-empty and contain no '/'",
+                    suffix,
+                    input.path.display()
+                ));
+            }
+            SourceAction::Move(dir) if dir == &input.path => {
+                report.error(format!(
+                    "source_action of input '{}' moves sources into the input directory itself",
+                    input.path.display()
+                ));
+            }
+            _ => {}
+        }
+
+        if input.on_match != CodecMatchAction::default() && input.skip_if_video_codec.is_empty() {
+            report.warning(format!(
+                "on_match of input '{}' has no effect without skip_if_video_codec",
+                input.path.display()
+            ));
+        }
+
+        if let (Some(min), Some(max)) = (input.min_size, input.max_size) {
+            if min > max {
+                report.error(format!(
+                    "min_size {} of input '{}' is larger than its max_size {}",
+                    min,
+                    input.path.display(),
+                    max
+                ));
+            }
+        }
+
+        if let (Some(min), Some(max)) = (input.min_age, input.max_age) {
+            if min >= max {
+                report.error(format!(
+                    "min_age {} of input '{}' must be shorter than its max_age {}",
+                    min,
+                    input.path.display(),
+                    max
+                ));
+            }
+        }
+
+        if let Some(window) = &input.recorded_between {
+            if let Err(e) = TimeWindow::parse(window) {
+                report.error(format!(
+                    "Invalid recorded_between of input '{}': {:#}",
+                    input.path.display(),
+                    e
+                ));
+            }
+        }
+
+        if let Some(pattern) = &input.timestamp_pattern {
+            let invalid = StrftimeItems::new(pattern).any(|item| matches!(item, Item::Error));
+            if invalid {
+                report.error(format!(
+                    "Invalid timestamp_pattern '{}' of input '{}'",
+                    pattern,
+                    input.path.display()
+                ));
+            }
+        }
+    }
+
+    let mut output_names: Vec<&String> = config.outputs.keys().collect();
+    output_names.sort();
+    for name in output_names {
+        let output = &config.outputs[name];
+        if let Err(e) = template::render(&output.filename_template, &TemplateVars::sample()) {
+            report.error(format!(
+                "Invalid filename_template of output '{}': {}",
+                name, e
+            ));
+        }
+        if let Some(subdir) = &output.subdir_template {
+            if let Err(e) = template::render(subdir, &TemplateVars::sample()) {
+                report.error(format!(
+                    "Invalid subdir_template of output '{}': {}",
+                    name, e
+                ));
+            }
+        }
+        if output.thumbnail.as_ref().and_then(|t| t.width) == Some(0) {
+            report.error(format!("Thumbnail width of output '{}' is zero", name));
+        }
+        if output.if_source_newer == SourceNewerPolicy::Retranscode
+            && output.on_existing != ExistingOutputPolicy::Skip
+        {
+            report.warning(format!(
+                "if_source_newer of output '{}' only applies with on_existing: skip",
+                name
+            ));
+        }
+    }
+
+    let mut preset_names: Vec<&String> = config.presets.keys().collect();
+    preset_names.sort();
+    for name in preset_names {
+        let preset = &config.presets[name];
+        let capped = preset.max_width.is_some() || preset.max_height.is_some();
+        if capped && preset.scale.is_some() {
+            report.error(format!(
+                "Preset '{}' sets scale together with max_width/max_height; use one or the other",
+                name
+            ));
+        }
+        if preset.max_width == Some(0) || preset.max_height == Some(0) {
+            report.error(format!("Preset '{}' has a zero max_width/max_height", name));
+        }
+        if let Some(container) = &preset.container {
+            if container.is_empty() || container.contains(['.', '/']) {
+                report.error(format!(
+                    "Container '{}' of preset '{}' must be a bare extension like mp4",
+                    container, name
+                ));
+            }
+        }
+        if let Some(interval) = preset.progress_interval {
+            check_progress_interval(interval, &format!(" of preset '{}'", name), &mut report);
+        }
+        if let Some(hwaccel) = &preset.hwaccel {
+            check_hwaccel(name, preset, hwaccel, &mut report);
+        }
+        if preset.is_audio_only() {
+            let video_settings: Vec<&str> = [
+                ("video_codec", preset.video_codec.is_some()),
+                ("video_bitrate", preset.video_bitrate.is_some()),
+                ("crf", preset.crf.is_some()),
+                ("encoder_preset", preset.encoder_preset.is_some()),
+                ("threads", preset.threads.is_some()),
+                ("pixel_format", preset.pixel_format.is_some()),
+                ("scale", preset.scale.is_some()),
+                ("video_filters", !preset.video_filters.is_empty()),
+                ("fps", preset.fps.is_some()),
+                ("max_width", preset.max_width.is_some()),
+                ("max_height", preset.max_height.is_some()),
+                ("hwaccel", preset.hwaccel.is_some()),
+                ("two_pass", preset.two_pass == Some(true)),
+                ("auto_crop", preset.auto_crop == Some(true)),
+                (
+                    "deinterlace",
+                    preset
+                        .deinterlace
+                        .is_some_and(|d| d != DeinterlaceMode::Off),
+                ),
+                (
+                    "tonemap",
+                    preset.tonemap.is_some_and(|t| t != TonemapMode::Off),
+                ),
+                (
+                    "rotation",
+                    preset.rotation.is_some_and(|r| r != Rotation::Auto),
+                ),
+                (
+                    "subtitles",
+                    preset.subtitles.is_some_and(|s| s != SubtitlePolicy::Drop),
+                ),
+            ]
+            .into_iter()
+            .filter_map(|(setting, set)| set.then_some(setting))
+            .collect();
+            if !video_settings.is_empty() {
+                report.warning(format!(
+                    "Preset '{}' is audio_only, so {} {} ignored",
+                    name,
+                    video_settings.join(", "),
+                    if video_settings.len() == 1 {
+                        "is"
+                    } else {
+                        "are"
+                    }
+                ));
+            }
+        }
+        if preset.subtitle_stream.is_some() && preset.subtitles != Some(SubtitlePolicy::Burn) {
+            report.warning(format!(
+                "Preset '{}' sets subtitle_stream, which only subtitles: burn uses",
+                name
+            ));
+        }
+        if preset.subtitles == Some(SubtitlePolicy::Burn)
+            && preset.hwaccel.as_ref().map(|h| h.kind) == Some(HwAccelKind::Vaapi)
+        {
+            report.error(format!(
+                "Preset '{}' can't burn in subtitles with vaapi, the frames never leave the GPU",
+                name
+            ));
+        }
+        if let Some(streams) = &preset.streams {
+            check_streams(name, preset, streams, &mut report);
+        }
+        if preset.is_remux() {
+            let encode_settings: Vec<&str> = [
+                ("video_codec", preset.video_codec.is_some()),
+                ("audio_codec", preset.audio_codec.is_some()),
+                ("video_bitrate", preset.video_bitrate.is_some()),
+                ("audio_bitrate", preset.audio_bitrate.is_some()),
+                ("crf", preset.crf.is_some()),
+                ("encoder_preset", preset.encoder_preset.is_some()),
+                ("threads", preset.threads.is_some()),
+                ("pixel_format", preset.pixel_format.is_some()),
+                ("scale", preset.scale.is_some()),
+                ("video_filters", !preset.video_filters.is_empty()),
+                ("fps", preset.fps.is_some()),
+                ("max_width", preset.max_width.is_some()),
+                ("max_height", preset.max_height.is_some()),
+                ("hwaccel", preset.hwaccel.is_some()),
+                ("two_pass", preset.two_pass.is_some()),
+                ("auto_crop", preset.auto_crop.is_some()),
+                ("deinterlace", preset.deinterlace.is_some()),
+                ("tonemap", preset.tonemap.is_some()),
+                ("rotation", preset.rotation.is_some()),
+                ("normalize_audio", preset.normalize_audio.is_some()),
+                ("audio_filters", !preset.audio_filters.is_empty()),
+                ("audio_only", preset.audio_only.is_some()),
+                ("streams", preset.streams.is_some()),
+                (
+                    "if_larger_than_source",
+                    preset.if_larger_than_source.is_some(),
+                ),
+                (
+                    "subtitles: burn",
+                    preset.subtitles == Some(SubtitlePolicy::Burn),
+                ),
+            ]
+            .into_iter()
+            .filter_map(|(setting, set)| set.then_some(setting))
+            .collect();
+            if !encode_settings.is_empty() {
+                report.error(format!(
+                    "Preset '{}' remuxes without encoding, so it can't set {}",
+                    name,
+                    encode_settings.join(", ")
+                ));
+            }
+        }
+        check_auto_crop(name, preset, &mut report);
+        check_tonemap(name, preset, &mut report);
+        check_filters(name, preset, &mut report);
+        check_quality(name, preset, &mut report);
+        if let Some(normalize) = &preset.normalize_audio {
+            check_normalize_audio(name, preset, normalize, &mut report);
+        }
+        if preset.two_pass == Some(true) && preset.video_bitrate.is_none() {
+            report.warning(format!(
+                "Preset '{}' sets two_pass without video_bitrate, so it encodes in one pass",
+                name
+            ));
+        }
+        if let Some(ratio) = preset.max_speed_ratio {
+            check_speed_ratio(ratio, &format!(" of preset '{}'", name), &mut report);
+        }
+        if let Some(priority) = &preset.process_priority {
+            check_process_priority(priority, &format!(" of preset '{}'", name), &mut report);
+        }
+        check_size_ratio(name, preset, &mut report);
+        check_threads(name, preset, &mut report);
+        if let Some(pct) = preset.duration_tolerance_pct {
+            if !pct.is_finite() || pct < 0.0 {
+                report.error(format!(
+                    "duration_tolerance_pct of preset '{}' must be 0 or more, got {}",
+                    name, pct
+                ));
+            }
+        }
+        if preset.verify == Some(false)
+            && (preset.duration_tolerance_pct.is_some() || preset.duration_tolerance.is_some())
+        {
+            report.warning(format!(
+                "Preset '{}' sets a duration tolerance but verify is false, so it is not used",
+                name
+            ));
+        }
+    }
+    if let Some(interval) = config.progress_interval {
+        check_progress_interval(interval, "", &mut report);
+    }
+    if let Some(ratio) = config.max_speed_ratio {
+        check_speed_ratio(ratio, "", &mut report);
+    }
+    if let Some(priority) = &config.process_priority {
+        check_process_priority(priority, "", &mut report);
+    }
+    let progress_off = config.progress_interval == Some(ProgressInterval::Off)
+        || config
+            .presets
+            .values()
+            .any(|preset| preset.progress_interval == Some(ProgressInterval::Off));
+    let needs_progress = config.verify_output_growth
+        || config.schedule.as_ref().is_some_and(|s| s.hard_stop)
+        || config
+            .presets
+            .values()
+            .any(|preset| preset.if_larger_than_source.is_some());
+    if progress_off && needs_progress {
+        report.warning(
+            "verify_output_growth, schedule.hard_stop and if_larger_than_source do nothing for jobs with progress_interval off"
+                .to_string(),
+        );
+    }
+
+    for input in &config.inputs {
+        let stability =
+            StabilityConfig::resolve(input.stability.as_ref(), config.stability.as_ref());
+        if stability.check_interval.is_zero() {
+            report.error(format!(
+                "stability.check_interval of input '{}' must be longer than zero",
+                input.path.display()
+            ));
+        }
+        if stability.timeout <= stability.stable_for {
+            report.error(format!(
+                "stability.timeout of input '{}' ({:?}) must be longer than stable_for ({:?}), or no file ever settles",
+                input.path.display(),
+                stability.timeout,
+                stability.stable_for
+            ));
+        }
+    }
+
+    if let Some(quarantine_dir) = &config.quarantine_dir {
+        if let Some(input) = config
+            .inputs
+            .iter()
+            .find(|input| quarantine_dir.starts_with(&input.path))
+        {
+            report.warning(format!(
+                "quarantine_dir {} is inside input '{}', quarantined files will be picked up again",
+                quarantine_dir.display(),
+                input.path.display()
+            ));
+        }
+    }
+    if config.max_queue_size == Some(0) {
+        report.error("max_queue_size must be at least 1".to_string());
+    }
+    if config.on_queue_full != QueueFullPolicy::default() && config.max_queue_size.is_none() {
+        report.warning("on_queue_full has no effect without max_queue_size".to_string());
+    }
+
+    if let Some(schedule) = &config.schedule {
+        if let Err(e) = schedule.window() {
+            report.error(format!("Invalid schedule: {:#}", e));
+        }
+        if let Err(e) = schedule.weekdays() {
+            report.error(format!("Invalid schedule: {:#}", e));
+        }
+    }
+
+    if let Some(distributed) = &config.distributed {
+        if distributed.lease.as_secs() == 0 {
+            report.error("distributed.lease must be at least one second".to_string());
+        }
+        if let Some(dir) = &distributed.claims_dir {
+            if !dir.is_dir() {
+                report.error(format!(
+                    "Claims directory does not exist: {}",
+                    dir.display()
+                ));
+            }
+        }
+    }
+
+    if config.job_logs_max_count == Some(0) {
+        report.error("job_logs_max_count must be at least 1".to_string());
+    }
+    if config.job_logs_dir.is_none()
+        && (config.job_logs_max_count.is_some() || config.job_logs_max_age.is_some())
+    {
+        report.warning(
+            "job_logs_max_count and job_logs_max_age are unused without job_logs_dir".to_string(),
+        );
+    }
+
+    if config.skip_if_in_history && config.history_db.is_none() {
+        report.error("skip_if_in_history needs a history_db to look jobs up in".to_string());
+    }
+
+    match &config.hash_store {
+        None if config.dedup_by_hash => {
+            report.error("dedup_by_hash needs a hash_store to keep the hashes in".to_string());
+        }
+        Some(_) if !config.dedup_by_hash => {
+            report.warning("hash_store is unused without dedup_by_hash".to_string());
+        }
+        Some(store) if store.max_entries == 0 => {
+            report.error("hash_store.max_entries must be at least 1".to_string());
+        }
+        _ => {}
+    }
+
+    check_overlapping_inputs(config, &mut report);
+    check_unreferenced_presets(config, &mut report);
+    check_unreferenced_outputs(config, &mut report);
+    check_legacy_extra_options(config, &mut report);
+    check_bitrate_crf_conflicts(config, &mut report);
+    check_input_output_overlap(config, &mut report);
+    check_same_file_outputs(config, &mut report);
+    check_codec_containers(config, &mut report);
+
+    report
+}
+
+fn canonical_or_raw(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn check_overlapping_inputs(config: &Config, report: &mut ValidationReport) {
+    for (i, a) in config.inputs.iter().enumerate() {
+        for b in config.inputs.iter().skip(i + 1) {
+            let path_a = canonical_or_raw(&a.path);
+            let path_b = canonical_or_raw(&b.path);
+
+            if path_a.starts_with(&path_b) || path_b.starts_with(&path_a) {
+                report.warning(format!(
+                    "Input paths overlap: '{}' and '{}'",
+                    a.path.display(),
+                    b.path.display()
+                ));
+            }
+        }
+    }
+}
+
+fn check_target(
+    config: &Config,
+    input: &InputConfig,
+    target: &TargetConfig,
+    report: &mut ValidationReport,
+) {
+    // Missing halves of the single-target form are reported on the input already
+    if !target.output.is_empty() && !config.outputs.contains_key(&target.output) {
+        report.error(format!(
+            "Output '{}' referenced by input '{}' does not exist{}",
+            target.output,
+            input.path.display(),
+            diagnostic::did_you_mean(&target.output, config.outputs.keys())
+        ));
+    }
+
+    if !target.preset.is_empty() && !config.presets.contains_key(&target.preset) {
+        report.error(format!(
+            "Preset '{}' referenced by input '{}' does not exist{}",
+            target.preset,
+            input.path.display(),
+            diagnostic::did_you_mean(&target.preset, config.presets.keys())
+        ));
+    }
+
+    if let Some(fallback) = &target.fallback_preset {
+        if fallback == &target.preset {
+            report.error(format!(
+                "Fallback preset '{}' of input '{}' is the same as its primary preset",
+                fallback,
+                input.path.display()
+            ));
+        } else if !config.presets.contains_key(fallback) {
+            report.error(format!(
+                "Fallback preset '{}' referenced by input '{}' does not exist{}",
+                fallback,
+                input.path.display(),
+                diagnostic::did_you_mean(fallback, config.presets.keys())
+            ));
+        }
+    }
+}
+
+fn check_unreferenced_presets(config: &Config, report: &mut ValidationReport) {
+    // A shared file is written for several machines; each only uses some of its presets
+    let mut names: Vec<&String> = config
+        .presets
+        .keys()
+        .filter(|name| !config.shared_presets.contains(*name))
+        .filter(|name| {
+            !config
+                .inputs
+                .iter()
+                .flat_map(InputConfig::targets)
+                .any(|target| {
+                    &target.preset == *name || target.fallback_preset.as_ref() == Some(*name)
+                })
+        })
+        .collect();
+    names.sort();
+
+    for name in names {
+        report.warning(format!("Preset '{}' is not used by any input", name));
+    }
+}
+
+fn check_unreferenced_outputs(config: &Config, report: &mut ValidationReport) {
+    let mut names: Vec<&String> = config
+        .outputs
+        .keys()
+        .filter(|name| {
+            !config
+                .inputs
+                .iter()
+                .flat_map(InputConfig::targets)
+                .any(|target| &target.output == *name)
+        })
+        .collect();
+    names.sort();
+
+    for name in names {
+        report.warning(format!("Output '{}' is not used by any input", name));
+    }
+}
+
+fn check_legacy_extra_options(config: &Config, report: &mut ValidationReport) {
+    let mut names: Vec<&String> = config.presets.keys().collect();
+    names.sort();
+
+    for name in names {
+        if config.presets[name].extra_options.is_legacy_map() {
+            report.warning(format!(
+                "Preset '{}' writes extra_options as a map, which is deprecated; use a list of `- flag: value` entries",
+                name
+            ));
+        }
+    }
+}
+
+fn check_bitrate_crf_conflicts(config: &Config, report: &mut ValidationReport) {
+    let mut names: Vec<&String> = config.presets.keys().collect();
+    names.sort();
+
+    for name in names {
+        let preset = &config.presets[name];
+        if preset.video_bitrate.is_some()
+            && (preset.crf.is_some() || preset.extra_options.contains("-crf"))
+        {
+            report.warning(format!(
+                "Preset '{}' sets both video_bitrate and crf; the encoder will pick one",
+                name
+            ));
+        }
+    }
+}
+
+fn check_input_output_overlap(config: &Config, report: &mut ValidationReport) {
+    let mut names: Vec<&String> = config.outputs.keys().collect();
+    names.sort();
+
+    for name in names {
+        let output_path = canonical_or_raw(&config.outputs[name].path);
+
+        for input in &config.inputs {
+            let input_path = canonical_or_raw(&input.path);
+            if output_path.starts_with(&input_path) {
+                report.warning(format!(
+                    "Output '{}' ({}) is inside input directory {}",
+                    name,
+                    config.outputs[name].path.display(),
+                    input.path.display()
+                ));
+            } else if input_path.starts_with(&output_path) {
+                report.warning(format!(
+                    "Input directory {} is inside output '{}' ({})",
+                    input.path.display(),
+                    name,
+                    config.outputs[name].path.display()
+                ));
+            }
+        }
+    }
+}
+
+fn check_same_file_outputs(config: &Config, report: &mut ValidationReport) {
+    for input in &config.inputs {
+        for target in input.targets() {
+            check_same_file_output(config, input, &target, report);
+        }
+    }
+}
+
+fn check_same_file_output(
+    config: &Config,
+    input: &InputConfig,
+    target: &TargetConfig,
+    report: &mut ValidationReport,
+) {
+    let output_name = &target.output;
+    let Some(output) = config.outputs.get(output_name) else {
+        return;
+    };
+    let container = config
+        .presets
+        .get(&target.preset)
+        .map_or(output.container.as_str(), |preset| {
+            preset.container_in(output)
+        });
+
+    let same_dir = canonical_or_raw(&output.path) == canonical_or_raw(&input.path);
+    let same_ext = input
+        .extensions
+        .iter()
+        .any(|ext| ext.eq_ignore_ascii_case(container));
+
+    if same_dir && same_ext {
+        let consequence = match output.on_same_file {
+            SameFilePolicy::Error => "those jobs will fail",
+            SameFilePolicy::Replace => "those sources will be replaced in place",
+        };
+        report.warning(format!(
+            "Input {} writes '{}' outputs into its own directory as .{}; files can map onto themselves and {}",
+            input.path.display(),
+            output_name,
+            container,
+            consequence
+        ));
+    }
+}
+
+/// Catch `hwaccel` settings that can't work together with the rest of the preset
+fn check_hwaccel(
+    name: &str,
+    preset: &PresetConfig,
+    hwaccel: &HwAccelConfig,
+    report: &mut ValidationReport,
+) {
+    match (hwaccel.kind, hwaccel.device.as_deref()) {
+        (HwAccelKind::Nvenc, Some(device)) if device.parse::<u32>().is_err() => {
+            report.error(format!(
+                "hwaccel device of preset '{}' must be a GPU index for nvenc, got '{}'",
+                name, device
+            ));
+        }
+        (HwAccelKind::Videotoolbox, Some(_)) => report.warning(format!(
+            "Preset '{}' sets an hwaccel device, which videotoolbox doesn't use",
+            name
+        )),
+        _ => {}
+    }
+    if hwaccel.kind == HwAccelKind::Vaapi && preset.pixel_format.is_some() {
+        report.warning(format!(
+            "Preset '{}' sets pixel_format, which can't convert vaapi frames on the GPU",
+            name
+        ));
+    }
+    if preset
+        .input_options
+        .iter()
+        .any(|o| o.starts_with("-hwaccel"))
+    {
+        report.warning(format!(
+            "Preset '{}' has hwaccel and also -hwaccel input_options; drop the input_options",
+            name
+        ));
+    }
+}
+
+/// Catch `streams` selectors ffmpeg can't take, or that other settings override
+fn check_streams(
+    name: &str,
+    preset: &PresetConfig,
+    streams: &StreamsConfig,
+    report: &mut ValidationReport,
+) {
+    for language in &streams.audio_languages {
+        if language.is_empty() || !language.chars().all(|c| c.is_ascii_alphanumeric()) {
+            report.error(format!(
+                "audio_languages of preset '{}' must be language codes like eng, got '{}'",
+                name, language
+            ));
+        }
+    }
+    if streams.map_all && !streams.audio_languages.is_empty() {
+        report.warning(format!(
+            "Preset '{}' sets map_all, which keeps every audio stream, so audio_languages is ignored",
+            name
+        ));
+    }
+    if preset.extra_options.contains("-map") {
+        report.warning(format!(
+            "Preset '{}' has -map in extra_options, which replaces its streams selection",
+            name
+        ));
+    }
+}
+
+/// Catch loudness targets loudnorm refuses, and audio it can't get at
+fn check_normalize_audio(
+    name: &str,
+    preset: &PresetConfig,
+    normalize: &NormalizeAudioConfig,
+    report: &mut ValidationReport,
+) {
+    if !(-70.0..=-5.0).contains(&normalize.target_lufs) {
+        report.error(format!(
+            "target_lufs of preset '{}' must be between -70 and -5, got {}",
+            name, normalize.target_lufs
+        ));
+    }
+    if !(-9.0..=0.0).contains(&normalize.true_peak) {
+        report.error(format!(
+            "true_peak of preset '{}' must be between -9 and 0, got {}",
+            name, normalize.true_peak
+        ));
+    }
+    if preset.audio_codec.as_deref() == Some("copy") {
+        report.error(format!(
+            "Preset '{}' copies its audio, which normalize_audio can't change; set an audio_codec",
+            name
+        ));
+    }
+}
+
+/// Catch `crf` and `encoder_preset` values the encoder refuses, and the same settings given
+/// again in `extra_options`, where they win
+fn check_quality(name: &str, preset: &PresetConfig, report: &mut ValidationReport) {
+    let encoder = preset.video_encoder().filter(|_| !preset.is_remux());
+    if let (Some(crf), Some(encoder)) = (preset.crf, &encoder) {
+        match quality_option(encoder) {
+            Some((_, max)) if crf > max => report.error(format!(
+                "crf of preset '{}' must be between 0 and {} for {}, got {}",
+                name, max, encoder, crf
+            )),
+            Some((flag, _)) if preset.extra_options.contains(flag) => report.warning(format!(
+                "Preset '{}' sets crf and {} in extra_options, which wins; drop one",
+                name, flag
+            )),
+            Some(_) => {}
+            None => report.error(format!(
+                "Preset '{}' sets crf, which {} has no equivalent of",
+                name, encoder
+            )),
+        }
+    }
+    if let (Some(speed), Some(encoder)) = (&preset.encoder_preset, &encoder) {
+        match encoder_presets(encoder) {
+            Some(&[]) => report.error(format!(
+                "Preset '{}' sets encoder_preset, which {} has no equivalent of",
+                name, encoder
+            )),
+            Some(known) if !known.contains(&speed.as_str()) => report.error(format!(
+                "encoder_preset of preset '{}' is '{}', which {} doesn't know; expected one of {}",
+                name,
+                speed,
+                encoder,
+                known.join(", ")
+            )),
+            _ => {}
+        }
+        if preset.extra_options.contains("-preset") {
+            report.warning(format!(
+                "Preset '{}' sets encoder_preset and -preset in extra_options, which wins; drop one",
+                name
+            ));
+        }
+    }
+}
+
+/// Catch a second filter chain in `extra_options`, of which ffmpeg would silently use only
+/// one, and filters on streams that are copied
+fn check_filters(name: &str, preset: &PresetConfig, report: &mut ValidationReport) {
+    for (kind, flags, setting) in [
+        ("video", ["-vf", "-filter:v"], "video_filters"),
+        ("audio", ["-af", "-filter:a"], "audio_filters"),
+    ] {
+        for flag in flags {
+            if preset.extra_options.contains(flag) {
+                report.error(format!(
+                    "Preset '{}' has {} in extra_options, which clashes with the {} filters sstc builds; move it to {}",
+                    name, flag, kind, setting
+                ));
+            }
+        }
+    }
+    for (setting, filters) in [
+        ("video_filters", &preset.video_filters),
+        ("audio_filters", &preset.audio_filters),
+    ] {
+        if filters.iter().any(|filter| filter.trim().is_empty()) {
+            report.error(format!(
+                "{} of preset '{}' has an empty filter",
+                setting, name
+            ));
+        }
+    }
+    if !preset.video_filters.is_empty() && preset.video_codec.as_deref() == Some("copy") {
+        report.error(format!(
+            "Preset '{}' copies its video, which video_filters can't change; set a video_codec",
+            name
+        ));
+    }
+    if !preset.audio_filters.is_empty() && preset.audio_codec.as_deref() == Some("copy") {
+        report.error(format!(
+            "Preset '{}' copies its audio, which audio_filters can't change; set an audio_codec",
+            name
+        ));
+    }
+}
+
+/// Catch `auto_crop` settings cropdetect can't take, or that nothing uses
+fn check_auto_crop(name: &str, preset: &PresetConfig, report: &mut ValidationReport) {
+    if preset.auto_crop != Some(true) {
+        if preset.crop_limit.is_some() || preset.crop_sample_duration.is_some() {
+            report.warning(format!(
+                "Preset '{}' sets crop_limit or crop_sample_duration, which only auto_crop uses",
+                name
+            ));
+        }
+        return;
+    }
+    if preset.crop_limit.is_some_and(|limit| limit > 255) {
+        report.error(format!(
+            "crop_limit of preset '{}' must be between 0 and 255",
+            name
+        ));
+    }
+    if preset.crop_sample_duration.is_some_and(|d| d.is_zero()) {
+        report.error(format!(
+            "crop_sample_duration of preset '{}' must be more than 0",
+            name
+        ));
+    }
+    if preset.hwaccel.as_ref().map(|h| h.kind) == Some(HwAccelKind::Vaapi) {
+        report.error(format!(
+            "Preset '{}' can't auto_crop with vaapi, the frames never leave the GPU",
+            name
+        ));
+    }
+}
+
+/// Catch tone mapping the rest of the preset undoes or can't run
+fn check_tonemap(name: &str, preset: &PresetConfig, report: &mut ValidationReport) {
+    if preset.tonemap.unwrap_or_default() == TonemapMode::Off {
+        return;
+    }
+    if preset.hwaccel.as_ref().map(|h| h.kind) == Some(HwAccelKind::Vaapi) {
+        report.error(format!(
+            "Preset '{}' can't tonemap with vaapi, zscale only takes frames in memory",
+            name
+        ));
+    }
+    if preset
+        .pixel_format
+        .as_deref()
+        .is_some_and(|format| format.contains("10") || format.contains("12"))
+    {
+        report.warning(format!(
+            "Preset '{}' tone maps to 8-bit SDR but sets pixel_format {}",
+            name,
+            preset.pixel_format.as_deref().unwrap_or_default()
+        ));
+    }
+}
+
+/// Error on a thread count of 0, warn on one larger than the cores of this machine
+fn check_threads(name: &str, preset: &PresetConfig, report: &mut ValidationReport) {
+    let Some(Threads::Count(count)) = preset.threads else {
+        return;
+    };
+    if count == 0 {
+        report.error(format!(
+            "threads of preset '{}' must be at least 1, or auto",
+            name
+        ));
+    } else if let Ok(cores) = std::thread::available_parallelism() {
+        if count as usize > cores.get() {
+            report.warning(format!(
+                "Preset '{}' sets threads to {}, more than the {} cores of this machine",
+                name, count, cores
+            ));
+        }
+    }
+}
+
+/// Error on a max_size_ratio that would stop every encode, warn on one nothing uses
+fn check_size_ratio(name: &str, preset: &PresetConfig, report: &mut ValidationReport) {
+    let Some(ratio) = preset.max_size_ratio else {
+        return;
+    };
+    if !ratio.is_finite() || ratio <= 0.0 {
+        report.error(format!(
+            "max_size_ratio of preset '{}' must be more than 0, got {}",
+            name, ratio
+        ));
+    } else if preset.if_larger_than_source.is_none() {
+        report.warning(format!(
+            "Preset '{}' sets max_size_ratio without if_larger_than_source, so it is not used",
+            name
+        ));
+    }
+}
+
+/// Error on a max_speed_ratio that would kill every job, or none
+fn check_speed_ratio(ratio: f64, owner: &str, report: &mut ValidationReport) {
+    if !ratio.is_finite() || ratio <= 0.0 {
+        report.error(format!(
+            "max_speed_ratio{} must be more than 0, got {}",
+            owner, ratio
+        ));
+    }
+}
+
+/// Error on niceness and I/O levels the system doesn't have, warn on settings that do nothing
+fn check_process_priority(priority: &ProcessPriority, owner: &str, report: &mut ValidationReport) {
+    if !(-20..=19).contains(&priority.nice) {
+        report.error(format!(
+            "process_priority.nice{} must be between -20 and 19, got {}",
+            owner, priority.nice
+        ));
+    } else if priority.nice < 0 {
+        report.warning(format!(
+            "process_priority.nice{} is below 0, which only works when sstc runs as root",
+            owner
+        ));
+    }
+    if let Some(level) = priority.ionice_level {
+        if level > 7 {
+            report.error(format!(
+                "process_priority.ionice_level{} must be between 0 and 7, got {}",
+                owner, level
+            ));
+        } else if priority.ionice_class != Some(IoniceClass::BestEffort) {
+            report.warning(format!(
+                "process_priority{} sets ionice_level without ionice_class: best_effort, so it is not used",
+                owner
+            ));
+        }
+    }
+}
+
+/// Error on a progress_interval outside the range ffmpeg's reporting is useful in
+fn check_progress_interval(interval: ProgressInterval, owner: &str, report: &mut ValidationReport) {
+    let Some(period) = interval.period() else {
+        return;
+    };
+    if !(ProgressInterval::MIN..=ProgressInterval::MAX).contains(&period) {
+        report.error(format!(
+            "progress_interval {}{} must be between {} and {}, or off",
+            interval,
+            owner,
+            HumanDuration(ProgressInterval::MIN),
+            HumanDuration(ProgressInterval::MAX)
+        ));
+    }
+}
+
+/// Warn about presets whose codecs the container they end up in can't hold, by the usual
+/// muxer support; odd but working pairs are left alone
+fn check_codec_containers(config: &Config, report: &mut ValidationReport) {
+    let mut pairs: Vec<(&String, String)> = config
+        .inputs
+        .iter()
+        .flat_map(InputConfig::targets)
+        .filter_map(|target| {
+            let (name, preset) = config.presets.get_key_value(&target.preset)?;
+            let output = config.outputs.get(&target.output)?;
+            Some((name, preset.container_in(output).to_ascii_lowercase()))
+        })
+        .collect();
+    pairs.sort();
+    pairs.dedup();
+
+    for (name, container) in pairs {
+        let preset = &config.presets[name];
+        if preset.subtitles == Some(SubtitlePolicy::Copy)
+            && !preset.is_audio_only()
+            && !container_holds_subtitles(&container)
+        {
+            report.warning(format!(
+                "Preset '{}' copies subtitles into .{}, which can't hold them",
+                name, container
+            ));
+        }
+        if preset.preserve_chapters == Some(true) && !container_holds_chapters(&container) {
+            report.warning(format!(
+                "Preset '{}' preserves chapters, which .{} can't hold, so they are dropped",
+                name, container
+            ));
+        }
+        if preset
+            .streams
+            .as_ref()
+            .is_some_and(|streams| streams.keep_attachments == Some(true))
+            && !container_holds_attachments(&container)
+        {
+            report.warning(format!(
+                "Preset '{}' keeps attachments, which .{} can't hold, so they are dropped",
+                name, container
+            ));
+        }
+        for encoder in [preset.video_encoder(), preset.audio_codec.clone()]
+            .into_iter()
+            .flatten()
+        {
+            let Some(codec) = codec_family(&encoder) else {
+                continue;
+            };
+            if !container_holds(&container, codec) {
+                report.warning(format!(
+                    "Preset '{}' writes {} ({}) into .{}, which doesn't support it",
+                    name, codec, encoder, container
+                ));
+            }
+        }
+    }
+}
+
+/// Codec an ffmpeg encoder produces, for the ones containers are picky about
+fn codec_family(encoder: &str) -> Option<&'static str> {
+    let encoder = encoder.to_ascii_lowercase();
+    let family = if encoder.contains("265") || encoder.contains("hevc") {
+        "hevc"
+    } else if encoder.contains("264") {
+        "h264"
+    } else if encoder.contains("av1") || encoder.contains("aom") || encoder == "librav1e" {
+        "av1"
+    } else if encoder.contains("vp9") {
+        "vp9"
+    } else if encoder.contains("vp8") || encoder == "libvpx" {
+        "vp8"
+    } else if encoder.contains("aac") {
+        "aac"
+    } else if encoder.contains("opus") {
+        "opus"
+    } else if encoder.contains("vorbis") {
+        "vorbis"
+    } else {
+        return None;
+    };
+    Some(family)
+}
+
+/// The option an encoder takes a constant quality level in, and the highest level it accepts
+pub fn quality_option(encoder: &str) -> Option<(&'static str, u8)> {
+    match encoder {
+        "libx264" | "libx265" => Some(("-crf", 51)),
+        "libsvtav1" | "libaom-av1" | "libvpx-vp9" | "libvpx" => Some(("-crf", 63)),
+        _ if encoder.ends_with("_nvenc") => Some(("-cq", 51)),
+        _ if encoder.ends_with("_vaapi") => Some(("-qp", 51)),
+        _ if encoder.ends_with("_qsv") => Some(("-global_quality", 51)),
+        _ => None,
+    }
+}
+
+/// Speed presets an encoder knows, empty for encoders without any; `None` when sstc doesn't
+/// know the encoder
+fn encoder_presets(encoder: &str) -> Option<&'static [&'static str]> {
+    const X26X: &[&str] = &[
+        "ultrafast",
+        "superfast",
+        "veryfast",
+        "faster",
+        "fast",
+        "medium",
+        "slow",
+        "slower",
+        "veryslow",
+        "placebo",
+    ];
+    const NVENC: &[&str] = &[
+        "p1",
+        "p2",
+        "p3",
+        "p4",
+        "p5",
+        "p6",
+        "p7",
+        "default",
+        "slow",
+        "medium",
+        "fast",
+        "hp",
+        "hq",
+        "bd",
+        "ll",
+        "llhq",
+        "llhp",
+        "lossless",
+        "losslesshp",
+    ];
+    const QSV: &[&str] = &[
+        "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow",
+    ];
+    const SVT_AV1: &[&str] = &[
+        "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13",
+    ];
+    match encoder {
+        "libx264" | "libx265" => Some(X26X),
+        "libsvtav1" => Some(SVT_AV1),
+        "libaom-av1" | "libvpx-vp9" | "libvpx" => Some(&[]),
+        _ if encoder.ends_with("_nvenc") => Some(NVENC),
+        _ if encoder.ends_with("_qsv") => Some(QSV),
+        _ if encoder.ends_with("_vaapi") || encoder.ends_with("_videotoolbox") => Some(&[]),
+        _ => None,
+    }
+}
+
+/// Chapters go nowhere in these; ffmpeg drops them silently
+pub fn container_holds_chapters(container: &str) -> bool {
+    !matches!(container, "webm" | "avi" | "flv" | "wav" | "ts")
+}
+
+/// Only Matroska carries attachments; other muxers refuse the whole output when given one
+pub fn container_holds_attachments(container: &str) -> bool {
+    matches!(container, "mkv" | "mka")
+}
+
+fn container_holds_subtitles(container: &str) -> bool {
+    !matches!(
+        container,
+        "webm" | "avi" | "m4a" | "mp3" | "opus" | "ogg" | "flv" | "wav"
+    )
+}
+
+fn container_holds(container: &str, codec: &str) -> bool {
+    match container {
+        "webm" => matches!(codec, "vp8" | "vp9" | "av1" | "opus" | "vorbis"),
+        "avi" => !matches!(codec, "hevc" | "av1" | "opus"),
+        "mp4" | "m4v" | "mov" => !matches!(codec, "vp8" | "vorbis"),
+        "m4a" => codec == "aac",
+        "opus" => codec == "opus",
+        "ogg" => matches!(codec, "opus" | "vorbis"),
+        // Known families are all other codecs; libmp3lame has none
+        "mp3" => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::PresetGenerator;
+
+    const FORMATS: [&str; 3] = ["yaml", "toml", "json"];
+
+    /// Everything a config says, in a form that compares regardless of map order
+    fn contents(config: &Config) -> serde_json::Value {
+        serde_json::to_value(config).unwrap()
+    }
+
+    #[test]
+    fn example_config_round_trips_in_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("config.yaml");
+        PresetGenerator::save_example_config(&yaml).unwrap();
+        let example = read_config(&yaml).unwrap();
+        assert!(!example.presets.is_empty());
+
+        for format in FORMATS {
+            let generated = dir.path().join(format!("generated.{}", format));
+            PresetGenerator::save_example_config(&generated).unwrap();
+            let read = read_config(&generated).unwrap();
+            assert_eq!(contents(&read), contents(&example), "{}", format);
+
+            // Written back as read, with nothing lost or reshaped
+            let rewritten = dir.path().join(format!("rewritten.{}", format));
+            write_config(&rewritten, &read).unwrap();
+            let reread = read_config(&rewritten).unwrap();
+            assert_eq!(contents(&reread), contents(&example), "{}", format);
+            for (name, preset) in &example.presets {
+                assert_eq!(
+                    reread.presets[name].extra_options, preset.extra_options,
+                    "{} in {}",
+                    name, format
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn extra_options_round_trip_in_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("config.yaml");
+        std::fs::write(
+            &source,
+            "inputs: []
+outputs: {}
+presets:
+  p:
+    extra_options:
+      - -map: '0:v'
+      - -map: '0:a:0'
+      - -an
+      - -sn:
+      - -x265-params: 'keyint=60:min-keyint=60'
+      - -metadata: 'title=a: b'
+",
+        )
+        .unwrap();
+        let expected = read_config(&source).unwrap().presets["p"]
+            .extra_options
+            .clone();
+        assert_eq!(
+            expected.iter().collect::<Vec<_>>(),
+            [
+                ("-map", Some("0:v")),
+                ("-map", Some("0:a:0")),
+                ("-an", None),
+                ("-sn", None),
+                ("-x265-params", Some("keyint=60:min-keyint=60")),
+                ("-metadata", Some("title=a: b")),
+            ]
+        );
+
+        let mut config = read_config(&source).unwrap();
+        for format in FORMATS {
+            let path = dir.path().join(format!("round-trip.{}", format));
+            write_config(&path, &config).unwrap();
+            config = read_config(&path).unwrap();
+            assert_eq!(config.presets["p"].extra_options, expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn legacy_extra_options_map_is_read_in_order_and_written_as_a_list() {
+        let dir = tempfile::tempdir().unwrap();
+        for (format, text) in [
+            (
+                "yaml",
+                "inputs: []\noutputs: {}\npresets:\n  p:\n    extra_options:\n      -tune: film\n      -an:\n      -movflags: +faststart\n",
+            ),
+            (
+                "toml",
+                "inputs = []\noutputs = {}\n\n[presets.p.extra_options]\n-tune = \"film\"\n-movflags = \"+faststart\"\n",
+            ),
+            (
+                "json",
+                r#"{"inputs": [], "outputs": {}, "presets": {"p": {"extra_options": {"-tune": "film", "-movflags": "+faststart"}}}}"#,
+            ),
+        ] {
+            let path = dir.path().join(format!("legacy.{}", format));
+            std::fs::write(&path, text).unwrap();
+            let config = read_config(&path).unwrap();
+            let options = &config.presets["p"].extra_options;
+            assert!(options.is_legacy_map(), "{}", format);
+            assert_eq!(options.get("-tune"), Some("film"), "{}", format);
+            assert_eq!(options.iter().last(), Some(("-movflags", Some("+faststart"))));
+
+            write_config(&path, &config).unwrap();
+            let rewritten = read_config(&path).unwrap();
+            let rewritten = &rewritten.presets["p"].extra_options;
+            assert!(!rewritten.is_legacy_map(), "{}", format);
+            assert_eq!(
+                rewritten.iter().collect::<Vec<_>>(),
+                options.iter().collect::<Vec<_>>(),
+                "{}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn parse_errors_give_the_line_and_column_in_every_format() {
+        for (format, text, location) in [
+            (
+                ConfigFormat::Yaml,
+                "inputs: []\nmax_parallel_jobs: lots\n",
+                "in max_parallel_jobs at line 2, column 20",
+            ),
+            (
+                ConfigFormat::Toml,
+                "inputs = []\nmax_parallel_jobs = \"lots\"\n",
+                "in max_parallel_jobs at line 2, column 21",
+            ),
+            (
+                ConfigFormat::Json,
+                "{\n  \"inputs\": [],\n  \"max_parallel_jobs\": \"lots\"\n}\n",
+                "in max_parallel_jobs at line 3, column 29",
+            ),
+        ] {
+            let error = format!("{:#}", format.parse::<Config>(text).unwrap_err());
+            assert!(error.contains(location), "{:?}: {}", format, error);
+        }
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        for (name, format) in [
+            ("config.yaml", ConfigFormat::Yaml),
+            ("config.yml", ConfigFormat::Yaml),
+            ("config.TOML", ConfigFormat::Toml),
+            ("config.json", ConfigFormat::Json),
+            ("config", ConfigFormat::Yaml),
+        ] {
+            assert_eq!(ConfigFormat::from_path(Path::new(name)), format, "{}", name);
+        }
+    }
+
+    fn options_of(preset: &PresetConfig) -> Vec<(&str, Option<&str>)> {
+        preset.extra_options.iter().collect()
+    }
+
+    #[test]
+    fn repeated_flags_survive_yaml() {
+        let mut config = Config::default();
+        PresetGenerator::generate_example_presets(&mut config).unwrap();
+        let gopro = &config.presets["gopro_compact"];
+        let maps: Vec<_> = options_of(gopro)
+            .into_iter()
+            .filter(|(flag, _)| *flag == "-map")
+            .collect();
+        assert_eq!(
+            maps,
+            [
+                ("-map", Some("0:v")),
+                ("-map", Some("0:a")),
+                ("-map", Some("0:m:handler_name:GoPro MET")),
+            ]
+        );
+
+        let yaml = serde_yaml::to_string(gopro).unwrap();
+        let read: PresetConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(read.extra_options, gopro.extra_options, "{}", yaml);
+        // And written back the same way
+        assert_eq!(serde_yaml::to_string(&read).unwrap(), yaml);
+    }
+
+    #[test]
+    fn extra_options_entries_take_one_flag_each() {
+        let error = serde_yaml::from_str::<PresetConfig>(
+            "extra_options:\n  - {-map: '0:v', -tune: film}\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.contains("must be a flag or a single `flag: value` pair, got 2 keys"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn extended_presets_replace_the_flags_they_set() {
+        let mut presets: HashMap<String, PresetConfig> = serde_yaml::from_str(
+            "
+base:
+  extra_options:
+    - -map: '0:v'
+    - -map: '0:a'
+    - -tag:v: hvc1
+    - -an
+child:
+  extends: base
+  extra_options:
+    - -map: '0:v:0'
+    - -map: '0:s'
+    - -map: '0:a:1'
+",
+        )
+        .unwrap();
+        resolve_preset_inheritance(&mut presets).unwrap();
+        assert_eq!(
+            options_of(&presets["child"]),
+            [
+                ("-tag:v", Some("hvc1")),
+                ("-an", None),
+                ("-map", Some("0:v:0")),
+                ("-map", Some("0:s")),
+                ("-map", Some("0:a:1")),
+            ]
+        );
+    }
+
+    /// The presets in `yaml` with their inheritance resolved
+    fn resolved(yaml: &str) -> Result<HashMap<String, PresetConfig>> {
+        let mut presets: HashMap<String, PresetConfig> = serde_yaml::from_str(yaml).unwrap();
+        resolve_preset_inheritance(&mut presets).map(|_| presets)
+    }
+
+    #[test]
+    fn broken_inheritance_is_refused() {
+        for (yaml, expected) in [
+            (
+                "a: {extends: b}\nb: {extends: a}\n",
+                "Preset inheritance cycle: a -> b -> a",
+            ),
+            ("a: {extends: a}\n", "Preset inheritance cycle: a -> a"),
+            (
+                "base: {video_codec: libx264}\nchild: {extends: bsae}\n",
+                "Preset 'child' extends 'bsae', which does not exist, did you mean 'base'?",
+            ),
+            (
+                "child: {extends: nothing_like_it}\n",
+                "Preset 'child' extends 'nothing_like_it', which does not exist",
+            ),
+        ] {
+            let error = resolved(yaml).unwrap_err().to_string();
+            assert_eq!(error, expected, "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn inheritance_follows_a_chain_with_the_nearest_setting_winning() {
+        let presets = resolved(
+            "
+grandparent:
+  video_codec: libx264
+  audio_codec: aac
+  crf: 28
+  encoder_preset: slow
+parent:
+  extends: grandparent
+  crf: 23
+  audio_bitrate: 128k
+child:
+  extends: parent
+  crf: 18
+  audio_codec: libopus
+",
+        )
+        .unwrap();
+        let child = &presets["child"];
+        assert_eq!(child.extends, None);
+        assert_eq!(child.video_codec.as_deref(), Some("libx264"));
+        assert_eq!(child.audio_codec.as_deref(), Some("libopus"));
+        assert_eq!(child.crf, Some(18));
+        assert_eq!(child.encoder_preset.as_deref(), Some("slow"));
+        assert_eq!(child.audio_bitrate.as_deref(), Some("128k"));
+
+        let parent = &presets["parent"];
+        assert_eq!(parent.crf, Some(23));
+        assert_eq!(parent.audio_codec.as_deref(), Some("aac"));
+        assert_eq!(presets["grandparent"].crf, Some(28));
+    }
+
+    #[test]
+    fn shared_presets_are_merged_under_the_inline_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["conf/shared", "in", "out"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        std::fs::write(
+            dir.path().join("conf/shared/first.yaml"),
+            "presets:\n  shared_only: {video_codec: libx265}\n  both_files: {video_codec: libx264, crf: 20}\n  also_inline: {video_codec: libvpx-vp9}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("conf/shared/second.yaml"),
+            "presets:\n  both_files: {video_codec: libx264, crf: 30}\n",
+        )
+        .unwrap();
+        let path = dir.path().join("conf/config.yaml");
+        std::fs::write(
+            &path,
+            format!(
+                "presets_file: [shared/first.yaml, shared/second.yaml]\ninputs:\n  - path: {dir}/in\n    extensions: [mp4]\n    preset: shared_only\n    output: o\noutputs:\n  o:\n    path: {dir}/out\n    filename_template: '{{filename}}'\n    container: mkv\npresets:\n  also_inline: {{video_codec: libx264}}\n",
+                dir = dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        let config = prepare_config(&path).unwrap();
+        assert_eq!(
+            config.presets["shared_only"].video_codec.as_deref(),
+            Some("libx265")
+        );
+        assert_eq!(config.presets["both_files"].crf, Some(30));
+        assert_eq!(
+            config.presets["also_inline"].video_codec.as_deref(),
+            Some("libx264")
+        );
+        let mut shared: Vec<_> = config.shared_presets.iter().map(String::as_str).collect();
+        shared.sort();
+        assert_eq!(shared, ["both_files", "shared_only"]);
+
+        // The input's shared preset is found, and only the unused inline preset is reported
+        let report = check_config(&path, false);
+        let findings: Vec<_> = report.findings.iter().map(|f| f.message.as_str()).collect();
+        assert_eq!(findings, ["Preset 'also_inline' is not used by any input"]);
+    }
+
+    #[test]
+    fn map_form_is_deprecated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        std::fs::write(
+            &path,
+            "inputs: []\noutputs: {}\npresets:\n  old:\n    extra_options:\n      -tune: film\n  new:\n    extra_options:\n      - -tune: film\n",
+        )
+        .unwrap();
+        let report = check_config(&path, false);
+        let warnings: Vec<_> = report
+            .findings
+            .iter()
+            .filter(|f| f.message.contains("extra_options as a map"))
+            .map(|f| (f.severity, f.message.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [(
+                Severity::Warning,
+                "Preset 'old' writes extra_options as a map, which is deprecated; use a list of `- flag: value` entries"
+            )]
+        );
+    }
+
+    /// A config in `dir` whose only problem is a preset no input uses, a warning
+    fn config_with_a_warning(dir: &Path, extra: &str) -> PathBuf {
+        // Existing, so checking doesn't warn that they will be created
+        std::fs::create_dir_all(dir.join("in")).unwrap();
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        let path = dir.join("config.yaml");
+        std::fs::write(
+            &path,
+            format!(
+                "inputs:
+  - path: {dir}/in
+    extensions: [mp4]
+    preset: p
+    output: o
+outputs:
+  o:
+    path: {dir}/out
+    filename_template: \"{{filename}}\"
+    container: mkv
+presets:
+  p:
+    video_codec: libx264
+  unused:
+    video_codec: libx265
+{extra}",
+                dir = dir.display(),
+                extra = extra
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    fn load_failure(path: &Path, strict: bool) -> ValidationFailed {
+        let error = load_config(path, strict).unwrap_err();
+        match error.downcast::<ValidationFailed>() {
+            Ok(failed) => failed,
+            Err(e) => panic!("not a validation failure: {:#}", e),
+        }
+    }
+
+    #[test]
+    fn strict_mode_escalates_warnings_to_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_with_a_warning(dir.path(), "");
+
+        let report = check_config(&path, false);
+        assert!(report.has_warnings() && !report.has_errors());
+        assert!(!report.is_failure());
+        assert!(load_config(&path, false).is_ok());
+
+        assert!(check_config(&path, true).is_failure());
+        let failed = load_failure(&path, true);
+        assert_eq!(failed.exit_code(), 3);
+        assert_eq!(
+            failed.to_string(),
+            "Config validation failed due to strict mode: 1 warning(s) treated as errors"
+        );
+
+        // The config can ask for strictness itself
+        let path = config_with_a_warning(dir.path(), "strict: true\n");
+        assert_eq!(load_failure(&path, false).exit_code(), 3);
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_with_a_warning(dir.path(), "");
+        let broken = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("    extensions: [mp4]\n", "    exclude: ['[']\n");
+        std::fs::write(&path, broken).unwrap();
+
+        let report = check_config(&path, false);
+        assert_eq!(report.findings.len(), 3, "{:?}", report.findings);
+        let findings: Vec<_> = report
+            .findings
+            .iter()
+            .map(|f| (f.severity, f.message.as_str()))
+            .collect();
+        let input = dir.path().join("in");
+        assert!(findings.contains(&(
+            Severity::Error,
+            &*format!(
+                "Input '{}' needs extensions or patterns to match files",
+                input.display()
+            )
+        )));
+        assert!(findings.iter().any(|(severity, message)| {
+            *severity == Severity::Error && message.starts_with("Invalid pattern '['")
+        }));
+        assert!(findings.contains(&(
+            Severity::Warning,
+            "Preset 'unused' is not used by any input"
+        )));
+
+        // Hard errors fail with 2, strict or not
+        for strict in [false, true] {
+            let failed = load_failure(&path, strict);
+            assert_eq!(failed.exit_code(), 2);
+            assert_eq!(failed.report.findings.len(), 3);
+            assert_eq!(
+                failed.to_string(),
+                "Config validation failed with 2 error(s) and 1 warning(s)"
+            );
+        }
+    }
+
+    /// Problems the config schema finds in `instance`
+    fn schema_errors(instance: &serde_json::Value) -> Vec<String> {
+        let schema: serde_json::Value = serde_json::from_str(&json_schema().unwrap()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        validator
+            .iter_errors(instance)
+            .map(|e| format!("{} at {}", e, e.instance_path))
+            .collect()
+    }
+
+    #[test]
+    fn schema_validates_the_generated_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        PresetGenerator::save_example_config(&path).unwrap();
+        let generated: serde_json::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(schema_errors(&generated), Vec::<String>::new());
+
+        // Including the older ways of writing things that are still read
+        let mut older = generated.clone();
+        older["presets"]["medium_h264"]["extra_options"] = serde_json::json!({"-tune": "film"});
+        older["drain_settle"] = serde_json::json!(30);
+        assert_eq!(schema_errors(&older), Vec::<String>::new());
+    }
+
+    #[test]
+    fn schema_rejects_what_the_config_does() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        PresetGenerator::save_example_config(&path).unwrap();
+        let generated: serde_json::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+        let mut unknown = generated.clone();
+        unknown["presets"]["medium_h264"]["video_codek"] = serde_json::json!("libx264");
+        let mut missing = generated.clone();
+        missing.as_object_mut().unwrap().remove("outputs");
+        let mut mistyped = generated.clone();
+        mistyped["max_parallel_jobs"] = serde_json::json!("lots");
+
+        for (broken, at) in [
+            (unknown, "/presets/medium_h264"),
+            (missing, ""),
+            (mistyped, "/max_parallel_jobs"),
+        ] {
+            let errors = schema_errors(&broken);
+            assert!(
+                errors.iter().any(|e| e.ends_with(&format!(" at {}", at))),
+                "{:?}",
+                errors
+            );
+            // serde agrees
+            assert!(serde_json::from_value::<Config>(broken).is_err());
+        }
+    }
+
+    #[test]
+    fn schema_carries_the_field_docs() {
+        let schema: serde_json::Value = serde_json::from_str(&json_schema().unwrap()).unwrap();
+        assert_eq!(schema["required"], serde_json::json!(["inputs", "outputs"]));
+        assert_eq!(schema["additionalProperties"], serde_json::json!(false));
+        assert!(schema["properties"]["input_options"]["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("ffmpeg options placed before `-i`"));
+    }
+
+    /// What `check` finds in preset `p` written as `yaml`
+    fn preset_findings(
+        check: fn(&str, &PresetConfig, &mut ValidationReport),
+        yaml: &str,
+    ) -> Vec<(Severity, String)> {
+        let mut report = ValidationReport::new(false);
+        check("p", &crate::test_support::preset(yaml), &mut report);
+        report
+            .findings
+            .into_iter()
+            .map(|finding| (finding.severity, finding.message))
+            .collect()
+    }
+
+    /// Check each preset of `cases` against the findings it should give
+    fn check_preset_table(
+        check: fn(&str, &PresetConfig, &mut ValidationReport),
+        cases: &[(&str, &[(Severity, &str)])],
+    ) {
+        for (yaml, expected) in cases {
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(severity, message)| (*severity, message.to_string()))
+                .collect();
+            assert_eq!(preset_findings(check, yaml), expected, "preset:\n{}", yaml);
+        }
+    }
+
+    #[test]
+    fn quality_settings_are_checked_against_the_encoder() {
+        use Severity::{Error, Warning};
+        check_preset_table(
+            check_quality,
+            &[
+                ("video_codec: libx264\ncrf: 51\nencoder_preset: veryslow", &[]),
+                ("video_codec: libsvtav1\ncrf: 63\nencoder_preset: '8'", &[]),
+                (
+                    "video_codec: libx264\ncrf: 52",
+                    &[(Error, "crf of preset 'p' must be between 0 and 51 for libx264, got 52")],
+                ),
+                (
+                    "video_codec: h264_nvenc\ncrf: 60",
+                    &[(Error, "crf of preset 'p' must be between 0 and 51 for h264_nvenc, got 60")],
+                ),
+                (
+                    "video_codec: mpeg4\ncrf: 20",
+                    &[(Error, "Preset 'p' sets crf, which mpeg4 has no equivalent of")],
+                ),
+                // Remuxing encodes nothing, so there is nothing to check
+                ("mode: remux\ncrf: 99", &[]),
+                (
+                    "video_codec: libx264\nencoder_preset: fastest",
+                    &[(Error, "encoder_preset of preset 'p' is 'fastest', which libx264 doesn't know; expected one of ultrafast, superfast, veryfast, faster, fast, medium, slow, slower, veryslow, placebo")],
+                ),
+                (
+                    "video_codec: libvpx-vp9\nencoder_preset: good",
+                    &[(Error, "Preset 'p' sets encoder_preset, which libvpx-vp9 has no equivalent of")],
+                ),
+                // Encoders sstc doesn't know may have any presets
+                ("video_codec: librav1e\nencoder_preset: whatever", &[]),
+                (
+                    "video_codec: libx264\ncrf: 20\nextra_options:\n  - -crf: '18'",
+                    &[(Warning, "Preset 'p' sets crf and -crf in extra_options, which wins; drop one")],
+                ),
+                (
+                    "video_codec: hevc_vaapi\ncrf: 20\nextra_options:\n  - -qp: '18'",
+                    &[(Warning, "Preset 'p' sets crf and -qp in extra_options, which wins; drop one")],
+                ),
+                (
+                    "video_codec: libx265\nencoder_preset: slow\nextra_options:\n  - -preset: fast",
+                    &[(Warning, "Preset 'p' sets encoder_preset and -preset in extra_options, which wins; drop one")],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn file_age_against_min_and_max_age() {
+        let input: InputConfig =
+            serde_yaml::from_str("path: /in\nextensions: [mp4]\nmin_age: 10m\nmax_age: 1d\n")
+                .unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let mins = |m: u64| Duration::from_secs(m * 60);
+        let too_old = || FileAge::TooOld("last modified more than max_age 1d ago".to_string());
+        for (modified, expected) in [
+            (now - mins(1), FileAge::TooNew(mins(9))),
+            (now - mins(10), FileAge::Ready),
+            (now - mins(60), FileAge::Ready),
+            (now - mins(24 * 60), FileAge::Ready),
+            (now - mins(24 * 60 + 1), too_old()),
+            // A modification time in the future counts as just now
+            (now + mins(5), FileAge::TooNew(mins(10))),
+        ] {
+            assert_eq!(input.file_age(modified, now), expected, "{:?}", modified);
+        }
+
+        let unlimited = InputConfig::default();
+        for modified in [now + mins(5), now, now - mins(1_000_000)] {
+            assert_eq!(unlimited.file_age(modified, now), FileAge::Ready);
+        }
+    }
 }
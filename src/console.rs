@@ -0,0 +1,192 @@
+use crate::progress::{short_duration, JobProgress};
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How often a job logs its progress in [`ProgressMode::Plain`]
+const PLAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// The bars of every running job, drawn together below the log on stderr
+pub fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()))
+}
+
+static MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+/// How running jobs show their progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// Live bars, redrawn in place
+    Bar,
+    /// A log line per job every 30s, for journals and container logs
+    Plain,
+    /// Nothing beyond the start and finish of each job
+    None,
+}
+
+/// Pick the progress mode; without this, bars are drawn only when stderr is a terminal
+pub fn set_progress_mode(mode: ProgressMode) {
+    let _ = MODE.set(mode);
+}
+
+pub fn progress_mode() -> ProgressMode {
+    *MODE.get_or_init(|| {
+        if io::stderr().is_terminal() {
+            ProgressMode::Bar
+        } else {
+            ProgressMode::Plain
+        }
+    })
+}
+
+/// Redraw the bars at most about once per `period`, the rate ffmpeg reports progress at
+pub fn set_refresh_interval(period: Duration) {
+    let hz = (1.0 / period.as_secs_f64()).clamp(1.0, 20.0) as u8;
+    bars().set_draw_target(ProgressDrawTarget::stderr_with_hz(hz));
+}
+
+/// Log output to stderr that clears the bars while a line is written, so neither tears the other
+pub fn log_writer() -> impl for<'a> MakeWriter<'a> {
+    || LogWriter
+}
+
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        bars().suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Progress display of one encode in the current [`ProgressMode`]: a bar on [`bars`], or a log
+/// line every [`PLAIN_PROGRESS_INTERVAL`]. The bar goes away when dropped.
+pub struct JobBar {
+    bar: ProgressBar,
+    multi: MultiProgress,
+    /// `{label} {filename} `, ahead of the stage in the prefix
+    prefix: String,
+    mode: ProgressMode,
+    last_logged: Instant,
+}
+
+impl JobBar {
+    pub fn new(
+        multi: &MultiProgress,
+        expected_duration: Option<f64>,
+        input_path: &Path,
+        label: &str,
+    ) -> Self {
+        let bar = match expected_duration {
+            // In milliseconds, so short sources move smoothly too
+            Some(duration) if duration > 0.0 => ProgressBar::new((duration * 1000.0).ceil() as u64)
+                .with_style(
+                    ProgressStyle::with_template(
+                        "{prefix}[{elapsed_precise}] {bar:40.cyan/blue} {msg}",
+                    )
+                    .unwrap(),
+                ),
+            _ => {
+                warn!("Could not get duration for {}", input_path.display());
+                spinner("transcoding")
+            }
+        };
+        Self::show(multi, bar, input_path, label)
+    }
+
+    /// A spinner instead of a bar, for jobs that finish too quickly for a bar to tell much
+    pub fn spinner(multi: &MultiProgress, input_path: &Path, label: &str, message: &str) -> Self {
+        Self::show(multi, spinner(message), input_path, label)
+    }
+
+    fn show(multi: &MultiProgress, bar: ProgressBar, input_path: &Path, label: &str) -> Self {
+        let name = input_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let mode = progress_mode();
+        let bar = if mode == ProgressMode::Bar {
+            multi.add(bar)
+        } else {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+            bar
+        };
+        let prefix = match label {
+            "" => format!("{} ", name),
+            label => format!("{} {} ", label, name),
+        };
+        bar.set_prefix(prefix.clone());
+
+        Self {
+            bar,
+            multi: multi.clone(),
+            prefix,
+            mode,
+            last_logged: Instant::now(),
+        }
+    }
+
+    /// Show an ffmpeg progress update, `position` being the milliseconds of output written
+    pub fn update(&mut self, progress: &JobProgress, position: Option<u64>) {
+        let message = progress.describe();
+        match position {
+            // Spinners have no length to move along, only a frame to advance
+            Some(_) if self.bar.length().is_none() => self.bar.tick(),
+            Some(position) => self.bar.set_position(position),
+            None => {}
+        }
+        if self.mode == ProgressMode::Plain
+            && !message.is_empty()
+            && self.last_logged.elapsed() >= PLAIN_PROGRESS_INTERVAL
+        {
+            self.last_logged = Instant::now();
+            info!("{}{}", self.bar.prefix(), message);
+        }
+        self.bar.set_message(message);
+    }
+
+    /// Show which step of the job is running, like `pass 1/2`
+    pub fn set_stage(&self, stage: &str) {
+        self.bar.set_prefix(format!("{}{} ", self.prefix, stage));
+    }
+
+    /// The encode is done
+    pub fn finish(&self) {
+        if self.mode == ProgressMode::Plain {
+            info!(
+                "{}finished in {}",
+                self.bar.prefix(),
+                short_duration(self.bar.elapsed().as_secs())
+            );
+        }
+    }
+}
+
+fn spinner(message: &str) -> ProgressBar {
+    ProgressBar::new_spinner()
+        .with_style(
+            ProgressStyle::with_template(
+                "{prefix}[{elapsed_precise}] {spinner} Processing... {msg}",
+            )
+            .unwrap(),
+        )
+        .with_message(message.to_string())
+}
+
+impl Drop for JobBar {
+    fn drop(&mut self) {
+        // Removed rather than finished, so a long-running service doesn't pile up bars
+        self.multi.remove(&self.bar);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::job::JobResult;
+use crate::transcoder::{CancelResult, ExpressStatus, Transcoder};
+use crate::units::HumanDuration;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// A command sent to the running service over `control_socket`, one JSON object per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Drop a source from the queue or stop its running job
+    Cancel { path: PathBuf },
+    /// Stop starting queued files
+    Pause,
+    /// Start queued files again after a pause
+    Resume,
+    /// Transcode a source right away, bypassing the queue. Answered when the job ends, or with
+    /// the job's id to ask after with [`ControlRequest::Job`] once `wait_timeout` passed.
+    Transcode {
+        path: PathBuf,
+        /// Preset to use for every target instead of the input's own
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        wait_timeout: Option<HumanDuration>,
+    },
+    /// How an express job started by [`ControlRequest::Transcode`] is doing
+    Job { id: u64 },
+}
+
+/// How long a [`ControlRequest::Transcode`] waits for its job when it gives no `wait_timeout`
+const DEFAULT_TRANSCODE_WAIT: Duration = Duration::from_secs(60);
+
+/// The service's answer to a [`ControlRequest`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    pub message: String,
+    /// Express job still running when the answer was given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+}
+
+impl ControlResponse {
+    fn ok(message: String) -> Self {
+        Self {
+            ok: true,
+            message,
+            job_id: None,
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Self {
+            ok: false,
+            message,
+            job_id: None,
+        }
+    }
+
+    fn running(id: u64, source: &Path) -> Self {
+        Self {
+            ok: true,
+            message: format!(
+                "Still transcoding {}, ask again with job {}",
+                source.display(),
+                id
+            ),
+            job_id: Some(id),
+        }
+    }
+
+    fn finished(source: &Path, result: &JobResult) -> Self {
+        match result {
+            Ok(records) => {
+                let outputs: Vec<String> = records
+                    .iter()
+                    .filter_map(|record| record.output.as_ref())
+                    .map(|output| output.display().to_string())
+                    .collect();
+                if outputs.is_empty() {
+                    Self::ok(format!("Nothing to do for {}", source.display()))
+                } else {
+                    Self::ok(format!(
+                        "Transcoded {} to {}",
+                        source.display(),
+                        outputs.join(", ")
+                    ))
+                }
+            }
+            Err(e) => Self::error(format!("Failed to transcode {}: {:#}", source.display(), e)),
+        }
+    }
+}
+
+/// Carry out a request on the running service
+pub async fn handle(transcoder: &Transcoder, request: ControlRequest) -> ControlResponse {
+    match request {
+        ControlRequest::Cancel { path } => match transcoder.cancel(&path).await {
+            CancelResult::Dequeued => {
+                ControlResponse::ok(format!("Removed {} from the queue", path.display()))
+            }
+            CancelResult::Stopped => {
+                ControlResponse::ok(format!("Stopped the running job of {}", path.display()))
+            }
+            CancelResult::NotFound => {
+                ControlResponse::error(format!("{} is neither queued nor running", path.display()))
+            }
+        },
+        ControlRequest::Pause => match transcoder.pause().await {
+            true => ControlResponse::ok("Paused".to_string()),
+            false => ControlResponse::ok("Already paused".to_string()),
+        },
+        ControlRequest::Resume => match transcoder.resume().await {
+            true => ControlResponse::ok("Resumed".to_string()),
+            false => ControlResponse::ok("Not paused".to_string()),
+        },
+        ControlRequest::Transcode {
+            path,
+            preset,
+            wait_timeout,
+        } => {
+            let job = match transcoder.express(&path, preset.as_deref()) {
+                Ok(job) => job,
+                Err(e) => return ControlResponse::error(format!("{:#}", e)),
+            };
+            let id = job.id;
+            let wait = wait_timeout.map_or(DEFAULT_TRANSCODE_WAIT, Duration::from);
+            // The job carries on when the wait runs out, so the connection needn't hang on it
+            match tokio::time::timeout(wait, job.result()).await {
+                Ok(result) => ControlResponse::finished(&path, &result),
+                Err(_) => ControlResponse::running(id, &path),
+            }
+        }
+        ControlRequest::Job { id } => match transcoder.express_status(id) {
+            Some(ExpressStatus::Running { source }) => ControlResponse::running(id, &source),
+            Some(ExpressStatus::Finished { source, result }) => {
+                ControlResponse::finished(&source, &result)
+            }
+            None => ControlResponse::error(format!("No express job {} is known", id)),
+        },
+    }
+}
+
+/// Pause on SIGUSR1 and resume on SIGUSR2, for as long as the service runs
+#[cfg(unix)]
+pub fn listen_for_pause_signals(transcoder: Transcoder) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut pauses = signal(SignalKind::user_defined1()).context("Failed to listen for SIGUSR1")?;
+    let mut resumes =
+        signal(SignalKind::user_defined2()).context("Failed to listen for SIGUSR2")?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = pauses.recv() => {
+                    debug!("Received SIGUSR1");
+                    transcoder.pause().await;
+                }
+                Some(()) = resumes.recv() => {
+                    debug!("Received SIGUSR2");
+                    transcoder.resume().await;
+                }
+                else => return,
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn listen_for_pause_signals(_transcoder: Transcoder) -> Result<()> {
+    Ok(())
+}
+
+/// The control socket of a running service, removed again when dropped
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl ControlSocket {
+    /// Listen on `path` and answer requests for as long as the socket is kept. A socket left
+    /// behind by a service that is gone is replaced; one another service listens on is not.
+    #[cfg(unix)]
+    pub fn bind(path: &Path, transcoder: Transcoder) -> Result<Self> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::UnixListener;
+
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(anyhow!(
+                    "Another sstc is already listening on control socket {}",
+                    path.display()
+                ));
+            }
+            std::fs::remove_file(path).context(format!(
+                "Failed to remove stale control socket: {}",
+                path.display()
+            ))?;
+        }
+        let listener = UnixListener::bind(path).context(format!(
+            "Failed to listen on control socket: {}",
+            path.display()
+        ))?;
+
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Control socket stopped accepting connections: {}", e);
+                        return;
+                    }
+                };
+                let transcoder = transcoder.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let response = match serde_json::from_str(&line) {
+                            Ok(request) => {
+                                debug!("Control request: {:?}", request);
+                                handle(&transcoder, request).await
+                            }
+                            Err(e) => ControlResponse::error(format!("Invalid request: {}", e)),
+                        };
+                        let Ok(mut reply) = serde_json::to_string(&response) else {
+                            return;
+                        };
+                        reply.push('\n');
+                        if writer.write_all(reply.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(path: &Path, _transcoder: Transcoder) -> Result<Self> {
+        Err(anyhow!(
+            "Control socket {} is only supported on Unix",
+            path.display()
+        ))
+    }
+}
+
+/// Send one request to the service listening on `path` and wait for its answer
+#[cfg(unix)]
+pub async fn send(path: &Path, request: &ControlRequest) -> Result<ControlResponse> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let stream = tokio::net::UnixStream::connect(path)
+        .await
+        .context(format!(
+            "Failed to connect to control socket {}, is sstc running?",
+            path.display()
+        ))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("sstc closed the control socket without answering"))?;
+    serde_json::from_str(&reply).context("Failed to parse the control response")
+}
+
+#[cfg(not(unix))]
+pub async fn send(path: &Path, _request: &ControlRequest) -> Result<ControlResponse> {
+    Err(anyhow!(
+        "Control socket {} is only supported on Unix",
+        path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Sandbox, BASIC_CONFIG};
+
+    #[tokio::test]
+    async fn cancel_over_the_socket() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(
+            sandbox.config(&BASIC_CONFIG.replace("presets:", "max_parallel_jobs: 1\npresets:")),
+        );
+        let socket_path = sandbox.path().join("sstc.sock");
+        let socket = ControlSocket::bind(&socket_path, transcoder.clone()).unwrap();
+        // A second service can't take the socket over
+        assert!(ControlSocket::bind(&socket_path, transcoder.clone()).is_err());
+
+        // The slow job holds the only slot, so the next file stays queued
+        transcoder
+            .process_file(&sandbox.file("in/slow.mp4"))
+            .await
+            .unwrap();
+        while transcoder.running_jobs() == 0 || transcoder.queued_files().await > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let source = sandbox.file("in/huge.mp4");
+        transcoder.process_file(&source).await.unwrap();
+
+        let cancel = ControlRequest::Cancel {
+            path: source.clone(),
+        };
+        let response = send(&socket_path, &cancel).await.unwrap();
+        assert!(response.ok, "{}", response.message);
+        assert_eq!(transcoder.queued_files().await, 0);
+        let response = send(&socket_path, &cancel).await.unwrap();
+        assert!(!response.ok);
+
+        transcoder.wait_until_idle().await;
+        assert!(!sandbox.path().join("out/huge.mkv").exists());
+        drop(socket);
+        assert!(!socket_path.exists());
+    }
+
+    #[tokio::test]
+    async fn transcode_answers_with_the_job_id_when_the_wait_runs_out() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let socket_path = sandbox.path().join("sstc.sock");
+        let _socket = ControlSocket::bind(&socket_path, transcoder.clone()).unwrap();
+        let source = sandbox.file("in/slow.mp4");
+
+        let request = format!(
+            r#"{{"command": "transcode", "path": {:?}, "wait_timeout": "10ms"}}"#,
+            source
+        );
+        let response = handle(&transcoder, serde_json::from_str(&request).unwrap()).await;
+        assert!(response.ok, "{}", response.message);
+        let id = response.job_id.expect("the job outlasts the wait");
+
+        // The job carries on and its result can be asked for until it is there
+        let job = ControlRequest::Job { id };
+        let response = loop {
+            let response = send(&socket_path, &job).await.unwrap();
+            if response.job_id.is_none() {
+                break response;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        };
+        assert!(response.ok, "{}", response.message);
+        assert!(
+            response.message.contains("out/slow.mkv"),
+            "{}",
+            response.message
+        );
+        assert!(
+            !send(&socket_path, &ControlRequest::Job { id: id + 1 })
+                .await
+                .unwrap()
+                .ok
+        );
+
+        // Done within the wait, the answer is the result itself
+        let request = ControlRequest::Transcode {
+            path: sandbox.file("in/clip.mp4"),
+            preset: None,
+            wait_timeout: None,
+        };
+        let response = send(&socket_path, &request).await.unwrap();
+        assert!(response.ok, "{}", response.message);
+        assert_eq!(response.job_id, None);
+        assert!(
+            response.message.contains("out/clip.mkv"),
+            "{}",
+            response.message
+        );
+    }
+}
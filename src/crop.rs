@@ -0,0 +1,229 @@
+use crate::config::ProcessPriority;
+use crate::priority;
+use crate::tools;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+/// Number of places across a file that are analysed for black bars
+pub const SAMPLES: u32 = 5;
+
+/// Default `crop_limit`: the brightness up to which a pixel counts as black, out of 255
+pub const DEFAULT_LIMIT: u32 = 24;
+
+/// Default `crop_sample_duration`, spread over the [`SAMPLES`]
+pub const DEFAULT_SAMPLE_DURATION: Duration = Duration::from_secs(150);
+
+/// The picture left once black bars are cut away, in source pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Crop {
+    /// Parse the `crop=w:h:x:y` cropdetect reports on each of its log lines
+    pub fn from_cropdetect_line(line: &str) -> Option<Self> {
+        let (_, value) = line.rsplit_once("crop=")?;
+        let mut parts = value.split_whitespace().next()?.split(':');
+        let mut next = || parts.next()?.parse::<u32>().ok();
+        let crop = Self {
+            width: next()?,
+            height: next()?,
+            x: next()?,
+            y: next()?,
+        };
+        // cropdetect reports negative sizes while it has seen nothing but black
+        (crop.width > 0 && crop.height > 0).then_some(crop)
+    }
+
+    pub fn filter(&self) -> String {
+        format!("crop={}", self)
+    }
+}
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Find the black bars of `input` by running cropdetect over [`SAMPLES`] stretches spread
+/// across its `duration` seconds, `sample_duration` in total.
+///
+/// Returns no crop when the samples disagree, as they do when dark scenes look like bars,
+/// or when there is nothing to cut away from `frame` (the source's width and height).
+/// `autorotate` must match the encode's, so the crop lands on the picture the way it is turned.
+pub async fn detect(
+    input: &Path,
+    duration: Option<f64>,
+    frame: Option<(u32, u32)>,
+    limit: u32,
+    sample_duration: Duration,
+    autorotate: bool,
+    priority: ProcessPriority,
+) -> Result<Option<Crop>> {
+    let sample_secs = sample_duration.as_secs_f64() / SAMPLES as f64;
+    let starts: Vec<f64> = match duration {
+        Some(duration) if duration > sample_duration.as_secs_f64() => (0..SAMPLES)
+            .map(|i| duration * (i as f64 + 0.5) / SAMPLES as f64 - sample_secs / 2.0)
+            .collect(),
+        // Short or unknown sources are analysed in one go from the start
+        _ => vec![0.0],
+    };
+    let length = if starts.len() == 1 {
+        sample_duration.as_secs_f64()
+    } else {
+        sample_secs
+    };
+
+    let mut found = Vec::new();
+    for start in starts {
+        match detect_sample(input, start, length, limit, autorotate, priority).await? {
+            Some(crop) => found.push(crop),
+            None => debug!("No crop detected at {:.0}s of {}", start, input.display()),
+        }
+    }
+
+    let crop = choose(&found, frame);
+    if crop.is_none() && found.iter().any(|c| frame != Some((c.width, c.height))) {
+        warn!(
+            "Crop detection for {} is inconsistent, the samples found {}; not cropping",
+            input.display(),
+            found
+                .iter()
+                .map(Crop::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(crop)
+}
+
+/// The crop more than half of the `found` samples agree on, unless it leaves all of `frame`
+fn choose(found: &[Crop], frame: Option<(u32, u32)>) -> Option<Crop> {
+    let mut counts: HashMap<Crop, usize> = HashMap::new();
+    for crop in found {
+        *counts.entry(*crop).or_default() += 1;
+    }
+    let (crop, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    if count * 2 <= found.len() || frame == Some((crop.width, crop.height)) {
+        return None;
+    }
+    Some(crop)
+}
+
+/// The crop cropdetect settles on over `length` seconds from `start`
+async fn detect_sample(
+    input: &Path,
+    start: f64,
+    length: f64,
+    limit: u32,
+    autorotate: bool,
+    priority: ProcessPriority,
+) -> Result<Option<Crop>> {
+    let mut cmd = Command::new(tools::ffmpeg());
+    priority::apply(&mut cmd, priority);
+    cmd.args(["-v", "info", "-nostats", "-ss", &format!("{:.3}", start)]);
+    if !autorotate {
+        cmd.arg("-noautorotate");
+    }
+    let output = cmd
+        .arg("-i")
+        .arg(input)
+        .args(["-t", &format!("{:.3}", length)])
+        // reset=0 keeps the widest picture seen, so the last line covers the whole sample
+        .args([
+            "-vf",
+            &format!("cropdetect=limit={}:round=2:reset=0", limit),
+        ])
+        .args(["-an", "-sn", "-dn", "-f", "null", "-"])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to execute ffmpeg for crop detection")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Crop detection failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .next_back()
+                .unwrap_or_default()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .rev()
+        .filter(|line| line.contains("cropdetect"))
+        .find_map(Crop::from_cropdetect_line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Option<(u32, u32)> = Some((1920, 1080));
+
+    fn crop(width: u32, height: u32) -> Crop {
+        Crop {
+            width,
+            height,
+            x: (1920 - width) / 2,
+            y: (1080 - height) / 2,
+        }
+    }
+
+    #[test]
+    fn reads_the_crop_of_a_cropdetect_line() {
+        let line = "[Parsed_cropdetect_0 @ 0x5581a2c0] x1:0 x2:1919 y1:132 y2:947 w:1920 h:816 x:0 y:132 pts:250250 t:10.010000 limit:0.094118 crop=1920:816:0:132";
+        assert_eq!(
+            Crop::from_cropdetect_line(line),
+            Some(Crop {
+                width: 1920,
+                height: 816,
+                x: 0,
+                y: 132
+            })
+        );
+
+        // All black so far
+        let black = "[Parsed_cropdetect_0 @ 0x5581a2c0] x1:1919 x2:0 y1:1079 y2:0 w:-1904 h:-1064 x:1912 y:1072 pts:0 t:0.000000 limit:0.094118 crop=-1904:-1064:1912:1072";
+        assert_eq!(Crop::from_cropdetect_line(black), None);
+        assert_eq!(Crop::from_cropdetect_line("[out#0/null] size=N/A"), None);
+    }
+
+    #[test]
+    fn samples_vote_on_the_crop() {
+        let letterbox = crop(1920, 816);
+        let other = crop(1920, 800);
+        let third = crop(1440, 1080);
+        for (found, expected) in [
+            (vec![letterbox; 5], Some(letterbox)),
+            // Three of five is a majority
+            (
+                vec![letterbox, other, letterbox, third, letterbox],
+                Some(letterbox),
+            ),
+            // Dark scenes in two samples split the vote 2-2-1
+            (vec![letterbox, other, letterbox, other, third], None),
+            (vec![letterbox, other], None),
+            // Nothing to cut
+            (vec![crop(1920, 1080); 5], None),
+            (vec![], None),
+        ] {
+            assert_eq!(choose(&found, FRAME), expected, "{:?}", found);
+        }
+
+        // Without the frame size, a full-frame crop is kept
+        assert_eq!(choose(&[crop(1920, 1080)], None), Some(crop(1920, 1080)));
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+
+/// Where a config file stopped parsing and why, rendered with the offending line and a
+/// suggestion for misspelled field and variant names
+#[derive(Debug)]
+pub struct ParseError {
+    /// Dotted path of the section the error occurred in, like `presets.fast_h264`
+    pub section: Option<String>,
+    /// 1-based line and column
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+    /// Closest known name to a misspelled field or variant
+    pub suggestion: Option<String>,
+    /// The source line at `location`
+    pub source_line: Option<String>,
+}
+
+impl ParseError {
+    /// `message` is the deserializer's own, without its location suffix
+    pub fn new(
+        text: &str,
+        section: String,
+        location: Option<(usize, usize)>,
+        message: &str,
+    ) -> Self {
+        // serde_path_to_error marks the root as `.` and keys it couldn't read as `?`
+        let mut section = section.trim_end_matches(".?").trim_end_matches('?');
+        // The path of an unknown field ends in the field itself
+        if message.starts_with("unknown field") {
+            section = section.rsplit_once('.').map_or("", |(parent, _)| parent);
+        }
+        let section = (!section.is_empty() && section != ".").then(|| section.to_string());
+        let source_line = location
+            .and_then(|(line, _)| text.lines().nth(line.checked_sub(1)?))
+            .map(str::to_string);
+
+        let (message, suggestion) = match suggest_name(message) {
+            Some((trimmed, suggestion)) => (trimmed.to_string(), Some(suggestion.to_string())),
+            None => (message.to_string(), None),
+        };
+
+        Self {
+            section,
+            location,
+            message,
+            suggestion,
+            source_line,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(section) = &self.section {
+            write!(f, " in {}", section)?;
+        }
+        if let Some((line, column)) = self.location {
+            write!(f, " at line {}, column {}", line, column)?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{}`?", suggestion)?;
+        }
+        if let Some((line, column)) = self.location {
+            if let Some(source) = &self.source_line {
+                write!(f, "\n{:>5} | {}", line, source)?;
+                write!(f, "\n{:>5} | {:>width$}", "", "^", width = column.max(1))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Drop the `path: ` serde_yaml puts in front and the `at line N column M` serde_yaml and
+/// serde_json append, since both are reported separately
+pub fn strip_location(message: &str) -> &str {
+    let message = match message.split_once(": ") {
+        Some((path, rest)) if !path.contains(char::is_whitespace) => rest,
+        _ => message,
+    };
+    match message.rfind(" at line ") {
+        Some(at)
+            if message[at + " at line ".len()..]
+                .split(" column ")
+                .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())) =>
+        {
+            &message[..at]
+        }
+        _ => message,
+    }
+}
+
+/// The closest expected name to the one serde didn't know, with the message cut short of its
+/// list of every expected name
+fn suggest_name(message: &str) -> Option<(&str, &str)> {
+    for kind in ["unknown field", "unknown variant"] {
+        let Some(start) = message.find(&format!("{} `", kind)) else {
+            continue;
+        };
+        let after = &message[start + kind.len() + 2..];
+        let Some(end) = after.find('`') else {
+            continue;
+        };
+        let name = &after[..end];
+        let expected: Vec<&str> = after[end + 1..].split('`').skip(1).step_by(2).collect();
+        if let Some(suggestion) = closest(name, expected) {
+            return Some((&message[..start + kind.len() + 2 + end + 1], suggestion));
+        }
+    }
+    None
+}
+
+/// The candidate most similar to `name`, if any is close enough to be a likely typo. Swapped
+/// neighbours count as one edit, like any other.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    // One edit in short names, roughly one in three in longer ones
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| {
+            (
+                strsim::osa_distance(&name, &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// `", did you mean 'x'?"` for the closest candidate, or nothing, to end an error message with
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a String>) -> String {
+    closest(name, candidates.into_iter().map(String::as_str))
+        .map(|suggestion| format!(", did you mean '{}'?", suggestion))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Severity};
+
+    /// Findings of `check_config` on a config file `name` holding `text`
+    fn findings(name: &str, text: &str) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        config::check_config(&path, false)
+            .findings
+            .into_iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.message)
+            .collect()
+    }
+
+    #[test]
+    fn misspelt_fields_and_values_are_suggested() {
+        for (name, text, error) in [
+            (
+                "config.yaml",
+                "inputs: []\noutputs: {}\npresets:\n  fast_h264:\n    video_codec: libx264\n    video_codek: libx265\n",
+                "Failed to parse YAML config: unknown field `video_codek` in presets.fast_h264 at line 6, column 5, did you mean `video_codec`?",
+            ),
+            (
+                "config.yaml",
+                "inputs: []\noutputs: {}\npresets:\n  fast_h264:\n    on_dropped_frames: fial\n",
+                "Failed to parse YAML config: unknown variant `fial` in presets.fast_h264.on_dropped_frames at line 5, column 24, did you mean `fail`?",
+            ),
+            (
+                "config.yaml",
+                "inputs: []\noutputs: {}\npresets:\n  fast_h264:\n    mode: remuxx\n",
+                "Failed to parse YAML config: unknown variant `remuxx` in presets.fast_h264.mode at line 5, column 11, did you mean `remux`?",
+            ),
+            (
+                "config.yaml",
+                "inputs: []\noutputs: {}\npresets: {}\nmax_paralel_jobs: 2\n",
+                "Failed to parse YAML config: unknown field `max_paralel_jobs` at line 4, column 1, did you mean `max_parallel_jobs`?",
+            ),
+            (
+                "config.toml",
+                "inputs = []\noutputs = {}\n[presets.fast_h264]\nvideo_codek = \"libx264\"\n",
+                "Failed to parse TOML config: unknown field `video_codek` in presets.fast_h264 at line 4, column 1, did you mean `video_codec`?",
+            ),
+            (
+                "config.json",
+                r#"{"inputs": [], "outputs": {}, "presets": {"fast_h264": {"scalee": 20}}}"#,
+                "Failed to parse JSON config: unknown field `scalee` in presets.fast_h264 at line 1, column 64, did you mean `scale`?",
+            ),
+            (
+                "config.json",
+                r#"{"inputs": [], "outputs": {}, "presets": {"fast_h264": {"crff": 20}}}"#,
+                "Failed to parse JSON config: unknown field `crff` in presets.fast_h264 at line 1, column 62, did you mean `crf`?",
+            ),
+        ] {
+            let errors = findings(name, text);
+            assert_eq!(errors[0].lines().next(), Some(error));
+            // The offending line is shown under the message
+            assert_eq!(errors[0].lines().count(), 3, "{}", errors[0]);
+        }
+    }
+
+    #[test]
+    fn unlike_fields_list_what_is_expected() {
+        let errors = findings(
+            "config.yaml",
+            "inputs: []\noutputs: {}\npresets: {}\nzzzzzz: 1\n",
+        );
+        assert!(
+            errors[0].starts_with(
+                "Failed to parse YAML config: unknown field `zzzzzz`, expected one of `inputs`, `outputs`, `presets`,"
+            ),
+            "{}",
+            errors[0]
+        );
+        assert!(!errors[0].contains("did you mean"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn misspelt_names_are_suggested() {
+        let errors = findings(
+            "config.yaml",
+            "
+inputs:
+  - path: in
+    preset: fast_h246
+    output: mian
+    fallback_preset: slwo
+  - path: other
+    preset: unrelated
+    output: main
+outputs:
+  main:
+    path: out
+    filename_template: '{filename}'
+    container: mkv
+presets:
+  fast_h264:
+    video_codec: libx264
+  slow:
+    video_codec: libx265
+",
+        );
+        for expected in [
+            "Output 'mian' referenced by input 'in' does not exist, did you mean 'main'?",
+            "Preset 'fast_h246' referenced by input 'in' does not exist, did you mean 'fast_h264'?",
+            "Fallback preset 'slwo' referenced by input 'in' does not exist, did you mean 'slow'?",
+            "Preset 'unrelated' referenced by input 'other' does not exist",
+        ] {
+            assert!(
+                errors.iter().any(|e| e.ends_with(expected)),
+                "{} not in {:?}",
+                expected,
+                errors
+            );
+        }
+
+        let errors = findings(
+            "config.yaml",
+            "inputs: []\noutputs: {}\npresets:\n  fast_h264: {}\n  slow:\n    extends: fats_h264\n",
+        );
+        assert_eq!(
+            errors,
+            ["Preset 'slow' extends 'fats_h264', which does not exist, did you mean 'fast_h264'?"]
+        );
+    }
+
+    #[test]
+    fn closest_allows_about_one_typo_in_three() {
+        let names = ["crf", "video_codec", "audio_codec", "max_parallel_jobs"];
+        assert_eq!(closest("crff", names), Some("crf"));
+        assert_eq!(closest("CRF", names), Some("crf"));
+        assert_eq!(closest("vidoe_codec", names), Some("video_codec"));
+        assert_eq!(closest("audio_codek", names), Some("audio_codec"));
+        assert_eq!(closest("max_jobs", names), None);
+        assert_eq!(closest("xyz", names), None);
+        assert_eq!(
+            did_you_mean("mian", &["main".to_string()]),
+            ", did you mean 'main'?"
+        );
+        assert_eq!(did_you_mean("other", &["main".to_string()]), "");
+    }
+
+    #[test]
+    fn locations_are_stripped_once() {
+        assert_eq!(
+            strip_location("presets.p: unknown field `x` at line 3 column 5"),
+            "unknown field `x`"
+        );
+        assert_eq!(
+            strip_location("expected a value at line 1"),
+            "expected a value"
+        );
+        assert_eq!(
+            strip_location("bad value: not at line three"),
+            "bad value: not at line three"
+        );
+        assert_eq!(
+            strip_location("invalid type: string, expected u32"),
+            "invalid type: string, expected u32"
+        );
+    }
+}
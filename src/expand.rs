@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+/// Expand `${VAR}`, `${VAR:-default}` and a leading `~` in a config path.
+///
+/// Unset variables without a default are an error so a typo never turns into a literal
+/// `${MEDIA_ROOT}` directory.
+pub fn expand_path(path: &Path) -> Result<PathBuf> {
+    // Paths that aren't UTF-8 can't contain anything to expand that we could parse
+    let Some(raw) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+
+    let raw = expand_tilde(raw)?;
+    let mut expanded = String::with_capacity(raw.len());
+    let mut rest = raw.as_str();
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed '${{' in path {}", path.display()))?;
+
+        let expression = &after[..end];
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!(
+                "Invalid variable name '{}' in path {}",
+                name,
+                path.display()
+            ));
+        }
+
+        match (std::env::var(name), default) {
+            (Ok(value), _) if !value.is_empty() => expanded.push_str(&value),
+            (_, Some(default)) => expanded.push_str(default),
+            (Ok(_), None) => {
+                return Err(anyhow!(
+                    "Environment variable {} used in path {} is empty",
+                    name,
+                    path.display()
+                ))
+            }
+            (Err(_), None) => {
+                return Err(anyhow!(
+                    "Environment variable {} used in path {} is not set",
+                    name,
+                    path.display()
+                ))
+            }
+        }
+
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(PathBuf::from(expanded))
+}
+
+fn expand_tilde(raw: &str) -> Result<String> {
+    let Some(rest) = raw.strip_prefix('~') else {
+        return Ok(raw.to_string());
+    };
+    // `~user` isn't supported; leave such paths alone
+    if !(rest.is_empty() || rest.starts_with('/')) {
+        return Ok(raw.to_string());
+    }
+
+    let home = std::env::var("HOME")
+        .ok()
+        .filter(|home| !home.is_empty())
+        .ok_or_else(|| anyhow!("Cannot expand ~ in {}: HOME is not set", raw))?;
+    Ok(format!("{}{}", home, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expand `raw`; variables set here are prefixed so no other test reads them
+    fn expand(raw: &str) -> Result<String> {
+        expand_path(Path::new(raw)).map(|path| path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn variables_and_defaults_are_expanded() {
+        std::env::set_var("SSTC_EXPAND_ROOT", "/srv/media");
+        std::env::remove_var("SSTC_EXPAND_UNSET");
+
+        assert_eq!(expand("${SSTC_EXPAND_ROOT}/in").unwrap(), "/srv/media/in");
+        assert_eq!(
+            expand("${SSTC_EXPAND_ROOT:-/mnt}/in").unwrap(),
+            "/srv/media/in"
+        );
+        assert_eq!(expand("${SSTC_EXPAND_UNSET:-/mnt}/in").unwrap(), "/mnt/in");
+        assert_eq!(expand("${SSTC_EXPAND_UNSET:-}/in").unwrap(), "/in");
+        assert_eq!(
+            expand("${SSTC_EXPAND_ROOT}/${SSTC_EXPAND_UNSET:-tv}").unwrap(),
+            "/srv/media/tv"
+        );
+        assert_eq!(expand("/plain/$HOME/path").unwrap(), "/plain/$HOME/path");
+    }
+
+    #[test]
+    fn empty_and_unset_variables_are_errors_naming_them() {
+        std::env::set_var("SSTC_EXPAND_EMPTY", "");
+        std::env::remove_var("SSTC_EXPAND_MISSING");
+
+        let error = expand("${SSTC_EXPAND_EMPTY}/in").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Environment variable SSTC_EXPAND_EMPTY used in path ${SSTC_EXPAND_EMPTY}/in is empty"
+        );
+        let error = expand("${SSTC_EXPAND_MISSING}/in").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Environment variable SSTC_EXPAND_MISSING used in path ${SSTC_EXPAND_MISSING}/in is not set"
+        );
+        // A default covers an empty value too
+        assert_eq!(expand("${SSTC_EXPAND_EMPTY:-/mnt}").unwrap(), "/mnt");
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        let error = expand("/media/${SSTC_EXPAND_ROOT").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unclosed '${' in path /media/${SSTC_EXPAND_ROOT"
+        );
+
+        for (raw, name) in [
+            ("${}/in", ""),
+            ("${:-/mnt}/in", ""),
+            ("${MEDIA-ROOT}/in", "MEDIA-ROOT"),
+            ("${MEDIA ROOT}/in", "MEDIA ROOT"),
+            ("${SSTC_EXPAND_ROOT:/mnt}/in", "SSTC_EXPAND_ROOT:/mnt"),
+        ] {
+            let error = expand(raw).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Invalid variable name '{}' in path {}", name, raw)
+            );
+        }
+    }
+
+    #[test]
+    fn tilde_expands_to_home_only_on_its_own() {
+        let home = std::env::var("HOME").unwrap();
+
+        assert_eq!(expand("~").unwrap(), home);
+        assert_eq!(expand("~/videos").unwrap(), format!("{}/videos", home));
+        // `~user` and a `~` further in are left alone
+        assert_eq!(expand("~alice/videos").unwrap(), "~alice/videos");
+        assert_eq!(expand("/media/~/videos").unwrap(), "/media/~/videos");
+    }
+
+    #[test]
+    fn expanded_values_are_not_expanded_again() {
+        std::env::set_var("SSTC_EXPAND_NESTED", "/media/${SSTC_EXPAND_ROOT}");
+        std::env::set_var("SSTC_EXPAND_TILDE", "~/videos");
+
+        assert_eq!(
+            expand("${SSTC_EXPAND_NESTED}/in").unwrap(),
+            "/media/${SSTC_EXPAND_ROOT}/in"
+        );
+        assert_eq!(expand("${SSTC_EXPAND_TILDE}").unwrap(), "~/videos");
+    }
+}
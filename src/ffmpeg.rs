@@ -0,0 +1,250 @@
+use crate::config::{Config, HwAccelConfig, HwAccelKind, InputConfig};
+use crate::tools;
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Severity of an ffmpeg stderr line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "panic" | "fatal" | "error" => Some(LogLevel::Error),
+            "warning" => Some(LogLevel::Warning),
+            "info" => Some(LogLevel::Info),
+            "verbose" | "debug" | "trace" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// Split an ffmpeg stderr line into its level and the message without the level tag.
+///
+/// With `-loglevel level+...` ffmpeg writes `[component @ 0x...] [level] message` or
+/// `[level] message`; libraries that log on their own, like x265, write
+/// `x265 [level]: message`. Lines without a tag, like encoder banners, have no level.
+pub fn parse_log_line(line: &str) -> (Option<LogLevel>, String) {
+    for (start, _) in line.match_indices('[') {
+        let Some(len) = line[start..].find(']') else {
+            break;
+        };
+        let Some(level) = LogLevel::from_tag(&line[start + 1..start + len]) else {
+            continue;
+        };
+        let before = &line[..start];
+        let after = line[start + len + 1..].trim_start();
+        let message = match after.strip_prefix(':') {
+            Some(rest) => format!("{}:{}", before.trim_end(), rest),
+            None => format!("{}{}", before, after),
+        };
+        return (Some(level), message);
+    }
+    (None, line.to_string())
+}
+
+/// First line of `ffmpeg -version`, probed once per process
+pub fn version() -> Option<String> {
+    static VERSION: OnceLock<Option<String>> = OnceLock::new();
+
+    VERSION
+        .get_or_init(|| {
+            let output = match Command::new(tools::ffmpeg()).arg("-version").output() {
+                Ok(output) if output.status.success() => output,
+                Ok(output) => {
+                    warn!("ffmpeg -version exited with {}", output.status);
+                    return None;
+                }
+                Err(e) => {
+                    warn!("Failed to run ffmpeg -version: {}", e);
+                    return None;
+                }
+            };
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_string())
+        })
+        .clone()
+}
+
+/// Names of the encoders compiled into the configured ffmpeg, from `ffmpeg -encoders`
+pub fn encoders() -> Result<HashSet<String>> {
+    let output = Command::new(tools::ffmpeg())
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .context("Failed to run ffmpeg -encoders")?;
+    if !output.status.success() {
+        return Err(anyhow!("ffmpeg -encoders exited with {}", output.status));
+    }
+
+    // Each encoder line after the `------` separator reads ` V....D libx264  description`
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Hardware acceleration methods of the configured ffmpeg, from `ffmpeg -hwaccels`
+pub fn hwaccels() -> Result<HashSet<String>> {
+    let output = Command::new(tools::ffmpeg())
+        .args(["-hide_banner", "-hwaccels"])
+        .output()
+        .context("Failed to run ffmpeg -hwaccels")?;
+    if !output.status.success() {
+        return Err(anyhow!("ffmpeg -hwaccels exited with {}", output.status));
+    }
+
+    // A `Hardware acceleration methods:` header, then one method per line
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip_while(|line| !line.trim_end().ends_with(':'))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Warn about presets the inputs use whose `hwaccel` backend this ffmpeg or machine lacks;
+/// the jobs would still fail, but the GPU may only be missing until a driver loads
+pub fn check_hwaccels(config: &Config) {
+    let used: BTreeSet<String> = config
+        .inputs
+        .iter()
+        .flat_map(InputConfig::targets)
+        .flat_map(|target| [Some(target.preset), target.fallback_preset])
+        .flatten()
+        .collect();
+    let hwaccels: Vec<(&String, &HwAccelConfig)> = used
+        .iter()
+        .filter_map(|name| Some((name, config.presets.get(name)?.hwaccel.as_ref()?)))
+        .collect();
+    if hwaccels.is_empty() {
+        return;
+    }
+
+    let available = match self::hwaccels() {
+        Ok(available) => available,
+        Err(e) => {
+            warn!("Cannot check hardware acceleration support: {}", e);
+            return;
+        }
+    };
+    for (name, hwaccel) in hwaccels {
+        if !available.contains(hwaccel.kind.method()) {
+            warn!(
+                "Preset '{}' uses {}, but {} doesn't list the {} hwaccel",
+                name,
+                hwaccel.kind,
+                tools::ffmpeg().display(),
+                hwaccel.kind.method()
+            );
+        }
+        let device = match (hwaccel.kind, &hwaccel.device) {
+            (HwAccelKind::Vaapi, None) => Some(HwAccelConfig::DEFAULT_VAAPI_DEVICE),
+            (HwAccelKind::Vaapi | HwAccelKind::Qsv, Some(device)) => Some(device.as_str()),
+            _ => None,
+        };
+        if let Some(device) = device.filter(|device| !Path::new(device).exists()) {
+            warn!(
+                "Preset '{}' uses {} device {}, which doesn't exist",
+                name, hwaccel.kind, device
+            );
+        }
+    }
+}
+
+/// Check that every codec of the presets the inputs use is an encoder this ffmpeg has
+pub fn check_encoders(config: &Config) -> Result<()> {
+    let available = encoders()?;
+
+    let used: BTreeSet<String> = config
+        .inputs
+        .iter()
+        .flat_map(InputConfig::targets)
+        .flat_map(|target| [Some(target.preset), target.fallback_preset])
+        .flatten()
+        .collect();
+
+    let mut missing = Vec::new();
+    for name in &used {
+        let Some(preset) = config.presets.get(name) else {
+            continue;
+        };
+        for codec in [preset.video_encoder(), preset.audio_codec.clone()]
+            .into_iter()
+            .flatten()
+        {
+            if codec != "copy" && !available.contains(&codec) {
+                missing.push(format!(
+                    "preset '{}' uses encoder '{}', which {} doesn't have",
+                    name,
+                    codec,
+                    tools::ffmpeg().display()
+                ));
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Unknown encoder(s): {}; pass --allow-unknown-encoders to start anyway",
+            missing.join("; ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_lines_are_split_by_their_level_tag() {
+        for (line, level, message) in [
+            (
+                "[h264 @ 0x55d0c8a4c2c0] [warning] Invalid NAL unit size",
+                Some(LogLevel::Warning),
+                "[h264 @ 0x55d0c8a4c2c0] Invalid NAL unit size",
+            ),
+            (
+                "[error] broken.mp4: Invalid data found when processing input",
+                Some(LogLevel::Error),
+                "broken.mp4: Invalid data found when processing input",
+            ),
+            (
+                "x265 [info]: HEVC encoder version 3.5",
+                Some(LogLevel::Info),
+                "x265: HEVC encoder version 3.5",
+            ),
+            // A bracket that isn't a level is part of the message
+            (
+                "[mp4 @ 0x1] [fatal] Could not open [out].mp4",
+                Some(LogLevel::Error),
+                "[mp4 @ 0x1] Could not open [out].mp4",
+            ),
+            ("Stream mapping:", None, "Stream mapping:"),
+        ] {
+            assert_eq!(
+                parse_log_line(line),
+                (level, message.to_string()),
+                "{}",
+                line
+            );
+        }
+    }
+}
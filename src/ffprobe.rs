@@ -1,7 +1,10 @@
+use crate::priority;
+use crate::tools;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use std::collections::HashMap;
 use std::path::Path;
-use std::process::Command;
+use tokio::process::Command;
 
 fn parse_duration<'de, D>(deserializer: D) -> Result<f32, D::Error>
 where
@@ -11,55 +14,296 @@ where
     s.parse::<f32>().map_err(serde::de::Error::custom)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FFprobeOutput {
-    format: Format,
-    // Other fields like streams, chapters, etc. can be added if needed
+/// Everything ffprobe reports about a file, produced once per job and passed along
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub format: Format,
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Format {
     pub filename: String,
     pub nb_streams: u32,
+    #[serde(default)]
     pub nb_programs: u32,
+    #[serde(default)]
     pub nb_stream_groups: u32,
     pub format_name: String,
+    #[serde(default)]
     pub format_long_name: String,
+    #[serde(default)]
     pub start_time: String,
-    #[serde(deserialize_with = "parse_duration")]
+    #[serde(default, deserialize_with = "parse_duration")]
     pub duration: f32,
+    #[serde(default)]
     pub size: String,
+    #[serde(default)]
     pub bit_rate: String,
+    #[serde(default)]
     pub probe_score: u32,
     pub tags: Option<Tags>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tags {
-    pub ENCODER: Option<String>,
+    #[serde(rename = "ENCODER")]
+    pub encoder: Option<String>,
+    #[serde(default)]
+    pub creation_time: Option<String>,
     // Add other potential tags here
 }
 
-pub fn get_format_info<P: AsRef<Path>>(file_path: P) -> Result<Format, Box<dyn Error>> {
-    let output = Command::new("ffprobe")
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stream {
+    pub index: u32,
+    pub codec_type: Option<String>,
+    pub codec_name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pix_fmt: Option<String>,
+    /// `progressive`, or the field order of interlaced video like `tt` or `bb`
+    pub field_order: Option<String>,
+    /// Transfer characteristics, like `bt709`, `smpte2084` (PQ) or `arib-std-b67` (HLG)
+    pub color_transfer: Option<String>,
+    /// Color primaries, like `bt709` or `bt2020`
+    pub color_primaries: Option<String>,
+    pub r_frame_rate: Option<String>,
+    pub avg_frame_rate: Option<String>,
+    pub nb_frames: Option<String>,
+    pub duration: Option<String>,
+    pub bit_rate: Option<String>,
+    pub sample_rate: Option<String>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(default)]
+    pub side_data_list: Vec<SideData>,
+}
+
+/// Side data of a stream, of which sstc only reads the display matrix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SideData {
+    pub side_data_type: Option<String>,
+    /// Counter-clockwise degrees of a display matrix
+    pub rotation: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    pub id: i64,
+    #[serde(default)]
+    pub start_time: String,
+    #[serde(default)]
+    pub end_time: String,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+impl Stream {
+    /// Whether this is a subtitle stream of pictures, like PGS or DVD subtitles, rather than text
+    pub fn is_bitmap_subtitle(&self) -> bool {
+        matches!(
+            self.codec_name.as_deref(),
+            Some("hdmv_pgs_subtitle" | "dvd_subtitle" | "dvb_subtitle" | "xsub")
+        )
+    }
+
+    /// Whether ffprobe saw interlaced fields; unknown field orders count as progressive
+    pub fn is_interlaced(&self) -> bool {
+        matches!(self.field_order.as_deref(), Some("tt" | "bb" | "tb" | "bt"))
+    }
+
+    /// Whether the transfer characteristics are HDR ones, PQ or HLG
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.color_transfer.as_deref(),
+            Some("smpte2084" | "arib-std-b67")
+        )
+    }
+
+    /// Clockwise degrees players turn the picture by, 0, 90, 180 or 270, from the display matrix
+    /// or the older `rotate` tag
+    pub fn rotation(&self) -> u32 {
+        let degrees = self
+            .side_data_list
+            .iter()
+            .filter(|sd| sd.side_data_type.as_deref() == Some("Display Matrix"))
+            .find_map(|sd| sd.rotation)
+            .map(|ccw| -ccw)
+            .or_else(|| self.tags.get("rotate").and_then(|r| r.trim().parse().ok()))
+            .unwrap_or(0.0);
+        ((degrees / 90.0).round() as i64 * 90).rem_euclid(360) as u32
+    }
+
+    /// The `language` tag, like `eng`, if the container sets one
+    pub fn language(&self) -> Option<&str> {
+        self.tags.get("language").map(String::as_str)
+    }
+}
+
+impl ProbeResult {
+    /// The first video stream, if the file has one
+    pub fn video_stream(&self) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some("video"))
+    }
+
+    /// Audio streams in file order; `0:a:N` counts among these
+    pub fn audio_streams(&self) -> Vec<&Stream> {
+        self.streams
+            .iter()
+            .filter(|s| s.codec_type.as_deref() == Some("audio"))
+            .collect()
+    }
+
+    /// Codec names of every audio stream
+    pub fn audio_codecs(&self) -> Vec<&str> {
+        self.audio_streams()
+            .into_iter()
+            .filter_map(|s| s.codec_name.as_deref())
+            .collect()
+    }
+
+    /// Whether some audio stream is tagged with `language`
+    pub fn has_audio_language(&self, language: &str) -> bool {
+        self.audio_streams()
+            .iter()
+            .any(|s| s.language() == Some(language))
+    }
+
+    /// Subtitle streams in file order; `subtitle_stream` and `0:s:N` count among these
+    pub fn subtitle_streams(&self) -> Vec<&Stream> {
+        self.streams
+            .iter()
+            .filter(|s| s.codec_type.as_deref() == Some("subtitle"))
+            .collect()
+    }
+
+    /// Bitrate of the first video stream in bits per second. Containers like mkv don't
+    /// report it per stream, so fall back to the overall bitrate, which includes audio.
+    pub fn video_bitrate(&self) -> Option<u64> {
+        self.video_stream()
+            .and_then(|s| s.bit_rate.as_deref())
+            .and_then(|rate| rate.parse().ok())
+            .or_else(|| self.format.bit_rate.parse().ok())
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.format.duration
+    }
+
+    /// Frames per second of the first video stream
+    pub fn frame_rate(&self) -> Option<f64> {
+        let stream = self.video_stream()?;
+        [&stream.avg_frame_rate, &stream.r_frame_rate]
+            .into_iter()
+            .flatten()
+            .filter_map(|rate| parse_rational(rate))
+            .find(|fps| *fps > 0.0)
+    }
+}
+
+/// Parse ffprobe rationals like `30000/1001`
+pub fn parse_rational(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((num, den)) => {
+            let den = den.parse::<f64>().ok()?;
+            (den != 0.0).then_some(num.parse::<f64>().ok()? / den)
+        }
+        None => value.parse().ok(),
+    }
+}
+
+pub async fn probe<P: AsRef<Path>>(file_path: P) -> Result<ProbeResult> {
+    let file_path = file_path.as_ref();
+    let mut cmd = Command::new(tools::ffprobe());
+    priority::apply(&mut cmd, priority::default_priority());
+    let output = cmd
         .args([
             "-v",
             "quiet",
             "-print_format",
             "json",
             "-show_format",
+            "-show_streams",
+            "-show_chapters",
             "-i",
-            file_path.as_ref().to_str().ok_or("Invalid path")?,
         ])
-        .output()?;
+        .arg(file_path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to execute ffprobe")?;
 
     if !output.status.success() {
-        return Err(format!("ffprobe failed with status: {}", output.status).into());
+        return Err(anyhow::anyhow!(
+            "ffprobe failed with status: {}",
+            output.status
+        ));
     }
 
     let stdout = String::from_utf8(output.stdout)?;
-    let ffprobe_data: FFprobeOutput = serde_json::from_str(&stdout)?;
+    let probe: ProbeResult = serde_json::from_str(&stdout).context(format!(
+        "Failed to parse ffprobe output for {}",
+        file_path.display()
+    ))?;
 
-    Ok(ffprobe_data.format)
+    Ok(probe)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn video(extra: serde_json::Value) -> Stream {
+        let mut stream = json!({"index": 0, "codec_type": "video", "codec_name": "h264"});
+        stream
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(stream).unwrap()
+    }
+
+    #[test]
+    fn rotation_from_tags_and_side_data() {
+        let matrix = |rotation: f64| {
+            json!({"side_data_list": [
+                {"side_data_type": "Display Matrix", "displaymatrix": "...", "rotation": rotation},
+            ]})
+        };
+        let cases = [
+            (json!({}), 0),
+            // The display matrix counts counter-clockwise, players turn clockwise
+            (matrix(-90.0), 90),
+            (matrix(90.0), 270),
+            (matrix(180.0), 180),
+            (matrix(-180.0), 180),
+            (matrix(-89.7), 90),
+            (json!({"tags": {"rotate": "90"}}), 90),
+            (json!({"tags": {"rotate": " 270 "}}), 270),
+            (json!({"tags": {"rotate": "-90"}}), 270),
+            (json!({"tags": {"rotate": "sideways"}}), 0),
+            // The display matrix wins over the older tag
+            (
+                json!({
+                    "tags": {"rotate": "180"},
+                    "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}],
+                }),
+                90,
+            ),
+            // Other side data says nothing about rotation
+            (
+                json!({"side_data_list": [{"side_data_type": "Mastering display metadata"}]}),
+                0,
+            ),
+        ];
+        for (fields, expected) in cases {
+            assert_eq!(video(fields.clone()).rotation(), expected, "{}", fields);
+        }
+    }
+}
@@ -1,61 +1,67 @@
-use anyhow::{Context, Result};
+use crate::ffprobe::{self, ProbeResult};
+use crate::job::JobError;
+use anyhow::Result;
+use std::io::ErrorKind;
 use std::path::Path;
-use std::process::Command;
 use std::time::{Duration, Instant};
-use tracing::{debug, warn};
+use tracing::{debug, info_span, warn, Instrument};
 
-pub async fn is_file_valid<P: AsRef<Path>>(path: P) -> Result<bool> {
-    let path = path.as_ref();
+/// Probe a file once its size has settled (see [`wait_for_stable_size`]).
+///
+/// Returns the probe when the file is a valid media file, `None` when ffprobe
+/// cannot make sense of it yet.
+pub async fn probe_valid(path: &Path) -> Option<ProbeResult> {
+    let probe = match ffprobe::probe(path).instrument(info_span!("probe")).await {
+        Ok(probe) => probe,
+        Err(e) => {
+            warn!("FFprobe failed for {}: {}", path.display(), e);
+            return None;
+        }
+    };
 
-    if !wait_for_stable_size(path).await? {
-        return Ok(false);
+    let duration = probe.duration();
+    if duration > 0.0 {
+        debug!(
+            "File {} is valid with duration {}s",
+            path.display(),
+            duration
+        );
+        Some(probe)
+    } else {
+        warn!("File {} has invalid duration: {}", path.display(), duration);
+        None
     }
+}
 
-    // Use ffprobe to check if the file is valid
-    let output = Command::new("ffprobe")
-        .arg("-v")
-        .arg("error")
-        .arg("-show_entries")
-        .arg("format=duration")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .arg(path)
-        .output()
-        .context("Failed to execute ffprobe")?;
-
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        warn!("FFprobe failed for {}: {}", path.display(), error);
-        return Ok(false);
-    }
+/// How [`wait_for_stable_size`] decides a file is no longer being written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stability {
+    pub check_interval: Duration,
+    /// How long the size must stay the same
+    pub stable_for: Duration,
+    /// Give up after this long, so the file is requeued
+    pub timeout: Duration,
+}
 
-    let duration_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    match duration_str.parse::<f64>() {
-        Ok(duration) if duration > 0.0 => {
-            debug!(
-                "File {} is valid with duration {}s",
-                path.display(),
-                duration
-            );
-            Ok(true)
-        }
-        _ => {
-            warn!(
-                "File {} has invalid duration: {}",
-                path.display(),
-                duration_str
-            );
-            Ok(false)
+impl Default for Stability {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(1),
+            // NOTE: Mb more time, can be some buffering on copy or on write while recording.
+            stable_for: Duration::from_secs(3),
+            timeout: Duration::from_secs(60),
         }
     }
 }
 
-async fn wait_for_stable_size<P: AsRef<Path>>(path: P) -> Result<bool> {
+/// Wait until the size of `path` stops changing; `false` when it still changed at the timeout
+pub async fn wait_for_stable_size<P: AsRef<Path>>(path: P, stability: Stability) -> Result<bool> {
     let path = path.as_ref();
-    let check_interval = Duration::from_secs(1);
-    let timeout = Duration::from_secs(60);
-    // NOTE: Mb more time, can be some buffering on copy or on write while recording.
-    let stability_threshold = Duration::from_secs(3);
+    let Stability {
+        check_interval,
+        stable_for: stability_threshold,
+        timeout,
+    } = stability;
 
     let start_time = Instant::now();
     let mut last_size = None;
@@ -97,6 +103,202 @@ async fn wait_for_stable_size<P: AsRef<Path>>(path: P) -> Result<bool> {
         tokio::time::sleep(check_interval).await;
     }
 
-    warn!("Timeout waiting for file size to stabilize");
+    warn!(
+        "Timeout waiting for the size of {} to stabilize after {:?}",
+        path.display(),
+        timeout
+    );
     Ok(false)
 }
+
+/// Fail fast with `JobError::PermissionDenied` when the source can't be opened for reading
+pub fn check_readable(path: &Path) -> Result<()> {
+    match std::fs::File::open(path) {
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            Err(permission_denied(path, "reading").into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Create the output directory if needed and make sure sstc may write into it
+pub fn ensure_writable_dir(dir: &Path) -> Result<()> {
+    match std::fs::create_dir_all(dir) {
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            return Err(permission_denied(dir, "creating").into());
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context("Failed to create output directory"));
+        }
+        Ok(()) => {}
+    }
+
+    if is_writable_dir(dir) {
+        Ok(())
+    } else {
+        Err(permission_denied(dir, "writing into").into())
+    }
+}
+
+#[cfg(unix)]
+fn is_writable_dir(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return true;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string that outlives the call
+    unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable_dir(dir: &Path) -> bool {
+    dir.metadata()
+        .map(|m| !m.permissions().readonly())
+        .unwrap_or(true)
+}
+
+fn permission_denied(path: &Path, action: &'static str) -> JobError {
+    JobError::PermissionDenied {
+        path: path.to_path_buf(),
+        action,
+        detail: access_detail(path),
+    }
+}
+
+/// Who sstc runs as and who owns the path, for permission error messages
+#[cfg(unix)]
+fn access_detail(path: &Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    // SAFETY: these calls have no preconditions and cannot fail
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    match path.metadata() {
+        Ok(m) => format!(
+            "running as uid {} gid {}; mode {:04o} owned by {}:{}",
+            uid,
+            gid,
+            m.mode() & 0o7777,
+            m.uid(),
+            m.gid()
+        ),
+        Err(_) => format!("running as uid {} gid {}", uid, gid),
+    }
+}
+
+#[cfg(not(unix))]
+fn access_detail(_path: &Path) -> String {
+    "check the file's access rights".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::runs_as_root;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn set_mode(path: &Path, mode: u32) {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn permission_denied_of(result: Result<()>) -> (std::path::PathBuf, &'static str, String) {
+        match result.unwrap_err().downcast::<JobError>() {
+            Ok(JobError::PermissionDenied {
+                path,
+                action,
+                detail,
+            }) => (path, action, detail),
+            other => panic!("expected PermissionDenied, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreadable_source_is_denied_and_not_retryable() {
+        if runs_as_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mp4");
+        std::fs::write(&source, b"video").unwrap();
+        set_mode(&source, 0o000);
+
+        let err = check_readable(&source).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JobError>(),
+            Some(JobError::PermissionDenied { .. })
+        ));
+        assert!(!err.downcast_ref::<JobError>().unwrap().is_retryable());
+        let message = err.to_string();
+        assert!(
+            message.starts_with(&format!(
+                "Permission denied reading {} (running as uid ",
+                source.display()
+            )),
+            "{}",
+            message
+        );
+        assert!(message.contains("mode 0000 owned by"), "{}", message);
+    }
+
+    #[test]
+    fn readable_and_missing_sources_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mp4");
+        std::fs::write(&source, b"video").unwrap();
+        set_mode(&source, 0o400);
+        assert!(check_readable(&source).is_ok());
+        // A source that is gone fails later, with its own message
+        assert!(check_readable(&dir.path().join("gone.mp4")).is_ok());
+    }
+
+    #[test]
+    fn read_only_output_directory_is_denied() {
+        if runs_as_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        set_mode(&locked, 0o500);
+
+        let (path, action, detail) = permission_denied_of(ensure_writable_dir(&locked));
+        assert_eq!((path.as_path(), action), (locked.as_path(), "writing into"));
+        assert!(detail.contains("mode 0500"), "{}", detail);
+
+        let (path, action, _) = permission_denied_of(ensure_writable_dir(&locked.join("out")));
+        assert_eq!(path, locked.join("out"));
+        assert_eq!(action, "creating");
+        set_mode(&locked, 0o700);
+    }
+
+    #[test]
+    fn missing_output_directory_is_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("a/b/out");
+        ensure_writable_dir(&out).unwrap();
+        assert!(out.is_dir());
+    }
+
+    #[test]
+    fn detail_names_the_mode_and_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mp4");
+        std::fs::write(&source, b"video").unwrap();
+        set_mode(&source, 0o640);
+        let metadata = source.metadata().unwrap();
+
+        let detail = access_detail(&source);
+        assert!(
+            detail.ends_with(&format!(
+                "; mode 0640 owned by {}:{}",
+                metadata.uid(),
+                metadata.gid()
+            )),
+            "{}",
+            detail
+        );
+        assert!(!access_detail(&dir.path().join("gone")).contains("mode"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::job::JobError;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Time between two size checks, whatever interval ffmpeg reports progress at
+const CHECK_PERIOD: Duration = Duration::from_secs(10);
+
+/// Output time that has to pass before the file must have grown. Generous because muxers
+/// like mp4 without faststart or mkv with big clusters buffer a lot before writing.
+const MIN_ADVANCE_SECS: f64 = 30.0;
+
+/// Consecutive checks without growth before the job is killed
+const MAX_VIOLATIONS: u32 = 3;
+
+/// Cross-checks ffmpeg's reported progress against the size of the output on disk, catching
+/// encodes that keep "progressing" while nothing reaches the filesystem
+pub struct OutputGrowthWatchdog {
+    output: PathBuf,
+    /// Progress updates between two size checks
+    check_every: u32,
+    updates: u32,
+    last_size: u64,
+    last_out_time: f64,
+    violations: u32,
+}
+
+impl OutputGrowthWatchdog {
+    /// Watch `output` of an ffmpeg reporting progress every `progress_period`
+    pub fn new(output: &Path, progress_period: Duration) -> Self {
+        let check_every = CHECK_PERIOD.as_secs_f64() / progress_period.as_secs_f64().max(0.001);
+        Self {
+            output: output.to_path_buf(),
+            check_every: (check_every.round() as u32).max(1),
+            updates: 0,
+            last_size: 0,
+            last_out_time: 0.0,
+            violations: 0,
+        }
+    }
+
+    /// Feed the output time of a progress update; errors once the output has stayed the same
+    /// size over too many checks while the reported output time kept advancing
+    pub fn observe(&mut self, out_time_secs: f64) -> Result<(), JobError> {
+        self.updates += 1;
+        if !self.updates.is_multiple_of(self.check_every) {
+            return Ok(());
+        }
+
+        let advanced = out_time_secs - self.last_out_time;
+        if advanced < MIN_ADVANCE_SECS {
+            return Ok(());
+        }
+
+        let size = std::fs::metadata(&self.output).map_or(0, |m| m.len());
+        if size > self.last_size {
+            self.violations = 0;
+        } else {
+            self.violations += 1;
+            warn!(
+                "Output {} stayed at {} bytes while ffmpeg advanced {:.0}s ({}/{})",
+                self.output.display(),
+                size,
+                advanced,
+                self.violations,
+                MAX_VIOLATIONS
+            );
+            if self.violations >= MAX_VIOLATIONS {
+                return Err(JobError::OutputStagnant {
+                    output: self.output.clone(),
+                    out_time_secs,
+                    size,
+                });
+            }
+        }
+
+        debug!(
+            "Output {} is {} bytes at {:.0}s",
+            self.output.display(),
+            size,
+            out_time_secs
+        );
+        self.last_size = size;
+        self.last_out_time = out_time_secs;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Progress once a second, so a check every tenth update
+    const PERIOD: Duration = Duration::from_secs(1);
+
+    /// Feed `count` updates, `step` seconds of output time apart, after `start`
+    fn feed(
+        watchdog: &mut OutputGrowthWatchdog,
+        start: f64,
+        step: f64,
+        count: u32,
+    ) -> Result<(), JobError> {
+        (1..=count).try_for_each(|i| watchdog.observe(start + step * i as f64))
+    }
+
+    #[test]
+    fn checks_wait_for_enough_updates_and_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        // Never written, so every check that happens finds no growth
+        let output = dir.path().join("out.mkv");
+
+        let mut watchdog = OutputGrowthWatchdog::new(&output, PERIOD);
+        // Plenty of progress, but only every tenth update checks
+        feed(&mut watchdog, 0.0, 100.0, 9).unwrap();
+        assert_eq!(watchdog.violations, 0);
+        feed(&mut watchdog, 900.0, 100.0, 1).unwrap();
+        assert_eq!(watchdog.violations, 1);
+
+        let mut watchdog = OutputGrowthWatchdog::new(&output, PERIOD);
+        // Two checks, but the output time moved less than MIN_ADVANCE_SECS
+        feed(&mut watchdog, 0.0, 1.0, 20).unwrap();
+        assert_eq!(watchdog.violations, 0);
+        // Its advance adds up over the skipped checks
+        feed(&mut watchdog, 20.0, 1.0, 10).unwrap();
+        assert_eq!(watchdog.violations, 1);
+    }
+
+    #[test]
+    fn check_period_follows_the_progress_interval() {
+        let output = Path::new("out.mkv");
+        let checks = |period| OutputGrowthWatchdog::new(output, period).check_every;
+
+        assert_eq!(checks(Duration::from_secs(1)), 10);
+        assert_eq!(checks(Duration::from_millis(500)), 20);
+        assert_eq!(checks(Duration::from_secs(5)), 2);
+        // Slower than the check period, every update checks
+        assert_eq!(checks(Duration::from_secs(30)), 1);
+    }
+
+    #[test]
+    fn growth_resets_the_violations() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.mkv");
+        std::fs::write(&output, b"header").unwrap();
+        let mut watchdog = OutputGrowthWatchdog::new(&output, PERIOD);
+
+        // The header counts as growth from nothing
+        feed(&mut watchdog, 0.0, 5.0, 10).unwrap();
+        feed(&mut watchdog, 50.0, 5.0, 10).unwrap();
+        feed(&mut watchdog, 100.0, 5.0, 10).unwrap();
+        assert_eq!(watchdog.violations, 2);
+
+        std::fs::write(&output, b"header and a cluster").unwrap();
+        feed(&mut watchdog, 150.0, 5.0, 10).unwrap();
+        assert_eq!(watchdog.violations, 0);
+        assert_eq!(watchdog.last_size, 20);
+    }
+
+    #[test]
+    fn output_that_never_grows_is_stagnant() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.mkv");
+        let mut watchdog = OutputGrowthWatchdog::new(&output, PERIOD);
+
+        for check in 0..MAX_VIOLATIONS - 1 {
+            feed(&mut watchdog, check as f64 * 50.0, 5.0, 10).unwrap();
+        }
+        let error = feed(&mut watchdog, 100.0, 5.0, 10).unwrap_err();
+
+        match error {
+            JobError::OutputStagnant {
+                output: stagnant,
+                out_time_secs,
+                size,
+            } => {
+                assert_eq!(stagnant, output);
+                assert_eq!(out_time_secs, 150.0);
+                assert_eq!(size, 0);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+}
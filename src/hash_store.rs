@@ -0,0 +1,374 @@
+use crate::artifacts;
+use crate::config::HashStoreConfig;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Bytes hashed from each end of a source. Container headers and indexes sit at the ends,
+/// so copies that differ anywhere that matters differ there too.
+const EDGE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Content hash of a source: its size and the first and last [`EDGE_BYTES`], as hex
+pub fn fingerprint(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let size = file
+        .metadata()
+        .context(format!("Failed to stat {}", path.display()))?
+        .len();
+
+    let mut hasher = Xxh3::new();
+    hasher.update(&size.to_le_bytes());
+    let mut chunk = Vec::new();
+    (&mut file)
+        .take(EDGE_BYTES)
+        .read_to_end(&mut chunk)
+        .context(format!("Failed to read {}", path.display()))?;
+    hasher.update(&chunk);
+    if size > EDGE_BYTES {
+        // Small files are hashed whole by the head already; the tail never overlaps it
+        file.seek(SeekFrom::Start(
+            size.saturating_sub(EDGE_BYTES).max(EDGE_BYTES),
+        ))?;
+        chunk.clear();
+        file.take(EDGE_BYTES)
+            .read_to_end(&mut chunk)
+            .context(format!("Failed to read {}", path.display()))?;
+        hasher.update(&chunk);
+    }
+
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+/// A source that was transcoded, as the hash store remembers it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashEntry {
+    pub source: PathBuf,
+    /// Unix time the source was recorded
+    pub recorded_at: u64,
+}
+
+/// Contents of the hash store file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredHashes {
+    hashes: HashMap<String, HashEntry>,
+}
+
+/// The store file as last read or written
+#[derive(Default)]
+struct Loaded {
+    stored: StoredHashes,
+    /// Modification time of the file then, or `None` when there was no file
+    modified: Option<SystemTime>,
+}
+
+/// Content hashes of the sources transcoded so far, for `dedup_by_hash`
+pub struct HashStore {
+    path: PathBuf,
+    max_entries: usize,
+    max_age: Option<Duration>,
+    loaded: Mutex<Loaded>,
+}
+
+impl HashStore {
+    /// Open the store, starting over when its file is unreadable
+    pub fn open(config: &HashStoreConfig) -> Self {
+        let store = Self {
+            path: config.path.clone(),
+            max_entries: config.max_entries,
+            max_age: config.max_age.map(Into::into),
+            loaded: Mutex::new(Loaded::default()),
+        };
+        if let Ok(mut loaded) = store.loaded.lock() {
+            store.refresh(&mut loaded);
+        }
+        store
+    }
+
+    /// The source transcoded earlier with this content hash, unless it has expired
+    pub fn lookup(&self, hash: &str) -> Option<HashEntry> {
+        let mut loaded = self.loaded.lock().ok()?;
+        self.refresh(&mut loaded);
+        let entry = loaded.stored.hashes.get(hash)?;
+        (!self.is_expired(entry, now())).then(|| entry.clone())
+    }
+
+    /// Remember a transcoded source, pruning expired and surplus hashes before saving
+    pub fn record(&self, hash: String, source: &Path) -> Result<()> {
+        let mut loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| anyhow!("Hash store lock poisoned"))?;
+        // `sstc hashes clear` may have emptied the file since; saving must not undo that
+        self.refresh(&mut loaded);
+        let hashes = &mut loaded.stored.hashes;
+        let now = now();
+        hashes.insert(
+            hash.clone(),
+            HashEntry {
+                source: source.to_path_buf(),
+                recorded_at: now,
+            },
+        );
+
+        hashes.retain(|_, entry| !self.is_expired(entry, now));
+        if hashes.len() > self.max_entries {
+            // Newest first, and the hash just recorded before others of the same second
+            let mut newest: Vec<(u64, bool, String)> = hashes
+                .iter()
+                .map(|(key, entry)| (entry.recorded_at, *key == hash, key.clone()))
+                .collect();
+            newest.sort_unstable_by(|a, b| b.cmp(a));
+            for (_, _, key) in newest.into_iter().skip(self.max_entries) {
+                hashes.remove(&key);
+            }
+        }
+
+        save(&self.path, &loaded.stored)?;
+        loaded.modified = modified(&self.path);
+        Ok(())
+    }
+
+    /// Read the file again when something else changed it since it was last seen
+    fn refresh(&self, loaded: &mut Loaded) {
+        let modified = modified(&self.path);
+        if loaded.modified.is_some() && modified == loaded.modified {
+            return;
+        }
+        loaded.stored = match load(&self.path) {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("Starting with an empty hash store: {:#}", e);
+                StoredHashes::default()
+            }
+        };
+        loaded.modified = modified;
+    }
+
+    fn is_expired(&self, entry: &HashEntry, now: u64) -> bool {
+        self.max_age
+            .is_some_and(|max_age| now.saturating_sub(entry.recorded_at) > max_age.as_secs())
+    }
+}
+
+/// Forget every hash in the store at `path`, returning how many there were
+pub fn clear(path: &Path) -> Result<usize> {
+    let count = load(path)?.hashes.len();
+    save(path, &StoredHashes::default())?;
+    Ok(count)
+}
+
+/// Hashes in the store file; none when there is no file yet
+fn load(path: &Path) -> Result<StoredHashes> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(StoredHashes::default()),
+        Err(e) => return Err(e).context(format!("Failed to read hash store: {}", path.display())),
+    };
+    serde_json::from_str(&content)
+        .context(format!("Failed to parse hash store: {}", path.display()))
+}
+
+/// Replace the store file through a temporary sibling, so a crash never truncates it
+fn save(path: &Path, stored: &StoredHashes) -> Result<()> {
+    let temp = artifacts::temp_path_for(path);
+    std::fs::write(&temp, serde_json::to_string(stored)?)
+        .context(format!("Failed to write hash store: {}", temp.display()))?;
+    std::fs::rename(&temp, path)
+        .context(format!("Failed to replace hash store: {}", path.display()))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &Path, max_entries: usize, max_age: Option<&str>) -> HashStore {
+        HashStore::open(&HashStoreConfig {
+            path: dir.join("hashes.json"),
+            max_entries,
+            max_age: max_age.map(|age| age.parse().unwrap()),
+        })
+    }
+
+    /// Write the store file with `hash` recorded `ago` seconds back, for each pair
+    fn write_hashes(dir: &Path, hashes: &[(&str, u64)]) {
+        let stored = StoredHashes {
+            hashes: hashes
+                .iter()
+                .map(|&(hash, ago)| {
+                    let entry = HashEntry {
+                        source: PathBuf::from(format!("/in/{}.mp4", hash)),
+                        recorded_at: now() - ago,
+                    };
+                    (hash.to_string(), entry)
+                })
+                .collect(),
+        };
+        save(&dir.join("hashes.json"), &stored).unwrap();
+    }
+
+    fn stored_hashes(dir: &Path) -> Vec<String> {
+        let mut hashes: Vec<_> = load(&dir.join("hashes.json"))
+            .unwrap()
+            .hashes
+            .into_keys()
+            .collect();
+        hashes.sort();
+        hashes
+    }
+
+    #[test]
+    fn small_files_are_hashed_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        let mut content = vec![0u8; 1024 * 1024];
+        std::fs::write(&path, &content).unwrap();
+        let before = fingerprint(&path).unwrap();
+
+        content[512 * 1024] = 1;
+        std::fs::write(&path, &content).unwrap();
+        assert_ne!(fingerprint(&path).unwrap(), before);
+        assert_eq!(fingerprint(&path).unwrap().len(), 16);
+    }
+
+    #[test]
+    fn large_files_are_hashed_by_their_ends_and_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        let edge = EDGE_BYTES as usize;
+        let mut content = vec![0u8; 3 * edge];
+        std::fs::write(&path, &content).unwrap();
+        let before = fingerprint(&path).unwrap();
+
+        // The middle is never read
+        content[edge + edge / 2] = 1;
+        std::fs::write(&path, &content).unwrap();
+        assert_eq!(fingerprint(&path).unwrap(), before);
+
+        for changed in [0, edge - 1, 2 * edge, 3 * edge - 1] {
+            let mut changed_content = content.clone();
+            changed_content[changed] = 1;
+            std::fs::write(&path, &changed_content).unwrap();
+            assert_ne!(fingerprint(&path).unwrap(), before, "byte {}", changed);
+        }
+
+        content.push(0);
+        std::fs::write(&path, &content).unwrap();
+        assert_ne!(fingerprint(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn files_between_one_and_two_edges_are_hashed_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        let edge = EDGE_BYTES as usize;
+        let mut content = vec![0u8; edge + edge / 2];
+        std::fs::write(&path, &content).unwrap();
+        let before = fingerprint(&path).unwrap();
+
+        content[edge + 1] = 1;
+        std::fs::write(&path, &content).unwrap();
+        assert_ne!(fingerprint(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn record_keeps_the_newest_max_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hashes(dir.path(), &[("old", 300), ("tie1", 100), ("tie2", 100)]);
+        let store = store(dir.path(), 2, None);
+
+        store
+            .record("new".into(), Path::new("/in/new.mp4"))
+            .unwrap();
+
+        // One of the two recorded in the same second has to go as well
+        let kept = stored_hashes(dir.path());
+        assert_eq!(kept.len(), 2, "{:?}", kept);
+        assert_eq!(kept[0], "new");
+        assert!(kept[1].starts_with("tie"), "{:?}", kept);
+        assert!(store.lookup("old").is_none());
+    }
+
+    #[test]
+    fn record_keeps_the_new_hash_among_others_of_the_same_second() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 1, None);
+
+        store.record("a".into(), Path::new("/in/a.mp4")).unwrap();
+        store.record("b".into(), Path::new("/in/b.mp4")).unwrap();
+
+        assert_eq!(stored_hashes(dir.path()), ["b"]);
+    }
+
+    #[test]
+    fn record_drops_hashes_past_max_age() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hashes(dir.path(), &[("old", 3 * 86400), ("recent", 60)]);
+        let store = store(dir.path(), 100, Some("1d"));
+
+        store
+            .record("new".into(), Path::new("/in/new.mp4"))
+            .unwrap();
+
+        assert_eq!(stored_hashes(dir.path()), ["new", "recent"]);
+    }
+
+    #[test]
+    fn lookup_ignores_expired_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hashes(dir.path(), &[("old", 3 * 86400), ("recent", 60)]);
+        let store = store(dir.path(), 100, Some("1d"));
+
+        assert!(store.lookup("old").is_none());
+        assert_eq!(
+            store.lookup("recent").unwrap().source,
+            Path::new("/in/recent.mp4")
+        );
+        assert!(store.lookup("unknown").is_none());
+    }
+
+    #[test]
+    fn clear_empties_the_store_and_counts_what_it_forgot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hashes.json");
+        assert_eq!(clear(&path).unwrap(), 0);
+
+        write_hashes(dir.path(), &[("a", 10), ("b", 20)]);
+        assert_eq!(clear(&path).unwrap(), 2);
+        assert_eq!(stored_hashes(dir.path()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn clearing_under_an_open_store_sticks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), 100, None);
+        store.record("a".into(), Path::new("/in/a.mp4")).unwrap();
+
+        // As `sstc hashes clear` does while the service runs
+        std::thread::sleep(Duration::from_millis(20));
+        clear(&dir.path().join("hashes.json")).unwrap();
+
+        assert!(store.lookup("a").is_none());
+        store.record("b".into(), Path::new("/in/b.mp4")).unwrap();
+        assert_eq!(stored_hashes(dir.path()), ["b"]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::job::{JobOutcome, JobRecord};
+use crate::rusage::ResourceUsage;
+use crate::shell;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One finished job as stored in the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    /// Unix time the job finished
+    pub finished_at: u64,
+    pub source: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub used_fallback: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub overwrote_existing: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_secs: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<JobOutcome>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Program and arguments exactly as passed to ffmpeg; the environment is never recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ffmpeg_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceUsage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Complete ffmpeg output of the job, while `job_logs_dir` keeps it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+}
+
+impl HistoryEntry {
+    /// The recorded ffmpeg invocation as a shell line, run from the original working directory
+    pub fn shell_command(&self) -> Option<String> {
+        let command = shell::join(self.command.as_ref()?);
+        Some(match &self.working_dir {
+            Some(dir) => format!("cd {} && {}", shell::quote(&dir.to_string_lossy()), command),
+            None => command,
+        })
+    }
+}
+
+/// Append-only JSON Lines log of finished jobs; ids are line numbers starting at 1
+pub struct History {
+    path: PathBuf,
+    /// Id of the next entry, counted from the file once on the first append
+    next_id: Mutex<Option<u64>>,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            next_id: Mutex::new(None),
+        }
+    }
+
+    pub fn append(&self, record: &JobRecord, error: Option<String>) -> Result<u64> {
+        let mut next_id = self
+            .next_id
+            .lock()
+            .map_err(|_| anyhow!("History lock poisoned"))?;
+
+        let id = match *next_id {
+            Some(id) => id,
+            None => count_lines(&self.path)? + 1,
+        };
+        let entry = HistoryEntry {
+            id,
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            source: record.source.clone(),
+            output: record.output.clone(),
+            preset: record.preset.clone(),
+            used_fallback: record.used_fallback,
+            overwrote_existing: record.overwrote_existing,
+            input_size: record.input_size,
+            output_size: record.output_size,
+            elapsed_secs: record.elapsed.map(|d| d.as_secs_f64()),
+            outcome: record.outcome.clone(),
+            error,
+            command: record.command.clone(),
+            ffmpeg_version: record.ffmpeg_version.clone(),
+            working_dir: record.working_dir.clone(),
+            resources: record.resources,
+            warnings: record.warnings.clone(),
+            log_file: record.log_file.clone(),
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!(
+                "Failed to open history file: {}",
+                self.path.display()
+            ))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?).context(format!(
+            "Failed to write history file: {}",
+            self.path.display()
+        ))?;
+
+        *next_id = Some(id + 1);
+        Ok(id)
+    }
+}
+
+/// ffmpeg CPU seconds recorded per preset, failed jobs included
+pub fn cpu_secs_by_preset(entries: &[HistoryEntry]) -> BTreeMap<String, f64> {
+    let mut totals = BTreeMap::new();
+    for entry in entries {
+        if let Some(usage) = &entry.resources {
+            let preset = entry.preset.clone().unwrap_or_else(|| "-".to_string());
+            *totals.entry(preset).or_insert(0.0) += usage.cpu_secs();
+        }
+    }
+    totals
+}
+
+/// Read every entry of a history file, oldest first
+pub fn load(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let file = std::fs::File::open(path)
+        .context(format!("Failed to open history file: {}", path.display()))?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|(n, line)| {
+            let line = line?;
+            serde_json::from_str(&line).context(format!("Invalid history entry on line {}", n + 1))
+        })
+        .collect()
+}
+
+fn count_lines(path: &Path) -> Result<u64> {
+    match std::fs::File::open(path) {
+        Ok(file) => Ok(BufReader::new(file).lines().count() as u64),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(anyhow::Error::new(e)
+            .context(format!("Failed to read history file: {}", path.display()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Sandbox, BASIC_CONFIG};
+    use crate::transcoder::Transcoder;
+
+    #[tokio::test]
+    async fn records_the_command_ffmpeg_ran() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(
+            &BASIC_CONFIG.replace("presets:", "history_file: {dir}/history.jsonl\npresets:"),
+        );
+        let transcoder = Transcoder::new(config);
+        let source = sandbox.file("in/it's a clip.mp4");
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let entries = load(&sandbox.path().join("history.jsonl")).unwrap();
+        let [entry] = entries.as_slice() else {
+            panic!("expected one entry, got {:?}", entries);
+        };
+        let command = entry.command.clone().unwrap();
+        let ran = sandbox.calls("ffmpeg").pop().unwrap();
+        assert_eq!(command[1..], ran);
+        assert_eq!(
+            entry.ffmpeg_version.as_deref(),
+            Some("ffmpeg version 6.1-fake Copyright (c) fake")
+        );
+        assert!(entry.resources.is_some());
+        assert_eq!(
+            entry.shell_command().unwrap(),
+            format!(
+                "cd {} && {}",
+                shell::quote(&std::env::current_dir().unwrap().to_string_lossy()),
+                shell::join(&command)
+            )
+        );
+    }
+
+    #[test]
+    fn ids_continue_the_file_counted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        std::fs::write(&path, "{}\n{}\n").unwrap();
+        let history = History::new(path.clone());
+        let record = JobRecord::new(PathBuf::from("/in/clip.mp4"));
+
+        assert_eq!(history.append(&record, None).unwrap(), 3);
+        // Counted once, so emptying the file doesn't start the ids over
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(history.append(&record, None).unwrap(), 4);
+        assert_eq!(history.append(&record, Some("failed".into())).unwrap(), 5);
+
+        let ids: Vec<u64> = load(&path).unwrap().iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [4, 5]);
+    }
+
+    #[test]
+    fn cpu_time_totals_by_preset() {
+        let entry = |preset: Option<&str>, cpu_secs: Option<f64>, error: Option<&str>| {
+            serde_json::from_value::<HistoryEntry>(serde_json::json!({
+                "id": 1,
+                "finished_at": 0,
+                "source": "/in/clip.mp4",
+                "preset": preset,
+                "error": error,
+                "resources": cpu_secs.map(|secs| serde_json::json!({
+                    "user_cpu_secs": secs,
+                    "system_cpu_secs": 1.0,
+                    "max_rss_kb": 0,
+                })),
+            }))
+            .unwrap()
+        };
+        let entries = [
+            entry(Some("hevc"), Some(9.0), None),
+            entry(Some("hevc"), Some(4.0), Some("encoder failed")),
+            entry(Some("remux"), None, None),
+            entry(None, Some(0.5), None),
+        ];
+        assert_eq!(
+            cpu_secs_by_preset(&entries),
+            BTreeMap::from([("-".to_string(), 1.5), ("hevc".to_string(), 15.0)])
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::job::{JobOutcome, JobRecord};
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Schema changes in the order they were made; a database at `user_version` N has the first
+/// N applied. Only ever append to this list, so databases of older versions catch up.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE jobs (
+        id INTEGER PRIMARY KEY,
+        source TEXT NOT NULL,
+        output TEXT,
+        preset TEXT,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL,
+        source_size INTEGER,
+        output_size INTEGER,
+        status TEXT NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX jobs_source ON jobs (source);
+"];
+
+/// How long a write waits for another sstc sharing the database to finish its own
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A successful transcode found in the database
+#[derive(Debug, Clone)]
+pub struct PastJob {
+    /// Unix time the job finished
+    pub finished_at: u64,
+}
+
+/// SQLite database every finished job is recorded in, one row per target
+pub struct HistoryDb {
+    path: PathBuf,
+    /// Opened on first use, so a database that can't be opened fails jobs rather than startup
+    connection: Mutex<Option<Connection>>,
+}
+
+impl HistoryDb {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn with_connection<T>(&self, f: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+        let mut slot = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("History database lock poisoned"))?;
+        let connection = match slot.take() {
+            Some(connection) => connection,
+            None => open(&self.path)?,
+        };
+        let result = f(&connection);
+        *slot = Some(connection);
+        result
+    }
+
+    /// Record a finished job, started `elapsed` before now
+    pub fn record(&self, record: &JobRecord, error: Option<&str>) -> Result<()> {
+        let finished_at = unix_now();
+        let started_at =
+            finished_at.saturating_sub(record.elapsed.map_or(0, |elapsed| elapsed.as_secs()));
+        let status = match &record.outcome {
+            Some(outcome) => outcome_kind(outcome)?,
+            None => "unknown".to_string(),
+        };
+        let source_size = record
+            .input_size
+            .or_else(|| std::fs::metadata(&record.source).ok().map(|m| m.len()));
+        self.with_connection(|connection| {
+            connection
+                .execute(
+                    "INSERT INTO jobs (source, output, preset, started_at, finished_at,
+                         source_size, output_size, status, error)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        record.source.to_string_lossy(),
+                        record.output.as_ref().map(|p| p.to_string_lossy()),
+                        record.preset,
+                        started_at as i64,
+                        finished_at as i64,
+                        source_size.map(|size| size as i64),
+                        record.output_size.map(|size| size as i64),
+                        status,
+                        error,
+                    ],
+                )
+                .context(format!(
+                    "Failed to write history database: {}",
+                    self.path.display()
+                ))?;
+            Ok(())
+        })
+    }
+
+    /// The latest successful transcode of `source` at `source_size` bytes to `output`
+    pub fn transcoded(
+        &self,
+        source: &Path,
+        source_size: u64,
+        output: &Path,
+    ) -> Result<Option<PastJob>> {
+        self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT finished_at FROM jobs
+                     WHERE source = ?1 AND source_size = ?2 AND output = ?3
+                         AND status = 'transcoded'
+                     ORDER BY finished_at DESC LIMIT 1",
+                    params![
+                        source.to_string_lossy(),
+                        source_size as i64,
+                        output.to_string_lossy()
+                    ],
+                    |row| {
+                        Ok(PastJob {
+                            finished_at: row.get::<_, i64>(0)? as u64,
+                        })
+                    },
+                )
+                .optional()
+                .context(format!(
+                    "Failed to query history database: {}",
+                    self.path.display()
+                ))
+        })
+    }
+}
+
+/// Open the database at `path`, bringing its schema up to date
+fn open(path: &Path) -> Result<Connection> {
+    let mut connection = Connection::open(path).context(format!(
+        "Failed to open history database: {}",
+        path.display()
+    ))?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "History database {} was written by a newer sstc (schema version {})",
+            path.display(),
+            version
+        ));
+    }
+    if version < MIGRATIONS.len() {
+        let transaction = connection.transaction()?;
+        for migration in &MIGRATIONS[version..] {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit().context(format!(
+            "Failed to migrate history database: {}",
+            path.display()
+        ))?;
+    }
+    Ok(connection)
+}
+
+/// The `kind` an outcome is serialized with, like `transcoded` or `skipped_existing`
+fn outcome_kind(outcome: &JobOutcome) -> Result<String> {
+    serde_json::to_value(outcome)?
+        .get("kind")
+        .and_then(|kind| kind.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Job outcome without a kind"))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(path: &Path) -> usize {
+        Connection::open(path)
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn transcoded(source: &str) -> JobRecord {
+        let mut record = JobRecord::new(PathBuf::from(source));
+        record.output = Some(PathBuf::from("/out/clip.mkv"));
+        record.input_size = Some(1000);
+        record.outcome = Some(JobOutcome::Transcoded);
+        record
+    }
+
+    #[test]
+    fn fresh_database_gets_the_whole_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        let db = HistoryDb::new(path.clone());
+        db.record(&transcoded("/in/clip.mp4"), None).unwrap();
+
+        assert_eq!(user_version(&path), MIGRATIONS.len());
+        let past = db
+            .transcoded(Path::new("/in/clip.mp4"), 1000, Path::new("/out/clip.mkv"))
+            .unwrap();
+        assert!(past.is_some());
+        // Another size is another version of the source
+        let past = db
+            .transcoded(Path::new("/in/clip.mp4"), 999, Path::new("/out/clip.mkv"))
+            .unwrap();
+        assert!(past.is_none());
+    }
+
+    #[test]
+    fn current_database_is_opened_as_it_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        HistoryDb::new(path.clone())
+            .record(&transcoded("/in/clip.mp4"), None)
+            .unwrap();
+
+        // Migrating again would fail on the existing table
+        let db = HistoryDb::new(path.clone());
+        db.record(&transcoded("/in/other.mp4"), Some("boom"))
+            .unwrap();
+        assert_eq!(user_version(&path), MIGRATIONS.len());
+        let rows: i64 = Connection::open(&path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM jobs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 2);
+    }
+
+    #[test]
+    fn database_of_a_newer_sstc_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+
+        let error = HistoryDb::new(path.clone())
+            .record(&transcoded("/in/clip.mp4"), None)
+            .unwrap_err();
+        assert!(error.to_string().contains("newer sstc"), "{}", error);
+        assert_eq!(user_version(&path), MIGRATIONS.len() + 1);
+    }
+}
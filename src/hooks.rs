@@ -0,0 +1,248 @@
+use crate::config::{Config, HookFailurePolicy};
+use anyhow::{anyhow, Context, Result};
+use owo_colors::OwoColorize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::PoisonError;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+const DEFAULT_DRAIN_SETTLE_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueueState {
+    Drained,
+    Active,
+}
+
+/// Runs the configured commands when the queue goes from idle to busy and back.
+pub struct QueueHooks {
+    on_active: Option<String>,
+    on_drained: Option<String>,
+    settle: Duration,
+    failure_policy: HookFailurePolicy,
+    state: Mutex<QueueState>,
+    /// Bumped on every activation so pending drain timers know they are stale
+    generation: AtomicU64,
+    /// The hook started last, which the next one waits for so the hooks run in the order the
+    /// queue changed state
+    last_hook: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl QueueHooks {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            on_active: config.on_queue_active.clone(),
+            on_drained: config.on_queue_drained.clone(),
+            settle: config
+                .drain_settle
+                .map_or(Duration::from_secs(DEFAULT_DRAIN_SETTLE_SECS), Into::into),
+            failure_policy: config.on_hook_failure,
+            state: Mutex::new(QueueState::Drained),
+            generation: AtomicU64::new(0),
+            last_hook: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn settle(&self) -> Duration {
+        self.settle
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Mark the queue as busy, starting the active hook on the 0→N transition. Only the hold
+    /// policy waits for the hook to finish; otherwise it runs in the background.
+    ///
+    /// Returns `false` when the hook failed and the policy says to hold the queue.
+    pub async fn activate(&self) -> bool {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        let mut state = self.state.lock().await;
+        if *state == QueueState::Active {
+            return true;
+        }
+
+        if let Some(command) = &self.on_active {
+            info!("Queue became active, running hook: {}", command.cyan());
+            let succeeded = self.start_hook(command, "active");
+            if self.failure_policy == HookFailurePolicy::Hold && !succeeded.await.unwrap_or(false) {
+                warn!("Holding the queue until the active hook succeeds");
+                return false;
+            }
+        }
+
+        *state = QueueState::Active;
+        true
+    }
+
+    /// Run the drained hook if nothing activated the queue since `generation` was taken.
+    pub async fn drain(&self, generation: u64) {
+        if self.generation() != generation {
+            return;
+        }
+
+        let mut state = self.state.lock().await;
+        if *state == QueueState::Drained {
+            return;
+        }
+        *state = QueueState::Drained;
+
+        if let Some(command) = &self.on_drained {
+            info!("Queue drained, running hook: {}", command.cyan());
+            self.start_hook(command, "drained");
+        }
+    }
+
+    /// Run `command` in the background once the hook started before it is done. The receiver
+    /// learns whether it succeeded.
+    fn start_hook(&self, command: &str, name: &'static str) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        let command = command.to_string();
+        let mut last_hook = self
+            .last_hook
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let previous = last_hook.take();
+        *last_hook = Some(tokio::spawn(async move {
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            let succeeded = match run_hook(&command).await {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Queue {} hook failed: {}", name, e.red());
+                    false
+                }
+            };
+            let _ = tx.send(succeeded);
+        }));
+        rx
+    }
+}
+
+async fn run_hook(command: &str) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .status()
+        .await
+        .context(format!("Failed to execute hook: {}", command))?;
+
+    if !status.success() {
+        return Err(anyhow!("Hook exited with status: {}", status));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Sandbox, BASIC_CONFIG};
+    use std::time::Instant;
+
+    /// Hooks of a config with `settings` added, logging to `hooks.log` in the sandbox
+    fn hooks(sandbox: &Sandbox, settings: &str) -> QueueHooks {
+        let config = sandbox.config(&format!("{}{}", settings, BASIC_CONFIG));
+        QueueHooks::new(&config)
+    }
+
+    /// Lines the hooks logged, once the last one started is done
+    async fn hook_log(sandbox: &Sandbox, hooks: &QueueHooks) -> Vec<String> {
+        let last = hooks
+            .last_hook
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(last) = last {
+            last.await.unwrap();
+        }
+        std::fs::read_to_string(sandbox.path().join("hooks.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    const LOGGING_HOOKS: &str = "
+on_queue_active: echo active >> {dir}/hooks.log
+on_queue_drained: echo drained >> {dir}/hooks.log
+";
+
+    #[tokio::test]
+    async fn hooks_run_once_per_transition_in_order() {
+        let sandbox = Sandbox::new();
+        let hooks = hooks(&sandbox, LOGGING_HOOKS);
+
+        // 0→N runs the active hook once, however many jobs start
+        assert!(hooks.activate().await);
+        assert!(hooks.activate().await);
+        assert_eq!(hook_log(&sandbox, &hooks).await, ["active"]);
+
+        // N→0 runs the drained hook once
+        hooks.drain(hooks.generation()).await;
+        hooks.drain(hooks.generation()).await;
+        assert_eq!(hook_log(&sandbox, &hooks).await, ["active", "drained"]);
+
+        assert!(hooks.activate().await);
+        hooks.drain(hooks.generation()).await;
+        assert_eq!(
+            hook_log(&sandbox, &hooks).await,
+            ["active", "drained", "active", "drained"]
+        );
+    }
+
+    #[tokio::test]
+    async fn activation_after_the_drain_timer_started_cancels_the_drain_hook() {
+        let sandbox = Sandbox::new();
+        let hooks = hooks(&sandbox, LOGGING_HOOKS);
+        assert!(hooks.activate().await);
+
+        let generation = hooks.generation();
+        assert!(hooks.activate().await);
+        hooks.drain(generation).await;
+
+        assert_eq!(hook_log(&sandbox, &hooks).await, ["active"]);
+    }
+
+    #[tokio::test]
+    async fn active_hook_runs_in_the_background_unless_it_holds_the_queue() {
+        let sandbox = Sandbox::new();
+        let hooks = hooks(&sandbox, "on_queue_active: sleep 5\n");
+
+        let started = Instant::now();
+        assert!(hooks.activate().await);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn failed_active_hook_holds_the_queue_only_under_the_hold_policy() {
+        let sandbox = Sandbox::new();
+        let hooks = hooks(&sandbox, "on_queue_active: exit 1\n");
+        assert!(hooks.activate().await);
+
+        let failing = "
+on_queue_active: echo tried >> {dir}/hooks.log; exit 1
+on_hook_failure: hold
+";
+        let hooks = self::hooks(&sandbox, failing);
+        assert!(!hooks.activate().await);
+        // Still idle, so the next activation tries the hook again
+        assert!(!hooks.activate().await);
+        assert_eq!(hook_log(&sandbox, &hooks).await, ["tried", "tried"]);
+
+        std::fs::remove_file(sandbox.path().join("hooks.log")).unwrap();
+        let succeeding = "
+on_queue_active: echo tried >> {dir}/hooks.log
+on_hook_failure: hold
+";
+        let hooks = self::hooks(&sandbox, succeeding);
+        assert!(hooks.activate().await);
+        assert!(hooks.activate().await);
+        assert_eq!(hook_log(&sandbox, &hooks).await, ["tried"]);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::artifacts;
+use crate::ffprobe;
+use anyhow::{anyhow, Context, Result};
+use dashmap::DashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::{debug, info, warn};
+
+/// Whether writing to `output` would overwrite `input` itself
+pub fn is_same_file(input: &Path, output: &Path) -> bool {
+    match (std::fs::canonicalize(input), std::fs::canonicalize(output)) {
+        (Ok(input), Ok(output)) => input == output,
+        _ => false,
+    }
+}
+
+/// Check that a finished replacement is a readable media file before it goes anywhere near the source
+pub async fn verify_replacement(temp_path: &Path) -> Result<()> {
+    let size = std::fs::metadata(temp_path)
+        .context(format!("Replacement is missing: {}", temp_path.display()))?
+        .len();
+    if size == 0 {
+        return Err(anyhow!("Replacement is empty: {}", temp_path.display()));
+    }
+
+    let probe = ffprobe::probe(temp_path).await.context(format!(
+        "Replacement is unreadable: {}",
+        temp_path.display()
+    ))?;
+    if probe.duration() <= 0.0 {
+        return Err(anyhow!(
+            "Replacement has no duration: {}",
+            temp_path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Swap a verified temp file in for the source, moving the original to `trash_dir` first if set.
+///
+/// The original is only touched once everything before it succeeded, and is put back if the
+/// final rename fails after it was trashed.
+pub fn replace_source(source: &Path, temp_path: &Path, trash_dir: Option<&Path>) -> Result<()> {
+    let trashed = match trash_dir {
+        Some(dir) => Some(move_to_trash(source, dir)?),
+        None => None,
+    };
+
+    if let Err(e) = std::fs::rename(temp_path, source) {
+        if let Some(trashed) = &trashed {
+            if let Err(restore) = move_file(trashed, source) {
+                warn!(
+                    "Failed to restore {} from {}: {}",
+                    source.display(),
+                    trashed.display(),
+                    restore
+                );
+            }
+        }
+        return Err(anyhow!(e).context(format!(
+            "Failed to replace {} with {}",
+            source.display(),
+            temp_path.display()
+        )));
+    }
+
+    info!("Replaced {} in place", source.display());
+    Ok(())
+}
+
+fn move_to_trash(source: &Path, trash_dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(trash_dir).context(format!(
+        "Failed to create trash directory: {}",
+        trash_dir.display()
+    ))?;
+
+    let name = source.file_name().context("Source has no file name")?;
+    let mut target = trash_dir.join(name);
+    if target.exists() {
+        let stamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        target = trash_dir.join(format!("{}.{}", stamp, name.to_string_lossy()));
+    }
+
+    move_file(source, &target).context(format!(
+        "Failed to move {} to trash {}",
+        source.display(),
+        target.display()
+    ))?;
+    debug!(
+        "Moved original {} to {}",
+        source.display(),
+        target.display()
+    );
+
+    Ok(target)
+}
+
+/// Rename, falling back to copy and delete when the target is on another filesystem.
+///
+/// Blocks for as long as the copy takes, so async callers run it on the blocking pool.
+pub fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_then_remove(from, to),
+        result => result,
+    }
+}
+
+/// Copy `from` next to `to` under a temporary name, sync it to disk and rename it into place
+/// before deleting `from`, so a crash or power loss at any point leaves a whole copy behind.
+/// A copy that fails part way is removed.
+fn copy_then_remove(from: &Path, to: &Path) -> std::io::Result<()> {
+    let temp = artifacts::temp_path_for(to);
+    let copied = std::fs::copy(from, &temp)
+        .and_then(|_| File::open(&temp)?.sync_all())
+        .and_then(|()| std::fs::rename(&temp, to));
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    // The rename itself only lasts once the directory holding it is synced too
+    if let Some(dir) = to.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    std::fs::remove_file(from)
+}
+
+/// Sources rewritten in place or moved by `source_action`, so their own change events don't
+/// queue them again
+#[derive(Debug, Clone, Default)]
+pub struct ReplacedFiles {
+    files: Arc<DashMap<PathBuf, (u64, SystemTime)>>,
+}
+
+impl ReplacedFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, path: &Path) {
+        if let Some(fingerprint) = fingerprint(path) {
+            self.files.insert(path.to_path_buf(), fingerprint);
+        }
+    }
+
+    /// Whether the file is still exactly what an in-place replace left behind
+    pub fn is_unchanged(&self, path: &Path) -> bool {
+        let Some(recorded) = self.files.get(path).map(|entry| *entry) else {
+            return false;
+        };
+
+        if fingerprint(path) == Some(recorded) {
+            return true;
+        }
+
+        self.files.remove(path);
+        false
+    }
+}
+
+fn fingerprint(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn replace_moves_the_original_to_the_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mkv");
+        let temp = dir.path().join("clip.sstc.tmp.mkv");
+        let trash = dir.path().join("trash");
+        std::fs::write(&source, b"original").unwrap();
+        std::fs::write(&temp, b"replacement").unwrap();
+        std::fs::create_dir(&trash).unwrap();
+        std::fs::write(trash.join("clip.mkv"), b"older original").unwrap();
+
+        replace_source(&source, &temp, Some(&trash)).unwrap();
+
+        assert_eq!(std::fs::read(&source).unwrap(), b"replacement");
+        assert!(!temp.exists());
+        // An earlier original of the same name is kept too
+        assert_eq!(std::fs::read_dir(&trash).unwrap().count(), 2);
+        assert_eq!(
+            std::fs::read(trash.join("clip.mkv")).unwrap(),
+            b"older original"
+        );
+    }
+
+    #[test]
+    fn failed_swap_puts_the_original_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mkv");
+        let trash = dir.path().join("trash");
+        std::fs::write(&source, b"original").unwrap();
+
+        let missing = dir.path().join("clip.sstc.tmp.mkv");
+        assert!(replace_source(&source, &missing, Some(&trash)).is_err());
+
+        assert_eq!(std::fs::read(&source).unwrap(), b"original");
+        assert_eq!(std::fs::read_dir(&trash).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn copy_across_filesystems_replaces_the_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mkv");
+        let target = dir.path().join("done/clip.mkv");
+        std::fs::write(&source, b"original").unwrap();
+        std::fs::create_dir(dir.path().join("done")).unwrap();
+
+        copy_then_remove(&source, &target).unwrap();
+
+        assert!(!source.exists());
+        assert_eq!(std::fs::read(&target).unwrap(), b"original");
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("done")).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn failed_copy_keeps_the_original_and_leaves_no_partial_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mkv");
+        std::fs::write(&source, b"original").unwrap();
+        // The copy succeeds, but renaming it over a directory doesn't
+        let target = dir.path().join("taken");
+        std::fs::create_dir_all(target.join("inside")).unwrap();
+
+        assert!(copy_then_remove(&source, &target).is_err());
+
+        assert_eq!(std::fs::read(&source).unwrap(), b"original");
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["clip.mkv", "taken"]);
+    }
+
+    #[test]
+    fn only_a_move_across_filesystems_falls_back_to_copying() {
+        if crate::test_support::runs_as_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        let source = locked.join("clip.mkv");
+        std::fs::write(&source, b"original").unwrap();
+        // The source can be read and so copied, but not renamed out of its directory
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o555)).unwrap();
+        let target = dir.path().join("clip.mkv");
+
+        let result = move_file(&source, &target);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert!(source.exists());
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn same_file_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("clip.mkv");
+        std::fs::write(&source, b"original").unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("link")).unwrap();
+
+        assert!(is_same_file(&source, &dir.path().join("link/clip.mkv")));
+        assert!(!is_same_file(&source, &dir.path().join("clip.mp4")));
+    }
+}
@@ -0,0 +1,513 @@
+use crate::progress::short_duration;
+use crate::rusage::ResourceUsage;
+use crate::scaling::ScaleDecision;
+use bytesize::ByteSize;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// What kind of problem made ffmpeg exit with a failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfmpegFailure {
+    /// The encoder or its parameters rejected the source
+    Encoder,
+    /// Reading the source or writing the output failed
+    Io,
+    /// The output appeared after sstc decided to write it and ffmpeg refused to overwrite
+    OutputExists,
+    Unknown,
+}
+
+impl FfmpegFailure {
+    const IO_MARKERS: &'static [&'static str] = &[
+        "no space left on device",
+        "permission denied",
+        "no such file or directory",
+        "input/output error",
+        "read-only file system",
+        "broken pipe",
+        "disk quota exceeded",
+    ];
+
+    /// Phrases only ffmpeg's encoders and hardware devices use. Generic errno texts like
+    /// "Invalid argument" or "Function not implemented" are left out, as every part of ffmpeg
+    /// reports them, and so is "Incompatible pixel format", a warning ffmpeg recovers from.
+    const ENCODER_MARKERS: &'static [&'static str] = &[
+        "unknown encoder",
+        "encoder not found",
+        "error while opening encoder",
+        "could not open encoder",
+        "error initializing output stream",
+        "is invalid or not supported",
+        "not divisible by",
+        "error setting option",
+        "codec not currently supported in container",
+        // Hardware encoders without a usable device or driver
+        "no capable devices found",
+        "openencodesessionex failed",
+        "driver does not support the required nvenc api version",
+        "cannot load libcuda",
+        "cannot load libnvidia-encode",
+        "no usable encoding profile found",
+        "error initializing an internal mfx session",
+        "cannot open drm device",
+        "failed to initialise vaapi connection",
+        "device creation failed",
+    ];
+
+    /// Classify a failure from the ffmpeg stderr lines. Encoder failures are told apart
+    /// first, since a hardware device missing or locked down reads like an I/O error.
+    pub fn classify(stderr: &[String]) -> Self {
+        let lines: Vec<String> = stderr.iter().map(|l| l.to_lowercase()).collect();
+
+        let matches = |markers: &[&str]| {
+            lines
+                .iter()
+                .any(|line| markers.iter().any(|marker| line.contains(marker)))
+        };
+
+        if matches(&["already exists. exiting"]) {
+            FfmpegFailure::OutputExists
+        } else if matches(Self::ENCODER_MARKERS) {
+            FfmpegFailure::Encoder
+        } else if matches(Self::IO_MARKERS) {
+            FfmpegFailure::Io
+        } else {
+            FfmpegFailure::Unknown
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    /// The source is not a valid media file yet, most likely still being copied
+    NotReady(PathBuf),
+    /// Another job is already writing the same output path
+    OutputInUse { output: PathBuf, owner: PathBuf },
+    /// Another sstc instance sharing the inputs is working on the source
+    ClaimedElsewhere {
+        source: PathBuf,
+        host: String,
+        pid: u32,
+    },
+    /// sstc may not read the source or write into the output directory; retrying won't help
+    PermissionDenied {
+        path: PathBuf,
+        action: &'static str,
+        detail: String,
+    },
+    /// ffmpeg kept reporting progress while the output on disk stopped growing
+    OutputStagnant {
+        output: PathBuf,
+        out_time_secs: f64,
+        size: u64,
+    },
+    /// The output was projected to end up larger than `if_larger_than_source` allows
+    OutputTooLarge { projected: u64, source_size: u64 },
+    /// The schedule window closed with `hard_stop` while ffmpeg was still running
+    StoppedBySchedule,
+    /// The service shut down before the job finished
+    Interrupted,
+    /// `sstc cancel` stopped the job
+    Cancelled,
+    /// ffmpeg ran past `max_job_duration` or `max_speed_ratio` and was killed
+    TimedOut {
+        limit: Duration,
+        setting: &'static str,
+    },
+    /// ffmpeg exited with a non-zero status
+    Ffmpeg {
+        status: String,
+        kind: FfmpegFailure,
+        stderr: Vec<String>,
+    },
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::NotReady(path) => write!(
+                f,
+                "File is not valid or still being copied: {}",
+                path.display()
+            ),
+            JobError::OutputInUse { output, owner } => write!(
+                f,
+                "Output {} is already being written by the job for {}",
+                output.display(),
+                owner.display()
+            ),
+            JobError::ClaimedElsewhere { source, host, pid } => write!(
+                f,
+                "{} is claimed by {} (pid {})",
+                source.display(),
+                host,
+                pid
+            ),
+            JobError::PermissionDenied {
+                path,
+                action,
+                detail,
+            } => write!(
+                f,
+                "Permission denied {} {} ({})",
+                action,
+                path.display(),
+                detail
+            ),
+            JobError::OutputStagnant {
+                output,
+                out_time_secs,
+                size,
+            } => write!(
+                f,
+                "ffmpeg reported progress up to {:.0}s but {} stopped growing at {} bytes",
+                out_time_secs,
+                output.display(),
+                size
+            ),
+            JobError::OutputTooLarge {
+                projected,
+                source_size,
+            } => write!(
+                f,
+                "Output projected to reach {} bytes, more than the {} byte source allows",
+                projected, source_size
+            ),
+            JobError::StoppedBySchedule => {
+                write!(f, "Stopped at the end of the schedule window (hard_stop)")
+            }
+            JobError::Interrupted => write!(f, "Interrupted by shutdown"),
+            JobError::Cancelled => write!(f, "Cancelled on request"),
+            JobError::TimedOut { limit, setting } => write!(
+                f,
+                "Killed ffmpeg after {} ({})",
+                short_duration(limit.as_secs()),
+                setting
+            ),
+            JobError::Ffmpeg {
+                status,
+                kind,
+                stderr,
+            } => {
+                write!(f, "FFmpeg process failed with status: {}", status)?;
+                if *kind != FfmpegFailure::Unknown {
+                    write!(f, " ({:?} error)", kind)?;
+                }
+                if let Some(last) = stderr.last() {
+                    write!(f, ": {}", last)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl JobError {
+    /// Whether the job may succeed when tried again later without anything else changing,
+    /// like a source that is still being copied
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, JobError::NotReady(_))
+    }
+}
+
+/// How a job that ran to completion ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobOutcome {
+    /// ffmpeg produced the output
+    Transcoded,
+    /// The output was already there from an earlier run
+    SkippedExisting,
+    /// A filter on the input excluded the source
+    SkippedFilter { reason: String },
+    /// The source already meets the input's normalize rules
+    SkippedCompliant,
+    /// The source's video is already in one of the input's `skip_if_video_codec` codecs
+    SkippedCodec { codec: String },
+    /// `skip_if_in_history` found the target transcoded before, at this unix time
+    SkippedInHistory { transcoded_at: u64 },
+    /// `dedup_by_hash` found the same content transcoded before from `original`
+    SkippedDuplicate { original: PathBuf },
+    /// `if_larger_than_source: keep_original` stopped an encode that was outgrowing the source
+    KeptOriginal {
+        projected_size: u64,
+        source_size: u64,
+    },
+    /// `--dry-run` printed the ffmpeg commands instead of running them
+    WouldTranscode,
+    /// `sstc cancel` stopped the job before it finished
+    Cancelled,
+    /// The job gave up with an error
+    Failed,
+}
+
+impl JobOutcome {
+    pub fn is_skipped(&self) -> bool {
+        matches!(
+            self,
+            JobOutcome::SkippedExisting
+                | JobOutcome::SkippedFilter { .. }
+                | JobOutcome::SkippedCompliant
+                | JobOutcome::SkippedCodec { .. }
+                | JobOutcome::SkippedDuplicate { .. }
+                | JobOutcome::SkippedInHistory { .. }
+                | JobOutcome::KeptOriginal { .. }
+        )
+    }
+}
+
+impl fmt::Display for JobOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobOutcome::Transcoded => write!(f, "transcoded"),
+            JobOutcome::SkippedExisting => write!(f, "output already exists"),
+            JobOutcome::SkippedFilter { reason } => write!(f, "{}", reason),
+            JobOutcome::SkippedCompliant => write!(f, "already compliant"),
+            JobOutcome::SkippedCodec { codec } => write!(f, "already {}", codec),
+            JobOutcome::SkippedInHistory { transcoded_at } => {
+                let when = chrono::DateTime::from_timestamp(*transcoded_at as i64, 0)
+                    .map(|time| time.with_timezone(&chrono::Local))
+                    .map_or_else(
+                        || "-".to_string(),
+                        |time| time.format("%Y-%m-%d %H:%M").to_string(),
+                    );
+                write!(f, "transcoded before on {}", when)
+            }
+            JobOutcome::SkippedDuplicate { original } => {
+                write!(f, "same content as {}", original.display())
+            }
+            JobOutcome::KeptOriginal {
+                projected_size,
+                source_size,
+            } => write!(
+                f,
+                "kept original, output projected at {} for a {} source",
+                ByteSize::b(*projected_size).display().si(),
+                ByteSize::b(*source_size).display().si()
+            ),
+            JobOutcome::WouldTranscode => write!(f, "would be transcoded"),
+            JobOutcome::Cancelled => write!(f, "cancelled"),
+            JobOutcome::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Final frame counters reported by ffmpeg for an encode
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    pub frames: i64,
+    pub dup_frames: i64,
+    pub drop_frames: i64,
+}
+
+impl FrameStats {
+    /// Dropped frames as a percentage of `total_frames`
+    pub fn dropped_pct(&self, total_frames: i64) -> Option<f64> {
+        (total_frames > 0).then(|| self.drop_frames as f64 / total_frames as f64 * 100.0)
+    }
+}
+
+/// Audit record of a single processed file
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub source: PathBuf,
+    pub output: Option<PathBuf>,
+    pub preset: Option<String>,
+    pub used_fallback: bool,
+    /// The output replaced a file that was already there (`on_existing: overwrite` or
+    /// `if_source_newer: retranscode`)
+    pub overwrote_existing: bool,
+    pub input_size: Option<u64>,
+    pub output_size: Option<u64>,
+    pub frames: Option<FrameStats>,
+    /// Time spent encoding, fallback attempts included
+    pub elapsed: Option<Duration>,
+    /// How the preset's resolution cap was applied, when it has one
+    pub scale: Option<ScaleDecision>,
+    /// ffmpeg program and arguments of the last attempt, as executed
+    pub command: Option<Vec<String>>,
+    pub ffmpeg_version: Option<String>,
+    /// Directory ffmpeg ran in, which relative paths in `command` resolve against
+    pub working_dir: Option<PathBuf>,
+    /// CPU time and peak memory of the ffmpeg processes run for the job
+    pub resources: Option<ResourceUsage>,
+    /// How the job ended, once it has
+    pub outcome: Option<JobOutcome>,
+    /// Problems that didn't stop the job but should show up in its report
+    pub warnings: Vec<String>,
+    /// File in `job_logs_dir` the job's ffmpeg output went to
+    pub log_file: Option<PathBuf>,
+}
+
+/// Result of an express job, one record per target, shared by every caller coalesced onto it
+pub type JobResult = Result<Vec<JobRecord>, Arc<anyhow::Error>>;
+
+impl JobRecord {
+    pub fn new(source: PathBuf) -> Self {
+        Self {
+            source,
+            output: None,
+            preset: None,
+            used_fallback: false,
+            overwrote_existing: false,
+            input_size: None,
+            output_size: None,
+            frames: None,
+            elapsed: None,
+            scale: None,
+            command: None,
+            ffmpeg_version: None,
+            working_dir: None,
+            resources: None,
+            outcome: None,
+            warnings: Vec::new(),
+            log_file: None,
+        }
+    }
+
+    pub fn log(&self) {
+        let Some(output) = &self.output else {
+            return;
+        };
+
+        let frames = self
+            .frames
+            .as_ref()
+            .map(|f| format!(", {} dup / {} dropped frames", f.dup_frames, f.drop_frames))
+            .unwrap_or_default();
+        let scale = self
+            .scale
+            .as_ref()
+            .map(|s| format!(", {}", s))
+            .unwrap_or_default();
+
+        info!(
+            "Job finished: {} -> {} (preset: {}{}{}{})",
+            self.source.display(),
+            output.display().green(),
+            self.preset.as_deref().unwrap_or("-").cyan(),
+            if self.used_fallback {
+                ", used fallback"
+            } else {
+                ""
+            },
+            frames,
+            scale
+        );
+        for warning in &self.warnings {
+            warn!("  {}", warning.yellow());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_share_of_the_expected_frames() {
+        let frames = FrameStats {
+            frames: 245,
+            dup_frames: 0,
+            drop_frames: 5,
+        };
+        assert_eq!(frames.dropped_pct(250), Some(2.0));
+        assert_eq!(frames.dropped_pct(500), Some(1.0));
+        assert_eq!(frames.dropped_pct(0), None);
+        assert_eq!(FrameStats::default().dropped_pct(250), Some(0.0));
+    }
+
+    #[test]
+    fn ffmpeg_failures_are_classified_by_their_stderr() {
+        use FfmpegFailure::*;
+        let cases: &[(FfmpegFailure, &[&str])] = &[
+            (Encoder, &["[vost#0:0 @ 0x55d0c3f0] Unknown encoder 'libsvtav1'"]),
+            (
+                Encoder,
+                &[
+                    "[h264_nvenc @ 0x5581a2c0] OpenEncodeSessionEx failed: unsupported device (2): (no details)",
+                    "[h264_nvenc @ 0x5581a2c0] No capable devices found",
+                    "[vost#0:0/h264_nvenc @ 0x5581a1c0] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height.",
+                    "[vf#0:0 @ 0x5581a3c0] Error sending frames to consumers: Function not implemented",
+                    "[vf#0:0 @ 0x5581a3c0] Task finished with error code: -38 (Function not implemented)",
+                    "Conversion failed!",
+                ],
+            ),
+            (
+                Encoder,
+                &[
+                    "[libx264 @ 0x55c1e0] width not divisible by 2 (1919x1080)",
+                    "Error initializing output stream 0:0 -- Error while opening encoder for output stream #0:0 - maybe incorrect parameters such as bit_rate, rate, width or height",
+                ],
+            ),
+            (
+                Encoder,
+                &["[libx264 @ 0x55c1e0] Error setting option crf to value fast."],
+            ),
+            (
+                Encoder,
+                &["[aac @ 0x5612] Specified sample format s32 is invalid or not supported"],
+            ),
+            (
+                Encoder,
+                &["[mp4 @ 0x5612] Could not find tag for codec pcm_s16le in stream #1, codec not currently supported in container"],
+            ),
+            (
+                Encoder,
+                &[
+                    "[AVHWDeviceContext @ 0x55e4] Cannot open DRM device /dev/dri/renderD128: No such file or directory",
+                    "Device creation failed: -2.",
+                ],
+            ),
+            (
+                Io,
+                &[
+                    "[out#0/matroska @ 0x5570] Error writing trailer: No space left on device",
+                    "[out#0/matroska @ 0x5570] Error closing file: No space left on device",
+                ],
+            ),
+            (
+                Io,
+                &[
+                    "[out#0/matroska @ 0x5570] Error opening output /out/clip.mkv: Permission denied",
+                    "Error opening output file /out/clip.mkv.",
+                    "Error opening output files: Permission denied",
+                ],
+            ),
+            (Io, &["/in/clip.mp4: Input/output error"]),
+            (Io, &["/in/clip.mp4: No such file or directory"]),
+            (
+                OutputExists,
+                &["File '/out/clip.mkv' already exists. Exiting."],
+            ),
+            (
+                Unknown,
+                &["/in/clip.mp4: Invalid data found when processing input"],
+            ),
+            // A warning ffmpeg recovers from doesn't make a failure an encoder's
+            (
+                Unknown,
+                &[
+                    "Incompatible pixel format 'yuv420p10le' for codec 'h264_nvenc', auto-selecting format 'yuv420p'",
+                    "[matroska @ 0x55] Error parsing Opus packet header.",
+                ],
+            ),
+            (
+                Unknown,
+                &["[in#0/mov,mp4,m4a,3gp,3g2,mj2 @ 0x55] Error during demuxing: Invalid argument"],
+            ),
+        ];
+
+        for (kind, stderr) in cases {
+            let stderr: Vec<String> = stderr.iter().map(|line| line.to_string()).collect();
+            assert_eq!(FfmpegFailure::classify(&stderr), *kind, "{:#?}", stderr);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::artifacts::ARTIFACT_INFIX;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Logs kept in `job_logs_dir` when `job_logs_max_count` is unset
+pub const DEFAULT_MAX_COUNT: usize = 1000;
+
+/// Reserve a new log file in `dir` for a job on `source`, named
+/// `<timestamp>-<source stem>.sstc.log` so it's never taken for media. Jobs starting in the
+/// same second get a number added.
+pub fn create(dir: &Path, source: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).context(format!(
+        "Failed to create job log directory {}",
+        dir.display()
+    ))?;
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+
+    for n in 0.. {
+        let name = match n {
+            0 => format!("{}-{}{}log", stamp, stem, ARTIFACT_INFIX),
+            n => format!("{}-{}-{}{}log", stamp, stem, n, ARTIFACT_INFIX),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).context(format!("Failed to create job log {}", path.display()))
+            }
+        }
+    }
+    unreachable!("an unbounded range always yields another number")
+}
+
+/// One ffmpeg invocation appended to a job's log: its command line, every stderr line and
+/// how it exited
+pub struct JobLog {
+    file: BufWriter<File>,
+}
+
+impl JobLog {
+    pub fn open(path: &Path, command: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open job log {}", path.display()))?;
+        let mut log = Self {
+            file: BufWriter::new(file),
+        };
+        log.line(&format!("$ {}", command));
+        Ok(log)
+    }
+
+    /// Append a line; a log that can't be written only loses lines, never the job
+    pub fn line(&mut self, line: &str) {
+        let _ = writeln!(self.file, "{}", line);
+    }
+
+    pub fn finish(mut self, status: &str) {
+        self.line(&format!("# {}", status));
+        self.line("");
+        let _ = self.file.flush();
+    }
+}
+
+/// Whether `name` is one [`create`] gives out, `<timestamp>-<stem>[-n].sstc.log`. The
+/// directory may be shared, so any other file in it is left alone.
+fn is_job_log(name: &str) -> bool {
+    let Some(stamp) = name.get(..16) else {
+        return false;
+    };
+    let stamp_shape = stamp.bytes().enumerate().all(|(i, b)| match i {
+        8 | 15 => b == b'-',
+        _ => b.is_ascii_digit(),
+    });
+    stamp_shape && name.len() > 16 && name.ends_with(&format!("{}log", ARTIFACT_INFIX))
+}
+
+/// Remove the logs in `dir` older than `max_age` and those beyond the newest `max_count`,
+/// returning how many were removed
+pub fn prune(dir: &Path, max_count: usize, max_age: Option<Duration>) -> Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).context(format!("Failed to read {}", dir.display())),
+    };
+    let mut logs: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| is_job_log(&name.to_string_lossy()))
+        })
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .collect();
+    logs.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for (index, (modified, path)) in logs.iter().enumerate() {
+        let expired = max_age
+            .is_some_and(|max_age| now.duration_since(*modified).is_ok_and(|age| age > max_age));
+        if (index >= max_count || expired) && std::fs::remove_file(path).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A job log for `source` in `dir`, last written `age` ago
+    fn log_aged(dir: &Path, source: &str, age: Duration) -> PathBuf {
+        let path = create(dir, Path::new(source)).unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        path
+    }
+
+    #[test]
+    fn jobs_in_the_same_second_get_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let names: Vec<String> = (0..3)
+            .map(|_| create(dir.path(), Path::new("/in/clip.mp4")).unwrap())
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        // A second may tick over between them, which restarts the numbering
+        if names.iter().all(|name| name[..15] == names[0][..15]) {
+            assert!(names[0].ends_with("-clip.sstc.log"), "{}", names[0]);
+            assert!(names[1].ends_with("-clip-1.sstc.log"), "{}", names[1]);
+            assert!(names[2].ends_with("-clip-2.sstc.log"), "{}", names[2]);
+        }
+        assert!(names.iter().all(|name| is_job_log(name)), "{:?}", names);
+    }
+
+    #[test]
+    fn prune_keeps_the_newest_max_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs: Vec<PathBuf> = (0..5)
+            .map(|n| log_aged(dir.path(), "clip.mp4", Duration::from_secs(60 * n)))
+            .collect();
+
+        assert_eq!(prune(dir.path(), 2, None).unwrap(), 3);
+        let kept: Vec<bool> = logs.iter().map(|log| log.exists()).collect();
+        assert_eq!(kept, [true, true, false, false, false]);
+    }
+
+    #[test]
+    fn prune_removes_logs_past_max_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let fresh = log_aged(dir.path(), "a.mp4", Duration::from_secs(60));
+        let stale = log_aged(dir.path(), "b.mp4", Duration::from_secs(3 * 3600));
+
+        let removed = prune(dir.path(), 100, Some(Duration::from_secs(3600))).unwrap();
+        assert_eq!(removed, 1);
+        assert!(fresh.exists());
+        assert!(!stale.exists());
+    }
+
+    #[test]
+    fn prune_leaves_logs_it_did_not_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let foreign = [
+            "syslog.log",
+            "20240101-120000-x.log",
+            "app.sstc.log",
+            "notes.txt",
+        ];
+        for name in foreign {
+            std::fs::write(dir.path().join(name), "keep me").unwrap();
+            let file = File::options()
+                .write(true)
+                .open(dir.path().join(name))
+                .unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(86400))
+                .unwrap();
+        }
+        let ours = log_aged(dir.path(), "clip.mp4", Duration::from_secs(86400));
+
+        assert_eq!(
+            prune(dir.path(), 0, Some(Duration::from_secs(1))).unwrap(),
+            1
+        );
+        assert!(!ours.exists());
+        for name in foreign {
+            assert!(dir.path().join(name).exists(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn prune_of_a_missing_directory_removes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(prune(&dir.path().join("gone"), 0, None).unwrap(), 0);
+    }
+}
@@ -0,0 +1,55 @@
+//! sstc watches folders and transcodes the media that lands in them with ffmpeg.
+//!
+//! The binary is a thin CLI over these modules; [`command::build_ffmpeg_command`] is the
+//! entry point for predicting the exact ffmpeg invocation of a preset.
+
+pub mod artifacts;
+pub mod batch;
+pub mod claim;
+pub mod command;
+pub mod companion;
+pub mod compliance;
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod crop;
+pub mod diagnostic;
+pub mod expand;
+pub mod ffmpeg;
+pub mod ffprobe;
+pub mod file_check;
+pub mod growth;
+pub mod hash_store;
+pub mod history;
+pub mod history_db;
+pub mod hooks;
+pub mod in_place;
+pub mod job;
+pub mod job_log;
+pub mod loudnorm;
+pub mod marker;
+pub mod metrics;
+pub mod passlog;
+pub mod presets;
+pub mod priority;
+pub mod progress;
+pub mod queue;
+pub mod queue_file;
+pub mod reload;
+pub mod rusage;
+pub mod scaling;
+pub mod shell;
+pub mod size_limit;
+pub mod source_action;
+pub mod summary;
+pub mod telemetry;
+pub mod template;
+#[cfg(test)]
+mod test_support;
+pub mod thumbnail;
+pub mod timestamp;
+pub mod timing;
+pub mod tools;
+pub mod transcoder;
+pub mod units;
+pub mod watcher;
@@ -0,0 +1,209 @@
+use crate::config::NormalizeAudioConfig;
+use serde::{Deserialize, Deserializer};
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Loudness range the encode aims for unless the source's own is wider. loudnorm only
+/// normalizes linearly, leaving the dynamics alone, when the target range isn't narrower
+/// than the measured one.
+const MIN_LOUDNESS_RANGE: f64 = 11.0;
+
+/// Widest loudness range loudnorm accepts
+const MAX_LOUDNESS_RANGE: f64 = 50.0;
+
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.trim().parse().map_err(serde::de::Error::custom)
+}
+
+/// Loudness of a source as the analysis pass of loudnorm measured it
+#[derive(Debug, Clone, Deserialize)]
+pub struct Measurement {
+    #[serde(deserialize_with = "number")]
+    pub input_i: f64,
+    #[serde(deserialize_with = "number")]
+    pub input_tp: f64,
+    #[serde(deserialize_with = "number")]
+    pub input_lra: f64,
+    #[serde(deserialize_with = "number")]
+    pub input_thresh: f64,
+    #[serde(deserialize_with = "number")]
+    pub target_offset: f64,
+}
+
+impl Measurement {
+    /// Pick the measurement out of the analysis pass's stderr. The JSON block comes after
+    /// loudnorm's own log line, and whatever else ffmpeg printed around it is skipped by
+    /// trying every `{` from the last.
+    pub fn parse(stderr: &str) -> Option<Self> {
+        stderr.match_indices('{').rev().find_map(|(start, _)| {
+            let end = start + stderr[start..].find('}')?;
+            serde_json::from_str(&stderr[start..=end]).ok()
+        })
+    }
+
+    /// Silent audio measures as `-inf` and leaves nothing to normalize
+    pub fn is_usable(&self) -> bool {
+        [
+            self.input_i,
+            self.input_tp,
+            self.input_lra,
+            self.input_thresh,
+            self.target_offset,
+        ]
+        .iter()
+        .all(|value| value.is_finite())
+    }
+
+    /// The loudnorm filter of the encode, fed with these measured values
+    pub fn filter(&self, target: &NormalizeAudioConfig) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+            target.target_lufs,
+            target.true_peak,
+            self.input_lra.clamp(MIN_LOUDNESS_RANGE, MAX_LOUDNESS_RANGE),
+            self.input_i,
+            self.input_tp,
+            self.input_lra,
+            self.input_thresh,
+            self.target_offset
+        )
+    }
+}
+
+/// Arguments of the analysis pass, which decodes only the audio and writes nothing. The
+/// `filters` that run ahead of loudnorm in the encode run ahead of it here too.
+pub fn analysis_args(
+    input: &Path,
+    filters: &[String],
+    target: &NormalizeAudioConfig,
+) -> Vec<OsString> {
+    let loudnorm = format!(
+        "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+        target.target_lufs, target.true_peak, MIN_LOUDNESS_RANGE
+    );
+    let filter = filters
+        .iter()
+        .chain(std::iter::once(&loudnorm))
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut args: Vec<OsString> = ["-v", "info", "-nostats", "-hide_banner", "-i"]
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    args.push(input.into());
+    args.extend(
+        [
+            "-vn", "-sn", "-dn", "-map", "0:a:0", "-af", &filter, "-f", "null", "-",
+        ]
+        .into_iter()
+        .map(Into::into),
+    );
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The analysis pass's stderr around loudnorm's report, as ffmpeg prints it
+    const STDERR: &str = r#"Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'clip.mp4':
+  Metadata: {creation_time: 2024-03-04}
+  Stream #0:1[0x2](und): Audio: aac (LC) (mp4a / 0x6134706D), 48000 Hz, stereo, fltp, 128 kb/s
+Output #0, null, to 'pipe:':
+[Parsed_loudnorm_0 @ 0x55d0c8a4f2c0] 
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-23.02",
+	"output_tp" : "-1.00",
+	"output_lra" : "11.10",
+	"output_thresh" : "-34.38",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.02"
+}
+[out#0/null @ 0x55d0c8a3e1c0] video:0KiB audio:1875KiB subtitle:0KiB {unknown}
+size=N/A time=00:00:10.00 bitrate=N/A speed= 312x {
+"#;
+
+    fn target() -> NormalizeAudioConfig {
+        serde_yaml::from_str("{}").unwrap()
+    }
+
+    fn measured(lra: f64) -> Measurement {
+        Measurement {
+            input_i: -27.61,
+            input_tp: -4.47,
+            input_lra: lra,
+            input_thresh: -39.2,
+            target_offset: 0.02,
+        }
+    }
+
+    #[test]
+    fn measurement_is_found_among_other_output() {
+        let measurement = Measurement::parse(STDERR).unwrap();
+
+        assert_eq!(measurement.input_i, -27.61);
+        assert_eq!(measurement.input_tp, -4.47);
+        assert_eq!(measurement.input_lra, 18.06);
+        assert_eq!(measurement.input_thresh, -39.2);
+        assert_eq!(measurement.target_offset, 0.02);
+        assert!(measurement.is_usable());
+
+        assert!(Measurement::parse("[Parsed_loudnorm_0 @ 0x1] {\n").is_none());
+        assert!(Measurement::parse("").is_none());
+    }
+
+    #[test]
+    fn silence_is_not_usable() {
+        let silent = STDERR
+            .replace("\"-27.61\"", "\"-inf\"")
+            .replace("\"-4.47\"", "\"-inf\"");
+        let measurement = Measurement::parse(&silent).unwrap();
+
+        assert_eq!(measurement.input_i, f64::NEG_INFINITY);
+        assert!(!measurement.is_usable());
+    }
+
+    #[test]
+    fn loudness_range_is_kept_within_what_loudnorm_takes() {
+        let lra = |measured_lra| {
+            let filter = measured(measured_lra).filter(&target());
+            filter
+                .split(':')
+                .find_map(|option| option.strip_prefix("LRA="))
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(lra(18.06), "18.06");
+        assert_eq!(lra(4.0), "11");
+        assert_eq!(lra(72.5), "50");
+        assert_eq!(
+            measured(4.0).filter(&target()),
+            "loudnorm=I=-23:TP=-1:LRA=11:measured_I=-27.61:measured_TP=-4.47:measured_LRA=4:measured_thresh=-39.2:offset=0.02:linear=true"
+        );
+    }
+
+    #[test]
+    fn analysis_runs_the_audio_filters_ahead_of_loudnorm() {
+        let args = analysis_args(
+            Path::new("/in/clip.mp4"),
+            &["highpass=f=80".to_string(), "volume=2".to_string()],
+            &target(),
+        );
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(
+            args.join(" "),
+            "-v info -nostats -hide_banner -i /in/clip.mp4 -vn -sn -dn -map 0:a:0 -af highpass=f=80,volume=2,loudnorm=I=-23:TP=-1:LRA=11:print_format=json -f null -"
+        );
+    }
+}
@@ -1,20 +1,22 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use tracing::info;
+use std::path::Path;
+use tracing::{error, info, warn};
 
 use owo_colors::OwoColorize;
-mod config;
-mod file_check;
-mod transcoder;
-use transcoder::Transcoder;
-mod watcher;
-use watcher::DirectoryWatcher;
-mod presets;
-use presets::PresetGenerator;
-mod ffprobe;
-
-const FFMPEG_BIN_NAME: &str = "ffmpeg";
-const FFPROBE_BIN_NAME: &str = "ffprobe";
+use sstc::control::{self, ControlRequest, ControlSocket};
+use sstc::job::JobOutcome;
+use sstc::presets::PresetGenerator;
+use sstc::reload::{self, ReloadTrigger};
+use sstc::transcoder::Transcoder;
+use sstc::units::HumanDuration;
+use sstc::watcher::DirectoryWatcher;
+use sstc::{
+    batch, claim, config, console, ffmpeg, hash_store, history, metrics, summary, telemetry, tools,
+};
+
+/// How long running jobs get to finish on shutdown when `shutdown_grace` is unset
+const DEFAULT_SHUTDOWN_GRACE: HumanDuration = HumanDuration::from_secs(300);
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -38,6 +40,148 @@ enum Commands {
         /// Override max parallel jobs
         #[arg(short = 'j', long)]
         max_jobs: Option<usize>,
+
+        /// Treat config validation warnings as errors
+        #[arg(long)]
+        strict: bool,
+
+        /// Print the summary table without colors
+        #[arg(long)]
+        no_color: bool,
+
+        /// Most files listed in the summary table; failures are always listed
+        #[arg(long, default_value_t = 50)]
+        summary_limit: usize,
+
+        /// ffmpeg binary to use, overriding `ffmpeg_path` and PATH
+        #[arg(long, value_name = "PATH")]
+        ffmpeg: Option<std::path::PathBuf>,
+
+        /// ffprobe binary to use, overriding `ffprobe_path` and PATH
+        #[arg(long, value_name = "PATH")]
+        ffprobe: Option<std::path::PathBuf>,
+
+        /// Start even when presets use encoders `ffmpeg -encoders` doesn't list, e.g. behind a
+        /// wrapper script
+        #[arg(long)]
+        allow_unknown_encoders: bool,
+
+        /// How jobs show progress; bars on a terminal and plain log lines otherwise by default
+        #[arg(long, value_enum, value_name = "MODE")]
+        progress: Option<console::ProgressMode>,
+
+        /// Print the ffmpeg command of every existing file instead of running it, and exit
+        /// after the scan instead of watching
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Transcode a single file right away, bypassing the queue
+    Transcode {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+
+        /// Use this preset instead of the one configured for the input
+        #[arg(short, long)]
+        preset: Option<String>,
+
+        /// File to transcode
+        file: std::path::PathBuf,
+
+        /// Print the ffmpeg command instead of running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Process existing files once and exit when everything is done
+    Scan {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+
+        /// Read the files to process from a list file instead of scanning inputs, `-` for stdin
+        #[arg(long, value_name = "FILE")]
+        from_list: Option<String>,
+
+        /// List entries are NUL-separated instead of newline-separated
+        #[arg(short = '0', long = "null", requires = "from_list")]
+        null: bool,
+
+        /// Accept listed files outside configured inputs, using --preset and --output
+        #[arg(long, requires_all = ["from_list", "preset", "output"])]
+        no_input_check: bool,
+
+        /// Preset for files accepted with --no-input-check
+        #[arg(short, long, requires = "no_input_check")]
+        preset: Option<String>,
+
+        /// Output for files accepted with --no-input-check
+        #[arg(short, long, requires = "no_input_check")]
+        output: Option<String>,
+
+        /// Print the summary table without colors
+        #[arg(long)]
+        no_color: bool,
+
+        /// Most files listed in the summary table; failures are always listed
+        #[arg(long, default_value_t = 50)]
+        summary_limit: usize,
+
+        /// Only print whether each file meets its input's normalize rules; nothing is encoded
+        #[arg(long)]
+        report_only: bool,
+
+        /// Only scan the configured input with this path
+        #[arg(long, value_name = "PATH", conflicts_with = "from_list")]
+        input: Option<std::path::PathBuf>,
+
+        /// Transcode files again even when their outputs exist, replacing each output only once
+        /// its new version is verified. Given paths, only the scanned files that are or lie
+        /// under one of them, like a single file or an input directory.
+        #[arg(long, value_name = "PATH", num_args = 0.., conflicts_with = "report_only")]
+        force: Option<Vec<std::path::PathBuf>>,
+
+        /// Print the ffmpeg command of every file instead of running it
+        #[arg(long, conflicts_with = "report_only")]
+        dry_run: bool,
+    },
+    /// Stop the queued or running job of a source in the service listening on `control_socket`;
+    /// its incomplete output is removed and it isn't retried
+    Cancel {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+
+        /// Source of the job to cancel
+        file: std::path::PathBuf,
+    },
+    /// Stop starting queued files in the service listening on `control_socket`, which keeps
+    /// queueing new ones; SIGUSR1 does the same
+    Pause {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+    },
+    /// Start queued files again after `sstc pause`; SIGUSR2 does the same
+    Resume {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+    },
+    /// Show which instances hold claims on sources in distributed mode
+    Claims {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+    },
+    /// Inspect jobs recorded in the history file
+    History {
+        #[command(subcommand)]
+        action: HistoryCommand,
+    },
+    /// Manage the content hashes `dedup_by_hash` skips duplicates by
+    Hashes {
+        #[command(subcommand)]
+        action: HashesCommand,
     },
     /// Configuration management commands
     Config {
@@ -46,8 +190,76 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// List recorded jobs, most recent last
+    List {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+
+        /// Only show this many of the most recent jobs
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show everything recorded about one job
+    Show {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+
+        /// Job id as shown by `history list`
+        id: u64,
+
+        /// Print only the ffmpeg command line, ready to paste into a shell
+        #[arg(long)]
+        command: bool,
+    },
+    /// Total the recorded jobs and the CPU time ffmpeg spent on them, per preset
+    Stats {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+
+        /// Print the totals as Prometheus metrics (`sstc_cpu_seconds_total`)
+        #[arg(long)]
+        prometheus: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HashesCommand {
+    /// Forget every recorded hash, so sources seen before are transcoded again. A running
+    /// service picks the cleared store up before its next lookup.
+    Clear {
+        /// Config file to use
+        #[arg(short, long)]
+        config: String,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum ConfigCommand {
+    /// Check a config file without creating any directories; exits non-zero if it's invalid
+    Validate {
+        /// Config file to check
+        #[arg(short, long)]
+        config: String,
+
+        /// Treat validation warnings as errors
+        #[arg(long)]
+        strict: bool,
+
+        /// Print the findings as JSON on stdout
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the JSON Schema of the config file
+    Schema {
+        /// Write the schema to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
     /// Generate a complete example configuration file
     Generate {
         /// Output file path
@@ -76,13 +288,33 @@ enum PresetsCommand {
         config: String,
     },
     /// Show example presets in terminal
-    Show,
+    Show {
+        /// Show the presets of this config instead, with `extends` resolved
+        #[arg(short, long)]
+        config: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    let result = run(args).await;
+    // Export the spans of the last jobs before the process goes away
+    let _ = tokio::task::spawn_blocking(telemetry::shutdown).await;
+
+    if let Err(e) = result {
+        if let Some(failed) = e.downcast_ref::<config::ValidationFailed>() {
+            error!("{}", failed.red());
+            std::process::exit(failed.exit_code());
+        }
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+async fn run(args: Args) -> Result<()> {
     let log_level = match args.log_level.to_lowercase().as_str() {
         "trace" => tracing::Level::TRACE,
         "debug" => tracing::Level::DEBUG,
@@ -95,18 +327,122 @@ async fn main() -> Result<()> {
         }
     };
 
-    tracing_subscriber::fmt()
-        .with_ansi(true)
-        .with_max_level(log_level)
-        .init();
+    telemetry::init(log_level);
 
     info!("Log level is set to: {}", log_level.yellow());
 
     match &args.command {
-        Commands::Run { config, max_jobs } => {
-            run_transcoder(config, max_jobs).await?;
+        Commands::Run {
+            config,
+            max_jobs,
+            strict,
+            no_color,
+            summary_limit,
+            ffmpeg,
+            ffprobe,
+            allow_unknown_encoders,
+            progress,
+            dry_run,
+        } => {
+            if let Some(mode) = progress {
+                console::set_progress_mode(*mode);
+            }
+            let summary = summary::SummaryOptions::new(*no_color, *summary_limit);
+            let binaries = (ffmpeg.as_deref(), ffprobe.as_deref());
+            let service_config =
+                load_service_config(config, max_jobs, *strict, binaries, *allow_unknown_encoders)?;
+            if *dry_run {
+                let options = batch::ScanOptions {
+                    from_list: None,
+                    null_separated: false,
+                    explicit_target: None,
+                    summary,
+                    report_only: false,
+                    input: None,
+                    force: None,
+                    dry_run: true,
+                };
+                batch::run_scan(std::sync::Arc::new(service_config), options).await?;
+            } else {
+                run_transcoder(config, service_config, max_jobs, *strict, summary).await?;
+            }
+        }
+        Commands::Scan {
+            config,
+            from_list,
+            null,
+            no_input_check: _,
+            preset,
+            output,
+            no_color,
+            summary_limit,
+            report_only,
+            input,
+            force,
+            dry_run,
+        } => {
+            info!("Loading configuration from {}", config.yellow());
+            let config =
+                config::load_config(config, false).context("Failed to load configuration")?;
+            telemetry::enable(config.otel.as_ref())?;
+            tools::configure(
+                config.ffmpeg_path.as_deref(),
+                config.ffprobe_path.as_deref(),
+            )?;
+            let options = batch::ScanOptions {
+                from_list: from_list.clone(),
+                null_separated: *null,
+                explicit_target: preset.clone().zip(output.clone()),
+                summary: summary::SummaryOptions::new(*no_color, *summary_limit),
+                report_only: *report_only,
+                input: input.clone(),
+                force: force.clone(),
+                dry_run: *dry_run,
+            };
+            batch::run_scan(std::sync::Arc::new(config), options).await?;
         }
+        Commands::Transcode {
+            config,
+            preset,
+            file,
+            dry_run,
+        } => {
+            transcode_now(config, file, preset.as_deref(), *dry_run).await?;
+        }
+        Commands::Cancel { config, file } => {
+            // The service knows sources by their full path
+            let path = std::path::absolute(file)
+                .context(format!("Failed to resolve {}", file.display()))?;
+            send_control(config, ControlRequest::Cancel { path }).await?;
+        }
+        Commands::Pause { config } => send_control(config, ControlRequest::Pause).await?,
+        Commands::Resume { config } => send_control(config, ControlRequest::Resume).await?,
+        Commands::Claims { config } => {
+            let config =
+                config::load_config(config, false).context("Failed to load configuration")?;
+            show_claims(&config)?;
+        }
+        Commands::History { action } => show_history(action)?,
+        Commands::Hashes { action } => match action {
+            HashesCommand::Clear { config } => clear_hashes(config)?,
+        },
         Commands::Config { action } => match action {
+            ConfigCommand::Validate {
+                config,
+                strict,
+                json,
+            } => validate_config_file(config, *strict, *json)?,
+            ConfigCommand::Schema { output } => {
+                let schema = config::json_schema()?;
+                match output {
+                    Some(output) => {
+                        std::fs::write(output, schema + "\n")
+                            .context(format!("Failed to write {}", output))?;
+                        info!("Wrote the config schema to {}", output.yellow());
+                    }
+                    None => println!("{}", schema),
+                }
+            }
             ConfigCommand::Generate { output } => {
                 info!(
                     "Generating complete example configuration to {}",
@@ -124,20 +460,30 @@ async fn main() -> Result<()> {
                 }
                 PresetsCommand::Add { config } => {
                     info!("Adding example presets to config file {}", config.yellow());
-                    let mut config_data = config::load_config(config)?;
+                    config::load_config(config, false)?;
+                    // Re-read so paths are written back with their variables unexpanded
+                    let mut config_data = config::read_config(config)?;
                     PresetGenerator::generate_example_presets(&mut config_data)?;
 
-                    let yaml = serde_yaml::to_string(&config_data)?;
-                    std::fs::write(config, yaml)?;
+                    config::write_config(config, &config_data)?;
                     info!("Updated config file with example presets");
                 }
-                PresetsCommand::Show => {
+                PresetsCommand::Show {
+                    config: Some(config),
+                } => {
+                    info!("Showing resolved presets of {}:", config.yellow());
+                    let config = config::load_config(config, false)
+                        .context("Failed to load configuration")?;
+                    let presets: std::collections::BTreeMap<_, _> = config.presets.iter().collect();
+
+                    let presets_yaml = serde_yaml::to_string(&presets)?;
+                    println!("\n{}\n", presets_yaml);
+                }
+                PresetsCommand::Show { config: None } => {
                     info!("Showing example presets:");
                     let mut empty_config = config::Config {
-                        inputs: Vec::new(),
-                        outputs: std::collections::HashMap::new(),
-                        presets: std::collections::HashMap::new(),
                         max_parallel_jobs: Some(1),
+                        ..Default::default()
                     };
 
                     PresetGenerator::generate_example_presets(&mut empty_config)?;
@@ -151,42 +497,314 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
-async fn run_transcoder(config_path: &str, max_jobs: &Option<usize>) -> Result<()> {
-    info!("Starting video transcoder service");
-
-    match which::which(FFMPEG_BIN_NAME) {
-        Ok(path) => info!(
-            "Found {} at: {}",
-            FFMPEG_BIN_NAME.green(),
-            path.display().green()
-        ),
-        Err(e) => println!("{} not found in PATH: {}", FFMPEG_BIN_NAME.red(), e.red()),
-    };
-
-    match which::which(FFPROBE_BIN_NAME) {
-        Ok(path) => info!(
-            "Found {} at: {}",
-            FFPROBE_BIN_NAME.green(),
-            path.display().green()
-        ),
-        Err(e) => println!("{} not found in PATH: {}", FFPROBE_BIN_NAME.red(), e.red()),
-    };
-
+/// Load the config of `run` with the command line overrides applied, and find the tools it
+/// names: `binaries` are the ffmpeg and ffprobe binaries given on the command line
+fn load_service_config(
+    config_path: &str,
+    max_jobs: &Option<usize>,
+    strict: bool,
+    binaries: (Option<&Path>, Option<&Path>),
+    allow_unknown_encoders: bool,
+) -> Result<config::Config> {
     info!("Loading configuration from {}", config_path.yellow());
-    let mut config = config::load_config(config_path).context("Failed to load configuration")?;
+    let mut config =
+        config::load_config(config_path, strict).context("Failed to load configuration")?;
 
     if let Some(jobs) = max_jobs {
         config.max_parallel_jobs = Some(*jobs);
     }
+    telemetry::enable(config.otel.as_ref())?;
+    let (ffmpeg, ffprobe) = binaries;
+    tools::configure(
+        ffmpeg.or(config.ffmpeg_path.as_deref()),
+        ffprobe.or(config.ffprobe_path.as_deref()),
+    )?;
+    if !allow_unknown_encoders {
+        ffmpeg::check_encoders(&config)?;
+    }
+    ffmpeg::check_hwaccels(&config);
+    Ok(config)
+}
+
+async fn run_transcoder(
+    config_path: &str,
+    config: config::Config,
+    max_jobs: &Option<usize>,
+    strict: bool,
+    summary: summary::SummaryOptions,
+) -> Result<()> {
+    info!("Starting video transcoder service");
 
     let config = std::sync::Arc::new(config);
     let transcoder = std::sync::Arc::new(Transcoder::new(config.clone()));
-    let mut watcher = DirectoryWatcher::new(config.clone(), transcoder);
+    transcoder.restore_queue();
+    let _control = config
+        .control_socket
+        .as_deref()
+        .map(|path| ControlSocket::bind(path, (*transcoder).clone()))
+        .transpose()?;
+    control::listen_for_pause_signals((*transcoder).clone())?;
+    let mut watcher = DirectoryWatcher::new(config.clone(), transcoder.clone());
 
     watcher.start_watching().await?;
 
-    tokio::signal::ctrl_c().await?;
+    let mut reloads = ReloadTrigger::new(Path::new(config_path), config.watch_config)?;
+    loop {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                break;
+            }
+            Some(()) = reloads.next() => {
+                reload::reload_config(config_path, max_jobs, strict, &transcoder, &mut watcher).await;
+            }
+        }
+    }
     info!("Received shutdown signal, shutting down...");
+    shut_down(&transcoder).await;
+
+    transcoder.stats().log_summary();
+    transcoder.stats().print_table(summary);
+
+    Ok(())
+}
+
+/// Stop starting jobs and give the running ones the grace period to finish, killing them
+/// when it runs out or on a second Ctrl-C
+async fn shut_down(transcoder: &Transcoder) {
+    transcoder.shut_down();
+
+    let running = transcoder.running_sources();
+    if !running.is_empty() {
+        let grace = transcoder
+            .config()
+            .shutdown_grace
+            .unwrap_or(DEFAULT_SHUTDOWN_GRACE);
+        info!(
+            "Waiting up to {} for {} running job(s) to finish, press Ctrl-C again to stop them now",
+            grace.cyan(),
+            running.len()
+        );
+
+        let second_ctrl_c = async {
+            let _ = tokio::signal::ctrl_c().await;
+        };
+        let interrupted = transcoder
+            .drain_running_jobs(grace.into(), second_ctrl_c)
+            .await;
+
+        if !interrupted.is_empty() {
+            warn!(
+                "Interrupted {} job(s), run again to transcode them:",
+                interrupted.len()
+            );
+            for source in &interrupted {
+                warn!("  {}", source.display().yellow());
+            }
+        }
+    }
+
+    let queued = transcoder.queued_files().await;
+    if queued > 0 {
+        info!("{} queued file(s) were not started", queued);
+    }
+    transcoder.flush_queue().await;
+}
+
+async fn transcode_now(
+    config_path: &str,
+    file: &std::path::Path,
+    preset: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    info!("Loading configuration from {}", config_path.yellow());
+    let config = config::load_config(config_path, false).context("Failed to load configuration")?;
+    telemetry::enable(config.otel.as_ref())?;
+    tools::configure(
+        config.ffmpeg_path.as_deref(),
+        config.ffprobe_path.as_deref(),
+    )?;
+
+    let transcoder = Transcoder::new(std::sync::Arc::new(config));
+    if dry_run {
+        info!("Dry run: printing the ffmpeg command instead of running it");
+        transcoder.enable_dry_run();
+    }
+    let records = transcoder
+        .transcode_now(file, preset)
+        .await
+        .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+
+    if records.iter().all(|record| record.output.is_none()) {
+        info!("Nothing to do for {}", file.display().yellow());
+    }
+
+    Ok(())
+}
+
+/// Hand a request to the service listening on the config's `control_socket`
+async fn send_control(config_path: &str, request: ControlRequest) -> Result<()> {
+    let config = config::load_config(config_path, false).context("Failed to load configuration")?;
+    let Some(socket) = &config.control_socket else {
+        return Err(anyhow::anyhow!(
+            "No control_socket is configured in {}",
+            config_path
+        ));
+    };
+    let response = control::send(socket, &request).await?;
+    if !response.ok {
+        return Err(anyhow::anyhow!("{}", response.message));
+    }
+    info!("{}", response.message);
+    Ok(())
+}
+
+fn validate_config_file(config_path: &str, strict: bool, json: bool) -> Result<()> {
+    info!("Validating configuration {}", config_path.yellow());
+    let report = config::check_config(config_path, strict);
+
+    if json {
+        let output = serde_json::json!({
+            "config": config_path,
+            "valid": !report.is_failure(),
+            "strict": report.strict,
+            "findings": report.findings,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        report.log();
+    }
+
+    if report.is_failure() {
+        return Err(config::ValidationFailed { report }.into());
+    }
+    info!("Configuration {} is valid", config_path.green());
+    Ok(())
+}
+
+fn show_claims(config: &config::Config) -> Result<()> {
+    let Some(settings) = &config.distributed else {
+        return Err(anyhow::anyhow!(
+            "Distributed mode is not enabled in this configuration"
+        ));
+    };
+
+    let inputs: Vec<_> = config.inputs.iter().map(|i| i.path.clone()).collect();
+    let claims = claim::list_claims(settings, &inputs);
+    if claims.is_empty() {
+        info!("No active claims");
+        return Ok(());
+    }
+
+    for (path, owner) in claims {
+        let state = if owner.is_stale(settings.lease.as_secs()) {
+            "stale".red().to_string()
+        } else {
+            "active".green().to_string()
+        };
+        info!(
+            "{} {} (pid {}, renewed {}s ago) {}",
+            state,
+            owner.host.cyan(),
+            owner.pid,
+            owner.age_secs(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+fn clear_hashes(config_path: &str) -> Result<()> {
+    let config = config::load_config(config_path, false).context("Failed to load configuration")?;
+    let Some(store) = &config.hash_store else {
+        return Err(anyhow::anyhow!(
+            "No hash_store is configured in {}",
+            config_path
+        ));
+    };
+    let count = hash_store::clear(&store.path)?;
+    info!(
+        "Forgot {} content hash(es) in {}",
+        count.magenta(),
+        store.path.display()
+    );
+    Ok(())
+}
+
+fn show_history(action: &HistoryCommand) -> Result<()> {
+    let config_path = match action {
+        HistoryCommand::List { config, .. }
+        | HistoryCommand::Show { config, .. }
+        | HistoryCommand::Stats { config, .. } => config,
+    };
+    let config = config::load_config(config_path, false).context("Failed to load configuration")?;
+    let Some(history_file) = &config.history_file else {
+        return Err(anyhow::anyhow!(
+            "No history_file is configured in {}",
+            config_path
+        ));
+    };
+    let entries = history::load(history_file)?;
+
+    match action {
+        HistoryCommand::List { limit, .. } => {
+            for entry in entries.iter().skip(entries.len().saturating_sub(*limit)) {
+                let status = match (&entry.error, &entry.outcome) {
+                    (Some(_), _) => "failed".red().to_string(),
+                    (None, Some(outcome)) if outcome.is_skipped() => "skipped".yellow().to_string(),
+                    (None, Some(JobOutcome::Cancelled)) => "cancelled".yellow().to_string(),
+                    (None, _) => "ok".green().to_string(),
+                };
+                println!(
+                    "{:>5}  {}  {}  {}",
+                    entry.id,
+                    status,
+                    entry.preset.as_deref().unwrap_or("-").cyan(),
+                    entry.source.display()
+                );
+            }
+        }
+        HistoryCommand::Show { id, command, .. } => {
+            let entry = entries
+                .iter()
+                .find(|entry| entry.id == *id)
+                .ok_or_else(|| anyhow::anyhow!("No job with id {} in history", id))?;
+
+            if *command {
+                let line = entry
+                    .shell_command()
+                    .ok_or_else(|| anyhow::anyhow!("Job {} never ran ffmpeg", id))?;
+                println!("{}", line);
+            } else {
+                println!("{}", serde_json::to_string_pretty(entry)?);
+            }
+        }
+        HistoryCommand::Stats { prometheus, .. } => {
+            let cpu = history::cpu_secs_by_preset(&entries);
+            if *prometheus {
+                print!("{}", metrics::render(&cpu));
+                return Ok(());
+            }
+            let mut jobs = std::collections::BTreeMap::<String, usize>::new();
+            for entry in &entries {
+                *jobs
+                    .entry(entry.preset.clone().unwrap_or_else(|| "-".to_string()))
+                    .or_default() += 1;
+            }
+            println!("{:<16} {:>6} {:>10}", "preset", "jobs", "CPU-hours");
+            for (preset, count) in &jobs {
+                let hours = cpu.get(preset).copied().unwrap_or_default() / 3600.0;
+                println!("{:<16} {:>6} {:>10.2}", preset.cyan(), count, hours);
+            }
+            println!(
+                "{:<16} {:>6} {:>10.2}",
+                "total",
+                entries.len(),
+                cpu.values().sum::<f64>() / 3600.0
+            );
+        }
+    }
 
     Ok(())
 }
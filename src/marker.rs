@@ -0,0 +1,246 @@
+use dashmap::DashMap;
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Name of the per-directory ignore file, and suffix of per-file sidecar markers
+pub const IGNORE_FILE_NAME: &str = ".sstc-ignore";
+
+/// Consults `.sstc-ignore` markers so files that must never be touched are skipped.
+///
+/// A file is ignored when a sidecar `<name>.sstc-ignore` exists next to it, or when a
+/// `.sstc-ignore` file in its directory (or any parent up to the input root) has a
+/// glob line matching it. Parsed directory files are cached until invalidated.
+#[derive(Clone, Default)]
+pub struct IgnoreMarkers {
+    rules: Arc<DashMap<PathBuf, Arc<Vec<Pattern>>>>,
+}
+
+impl IgnoreMarkers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the path is one of the marker files themselves
+    pub fn is_marker_file(path: &Path) -> bool {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|name| name.ends_with(IGNORE_FILE_NAME))
+    }
+
+    /// Drop cached rules for the directory containing a changed marker file
+    pub fn invalidate(&self, marker_path: &Path) {
+        let Some(dir) = marker_path.parent() else {
+            return;
+        };
+        // Rules are cached by the directory as files were found in it, which is the
+        // resolved one only when the input path has no symlinks in it
+        let resolved = std::fs::canonicalize(dir).ok();
+        for dir in std::iter::once(dir).chain(resolved.as_deref()) {
+            if self.rules.remove(dir).is_some() {
+                debug!("Invalidated ignore rules for {}", dir.display());
+            }
+        }
+    }
+
+    /// Returns a description of the marker that excludes the file, if any
+    pub fn ignored_by(&self, file_path: &Path, input_root: &Path) -> Option<String> {
+        if let Some(sidecar) = Self::sidecar_for(file_path) {
+            return Some(sidecar.display().to_string());
+        }
+
+        let mut dir = file_path.parent();
+        while let Some(current) = dir {
+            let rules = self.rules_for(current);
+            if let Ok(relative) = file_path.strip_prefix(current) {
+                if let Some(pattern) = Self::matching_rule(&rules, relative) {
+                    return Some(format!(
+                        "{} ({})",
+                        current.join(IGNORE_FILE_NAME).display(),
+                        pattern
+                    ));
+                }
+            }
+
+            if current == input_root || !current.starts_with(input_root) {
+                break;
+            }
+            dir = current.parent();
+        }
+
+        None
+    }
+
+    fn sidecar_for(file_path: &Path) -> Option<PathBuf> {
+        let name = file_path.file_name()?.to_str()?;
+        let stem = file_path.file_stem()?.to_str()?;
+
+        [name, stem]
+            .iter()
+            .map(|base| file_path.with_file_name(format!("{}{}", base, IGNORE_FILE_NAME)))
+            .find(|sidecar| sidecar.is_file())
+    }
+
+    fn matching_rule(rules: &[Pattern], relative: &Path) -> Option<String> {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let file_name = relative.file_name()?;
+
+        rules
+            .iter()
+            .find(|pattern| {
+                if pattern.as_str().contains('/') {
+                    pattern.matches_path_with(relative, options)
+                } else {
+                    pattern.matches_path_with(Path::new(file_name), options)
+                }
+            })
+            .map(|pattern| pattern.as_str().to_string())
+    }
+
+    fn rules_for(&self, dir: &Path) -> Arc<Vec<Pattern>> {
+        if let Some(rules) = self.rules.get(dir) {
+            return rules.clone();
+        }
+
+        let rules = Arc::new(Self::load_rules(&dir.join(IGNORE_FILE_NAME)));
+        self.rules.insert(dir.to_path_buf(), rules.clone());
+        rules
+    }
+
+    fn load_rules(path: &Path) -> Vec<Pattern> {
+        let Ok(content) = std::fs::read_to_string(path) else {
+            return Vec::new();
+        };
+
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| match Pattern::new(line.trim_start_matches('/')) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    warn!("Invalid pattern '{}' in {}: {}", line, path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn sidecar_ignores_its_file_only() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let markers = IgnoreMarkers::new();
+        write(&root.join("keep.mp4.sstc-ignore"), "");
+        write(&root.join("raw.sstc-ignore"), "");
+
+        let by_name = markers.ignored_by(&root.join("keep.mp4"), root).unwrap();
+        assert!(by_name.ends_with("keep.mp4.sstc-ignore"));
+        let by_stem = markers.ignored_by(&root.join("raw.mkv"), root).unwrap();
+        assert!(by_stem.ends_with("raw.sstc-ignore"));
+        assert_eq!(markers.ignored_by(&root.join("other.mp4"), root), None);
+    }
+
+    #[test]
+    fn directory_marker_matches_names_and_relative_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let markers = IgnoreMarkers::new();
+        write(
+            &root.join(IGNORE_FILE_NAME),
+            "# originals stay\n\n*.mov\n/drafts/*.mp4\n",
+        );
+
+        let ignored = markers.ignored_by(&root.join("clip.mov"), root).unwrap();
+        assert!(ignored.ends_with(".sstc-ignore (*.mov)"));
+        // A name pattern matches in every directory below the marker
+        assert!(markers
+            .ignored_by(&root.join("a/b/clip.mov"), root)
+            .is_some());
+        assert!(markers
+            .ignored_by(&root.join("drafts/cut.mp4"), root)
+            .is_some());
+        // A path pattern only below the marker's own directory, and `*` stops at separators
+        assert_eq!(
+            markers.ignored_by(&root.join("drafts/old/cut.mp4"), root),
+            None
+        );
+        assert_eq!(markers.ignored_by(&root.join("clip.mp4"), root), None);
+    }
+
+    #[test]
+    fn nested_markers_apply_up_to_the_input_root() {
+        let outer = tempfile::tempdir().unwrap();
+        let root = outer.path().join("input");
+        let markers = IgnoreMarkers::new();
+        // Above the input root, so never read
+        write(&outer.path().join(IGNORE_FILE_NAME), "*.mp4\n");
+        write(&root.join(IGNORE_FILE_NAME), "season1/*.mkv\n");
+        write(
+            &root.join("season1/extras").join(IGNORE_FILE_NAME),
+            "*.srt\n",
+        );
+
+        assert!(markers
+            .ignored_by(&root.join("season1/e01.mkv"), &root)
+            .is_some());
+        assert!(markers
+            .ignored_by(&root.join("season1/extras/e01.srt"), &root)
+            .is_some());
+        assert_eq!(
+            markers.ignored_by(&root.join("season1/e01.srt"), &root),
+            None
+        );
+        assert_eq!(
+            markers.ignored_by(&root.join("season1/e01.mp4"), &root),
+            None
+        );
+        assert_eq!(
+            markers.ignored_by(&root.join("season1/extras/e01.mkv"), &root),
+            None
+        );
+    }
+
+    #[test]
+    fn changed_marker_is_read_again_once_invalidated() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        // Through a symlink, as an input path may well be
+        let root = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &root).unwrap();
+        let markers = IgnoreMarkers::new();
+        let marker = root.join(IGNORE_FILE_NAME);
+        write(&marker, "*.mov\n");
+        assert_eq!(markers.ignored_by(&root.join("clip.mp4"), &root), None);
+
+        write(&marker, "*.mp4\n");
+        assert_eq!(markers.ignored_by(&root.join("clip.mp4"), &root), None);
+        markers.invalidate(&marker);
+        assert!(markers.ignored_by(&root.join("clip.mp4"), &root).is_some());
+    }
+
+    #[test]
+    fn recognises_marker_files() {
+        assert!(IgnoreMarkers::is_marker_file(Path::new("/in/.sstc-ignore")));
+        assert!(IgnoreMarkers::is_marker_file(Path::new(
+            "/in/clip.mp4.sstc-ignore"
+        )));
+        assert!(!IgnoreMarkers::is_marker_file(Path::new("/in/clip.mp4")));
+    }
+}
@@ -0,0 +1,81 @@
+//! Prometheus metrics in the text exposition format, for node_exporter's textfile collector
+//! or anything else that scrapes files.
+
+use crate::artifacts;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The metrics of `cpu_secs_by_preset`, CPU seconds ffmpeg spent per preset
+pub fn render(cpu_secs_by_preset: &BTreeMap<String, f64>) -> String {
+    let mut text = String::new();
+    text.push_str(
+        "# HELP sstc_cpu_seconds_total CPU time ffmpeg spent, user and system, by preset.\n",
+    );
+    text.push_str("# TYPE sstc_cpu_seconds_total counter\n");
+    for (preset, secs) in cpu_secs_by_preset {
+        let _ = writeln!(
+            text,
+            "sstc_cpu_seconds_total{{preset=\"{}\"}} {}",
+            escape_label(preset),
+            secs
+        );
+    }
+    text
+}
+
+/// Replace the metrics file at `path`, through a temporary sibling so a scrape never reads
+/// half of it
+pub fn write(path: &Path, cpu_secs_by_preset: &BTreeMap<String, f64>) -> Result<()> {
+    let temp = artifacts::temp_path_for(path);
+    std::fs::write(&temp, render(cpu_secs_by_preset))
+        .context(format!("Failed to write metrics file: {}", temp.display()))?;
+    std::fs::rename(&temp, path).context(format!(
+        "Failed to replace metrics file: {}",
+        path.display()
+    ))
+}
+
+/// A label value with the backslashes, quotes and newlines the format reserves escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_sample_per_preset() {
+        let totals = BTreeMap::from([
+            ("archive".to_string(), 7200.5),
+            ("say \"hi\"\\now".to_string(), 1.0),
+        ]);
+        assert_eq!(
+            render(&totals),
+            "# HELP sstc_cpu_seconds_total CPU time ffmpeg spent, user and system, by preset.\n\
+             # TYPE sstc_cpu_seconds_total counter\n\
+             sstc_cpu_seconds_total{preset=\"archive\"} 7200.5\n\
+             sstc_cpu_seconds_total{preset=\"say \\\"hi\\\"\\\\now\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn written_file_replaces_the_last_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sstc.prom");
+        write(&path, &BTreeMap::from([("a".to_string(), 1.0)])).unwrap();
+        write(&path, &BTreeMap::from([("a".to_string(), 2.0)])).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            text.ends_with("sstc_cpu_seconds_total{preset=\"a\"} 2\n"),
+            "{}",
+            text
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::warn;
+
+/// Private directory for the statistics files of one two-pass encode, removed with
+/// everything in it when dropped, whether the job succeeded or not
+pub struct PassLogDir {
+    dir: PathBuf,
+}
+
+impl PassLogDir {
+    pub fn new() -> Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "sstc-passlog-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create pass log directory {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// Prefix for `-passlogfile`; ffmpeg appends the stream index and `.log`
+    pub fn prefix(&self) -> PathBuf {
+        self.dir.join("pass")
+    }
+}
+
+impl Drop for PassLogDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!(
+                "Failed to remove pass log directory {}: {}",
+                self.dir.display(),
+                e
+            );
+        }
+    }
+}
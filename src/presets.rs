@@ -1,7 +1,6 @@
-use crate::config::{Config, PresetConfig};
+use crate::config::{Config, ExtraOptions, HwAccelConfig, HwAccelKind, PresetConfig, PresetMode};
 use anyhow::Result;
 use owo_colors::OwoColorize;
-use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tracing::info;
 
@@ -20,13 +19,14 @@ impl PresetGenerator {
             video_bitrate: Some("2M".to_string()),
             audio_bitrate: Some("128k".to_string()),
             scale: None,
+            crf: Some(28),
+            encoder_preset: Some("ultrafast".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "ultrafast".to_string());
-                options.insert("-crf".to_string(), "28".to_string());
-                options.insert("-tune".to_string(), "fastdecode".to_string());
+                let mut options = ExtraOptions::new();
+                options.push("-tune", "fastdecode");
                 options
             },
+            ..Default::default()
         };
 
         // Medium preset (balanced quality/speed)
@@ -37,13 +37,14 @@ impl PresetGenerator {
             video_bitrate: Some("4M".to_string()),
             audio_bitrate: Some("192k".to_string()),
             scale: None,
+            crf: Some(23),
+            encoder_preset: Some("medium".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "medium".to_string());
-                options.insert("-crf".to_string(), "23".to_string());
-                options.insert("-tune".to_string(), "film".to_string());
+                let mut options = ExtraOptions::new();
+                options.push("-tune", "film");
                 options
             },
+            ..Default::default()
         };
 
         // Slow preset (high quality, slower encoding)
@@ -54,14 +55,15 @@ impl PresetGenerator {
             video_bitrate: Some("6M".to_string()),
             audio_bitrate: Some("256k".to_string()),
             scale: None,
+            crf: Some(18),
+            encoder_preset: Some("slow".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "slow".to_string());
-                options.insert("-crf".to_string(), "18".to_string());
-                options.insert("-tune".to_string(), "film".to_string());
-                options.insert("-x264-params".to_string(), "ref=5:me=umh".to_string());
+                let mut options = ExtraOptions::new();
+                options.push("-tune", "film");
+                options.push("-x264-params", "ref=5:me=umh");
                 options
             },
+            ..Default::default()
         };
 
         // Fast H.265/HEVC preset
@@ -72,13 +74,14 @@ impl PresetGenerator {
             video_bitrate: None,
             audio_bitrate: Some("128k".to_string()),
             scale: None,
+            crf: Some(28),
+            encoder_preset: Some("ultrafast".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "ultrafast".to_string());
-                options.insert("-crf".to_string(), "28".to_string());
-                options.insert("-tag:v".to_string(), "hvc1".to_string());
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
                 options
             },
+            ..Default::default()
         };
 
         // Medium H.265/HEVC preset
@@ -89,14 +92,15 @@ impl PresetGenerator {
             video_bitrate: None,
             audio_bitrate: Some("192k".to_string()),
             scale: None,
+            crf: Some(23),
+            encoder_preset: Some("medium".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "medium".to_string());
-                options.insert("-crf".to_string(), "23".to_string());
-                options.insert("-tag:v".to_string(), "hvc1".to_string());
-                options.insert("-x265-params".to_string(), "log-level=error".to_string());
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
+                options.push("-x265-params", "log-level=error");
                 options
             },
+            ..Default::default()
         };
 
         // Slow/High Quality H.265/HEVC preset
@@ -107,17 +111,15 @@ impl PresetGenerator {
             video_bitrate: None,
             audio_bitrate: Some("256k".to_string()),
             scale: None,
+            crf: Some(18),
+            encoder_preset: Some("slow".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "slow".to_string());
-                options.insert("-crf".to_string(), "18".to_string());
-                options.insert("-tag:v".to_string(), "hvc1".to_string());
-                options.insert(
-                    "-x265-params".to_string(),
-                    "ref=5:me=star:rd=4:log-level=error".to_string(),
-                );
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
+                options.push("-x265-params", "ref=5:me=star:rd=4:log-level=error");
                 options
             },
+            ..Default::default()
         };
 
         // Create special GoPro preset that reduces size while maintaining quality
@@ -128,20 +130,125 @@ impl PresetGenerator {
             video_bitrate: None,
             audio_bitrate: None,
             scale: None,
+            crf: Some(24),
+            encoder_preset: Some("fast".to_string()),
             extra_options: {
-                let mut options = HashMap::new();
-                options.insert("-preset".to_string(), "fast".to_string());
-                options.insert("-crf".to_string(), "24".to_string());
-                options.insert("-x265-params".to_string(), "log-level=error".to_string());
-                options.insert("-tag:v".to_string(), "hvc1".to_string());
-                options.insert(
-                    "-map".to_string(),
-                    "0:v,0:a,0:m:handler_name:GoPro MET".to_string(),
-                );
-                options.insert("-map_metadata".to_string(), "0".to_string());
-                options.insert("-movflags".to_string(), "use_metadata_tags".to_string());
+                let mut options = ExtraOptions::new();
+                options.push("-x265-params", "log-level=error");
+                options.push("-tag:v", "hvc1");
+                options.push("-map", "0:v");
+                options.push("-map", "0:a");
+                options.push("-map", "0:m:handler_name:GoPro MET");
+                options.push("-movflags", "use_metadata_tags");
                 options
             },
+            ..Default::default()
+        };
+
+        // Video-only H.265 with the audio stripped, e.g. for timelapses and surveillance clips
+        let silent_h265 = PresetConfig {
+            video_codec: Some("libx265".to_string()),
+            pixel_format: Some("yuv420p10le".to_string()),
+            crf: Some(26),
+            encoder_preset: Some("medium".to_string()),
+            extra_options: {
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
+                options.push_flag("-an");
+                options
+            },
+            ..Default::default()
+        };
+
+        // Audio track only, e.g. to listen to recorded talks
+        let audio_m4a = PresetConfig {
+            audio_only: Some(true),
+            audio_codec: Some("aac".to_string()),
+            audio_bitrate: Some("128k".to_string()),
+            container: Some("m4a".to_string()),
+            ..Default::default()
+        };
+
+        // Camcorder .MTS and old .avi files rewrapped into mp4 as they are, without re-encoding
+        let remux_mp4 = PresetConfig {
+            mode: Some(PresetMode::Remux),
+            container: Some("mp4".to_string()),
+            extra_options: {
+                let mut options = ExtraOptions::new();
+                options.push("-movflags", "+faststart");
+                options
+            },
+            ..Default::default()
+        };
+
+        // HEVC with VAAPI decoding and encoding; frames stay on the GPU in between
+        let vaapi_hevc = PresetConfig {
+            video_codec: Some("hevc".to_string()),
+            audio_codec: Some("copy".to_string()),
+            hwaccel: Some(HwAccelConfig {
+                kind: HwAccelKind::Vaapi,
+                device: Some(HwAccelConfig::DEFAULT_VAAPI_DEVICE.to_string()),
+            }),
+            crf: Some(24),
+            extra_options: {
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
+                options
+            },
+            ..Default::default()
+        };
+
+        // HEVC with NVDEC decoding and NVENC encoding
+        let nvenc_hevc = PresetConfig {
+            video_codec: Some("hevc".to_string()),
+            audio_codec: Some("copy".to_string()),
+            hwaccel: Some(HwAccelConfig {
+                kind: HwAccelKind::Nvenc,
+                device: None,
+            }),
+            crf: Some(24),
+            encoder_preset: Some("p5".to_string()),
+            extra_options: {
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
+                options
+            },
+            ..Default::default()
+        };
+
+        // HEVC with Intel Quick Sync decoding and encoding
+        let qsv_hevc = PresetConfig {
+            video_codec: Some("hevc".to_string()),
+            audio_codec: Some("copy".to_string()),
+            hwaccel: Some(HwAccelConfig {
+                kind: HwAccelKind::Qsv,
+                device: None,
+            }),
+            crf: Some(24),
+            encoder_preset: Some("medium".to_string()),
+            extra_options: {
+                let mut options = ExtraOptions::new();
+                options.push("-tag:v", "hvc1");
+                options
+            },
+            ..Default::default()
+        };
+
+        // HEVC with VideoToolbox on macOS
+        let videotoolbox_hevc = PresetConfig {
+            video_codec: Some("hevc".to_string()),
+            audio_codec: Some("copy".to_string()),
+            hwaccel: Some(HwAccelConfig {
+                kind: HwAccelKind::Videotoolbox,
+                device: None,
+            }),
+            extra_options: {
+                let mut options = ExtraOptions::new();
+                options.push("-q:v", "60");
+                options.push("-tag:v", "hvc1");
+                options
+            },
+            ..Default::default()
         };
 
         // Insert presets into config if they don't already exist
@@ -153,6 +260,13 @@ impl PresetGenerator {
             ("medium_h265", medium_h265),
             ("slow_h265", slow_h265),
             ("gopro_compact", gopro_compact),
+            ("silent_h265", silent_h265),
+            ("audio_m4a", audio_m4a),
+            ("remux_mp4", remux_mp4),
+            ("vaapi_hevc", vaapi_hevc),
+            ("nvenc_hevc", nvenc_hevc),
+            ("qsv_hevc", qsv_hevc),
+            ("videotoolbox_hevc", videotoolbox_hevc),
         ];
 
         for (name, preset) in presets_to_add {
@@ -170,16 +284,13 @@ impl PresetGenerator {
     /// Save the config with example presets to a file
     pub fn save_example_presets<P: AsRef<Path>>(path: P) -> Result<()> {
         let mut config = Config {
-            inputs: Vec::new(),
-            outputs: HashMap::new(),
-            presets: HashMap::new(),
             max_parallel_jobs: Some(1),
+            ..Default::default()
         };
 
         Self::generate_example_presets(&mut config)?;
 
-        let yaml = serde_yaml::to_string(&config)?;
-        std::fs::write(&path, yaml)?;
+        crate::config::write_config(&path, &config)?;
 
         info!(
             "Saved example presets to {}",
@@ -191,18 +302,17 @@ impl PresetGenerator {
     /// Generate and save a complete example configuration file
     pub fn save_example_config<P: AsRef<Path>>(path: P) -> Result<()> {
         let mut config = Config {
-            inputs: Vec::new(),
-            outputs: HashMap::new(),
-            presets: HashMap::new(),
             max_parallel_jobs: Some(1),
+            default_preset: Some("medium_h264".to_string()),
+            default_output: Some("main_output".to_string()),
+            ..Default::default()
         };
 
-        // Add example inputs
+        // Add example inputs; the first one goes by the defaults
         config.inputs.push(crate::config::InputConfig {
             path: PathBuf::from("./ingest/default"),
             extensions: vec!["mp4".to_string(), "mkv".to_string(), "mov".to_string()],
-            preset: "medium_h264".to_string(),
-            output: "main_output".to_string(),
+            ..Default::default()
         });
 
         config.inputs.push(crate::config::InputConfig {
@@ -210,13 +320,16 @@ impl PresetGenerator {
             extensions: vec!["mp4".to_string(), "mkv".to_string()],
             preset: "gopro_compact".to_string(),
             output: "gopro_output".to_string(),
+            fallback_preset: Some("medium_h265".to_string()),
+            ..Default::default()
         });
 
         config.inputs.push(crate::config::InputConfig {
-            path: PathBuf::from("./ingest/archival"),
+            path: PathBuf::from("${MEDIA_ROOT:-.}/ingest/archival"),
             extensions: vec!["mp4".to_string(), "mkv".to_string(), "mov".to_string()],
             preset: "slow_h264".to_string(),
             output: "archive_output".to_string(),
+            ..Default::default()
         });
 
         config.outputs.insert(
@@ -224,7 +337,14 @@ impl PresetGenerator {
             crate::config::OutputConfig {
                 path: PathBuf::from("./output"),
                 filename_template: "{filename}".to_string(),
+                subdir_template: None,
                 container: "mp4".to_string(),
+                on_same_file: Default::default(),
+                on_existing: Default::default(),
+                if_source_newer: Default::default(),
+                trash_dir: None,
+                create_subdirs: true,
+                thumbnail: None,
             },
         );
 
@@ -233,23 +353,36 @@ impl PresetGenerator {
             crate::config::OutputConfig {
                 path: PathBuf::from("./output/gopro"),
                 filename_template: "{filename}".to_string(),
+                subdir_template: None,
                 container: "mkv".to_string(),
+                on_same_file: Default::default(),
+                on_existing: Default::default(),
+                if_source_newer: Default::default(),
+                trash_dir: None,
+                create_subdirs: true,
+                thumbnail: None,
             },
         );
 
         config.outputs.insert(
             "archive_output".to_string(),
             crate::config::OutputConfig {
-                path: PathBuf::from("./output/archive"),
+                path: PathBuf::from("${MEDIA_ROOT:-.}/output/archive"),
                 filename_template: "{filename}_hq".to_string(),
+                subdir_template: Some("{year}/{month}".to_string()),
                 container: "mp4".to_string(),
+                on_same_file: Default::default(),
+                on_existing: Default::default(),
+                if_source_newer: Default::default(),
+                trash_dir: None,
+                create_subdirs: true,
+                thumbnail: None,
             },
         );
 
         Self::generate_example_presets(&mut config)?;
 
-        let yaml = serde_yaml::to_string(&config)?;
-        std::fs::write(&path, yaml)?;
+        crate::config::write_config(&path, &config)?;
 
         info!(
             "Saved complete example configuration to {}",
@@ -0,0 +1,141 @@
+use crate::config::ProcessPriority;
+use std::sync::RwLock;
+use tokio::process::Command;
+
+/// Priority of processes started outside of a preset's jobs, like ffprobe; the global
+/// `process_priority`, kept up to date by the transcoder
+static DEFAULT: RwLock<ProcessPriority> = RwLock::new(ProcessPriority {
+    nice: 0,
+    ionice_class: None,
+    ionice_level: None,
+});
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+/// Level the kernel gives best-effort processes without one of their own
+#[cfg(target_os = "linux")]
+const DEFAULT_IONICE_LEVEL: u8 = 4;
+
+#[cfg(windows)]
+const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+
+pub fn set_default(priority: ProcessPriority) {
+    if let Ok(mut default) = DEFAULT.write() {
+        *default = priority;
+    }
+}
+
+pub fn default_priority() -> ProcessPriority {
+    DEFAULT.read().map(|default| *default).unwrap_or_default()
+}
+
+/// Have `cmd` start its process at `priority`. A priority the system refuses, like a negative
+/// niceness without root, leaves the process at normal priority rather than failing the spawn.
+pub fn apply(cmd: &mut Command, priority: ProcessPriority) {
+    if priority.is_normal() {
+        return;
+    }
+    #[cfg(unix)]
+    // SAFETY: the hook runs between fork and exec and only makes async-signal-safe system calls
+    unsafe {
+        cmd.pre_exec(move || {
+            lower(priority);
+            Ok(())
+        });
+    }
+    #[cfg(windows)]
+    if priority.nice > 0 {
+        cmd.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+    }
+}
+
+/// Set the priority of the calling process, ignoring refusals
+#[cfg(unix)]
+fn lower(priority: ProcessPriority) {
+    if priority.nice != 0 {
+        // SAFETY: plain system call on the calling process
+        unsafe {
+            libc::setpriority(libc::PRIO_PROCESS, 0, priority.nice);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(class) = priority.ionice_class {
+        let (class, level) = match class {
+            crate::config::IoniceClass::BestEffort => {
+                (2, priority.ionice_level.unwrap_or(DEFAULT_IONICE_LEVEL))
+            }
+            crate::config::IoniceClass::Idle => (3, 0),
+        };
+        // SAFETY: plain system call on the calling process; libc has no wrapper for it
+        unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                class << IOPRIO_CLASS_SHIFT | libc::c_long::from(level),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, IoniceClass};
+    use crate::test_support::preset;
+
+    #[test]
+    fn presets_fall_back_to_the_global_priority() {
+        let global = ProcessPriority {
+            nice: 10,
+            ionice_class: Some(IoniceClass::Idle),
+            ionice_level: None,
+        };
+        let with_global = Config {
+            process_priority: Some(global),
+            ..Config::default()
+        };
+        let own = preset("process_priority: {nice: 5}");
+        let unset = preset("video_codec: libx264");
+
+        assert_eq!(
+            own.process_priority_in(&with_global),
+            ProcessPriority {
+                nice: 5,
+                ..ProcessPriority::default()
+            }
+        );
+        assert_eq!(unset.process_priority_in(&with_global), global);
+        assert!(unset.process_priority_in(&Config::default()).is_normal());
+    }
+
+    /// Niceness `sh` reports when started at `priority`
+    #[cfg(target_os = "linux")]
+    async fn niceness_at(priority: ProcessPriority) -> i32 {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "nice"]);
+        apply(&mut cmd, priority);
+        let output = cmd.output().await.unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn spawned_processes_start_at_the_priority() {
+        let base = niceness_at(ProcessPriority::default()).await;
+        let lowered = ProcessPriority {
+            nice: 5,
+            ..ProcessPriority::default()
+        };
+        // Set rather than added, and a process already nicer than that may not go back to it
+        assert_eq!(niceness_at(lowered).await, base.max(5));
+        // Only the child was lowered
+        assert_eq!(niceness_at(ProcessPriority::default()).await, base);
+    }
+}
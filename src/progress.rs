@@ -0,0 +1,445 @@
+use crate::job::FrameStats;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// One block of `-progress` output from ffmpeg
+#[derive(Debug, Default, Clone)]
+pub struct FFmpegProgress {
+    pub frame: Option<i64>,
+    pub fps: Option<f64>,
+    pub stream_0_0_q: Option<f64>,
+    pub bitrate: Option<String>,
+    pub total_size: Option<i64>,
+    /// Output timestamp in microseconds, whichever of `out_time_us`, `out_time` and
+    /// `out_time_ms` the ffmpeg version wrote
+    pub out_time_us: Option<i64>,
+    pub out_time: Option<String>,
+    pub dup_frames: Option<i64>,
+    pub drop_frames: Option<i64>,
+    pub speed: Option<String>,
+    pub progress: Option<String>,
+}
+
+impl FFmpegProgress {
+    pub fn from_key_values(key_values: &HashMap<String, String>) -> Self {
+        let mut progress = Self::default();
+        // Despite its name ffmpeg writes `out_time_ms` in microseconds too
+        let mut out_time_ms = None;
+
+        for (key, value) in key_values {
+            match key.as_str() {
+                "frame" => progress.frame = value.parse().ok(),
+                "fps" => progress.fps = value.parse().ok(),
+                "stream_0_0_q" => progress.stream_0_0_q = value.parse().ok(),
+                "bitrate" => progress.bitrate = Some(value.clone()),
+                "total_size" => progress.total_size = value.parse().ok(),
+                "out_time_us" => progress.out_time_us = value.parse().ok(),
+                "out_time_ms" => out_time_ms = value.parse().ok(),
+                "out_time" => progress.out_time = Some(value.clone()),
+                "dup_frames" => progress.dup_frames = value.parse().ok(),
+                "drop_frames" => progress.drop_frames = value.parse().ok(),
+                "speed" => progress.speed = Some(value.clone()),
+                "progress" => progress.progress = Some(value.clone()),
+                _ => {} // Ignore unknown fields
+            }
+        }
+
+        // Versions differ in which of the three they write, and write `N/A` before the first
+        // frame; the named microseconds win, then the timestamp, which can't be misread
+        progress.out_time_us = progress
+            .out_time_us
+            .or_else(|| progress.out_time.as_deref().and_then(parse_timestamp_us))
+            .or(out_time_ms);
+        progress
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.progress.as_deref(), Some("end"))
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        FrameStats {
+            frames: self.frame.unwrap_or_default(),
+            dup_frames: self.dup_frames.unwrap_or_default(),
+            drop_frames: self.drop_frames.unwrap_or_default(),
+        }
+    }
+
+    /// Output timestamp reached so far, in seconds
+    pub fn out_time_secs(&self) -> Option<f64> {
+        self.out_time_us
+            .filter(|us| *us >= 0)
+            .map(|us| us as f64 / 1_000_000.0)
+    }
+
+    /// Encoding speed relative to realtime, parsed from values like `1.5x`
+    pub fn speed_factor(&self) -> Option<f64> {
+        self.speed
+            .as_deref()?
+            .trim()
+            .trim_end_matches('x')
+            .parse()
+            .ok()
+            .filter(|speed: &f64| speed.is_finite() && *speed > 0.0)
+    }
+
+    /// Output bitrate in kbit/s, parsed from values like `1843.2kbits/s`
+    pub fn bitrate_kbps(&self) -> Option<f64> {
+        self.bitrate
+            .as_deref()?
+            .trim()
+            .trim_end_matches("kbits/s")
+            .parse()
+            .ok()
+            .filter(|bitrate: &f64| bitrate.is_finite() && *bitrate > 0.0)
+    }
+}
+
+/// Below this speed the ETA would swing wildly, or be absurdly long, so none is shown
+const MIN_ETA_SPEED: f64 = 0.05;
+
+/// Progress of a running job, in the shape reported to anything outside the encoder loop
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct JobProgress {
+    pub out_time_secs: Option<f64>,
+    pub speed: Option<f64>,
+    /// Frames encoded per second
+    pub fps: Option<f64>,
+    /// Output bitrate so far, in kbit/s
+    pub bitrate_kbps: Option<f64>,
+    /// Percent of the expected output duration; `None` when that duration is unknown
+    pub percent: Option<f32>,
+    /// Estimated seconds left at the current speed
+    pub eta_secs: Option<u64>,
+}
+
+impl JobProgress {
+    /// Build from an ffmpeg progress block and the trim-aware expected output duration
+    pub fn new(progress: &FFmpegProgress, expected_duration: Option<f64>) -> Self {
+        let out_time_secs = progress.out_time_secs();
+        let speed = progress.speed_factor();
+        let expected_duration = expected_duration.filter(|d| d.is_finite() && *d > 0.0);
+
+        let percent = match (out_time_secs, expected_duration) {
+            (Some(done), Some(total)) => Some(percent_of(done, total)),
+            _ => None,
+        };
+
+        // ffmpeg reports `speed=N/A` until it has timing to go on, which leaves no ETA. Going by
+        // out_time rather than frames keeps it right when `fps` changes the frame count
+        let eta_secs = match (out_time_secs, expected_duration, speed) {
+            (Some(done), Some(total), Some(speed)) if speed >= MIN_ETA_SPEED => {
+                Some(((total - done).max(0.0) / speed).round() as u64)
+            }
+            _ => None,
+        };
+
+        Self {
+            out_time_secs,
+            speed,
+            fps: progress.fps.filter(|fps| fps.is_finite() && *fps > 0.0),
+            bitrate_kbps: progress.bitrate_kbps(),
+            percent,
+            eta_secs,
+        }
+    }
+
+    /// Short human-readable form for progress bars and log lines
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(percent) = self.percent {
+            parts.push(format!("{:.1}%", percent));
+        } else if let Some(done) = self.out_time_secs {
+            parts.push(short_duration(done as u64));
+        }
+        if let Some(speed) = self.speed {
+            parts.push(format!("{:.2}x", speed));
+        }
+        if let Some(fps) = self.fps {
+            parts.push(format!("{:.0} fps", fps));
+        }
+        if let Some(bitrate) = self.bitrate_kbps {
+            parts.push(if bitrate >= 10_000.0 {
+                format!("{:.1} Mb/s", bitrate / 1000.0)
+            } else {
+                format!("{:.0} kb/s", bitrate)
+            });
+        }
+        if let Some(eta) = self.eta_secs {
+            parts.push(format!("ETA {}", short_duration(eta)));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Microseconds of an ffmpeg timestamp like `01:02:03.456789`, negative ones included
+fn parse_timestamp_us(value: &str) -> Option<i64> {
+    let (sign, value) = match value.trim().strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, value.trim()),
+    };
+    let mut parts = value.splitn(3, ':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let secs = hours * 3600.0 + minutes * 60.0 + seconds;
+    secs.is_finite()
+        .then(|| (sign * secs * 1_000_000.0).round() as i64)
+}
+
+/// Time span like `42s`, `3m05s` or `1h02m`
+pub fn short_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Share of `total` covered by `done`, clamped to 0..=100 since growing inputs can run past it
+fn percent_of(done: f64, total: f64) -> f32 {
+    ((done / total) * 100.0).clamp(0.0, 100.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(out_time_us: Option<i64>, speed: &str) -> FFmpegProgress {
+        FFmpegProgress {
+            out_time_us,
+            speed: Some(speed.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn percent_starts_at_zero() {
+        let progress = JobProgress::new(&block(Some(0), "N/A"), Some(60.0));
+        assert_eq!(progress.percent, Some(0.0));
+        assert_eq!(progress.eta_secs, None);
+    }
+
+    #[test]
+    fn percent_and_eta_midway() {
+        let progress = JobProgress::new(&block(Some(15_000_000), "2.0x"), Some(60.0));
+        assert_eq!(progress.percent, Some(25.0));
+        assert_eq!(progress.eta_secs, Some(23));
+        assert_eq!(progress.describe(), "25.0% 2.00x ETA 23s");
+    }
+
+    #[test]
+    fn percent_is_clamped_for_growing_inputs() {
+        let progress = JobProgress::new(&block(Some(90_000_000), "1.0x"), Some(60.0));
+        assert_eq!(progress.percent, Some(100.0));
+        assert_eq!(progress.eta_secs, Some(0));
+    }
+
+    #[test]
+    fn negative_out_time_is_not_progress() {
+        let progress = JobProgress::new(&block(Some(-23_220), "1.0x"), Some(60.0));
+        assert_eq!(progress.out_time_secs, None);
+        assert_eq!(progress.percent, None);
+    }
+
+    #[test]
+    fn unknown_duration_leaves_percent_null() {
+        for expected in [None, Some(0.0), Some(f64::NAN), Some(-5.0)] {
+            let progress = JobProgress::new(&block(Some(15_000_000), "2.0x"), expected);
+            assert_eq!(progress.percent, None, "{:?}", expected);
+            assert_eq!(progress.eta_secs, None, "{:?}", expected);
+        }
+        let progress = JobProgress::new(&block(Some(75_000_000), "2.0x"), None);
+        assert_eq!(progress.describe(), "1m15s 2.00x");
+        let json = serde_json::to_value(&progress).unwrap();
+        assert!(json["percent"].is_null());
+        assert!(json["eta_secs"].is_null());
+    }
+
+    #[test]
+    fn no_eta_at_a_crawl() {
+        let progress = JobProgress::new(&block(Some(15_000_000), "0.01x"), Some(60.0));
+        assert_eq!(progress.percent, Some(25.0));
+        assert_eq!(progress.eta_secs, None);
+    }
+
+    /// Each block of captured `-progress` output as the encoder loop reads it, keys carrying
+    /// over from earlier blocks
+    fn blocks(captured: &str) -> Vec<FFmpegProgress> {
+        let mut current = HashMap::new();
+        let mut blocks = Vec::new();
+        for line in captured.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (key, value) = line.split_once('=').unwrap();
+            current.insert(key.to_string(), value.to_string());
+            if key == "progress" {
+                blocks.push(FFmpegProgress::from_key_values(&current));
+            }
+        }
+        blocks
+    }
+
+    /// ffmpeg 3.4 has no `out_time_us`, and writes microseconds as `out_time_ms`
+    const FFMPEG_3_4: &str = "
+frame=120
+fps=47.9
+stream_0_0_q=28.0
+bitrate=1843.2kbits/s
+total_size=1155072
+out_time_ms=5013333
+out_time=00:00:05.013333
+dup_frames=0
+drop_frames=0
+speed=2.00x
+progress=continue
+frame=301
+fps=49.8
+stream_0_0_q=-1.0
+bitrate=1901.7kbits/s
+total_size=2862080
+out_time_ms=12040000
+out_time=00:00:12.040000
+dup_frames=1
+drop_frames=0
+speed=1.99x
+progress=end
+";
+
+    /// ffmpeg 6.1 writes all three, `N/A` before the first frame
+    const FFMPEG_6_1: &str = "
+frame=0
+fps=0.00
+stream_0_0_q=0.0
+bitrate=N/A
+total_size=48
+out_time_us=N/A
+out_time_ms=N/A
+out_time=N/A
+dup_frames=0
+drop_frames=0
+speed=N/A
+progress=continue
+frame=96
+fps=95.87
+stream_0_0_q=29.0
+bitrate= 612.1kbits/s
+total_size=307248
+out_time_us=4015986
+out_time_ms=4015986
+out_time=00:00:04.015986
+dup_frames=0
+drop_frames=0
+speed=4.01x
+progress=continue
+frame=301
+fps=96.12
+stream_0_0_q=-1.0
+bitrate= 598.4kbits/s
+total_size=900712
+out_time_us=12040000
+out_time_ms=12040000
+out_time=00:00:12.040000
+dup_frames=0
+drop_frames=2
+speed=4.02x
+progress=end
+";
+
+    /// ffmpeg 7.0 starts audio encodes before zero, from the encoder delay
+    const FFMPEG_7_0: &str = "
+bitrate=N/A
+total_size=0
+out_time_us=-23220
+out_time_ms=-23220
+out_time=-00:00:00.023220
+speed=N/A
+progress=continue
+bitrate= 128.3kbits/s
+total_size=96256
+out_time_us=6002000
+out_time_ms=6002000
+out_time=00:00:06.002000
+speed= 123x
+progress=continue
+";
+
+    #[test]
+    fn captured_progress_of_ffmpeg_3_4() {
+        let blocks = blocks(FFMPEG_3_4);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].out_time_secs(), Some(5.013333));
+        assert_eq!(blocks[0].bitrate_kbps(), Some(1843.2));
+
+        let midway = JobProgress::new(&blocks[0], Some(12.04));
+        assert_eq!(midway.percent, Some(41.638977));
+        assert_eq!(midway.eta_secs, Some(4));
+        assert_eq!(midway.describe(), "41.6% 2.00x 48 fps 1843 kb/s ETA 4s");
+
+        assert!(blocks[1].is_complete());
+        assert_eq!(blocks[1].out_time_secs(), Some(12.04));
+        assert_eq!(blocks[1].frame_stats().frames, 301);
+        assert_eq!(blocks[1].frame_stats().dup_frames, 1);
+        assert_eq!(
+            JobProgress::new(&blocks[1], Some(12.04)).percent,
+            Some(100.0)
+        );
+    }
+
+    #[test]
+    fn captured_progress_of_ffmpeg_6_1() {
+        let blocks = blocks(FFMPEG_6_1);
+        assert_eq!(blocks.len(), 3);
+
+        let start = JobProgress::new(&blocks[0], Some(12.04));
+        assert_eq!(start.out_time_secs, None);
+        assert_eq!(start.speed, None);
+        assert_eq!(start.bitrate_kbps, None);
+        assert_eq!(start.percent, None);
+        assert_eq!(start.describe(), "");
+
+        assert_eq!(blocks[1].out_time_secs(), Some(4.015986));
+        let midway = JobProgress::new(&blocks[1], Some(12.04));
+        assert_eq!(midway.eta_secs, Some(2));
+        assert_eq!(midway.describe(), "33.4% 4.01x 96 fps 612 kb/s ETA 2s");
+
+        assert!(blocks[2].is_complete());
+        assert_eq!(blocks[2].out_time_secs(), Some(12.04));
+        assert_eq!(blocks[2].frame_stats().drop_frames, 2);
+    }
+
+    #[test]
+    fn captured_progress_of_ffmpeg_7_0() {
+        let blocks = blocks(FFMPEG_7_0);
+        assert_eq!(blocks[0].out_time_secs(), None);
+        assert_eq!(blocks[1].out_time_secs(), Some(6.002));
+        let progress = JobProgress::new(&blocks[1], Some(300.0));
+        assert_eq!(progress.speed, Some(123.0));
+        assert_eq!(progress.eta_secs, Some(2));
+        assert_eq!(progress.describe(), "2.0% 123.00x 128 kb/s ETA 2s");
+    }
+
+    #[test]
+    fn out_time_fields_agree_whichever_is_written() {
+        let parse = |pairs: &[(&str, &str)]| {
+            let key_values = pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            FFmpegProgress::from_key_values(&key_values).out_time_secs()
+        };
+        for pairs in [
+            &[("out_time_us", "90500000")][..],
+            &[("out_time_ms", "90500000")],
+            &[("out_time", "00:01:30.500000")],
+            &[("out_time_us", "N/A"), ("out_time", "00:01:30.500000")],
+            &[
+                ("out_time_us", "90500000"),
+                ("out_time_ms", "90500"),
+                ("out_time", "00:01:30.500000"),
+            ],
+        ] {
+            assert_eq!(parse(pairs), Some(90.5), "{:?}", pairs);
+        }
+        assert_eq!(parse(&[("out_time", "N/A")]), None);
+        assert_eq!(parse(&[]), None);
+    }
+}
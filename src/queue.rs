@@ -0,0 +1,151 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Queue handing out its highest-priority item first, first-in first-out within a priority
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    items: BTreeMap<(Reverse<u8>, i64), T>,
+    /// Sequence numbers for the back grow up and those for the front grow down, so both ends
+    /// stay ordered within a priority
+    next_back: i64,
+    next_front: i64,
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        Self {
+            items: BTreeMap::new(),
+            next_back: 0,
+            next_front: -1,
+        }
+    }
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an item behind everything else of the same priority
+    pub fn push_back(&mut self, priority: u8, item: T) {
+        self.items.insert((Reverse(priority), self.next_back), item);
+        self.next_back += 1;
+    }
+
+    /// Add an item ahead of everything else of the same priority
+    pub fn push_front(&mut self, priority: u8, item: T) {
+        self.items
+            .insert((Reverse(priority), self.next_front), item);
+        self.next_front -= 1;
+    }
+
+    /// Take the item of the highest priority that `key` ranks lowest, the oldest among equals.
+    /// Older items of that priority it goes ahead of are handed to `passed_over`.
+    pub fn pop_by<K: Ord>(
+        &mut self,
+        key: impl Fn(&T) -> K,
+        mut passed_over: impl FnMut(&mut T),
+    ) -> Option<T> {
+        let (&(top, _), _) = self.items.first_key_value()?;
+        let (&chosen, _) = self
+            .items
+            .iter()
+            .take_while(|((priority, _), _)| *priority == top)
+            .min_by_key(|(_, item)| key(item))?;
+        for (_, item) in self.items.range_mut(..chosen) {
+            passed_over(item);
+        }
+        self.items.remove(&chosen)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.values()
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        self.items.retain(|_, item| keep(item));
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Take every item, ranked by `key`, counting how often each was passed over
+    fn drain<T: Copy, K: Ord>(
+        queue: &mut PriorityQueue<(T, u32)>,
+        key: impl Fn(&T) -> K,
+    ) -> Vec<(T, u32)> {
+        std::iter::from_fn(|| queue.pop_by(|(item, _)| key(item), |(_, skips)| *skips += 1))
+            .collect()
+    }
+
+    fn names<T: Copy>(taken: &[(T, u32)]) -> Vec<T> {
+        taken.iter().map(|(item, _)| *item).collect()
+    }
+
+    fn order(taken: &[((&'static str, u64, u64), u32)]) -> Vec<&'static str> {
+        names(taken).into_iter().map(|(name, _, _)| name).collect()
+    }
+
+    #[test]
+    fn higher_priorities_go_first_in_queued_order() {
+        let mut queue = PriorityQueue::new();
+        for (priority, name) in [(0, "a"), (5, "b"), (0, "c"), (9, "d"), (5, "e")] {
+            queue.push_back(priority, (name, 0));
+        }
+
+        let taken = drain(&mut queue, |_| ());
+
+        assert_eq!(names(&taken), ["d", "b", "e", "a", "c"]);
+        // First in first out passes nothing over
+        assert!(taken.iter().all(|(_, skips)| *skips == 0));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn push_front_goes_ahead_within_its_priority_only() {
+        let mut queue = PriorityQueue::new();
+        queue.push_back(0, ("a", 0));
+        queue.push_back(5, ("b", 0));
+        queue.push_front(0, ("c", 0));
+        queue.push_front(0, ("d", 0));
+
+        assert_eq!(names(&drain(&mut queue, |_| ())), ["b", "d", "c", "a"]);
+    }
+
+    #[test]
+    fn key_picks_within_the_top_priority() {
+        // Name, size and age in days
+        let files = [("a", 300, 1), ("b", 100, 7), ("c", 200, 3), ("d", 100, 9)];
+        let queued = || {
+            let mut queue = PriorityQueue::new();
+            for file in files {
+                queue.push_back(0, (file, 0));
+            }
+            // Larger than all, but it's the only one of its priority
+            queue.push_back(1, (("e", 900, 0), 0));
+            queue
+        };
+        // Smallest first, the oldest queued among equal sizes
+        let smallest = drain(&mut queued(), |(_, size, _)| *size);
+        assert_eq!(order(&smallest), ["e", "b", "d", "c", "a"]);
+        // Taking b passed a over, d passed a and c, and c passed a once more
+        let skips: Vec<u32> = smallest.iter().map(|(_, skips)| *skips).collect();
+        assert_eq!(skips, [0, 0, 0, 1, 3]);
+
+        let largest = drain(&mut queued(), |(_, size, _)| Reverse(*size));
+        assert_eq!(order(&largest), ["e", "a", "c", "b", "d"]);
+
+        let oldest = drain(&mut queued(), |(_, _, age)| Reverse(*age));
+        assert_eq!(order(&oldest), ["e", "d", "b", "c", "a"]);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::artifacts;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Contents of the queue file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueueState {
+    /// Queued sources in the order they would have started
+    files: Vec<PathBuf>,
+}
+
+/// The pending queue kept on disk, so files still queued when the service stops are picked up
+/// again on the next start
+#[derive(Debug, Clone)]
+pub struct QueueFile {
+    path: PathBuf,
+}
+
+impl QueueFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Files queued when the queue was last saved; nothing when there is no queue file yet
+    pub fn load(&self) -> Result<Vec<PathBuf>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).context(format!(
+                    "Failed to read queue file: {}",
+                    self.path.display()
+                ))
+            }
+        };
+        let state: QueueState = serde_json::from_str(&content).context(format!(
+            "Failed to parse queue file: {}",
+            self.path.display()
+        ))?;
+        Ok(state.files)
+    }
+
+    /// Replace the saved queue. Written to a temporary sibling and synced to disk first, then
+    /// renamed over the file, so a crash or power loss halfway leaves the previous queue rather
+    /// than a truncated one.
+    ///
+    /// Blocks on the disk, so async callers run it on the blocking pool.
+    pub fn save<'a>(&self, files: impl IntoIterator<Item = &'a Path>) -> Result<()> {
+        let state = QueueState {
+            files: files.into_iter().map(Path::to_path_buf).collect(),
+        };
+        let temp = artifacts::temp_path_for(&self.path);
+        let write = |content: String| -> std::io::Result<()> {
+            let mut file = File::create(&temp)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        };
+        write(serde_json::to_string_pretty(&state)?)
+            .context(format!("Failed to write queue file: {}", temp.display()))?;
+        std::fs::rename(&temp, &self.path).context(format!(
+            "Failed to replace queue file: {}",
+            self.path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_queue_loads_back_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue_file = QueueFile::new(dir.path().join("queue.json"));
+        assert_eq!(queue_file.load().unwrap(), Vec::<PathBuf>::new());
+
+        let files = [Path::new("/in/b.mp4"), Path::new("/in/a b.mp4")];
+        queue_file.save(files).unwrap();
+        assert_eq!(queue_file.load().unwrap(), files);
+        // Nothing is left of the temporary file the save went through
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        queue_file.save([]).unwrap();
+        assert_eq!(queue_file.load().unwrap(), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn corrupt_queue_file_fails_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.json");
+        std::fs::write(&path, "{\"files\": [\"/in/a.mp4\"").unwrap();
+        let error = QueueFile::new(path).load().unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("Failed to parse queue file"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config;
+use crate::transcoder::Transcoder;
+use crate::watcher::DirectoryWatcher;
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use owo_colors::OwoColorize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+/// Editors save in several steps; changes this close together cause a single reload
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Fires when the config should be reloaded: on SIGHUP, and on changes to the file itself
+/// when `watch_config` is enabled
+pub struct ReloadTrigger {
+    rx: mpsc::Receiver<()>,
+    _watcher: Option<Box<dyn Watcher + Send>>,
+}
+
+impl ReloadTrigger {
+    pub fn new(config_path: &Path, watch_file: bool) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(8);
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangups =
+                signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while hangups.recv().await.is_some() {
+                    debug!("Received SIGHUP");
+                    if tx.send(()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        let watcher = if watch_file {
+            Some(watch_config_file(config_path, tx)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            rx,
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait for the next reload request, coalescing a burst of them into one
+    pub async fn next(&mut self) -> Option<()> {
+        self.rx.recv().await?;
+        tokio::time::sleep(DEBOUNCE).await;
+        while self.rx.try_recv().is_ok() {}
+        Some(())
+    }
+}
+
+/// Watch the directory rather than the file, which editors often replace instead of writing
+fn watch_config_file(config_path: &Path, tx: mpsc::Sender<()>) -> Result<Box<dyn Watcher + Send>> {
+    let config_path = std::fs::canonicalize(config_path).context(format!(
+        "Failed to resolve config path: {}",
+        config_path.display()
+    ))?;
+    let dir = config_path
+        .parent()
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    let file_name = config_path.file_name().map(|n| n.to_os_string());
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        match res {
+            Ok(event) => {
+                let ours = event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name().map(|n| n.to_os_string()) == file_name);
+                if ours && (event.kind.is_create() || event.kind.is_modify()) {
+                    // A full channel already has a reload pending
+                    let _ = tx.try_send(());
+                }
+            }
+            Err(e) => error!("Config watch error: {}", e),
+        }
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .context(format!(
+            "Failed to watch config directory: {}",
+            dir.display()
+        ))?;
+
+    Ok(Box::new(watcher))
+}
+
+/// Load the config again and hand it to the running service, keeping the old one if it's invalid
+pub async fn reload_config(
+    config_path: &str,
+    max_jobs: &Option<usize>,
+    strict: bool,
+    transcoder: &Transcoder,
+    watcher: &mut DirectoryWatcher,
+) {
+    info!("Reloading configuration from {}", config_path.yellow());
+    let mut config = match config::load_config(config_path, strict) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(
+                "Keeping the current configuration, reload failed: {:#}",
+                e.red()
+            );
+            return;
+        }
+    };
+
+    if let Some(jobs) = max_jobs {
+        config.max_parallel_jobs = Some(*jobs);
+    }
+
+    for setting in config::restart_required_changes(&transcoder.config(), &config) {
+        warn!(
+            "Changed {} only takes effect after a restart",
+            setting.yellow()
+        );
+    }
+
+    let config = Arc::new(config);
+    transcoder.reload(config.clone()).await;
+    if let Err(e) = watcher.reload(config).await {
+        error!("Failed to update watched directories: {:#}", e.red());
+    }
+    info!("Configuration reloaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Sandbox, BASIC_CONFIG};
+
+    #[tokio::test]
+    async fn invalid_config_keeps_the_running_one() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(BASIC_CONFIG);
+        let transcoder = Transcoder::new(config.clone());
+        let mut watcher = DirectoryWatcher::new(config.clone(), Arc::new(transcoder.clone()));
+        let config_path = sandbox.path().join("config.yaml");
+        let config_path = config_path.to_str().unwrap();
+
+        std::fs::write(config_path, "inputs: [").unwrap();
+        reload_config(config_path, &None, false, &transcoder, &mut watcher).await;
+        assert!(Arc::ptr_eq(&transcoder.config(), &config));
+
+        // A valid one is taken, with the command line's job limit still applied
+        sandbox.config(&BASIC_CONFIG.replace("presets:", "max_parallel_jobs: 4\npresets:"));
+        reload_config(config_path, &Some(2), false, &transcoder, &mut watcher).await;
+        assert!(!Arc::ptr_eq(&transcoder.config(), &config));
+        assert_eq!(transcoder.config().max_parallel_jobs, Some(2));
+    }
+}
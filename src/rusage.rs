@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::process::ExitStatus;
+#[cfg(not(unix))]
+use tokio::process::Child;
+use tokio::process::{ChildStderr, ChildStdout, Command};
+
+/// CPU time and memory used by a reaped child process
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub user_cpu_secs: f64,
+    pub system_cpu_secs: f64,
+    /// Peak resident set size in kilobytes
+    pub max_rss_kb: u64,
+}
+
+impl ResourceUsage {
+    pub fn cpu_secs(&self) -> f64 {
+        self.user_cpu_secs + self.system_cpu_secs
+    }
+
+    /// Combine the usage of several attempts: CPU adds up, memory is the highest peak
+    pub fn add(&self, other: &ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            user_cpu_secs: self.user_cpu_secs + other.user_cpu_secs,
+            system_cpu_secs: self.system_cpu_secs + other.system_cpu_secs,
+            max_rss_kb: self.max_rss_kb.max(other.max_rss_kb),
+        }
+    }
+}
+
+/// A child process sstc reaps itself, collecting its resource usage on the way with `wait4`,
+/// which `Child::wait` throws away.
+///
+/// The process is spawned from the std command, so no tokio handle holds on to its pid or a
+/// pidfd; only the pipes are handed to tokio. Dropped before it was waited for, as along with
+/// a job that panics or is aborted, the process is killed and reaped in the background.
+#[cfg(unix)]
+pub struct Process {
+    pid: u32,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    /// Taken once the process has exited, and called before it is reaped, while its pid can't
+    /// be reused yet
+    before_reap: Option<Box<dyn FnOnce() + Send>>,
+}
+
+#[cfg(unix)]
+impl Process {
+    /// Start `cmd`, calling `before_reap` once it has exited and before it is reaped, so
+    /// anything that signals the pid can let go of it in time
+    pub fn spawn(
+        cmd: &mut Command,
+        before_reap: impl FnOnce() + Send + 'static,
+    ) -> std::io::Result<Self> {
+        // Dropping the std handle neither kills nor waits for the process
+        let mut child = cmd.as_std_mut().spawn()?;
+        let mut process = Self {
+            pid: child.id(),
+            stdout: None,
+            stderr: None,
+            before_reap: Some(Box::new(before_reap)),
+        };
+        process.stdout = child.stdout.take().map(ChildStdout::from_std).transpose()?;
+        process.stderr = child.stderr.take().map(ChildStderr::from_std).transpose()?;
+        Ok(process)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Send the process SIGKILL without waiting for it to exit
+    pub fn start_kill(&self) {
+        // SAFETY: kill only sends a signal; the pid is only reaped by consuming `self`
+        unsafe {
+            libc::kill(self.pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    /// Wait for the process to exit, on a blocking thread, and reap it with its usage
+    pub async fn wait(mut self) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+        let pid = self.pid;
+        tokio::task::spawn_blocking(move || wait_for_exit(pid))
+            .await
+            .map_err(std::io::Error::other)??;
+        if let Some(before_reap) = self.before_reap.take() {
+            before_reap();
+        }
+        // Exited already, so this returns right away
+        wait4(pid)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Process {
+    fn drop(&mut self) {
+        let Some(before_reap) = self.before_reap.take() else {
+            return;
+        };
+        let pid = self.pid;
+        // SAFETY: kill only sends a signal; the pid is our child and not reaped yet
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+        std::thread::spawn(move || {
+            let _ = wait_for_exit(pid);
+            before_reap();
+            let _ = wait4(pid);
+        });
+    }
+}
+
+/// Block until child `pid` has exited, leaving it to be reaped
+#[cfg(unix)]
+fn wait_for_exit(pid: u32) -> std::io::Result<()> {
+    loop {
+        // SAFETY: siginfo_t is plain old data, so all zeroes is a valid value
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: the pointer is valid for the duration of the call and the pid is our own child
+        let waited = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if waited == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn wait4(pid: u32) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = pid as libc::pid_t;
+    let mut status: libc::c_int = 0;
+    // SAFETY: rusage is plain old data, so all zeroes is a valid value
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    loop {
+        // SAFETY: both pointers are valid for the duration of the call and the pid is our own child
+        let reaped = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if reaped == pid {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    let seconds = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1_000_000.0;
+    let usage = ResourceUsage {
+        user_cpu_secs: seconds(usage.ru_utime),
+        system_cpu_secs: seconds(usage.ru_stime),
+        // Linux reports kilobytes, macOS bytes
+        max_rss_kb: if cfg!(target_os = "macos") {
+            usage.ru_maxrss as u64 / 1024
+        } else {
+            usage.ru_maxrss as u64
+        },
+    };
+
+    Ok((ExitStatus::from_raw(status), Some(usage)))
+}
+
+/// A child process, reaped by tokio; other systems report no usage
+#[cfg(not(unix))]
+pub struct Process {
+    child: Child,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    before_reap: Option<Box<dyn FnOnce() + Send>>,
+}
+
+#[cfg(not(unix))]
+impl Process {
+    pub fn spawn(
+        cmd: &mut Command,
+        before_reap: impl FnOnce() + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let mut child = cmd.kill_on_drop(true).spawn()?;
+        Ok(Self {
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            child,
+            before_reap: Some(Box::new(before_reap)),
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id().unwrap_or_default()
+    }
+
+    pub fn start_kill(&mut self) {
+        let _ = self.child.start_kill();
+    }
+
+    pub async fn wait(mut self) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+        let status = self.child.wait().await;
+        if let Some(before_reap) = self.before_reap.take() {
+            before_reap();
+        }
+        Ok((status?, None))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Whether `pid` still names a process, a zombie included
+    fn exists(pid: u32) -> bool {
+        // SAFETY: signal 0 only checks that the pid exists
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    fn shell(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[tokio::test]
+    async fn pid_is_let_go_of_before_the_process_is_reaped() {
+        let pid = Arc::new(AtomicU32::new(0));
+        let exited_unreaped = Arc::new(AtomicBool::new(false));
+        let process = Process::spawn(&mut shell("exit 3"), {
+            let (pid, exited_unreaped) = (pid.clone(), exited_unreaped.clone());
+            move || exited_unreaped.store(exists(pid.load(Ordering::SeqCst)), Ordering::SeqCst)
+        })
+        .unwrap();
+        pid.store(process.id(), Ordering::SeqCst);
+
+        let (status, usage) = process.wait().await.unwrap();
+
+        assert_eq!(status.code(), Some(3));
+        assert!(usage.is_some());
+        assert!(exited_unreaped.load(Ordering::SeqCst));
+        assert!(!exists(pid.load(Ordering::SeqCst)));
+    }
+
+    #[tokio::test]
+    async fn dropped_process_is_killed_and_reaped() {
+        let let_go = Arc::new(AtomicBool::new(false));
+        let process = Process::spawn(&mut shell("sleep 60"), {
+            let let_go = let_go.clone();
+            move || let_go.store(true, Ordering::SeqCst)
+        })
+        .unwrap();
+        let pid = process.id();
+
+        drop(process);
+
+        for _ in 0..100 {
+            if !exists(pid) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!exists(pid));
+        assert!(let_go.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::{PresetConfig, Rotation};
+use crate::ffprobe::{ProbeResult, Stream};
+use std::fmt;
+
+/// How a preset's `max_width` / `max_height` cap applies to one source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScaleDecision {
+    /// The preset has no resolution cap
+    Uncapped,
+    /// The source fits within the cap and is left at its own resolution
+    Passthrough { width: u32, height: u32 },
+    /// The source is downscaled; `to` is unknown when ffprobe reported no dimensions
+    Capped {
+        from: Option<(u32, u32)>,
+        to: Option<(u32, u32)>,
+        filter: String,
+    },
+}
+
+impl ScaleDecision {
+    pub fn new(preset: &PresetConfig, probe: &ProbeResult) -> Self {
+        if preset.is_audio_only() || (preset.max_width.is_none() && preset.max_height.is_none()) {
+            return Self::Uncapped;
+        }
+
+        let filter = cap_filter(preset.max_width, preset.max_height);
+        // The cap applies to the picture as encoded, after any turn
+        let dimensions = probe
+            .video_stream()
+            .and_then(|v| {
+                let (width, height) = v.width.zip(v.height)?;
+                Some(match effective_rotation(preset, v) {
+                    90 | 270 => (height, width),
+                    _ => (width, height),
+                })
+            })
+            .filter(|(w, h)| *w > 0 && *h > 0);
+
+        // Without dimensions the conditional filter is still safe: min() leaves small sources alone
+        let Some((width, height)) = dimensions else {
+            return Self::Capped {
+                from: None,
+                to: None,
+                filter,
+            };
+        };
+
+        let max_width = preset.max_width.unwrap_or(width);
+        let max_height = preset.max_height.unwrap_or(height);
+        if width <= max_width && height <= max_height {
+            return Self::Passthrough { width, height };
+        }
+
+        let ratio = f64::min(
+            max_width as f64 / width as f64,
+            max_height as f64 / height as f64,
+        );
+        let to = (even(width as f64 * ratio), even(height as f64 * ratio));
+
+        Self::Capped {
+            from: Some((width, height)),
+            to: Some(to),
+            filter,
+        }
+    }
+
+    /// The `scale=` filter to pass to ffmpeg, if any
+    pub fn filter(&self) -> Option<&str> {
+        match self {
+            Self::Capped { filter, .. } => Some(filter),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ScaleDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uncapped => write!(f, "uncapped"),
+            Self::Passthrough { width, height } => {
+                write!(f, "passthrough at {}x{}", width, height)
+            }
+            Self::Capped {
+                from: Some((fw, fh)),
+                to: Some((tw, th)),
+                ..
+            } => write!(f, "capped {}x{} -> {}x{}", fw, fh, tw, th),
+            Self::Capped { .. } => write!(f, "capped from unknown size"),
+        }
+    }
+}
+
+/// Degrees the encoded picture is turned from the stored one: the display matrix's turn while
+/// ffmpeg autorotates, then the preset's explicit `rotation`
+fn effective_rotation(preset: &PresetConfig, stream: &Stream) -> u32 {
+    let autorotate = preset.rotation != Some(Rotation::KeepMetadata)
+        && !preset
+            .input_options
+            .iter()
+            .any(|option| option == "-noautorotate");
+    let matrix = if autorotate { stream.rotation() } else { 0 };
+    let explicit = match preset.rotation {
+        Some(Rotation::Degrees(degrees)) => degrees,
+        _ => 0,
+    };
+    (matrix + explicit) % 360
+}
+
+/// Aspect-preserving downscale that never upsizes and keeps dimensions even
+fn cap_filter(max_width: Option<u32>, max_height: Option<u32>) -> String {
+    let width = max_width
+        .map(|w| format!("'min(iw,{})'", w))
+        .unwrap_or_else(|| "iw".to_string());
+    let height = max_height
+        .map(|h| format!("'min(ih,{})'", h))
+        .unwrap_or_else(|| "ih".to_string());
+
+    format!(
+        "scale={}:{}:force_original_aspect_ratio=decrease:force_divisible_by=2",
+        width, height
+    )
+}
+
+/// Round down to an even size, as `force_divisible_by=2` does
+fn even(value: f64) -> u32 {
+    ((value as u32) & !1).max(2)
+}
@@ -0,0 +1,82 @@
+/// Characters that never need quoting in a POSIX shell word
+fn is_plain(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-+=/.,:@%^".contains(c)
+}
+
+/// Quote one argument so a POSIX shell reads it back verbatim
+pub fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Join arguments into a single copy-pasteable shell line
+pub fn join<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    args.into_iter()
+        .map(|arg| quote(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Arguments as `sh` splits the line back up
+    fn words_of(line: &str) -> Vec<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\0' {}", line))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .split_terminator('\0')
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn plain_words_stay_bare() {
+        assert_eq!(quote("-c:v"), "-c:v");
+        assert_eq!(quote("/srv/in/clip_01.mp4"), "/srv/in/clip_01.mp4");
+        assert_eq!(quote("scale=-2:720,fps=30"), "scale=-2:720,fps=30");
+    }
+
+    #[test]
+    fn quotes_what_the_shell_would_read_differently() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("my clip.mp4"), "'my clip.mp4'");
+        assert_eq!(quote("it's.mp4"), r"'it'\''s.mp4'");
+        assert_eq!(quote(r#"say "hi".mp4"#), r#"'say "hi".mp4'"#);
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("~/clip.mp4"), "'~/clip.mp4'");
+        assert_eq!(quote("a;b"), "'a;b'");
+        assert_eq!(quote("*.mp4"), "'*.mp4'");
+        assert_eq!(quote("Über café.mp4"), "'Über café.mp4'");
+    }
+
+    #[test]
+    fn shell_reads_the_line_back_verbatim() {
+        let args = [
+            "ffmpeg",
+            "-i",
+            "/srv/in/my holiday/it's \"great\".mp4",
+            "-vf",
+            "drawtext=text='$USER `id`'",
+            "",
+            "Über café 東京 🎬.mkv",
+            "back\\slash",
+            "line\nbreak",
+            "'",
+        ];
+        assert_eq!(words_of(&join(args)), args);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::job::JobError;
+use tracing::debug;
+
+/// Share of the output duration that has to be encoded before the final size is projected.
+/// Earlier, container headers and the encoder's warm-up skew the estimate too much.
+const MIN_PROGRESS: f64 = 0.2;
+
+/// Projects the final output size from ffmpeg's progress, for `if_larger_than_source`
+pub struct OutputSizeLimit {
+    source_size: u64,
+    /// Expected output duration in seconds
+    duration: f64,
+    max_ratio: f64,
+    logged: bool,
+}
+
+impl OutputSizeLimit {
+    pub fn new(source_size: u64, duration: f64, max_ratio: f64) -> Self {
+        Self {
+            source_size,
+            duration,
+            max_ratio,
+            logged: false,
+        }
+    }
+
+    /// Feed the output size and time of a progress update; errors once enough is encoded and
+    /// the output, growing at the same rate, would end up over the limit
+    pub fn observe(&mut self, total_size: u64, out_time_secs: f64) -> Result<(), JobError> {
+        if self.duration <= 0.0 || out_time_secs < self.duration * MIN_PROGRESS {
+            return Ok(());
+        }
+
+        let projected = (total_size as f64 * self.duration / out_time_secs) as u64;
+        if !self.logged {
+            debug!(
+                "Projected output size {} bytes for a {} byte source",
+                projected, self.source_size
+            );
+            self.logged = true;
+        }
+        if projected as f64 > self.source_size as f64 * self.max_ratio {
+            return Err(JobError::OutputTooLarge {
+                projected,
+                source_size: self.source_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_is_decided_before_a_fifth_is_encoded() {
+        // Way over the limit, but only 1.9 of 10 seconds in
+        let mut limit = OutputSizeLimit::new(1000, 10.0, 1.0);
+        assert!(limit.observe(1_000_000, 1.9).is_ok());
+        assert!(limit.observe(1_000_000, 2.0).is_err());
+    }
+
+    #[test]
+    fn projection_is_checked_against_the_ratio() {
+        let cases = [
+            // 500 bytes at 5 of 10 seconds projects 1000
+            (1000, 1.0, 500, true),
+            (1000, 1.0, 501, false),
+            (1000, 0.5, 250, true),
+            (1000, 0.5, 251, false),
+            (1000, 1.5, 750, true),
+            (1000, 1.5, 760, false),
+        ];
+        for (source_size, max_ratio, total_size, fits) in cases {
+            let result =
+                OutputSizeLimit::new(source_size, 10.0, max_ratio).observe(total_size, 5.0);
+            assert_eq!(
+                result.is_ok(),
+                fits,
+                "{} bytes at a ratio of {}",
+                total_size,
+                max_ratio
+            );
+        }
+    }
+
+    #[test]
+    fn error_carries_the_projection() {
+        let mut limit = OutputSizeLimit::new(1000, 10.0, 1.0);
+        match limit.observe(800, 4.0) {
+            Err(JobError::OutputTooLarge {
+                projected,
+                source_size,
+            }) => assert_eq!((projected, source_size), (2000, 1000)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_duration_never_stops_the_encode() {
+        let mut limit = OutputSizeLimit::new(1000, 0.0, 1.0);
+        assert!(limit.observe(1_000_000, 100.0).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::config::SourceAction;
+use crate::in_place;
+use anyhow::{anyhow, Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Carry out `action` on a source whose outputs are all done.
+///
+/// Returns the path the source now lives at when it was moved or renamed.
+pub fn apply(action: &SourceAction, source: &Path) -> Result<Option<PathBuf>> {
+    let target = match action {
+        SourceAction::Keep => return Ok(None),
+        SourceAction::Delete => {
+            std::fs::remove_file(source)
+                .context(format!("Failed to delete source {}", source.display()))?;
+            info!("Deleted source {}", source.display());
+            return Ok(None);
+        }
+        SourceAction::Move(dir) => {
+            std::fs::create_dir_all(dir).context(format!(
+                "Failed to create source_action directory {}",
+                dir.display()
+            ))?;
+            let name = source.file_name().context("Source has no file name")?;
+            dir.join(name)
+        }
+        SourceAction::Suffix(suffix) => {
+            let mut name = OsString::from(source.as_os_str());
+            name.push(suffix);
+            PathBuf::from(name)
+        }
+    };
+
+    if target.exists() {
+        return Err(anyhow!(
+            "Not moving source {}, {} already exists",
+            source.display(),
+            target.display()
+        ));
+    }
+    in_place::move_file(source, &target).context(format!(
+        "Failed to move source {} to {}",
+        source.display(),
+        target.display()
+    ))?;
+    info!("Moved source {} to {}", source.display(), target.display());
+
+    Ok(Some(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_in(dir: &Path) -> PathBuf {
+        let source = dir.join("clip.mp4");
+        std::fs::write(&source, b"original").unwrap();
+        source
+    }
+
+    #[test]
+    fn keep_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source_in(dir.path());
+
+        assert_eq!(apply(&SourceAction::Keep, &source).unwrap(), None);
+        assert!(source.exists());
+
+        assert_eq!(apply(&SourceAction::Delete, &source).unwrap(), None);
+        assert!(!source.exists());
+    }
+
+    #[test]
+    fn move_creates_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source_in(dir.path());
+        let done = dir.path().join("done/today");
+
+        let moved = apply(&SourceAction::Move(done.clone()), &source).unwrap();
+
+        assert_eq!(moved, Some(done.join("clip.mp4")));
+        assert!(!source.exists());
+        assert_eq!(std::fs::read(done.join("clip.mp4")).unwrap(), b"original");
+    }
+
+    #[test]
+    fn suffix_appends_to_the_whole_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source_in(dir.path());
+
+        let renamed = apply(&SourceAction::Suffix(".done".into()), &source).unwrap();
+
+        assert_eq!(renamed, Some(dir.path().join("clip.mp4.done")));
+        assert!(!source.exists());
+    }
+
+    #[test]
+    fn taken_names_are_never_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source_in(dir.path());
+        let done = dir.path().join("done");
+        std::fs::create_dir(&done).unwrap();
+        std::fs::write(done.join("clip.mp4"), b"an earlier source").unwrap();
+        std::fs::write(dir.path().join("clip.mp4.done"), b"an earlier source").unwrap();
+
+        for action in [
+            SourceAction::Move(done.clone()),
+            SourceAction::Suffix(".done".into()),
+        ] {
+            let error = apply(&action, &source).unwrap_err();
+            assert!(error.to_string().contains("already exists"), "{}", error);
+        }
+
+        assert_eq!(std::fs::read(&source).unwrap(), b"original");
+        assert_eq!(
+            std::fs::read(done.join("clip.mp4")).unwrap(),
+            b"an earlier source"
+        );
+    }
+}
@@ -0,0 +1,583 @@
+use crate::job::{JobOutcome, JobRecord};
+use bytesize::ByteSize;
+use owo_colors::{OwoColorize, Style};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Widest input name shown in the summary table before it gets shortened
+const NAME_WIDTH: usize = 40;
+
+/// Counters collected over the lifetime of a run and reported at shutdown
+#[derive(Debug, Default)]
+pub struct RunStats {
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    ignored_by_marker: AtomicUsize,
+    skipped_by_filter: AtomicUsize,
+    skipped_existing: AtomicUsize,
+    skipped_compliant: AtomicUsize,
+    skipped_codec: AtomicUsize,
+    skipped_duplicate: AtomicUsize,
+    kept_original: AtomicUsize,
+    would_transcode: AtomicUsize,
+    cancelled: AtomicUsize,
+    input_bytes: AtomicU64,
+    output_bytes: AtomicU64,
+    /// Sources that failed on permissions; fixing access and rescanning picks them up again
+    permission_denied: Mutex<Vec<PathBuf>>,
+    /// Sources whose `wait_for_companion` timed out, with the extensions that never arrived
+    missing_companions: Mutex<Vec<(PathBuf, Vec<String>)>>,
+    /// Every finished job in completion order, for the summary table
+    jobs: Mutex<Vec<FinishedJob>>,
+}
+
+/// A job as it appears in the summary table
+#[derive(Debug, Clone)]
+struct FinishedJob {
+    record: JobRecord,
+    error: Option<String>,
+}
+
+/// How the summary table is rendered
+#[derive(Debug, Clone, Copy)]
+pub struct SummaryOptions {
+    /// Colors and unicode glyphs; plain ASCII otherwise
+    pub color: bool,
+    /// Most per-file rows to show; failures are always listed
+    pub limit: usize,
+}
+
+impl SummaryOptions {
+    /// Colors only when stdout is a terminal, `--no-color` isn't given and `NO_COLOR` isn't set
+    pub fn new(no_color: bool, limit: usize) -> Self {
+        let color =
+            !no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+        Self { color, limit }
+    }
+}
+
+impl RunStats {
+    pub fn record_success(&self, record: &JobRecord) {
+        self.succeeded.fetch_add(1, Ordering::Relaxed);
+        self.input_bytes
+            .fetch_add(record.input_size.unwrap_or_default(), Ordering::Relaxed);
+        self.output_bytes
+            .fetch_add(record.output_size.unwrap_or_default(), Ordering::Relaxed);
+        self.push_job(record, None);
+    }
+
+    /// Count a job `--dry-run` printed the commands of
+    pub fn record_would_transcode(&self, record: &JobRecord) {
+        self.would_transcode.fetch_add(1, Ordering::Relaxed);
+        self.push_job(record, None);
+    }
+
+    pub fn record_failure(&self, record: &JobRecord, error: &str) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.push_job(record, Some(error.to_string()));
+    }
+
+    /// Count a job `sstc cancel` stopped
+    pub fn record_cancelled(&self, record: &JobRecord) {
+        self.cancelled.fetch_add(1, Ordering::Relaxed);
+        self.push_job(record, None);
+    }
+
+    /// Count a failure that won't be retried until the file's access rights change
+    pub fn record_permission_denied(&self, record: &JobRecord, error: &str) {
+        self.record_failure(record, error);
+        if let Ok(mut paths) = self.permission_denied.lock() {
+            paths.push(record.source.clone());
+        }
+    }
+
+    /// Note a source that went ahead without the companions it waited for
+    pub fn record_missing_companions(&self, source: &Path, extensions: &[String]) {
+        if let Ok(mut missing) = self.missing_companions.lock() {
+            missing.push((source.to_path_buf(), extensions.to_vec()));
+        }
+    }
+
+    /// Sources that went ahead without their companions, with the extensions missing
+    pub fn missing_companions(&self) -> Vec<(PathBuf, Vec<String>)> {
+        self.missing_companions
+            .lock()
+            .map(|missing| missing.clone())
+            .unwrap_or_default()
+    }
+
+    fn push_job(&self, record: &JobRecord, error: Option<String>) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.push(FinishedJob {
+                record: record.clone(),
+                error,
+            });
+        }
+    }
+
+    /// Count a job that ended without transcoding, by the reason it was skipped
+    pub fn record_skipped(&self, record: &JobRecord) {
+        let counter = match record.outcome {
+            Some(JobOutcome::SkippedExisting | JobOutcome::SkippedInHistory { .. }) => {
+                &self.skipped_existing
+            }
+            Some(JobOutcome::SkippedCompliant) => &self.skipped_compliant,
+            Some(JobOutcome::SkippedCodec { .. }) => &self.skipped_codec,
+            Some(JobOutcome::SkippedDuplicate { .. }) => &self.skipped_duplicate,
+            Some(JobOutcome::KeptOriginal { .. }) => &self.kept_original,
+            _ => &self.skipped_by_filter,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.push_job(record, None);
+    }
+
+    pub fn record_ignored_by_marker(&self) {
+        self.ignored_by_marker.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Source and error of every failed job, in the order they finished
+    #[cfg(test)]
+    pub fn failures(&self) -> Vec<(PathBuf, String)> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|job| Some((job.record.source.clone(), job.error.clone()?)))
+            .collect()
+    }
+
+    /// Source and outcome of every finished job, in the order they finished
+    #[cfg(test)]
+    pub fn outcomes(&self) -> Vec<(PathBuf, Option<JobOutcome>)> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| (job.record.source.clone(), job.record.outcome.clone()))
+            .collect()
+    }
+
+    /// Record of every finished job, in the order they finished
+    #[cfg(test)]
+    pub fn records(&self) -> Vec<JobRecord> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| job.record.clone())
+            .collect()
+    }
+
+    /// Jobs that ended without transcoding, whatever the reason
+    fn skipped(&self) -> usize {
+        self.skipped_by_filter.load(Ordering::Relaxed)
+            + self.skipped_existing.load(Ordering::Relaxed)
+            + self.skipped_compliant.load(Ordering::Relaxed)
+            + self.skipped_codec.load(Ordering::Relaxed)
+            + self.skipped_duplicate.load(Ordering::Relaxed)
+            + self.kept_original.load(Ordering::Relaxed)
+    }
+
+    /// What a `--dry-run` found, in place of the summary of a real run
+    pub fn log_dry_run_summary(&self) {
+        info!(
+            "Dry run: {} would be transcoded, {} skipped, {} failed",
+            self.would_transcode.load(Ordering::Relaxed).green(),
+            self.skipped().yellow(),
+            self.failed().red()
+        );
+    }
+
+    /// ffmpeg CPU seconds spent per preset, failed jobs included
+    pub fn cpu_secs_by_preset(&self) -> BTreeMap<String, f64> {
+        let mut totals = BTreeMap::new();
+        if let Ok(jobs) = self.jobs.lock() {
+            for job in jobs.iter() {
+                if let Some(usage) = &job.record.resources {
+                    let preset = job.record.preset.clone().unwrap_or_else(|| "-".to_string());
+                    *totals.entry(preset).or_insert(0.0) += usage.cpu_secs();
+                }
+            }
+        }
+        totals
+    }
+
+    pub fn log_summary(&self) {
+        info!("Summary:");
+        info!(
+            "  Succeeded:         {}",
+            self.succeeded.load(Ordering::Relaxed).green()
+        );
+        info!("  Failed:            {}", self.failed().red());
+        info!(
+            "  Cancelled:         {}",
+            self.cancelled.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Ignored by marker: {}",
+            self.ignored_by_marker.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Skipped by filter: {}",
+            self.skipped_by_filter.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Already existing:  {}",
+            self.skipped_existing.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Already compliant: {}",
+            self.skipped_compliant.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Already in codec:  {}",
+            self.skipped_codec.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Duplicate content: {}",
+            self.skipped_duplicate.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Kept original:     {}",
+            self.kept_original.load(Ordering::Relaxed).yellow()
+        );
+        info!(
+            "  Total size:        {} -> {}",
+            bytesize::ByteSize::b(self.input_bytes.load(Ordering::Relaxed))
+                .display()
+                .si(),
+            bytesize::ByteSize::b(self.output_bytes.load(Ordering::Relaxed))
+                .display()
+                .si()
+        );
+
+        let cpu = self.cpu_secs_by_preset();
+        if !cpu.is_empty() {
+            info!(
+                "  CPU time:          {:.2} h",
+                cpu.values().sum::<f64>() / 3600.0
+            );
+            for (preset, secs) in &cpu {
+                info!("    {:<16} {:.1} s", preset.cyan(), secs);
+            }
+        }
+
+        let missing = self.missing_companions();
+        if !missing.is_empty() {
+            warn!("  Missing companions: {}", missing.len().yellow());
+            for (source, extensions) in &missing {
+                warn!("    {} ({})", source.display(), extensions.join(", "));
+            }
+        }
+
+        if let Ok(paths) = self.permission_denied.lock() {
+            if !paths.is_empty() {
+                warn!("  Permission denied: {}", paths.len().red());
+                for path in paths.iter() {
+                    warn!("    {}", path.display());
+                }
+            }
+        }
+    }
+
+    /// Print one aligned row per finished job followed by totals
+    pub fn print_table(&self, options: SummaryOptions) {
+        let lines = self.table(options);
+        if lines.is_empty() {
+            return;
+        }
+        println!();
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+
+    /// Lines of the summary table, none when no job finished
+    fn table(&self, options: SummaryOptions) -> Vec<String> {
+        let jobs = match self.jobs.lock() {
+            Ok(jobs) => jobs.clone(),
+            Err(_) => return Vec::new(),
+        };
+        if jobs.is_empty() {
+            return Vec::new();
+        }
+
+        let paint = |text: String, style: Style| {
+            if options.color {
+                text.style(style).to_string()
+            } else {
+                text
+            }
+        };
+
+        // Failures first so they are never cut off by the row limit
+        let (failed, succeeded): (Vec<_>, Vec<_>) = jobs.iter().partition(|j| j.error.is_some());
+        let shown_ok = options
+            .limit
+            .saturating_sub(failed.len())
+            .min(succeeded.len());
+        let hidden = succeeded.len() - shown_ok;
+
+        let preset_width = jobs
+            .iter()
+            .map(|j| j.record.preset.as_deref().unwrap_or("-").len())
+            .max()
+            .unwrap_or(1);
+        let arrow = if options.color { "→" } else { "->" };
+
+        let mut lines = Vec::new();
+        for job in failed.iter().chain(succeeded.iter().take(shown_ok)) {
+            let record = &job.record;
+            let (glyph, style) = match (&job.error, &record.outcome) {
+                (Some(_), _) => (if options.color { "✘" } else { "x" }, Style::new().red()),
+                (None, Some(JobOutcome::Transcoded)) => {
+                    (if options.color { "✔" } else { "+" }, Style::new().green())
+                }
+                (None, _) => (if options.color { "–" } else { "-" }, Style::new().dimmed()),
+            };
+            let name = shorten_name(record, options.color);
+            let preset = record.preset.as_deref().unwrap_or("-");
+
+            let detail = match &job.error {
+                Some(error) => paint(error.clone(), Style::new().red()),
+                None => match (record.input_size, record.output_size) {
+                    (Some(before), Some(after))
+                        if record.outcome == Some(JobOutcome::Transcoded) =>
+                    {
+                        format!(
+                            "{:>9} {} {:<9} {:>7}  {:>8}",
+                            ByteSize::b(before).display().si().to_string(),
+                            arrow,
+                            ByteSize::b(after).display().si().to_string(),
+                            saved_pct(before, after),
+                            record.elapsed.map(format_elapsed).unwrap_or_default()
+                        )
+                    }
+                    _ => paint(
+                        record
+                            .outcome
+                            .as_ref()
+                            .map_or("skipped".to_string(), |o| o.to_string()),
+                        Style::new().dimmed(),
+                    ),
+                },
+            };
+
+            lines.push(format!(
+                "{} {}  {}  {}",
+                paint(glyph.to_string(), style),
+                paint(format!("{:<NAME_WIDTH$}", name), style),
+                paint(format!("{:<preset_width$}", preset), Style::new().cyan()),
+                detail
+            ));
+        }
+        if hidden > 0 {
+            lines.push(format!(
+                "  {}",
+                paint(
+                    format!("... {} more succeeded or skipped", hidden),
+                    Style::new().dimmed()
+                )
+            ));
+        }
+
+        let before = self.input_bytes.load(Ordering::Relaxed);
+        let after = self.output_bytes.load(Ordering::Relaxed);
+        let elapsed: Duration = jobs.iter().filter_map(|j| j.record.elapsed).sum();
+        let cpu_hours = self.cpu_secs_by_preset().values().fold(0.0, |a, b| a + b) / 3600.0;
+        let skipped = self.skipped();
+        lines.push(format!(
+            "{} succeeded, {} skipped, {} failed  {} {} {} ({} saved) in {}, {:.2} CPU-hours",
+            paint(
+                self.succeeded.load(Ordering::Relaxed).to_string(),
+                Style::new().green()
+            ),
+            paint(skipped.to_string(), Style::new().dimmed()),
+            paint(self.failed().to_string(), Style::new().red()),
+            ByteSize::b(before).display().si(),
+            arrow,
+            ByteSize::b(after).display().si(),
+            saved_pct(before, after),
+            format_elapsed(elapsed),
+            cpu_hours
+        ));
+        lines
+    }
+}
+
+/// File name of the source, shortened in the middle so the extension stays visible
+fn shorten_name(record: &JobRecord, unicode: bool) -> String {
+    let name = record
+        .source
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| record.source.display().to_string());
+
+    let chars: Vec<char> = name.chars().collect();
+    if chars.len() <= NAME_WIDTH {
+        return name;
+    }
+
+    let ellipsis = if unicode { "…" } else { "..." };
+    let keep = NAME_WIDTH - ellipsis.chars().count();
+    let tail = keep / 2;
+    let head = keep - tail;
+    format!(
+        "{}{}{}",
+        chars[..head].iter().collect::<String>(),
+        ellipsis,
+        chars[chars.len() - tail..].iter().collect::<String>()
+    )
+}
+
+fn saved_pct(before: u64, after: u64) -> String {
+    if before == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", (1.0 - after as f64 / before as f64) * 100.0)
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rusage::ResourceUsage;
+
+    fn job(preset: &str, cpu_secs: Option<f64>) -> JobRecord {
+        let mut record = JobRecord::new(PathBuf::from(format!("/in/{}.mp4", preset)));
+        record.preset = Some(preset.to_string());
+        record.resources = cpu_secs.map(|secs| ResourceUsage {
+            user_cpu_secs: secs * 0.75,
+            system_cpu_secs: secs * 0.25,
+            max_rss_kb: 1024,
+        });
+        record
+    }
+
+    #[test]
+    fn cpu_time_adds_up_per_preset_failures_included() {
+        let stats = RunStats::default();
+        stats.record_success(&job("hevc", Some(100.0)));
+        stats.record_failure(&job("hevc", Some(20.0)), "encoder failed");
+        stats.record_success(&job("remux", Some(1.0)));
+        // Skipped before ffmpeg ran, so nothing to add
+        stats.record_skipped(&job("av1", None));
+
+        assert_eq!(
+            stats.cpu_secs_by_preset(),
+            BTreeMap::from([("hevc".to_string(), 120.0), ("remux".to_string(), 1.0)])
+        );
+    }
+
+    /// A transcoded job of `name`, 100 MB down to 40 MB in a minute
+    fn transcoded(name: &str) -> JobRecord {
+        let mut record = JobRecord::new(PathBuf::from(format!("/in/{}", name)));
+        record.preset = Some("hevc".to_string());
+        record.outcome = Some(JobOutcome::Transcoded);
+        record.input_size = Some(100_000_000);
+        record.output_size = Some(40_000_000);
+        record.elapsed = Some(Duration::from_secs(60));
+        record
+    }
+
+    fn plain(limit: usize) -> SummaryOptions {
+        SummaryOptions {
+            color: false,
+            limit,
+        }
+    }
+
+    #[test]
+    fn row_limit_cuts_successes_but_never_failures() {
+        let stats = RunStats::default();
+        for i in 0..5 {
+            stats.record_success(&transcoded(&format!("ok{}.mp4", i)));
+        }
+        stats.record_failure(&transcoded("bad0.mp4"), "encoder failed");
+        stats.record_failure(&transcoded("bad1.mp4"), "encoder failed");
+
+        let lines = stats.table(plain(3));
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("x bad0.mp4 "));
+        assert!(lines[1].starts_with("x bad1.mp4 "));
+        assert!(lines[2].starts_with("+ ok0.mp4 "));
+        assert_eq!(lines[3], "  ... 4 more succeeded or skipped");
+        assert!(lines[4].starts_with("5 succeeded, 0 skipped, 2 failed"));
+
+        // Failures past the limit are still listed, and no success
+        let lines = stats.table(plain(1));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[..2].iter().all(|line| line.starts_with("x bad")));
+        assert_eq!(lines[2], "  ... 5 more succeeded or skipped");
+
+        // Everything fits, so no line about the rest
+        assert_eq!(stats.table(plain(50)).len(), 8);
+        assert!(RunStats::default().table(plain(50)).is_empty());
+    }
+
+    #[test]
+    fn table_without_color_is_plain_ascii() {
+        let stats = RunStats::default();
+        stats.record_success(&transcoded("clip.mp4"));
+        stats.record_failure(&transcoded("broken.mp4"), "encoder failed");
+        let mut skipped = transcoded("old.mp4");
+        skipped.outcome = Some(JobOutcome::SkippedExisting);
+        stats.record_skipped(&skipped);
+
+        let lines = stats.table(plain(50));
+        assert!(lines.iter().all(|line| line.is_ascii()), "{:#?}", lines);
+        let glyphs: Vec<char> = lines[..3]
+            .iter()
+            .map(|line| line.chars().next().unwrap())
+            .collect();
+        assert_eq!(glyphs, ['x', '+', '-']);
+        assert!(lines[1].contains("100.0 MB -> 40.0 MB"), "{}", lines[1]);
+        assert!(lines[1].contains("60.0%"));
+
+        let colored = stats.table(SummaryOptions {
+            color: true,
+            limit: 50,
+        });
+        assert!(colored[1].contains('✔') && colored[1].contains('→'));
+        assert!(colored[1].contains('\x1b'));
+    }
+
+    #[test]
+    fn long_names_lose_their_middle() {
+        let name = format!("{}.mp4", "日本語のとても長いファイル名".repeat(4));
+        let record = JobRecord::new(PathBuf::from(format!("/in/{}", name)));
+
+        let short = shorten_name(&record, true);
+        assert_eq!(short.chars().count(), NAME_WIDTH);
+        assert_eq!(short.chars().nth(20), Some('…'));
+        assert!(name.starts_with(short.split('…').next().unwrap()));
+        assert!(short.ends_with("長いファイル名.mp4"));
+
+        let ascii = shorten_name(&record, false);
+        assert_eq!(ascii.chars().count(), NAME_WIDTH);
+        assert!(ascii.contains("...") && ascii.ends_with(".mp4"));
+
+        // Short enough names are left whole
+        let record = JobRecord::new(PathBuf::from("/in/ファイル.mp4"));
+        assert_eq!(shorten_name(&record, true), "ファイル.mp4");
+    }
+}
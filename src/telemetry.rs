@@ -0,0 +1,164 @@
+use crate::config::{OtelConfig, TargetConfig};
+use crate::console;
+use crate::job::{JobError, JobOutcome, JobRecord};
+use crate::progress::FFmpegProgress;
+use anyhow::{anyhow, Context, Result};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use tracing::{field, info, info_span, warn, Span};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::filter::{filter_fn, FilterExt, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Layer, Registry};
+
+const DEFAULT_SERVICE_NAME: &str = "sstc";
+/// Target of the per-update encode progress events, which only go to the exporter
+const PROGRESS_TARGET: &str = "sstc::progress";
+
+type OtelLayer = OpenTelemetryLayer<Registry, Tracer>;
+
+/// Swaps the OpenTelemetry layer in once the config has been read
+static OTEL_LAYER: OnceLock<reload::Handle<Option<OtelLayer>, Registry>> = OnceLock::new();
+static PROVIDER: Mutex<Option<SdkTracerProvider>> = Mutex::new(None);
+
+/// Install the global subscriber: console logging right away, OpenTelemetry export only after
+/// [`enable`] is called with a config that has an endpoint
+pub fn init(level: tracing::Level) {
+    let (otel, handle) = reload::Layer::new(None::<OtelLayer>);
+
+    // Job spans and progress events are for the exported traces; keep them off the console
+    // stderr keeps stdout free for what commands print, like `config validate --json`, and
+    // is shared with the progress bars
+    let console = fmt::layer()
+        .with_writer(console::log_writer())
+        .with_ansi(true)
+        .with_filter(LevelFilter::from_level(level).and(filter_fn(|meta| {
+            meta.is_event() && meta.target() != PROGRESS_TARGET
+        })));
+
+    tracing_subscriber::registry()
+        .with(otel)
+        .with(console)
+        .with(LevelFilter::from_level(level))
+        .init();
+
+    let _ = OTEL_LAYER.set(handle);
+}
+
+/// Start exporting job traces over OTLP/gRPC; does nothing without an endpoint
+pub fn enable(config: Option<&OtelConfig>) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    let Some(endpoint) = config.endpoint.as_deref() else {
+        return Ok(());
+    };
+    let handle = OTEL_LAYER
+        .get()
+        .ok_or_else(|| anyhow!("Logging is not initialized"))?;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .context(format!("Failed to create OTLP exporter for {}", endpoint))?;
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.clone())
+                .build(),
+        )
+        .build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE_NAME));
+    handle
+        .reload(Some(layer))
+        .context("Failed to enable OpenTelemetry tracing")?;
+    *PROVIDER.lock().unwrap() = Some(provider);
+
+    info!("Exporting job traces to {} as {}", endpoint, service_name);
+    Ok(())
+}
+
+/// Flush the spans still buffered and stop the exporter. Blocks, so call it off the runtime.
+pub fn shutdown() {
+    let Some(provider) = PROVIDER.lock().unwrap().take() else {
+        return;
+    };
+    if let Err(e) = provider.shutdown() {
+        warn!("Failed to flush OpenTelemetry spans: {}", e);
+    }
+}
+
+/// Root span of one job, covering every target of the source
+pub fn job_span(source: &Path) -> Span {
+    info_span!("job", source = %source.display(), outcome = field::Empty)
+}
+
+/// Record how the job went as a whole: failed if any target failed, transcoded if any was
+pub fn finish_job_span(span: &Span, records: &[JobRecord]) {
+    let outcomes = || records.iter().filter_map(|record| record.outcome.as_ref());
+    let overall = outcomes()
+        .find(|outcome| **outcome == JobOutcome::Failed)
+        .or_else(|| outcomes().find(|outcome| **outcome == JobOutcome::Transcoded))
+        .or_else(|| outcomes().next());
+    if let Some(outcome) = overall {
+        span.record("outcome", outcome.to_string());
+    }
+}
+
+/// Span of one target of a job; the remaining fields are filled in by [`finish_target_span`]
+pub fn target_span(target: &TargetConfig) -> Span {
+    info_span!(
+        "target",
+        preset = %target.preset,
+        output = %target.output,
+        input_size = field::Empty,
+        output_size = field::Empty,
+        outcome = field::Empty,
+    )
+}
+
+pub fn finish_target_span(span: &Span, record: &JobRecord, result: &Result<JobOutcome>) {
+    // A fallback replaces the configured preset
+    if let Some(preset) = &record.preset {
+        span.record("preset", preset.as_str());
+    }
+    if let Some(size) = record.input_size {
+        span.record("input_size", size);
+    }
+    if let Some(size) = record.output_size {
+        span.record("output_size", size);
+    }
+    let outcome = match result {
+        Ok(outcome) => outcome.to_string(),
+        Err(e) if matches!(e.downcast_ref(), Some(JobError::Cancelled)) => {
+            JobOutcome::Cancelled.to_string()
+        }
+        Err(_) => JobOutcome::Failed.to_string(),
+    };
+    span.record("outcome", outcome);
+}
+
+/// Record an ffmpeg progress update as an event of the current (encode) span
+pub fn progress_event(progress: &FFmpegProgress) {
+    info!(
+        target: PROGRESS_TARGET,
+        out_time_secs = progress.out_time_secs(),
+        frame = progress.frame,
+        fps = progress.fps,
+        speed = progress.speed.as_deref(),
+        total_size = progress.total_size,
+        "progress"
+    );
+}
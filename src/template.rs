@@ -0,0 +1,224 @@
+use crate::config::InputConfig;
+use crate::ffprobe::ProbeResult;
+use crate::timestamp;
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDateTime};
+use std::path::Path;
+
+/// Tokens the output templates understand, for error messages
+const TOKENS: &[&str] = &[
+    "filename", "date", "time", "year", "month", "day", "preset", "input", "ext",
+];
+
+/// Values substituted for the tokens of `filename_template` and `subdir_template`
+#[derive(Debug, Clone)]
+pub struct TemplateVars {
+    /// Source file name without its extension
+    pub filename: String,
+    /// When the source was recorded, local time: see [`timestamp::recording_time`]
+    pub recorded: NaiveDateTime,
+    pub preset: String,
+    /// Base name of the input directory the source was found in
+    pub input: String,
+    /// Source extension without the dot
+    pub ext: String,
+}
+
+impl TemplateVars {
+    pub fn for_source(
+        source: &Path,
+        input: &InputConfig,
+        preset: &str,
+        probe: Option<&ProbeResult>,
+    ) -> Result<Self> {
+        let filename = source
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow!("Failed to get file stem of {}", source.display()))?;
+        let (recorded, _) =
+            timestamp::recording_time(source, input.timestamp_pattern.as_deref(), probe)
+                .ok_or_else(|| {
+                    anyhow!("Failed to get the recording time of {}", source.display())
+                })?;
+
+        Ok(Self {
+            filename: filename.to_string(),
+            recorded,
+            preset: preset.to_string(),
+            input: base_name(&input.path),
+            ext: source
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Placeholder values to dry-run a template with while validating the config
+    pub fn sample() -> Self {
+        Self {
+            filename: "sample".to_string(),
+            recorded: Local::now().naive_local(),
+            preset: "preset".to_string(),
+            input: "input".to_string(),
+            ext: "mp4".to_string(),
+        }
+    }
+}
+
+/// Expand `{token}`s in `template`; unknown tokens and unbalanced braces are errors
+pub fn render(template: &str, vars: &TemplateVars) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..start]);
+        if rest[start..].starts_with('}') {
+            return Err(anyhow!("Unmatched '}}' in template '{}'", template));
+        }
+
+        let after = &rest[start + 1..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed '{{' in template '{}'", template))?;
+        let token = &after[..end];
+        match token {
+            "filename" => rendered.push_str(&vars.filename),
+            "date" => rendered.push_str(&vars.recorded.format("%Y-%m-%d").to_string()),
+            "time" => rendered.push_str(&vars.recorded.format("%H-%M-%S").to_string()),
+            "year" => rendered.push_str(&vars.recorded.format("%Y").to_string()),
+            "month" => rendered.push_str(&vars.recorded.format("%m").to_string()),
+            "day" => rendered.push_str(&vars.recorded.format("%d").to_string()),
+            "preset" => rendered.push_str(&vars.preset),
+            "input" => rendered.push_str(&vars.input),
+            "ext" => rendered.push_str(&vars.ext),
+            _ => {
+                return Err(anyhow!(
+                    "Unknown token '{{{}}}' in template '{}', expected one of {}",
+                    token,
+                    template,
+                    TOKENS
+                        .iter()
+                        .map(|t| format!("{{{}}}", t))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+fn base_name(dir: &Path) -> String {
+    // `.` and `..` have no file name of their own
+    let resolved = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    resolved
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn vars() -> TemplateVars {
+        TemplateVars {
+            filename: "clip".to_string(),
+            recorded: NaiveDateTime::parse_from_str("2023-10-15 21:30:45", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            preset: "h265".to_string(),
+            input: "camera".to_string(),
+            ext: "MOV".to_string(),
+        }
+    }
+
+    #[test]
+    fn tokens_are_expanded_wherever_they_appear() {
+        for (template, rendered) in [
+            ("{filename}", "clip"),
+            ("{filename}-{filename}", "clip-clip"),
+            ("{date}_{time}", "2023-10-15_21-30-45"),
+            (
+                "{year}/{month}/{day}/{year}{month}{day}",
+                "2023/10/15/20231015",
+            ),
+            (
+                "{input}-{preset}-{filename}.{ext}.{preset}",
+                "camera-h265-clip.MOV.h265",
+            ),
+            ("{filename}{filename}{filename}", "clipclipclip"),
+        ] {
+            assert_eq!(render(template, &vars()).unwrap(), rendered, "{}", template);
+        }
+    }
+
+    #[test]
+    fn templates_without_tokens_are_used_as_written() {
+        for template in ["", "movie", "archive/2023 final", "a.b-c_d"] {
+            assert_eq!(render(template, &vars()).unwrap(), template);
+        }
+    }
+
+    #[test]
+    fn unknown_tokens_and_stray_braces_are_errors() {
+        for (template, error) in [
+            ("movie-{dat}", "Unknown token '{dat}' in template 'movie-{dat}', expected one of {filename}, {date}, {time}, {year}, {month}, {day}, {preset}, {input}, {ext}"),
+            ("{filename}-{}", "Unknown token '{}' in template '{filename}-{}'"),
+            ("{FILENAME}", "Unknown token '{FILENAME}'"),
+            ("{filename", "Unclosed '{' in template '{filename'"),
+            ("filename}", "Unmatched '}' in template 'filename}'"),
+            ("{{filename}}", "Unknown token '{{filename}'"),
+        ] {
+            let message = render(template, &vars()).unwrap_err().to_string();
+            assert!(message.starts_with(error), "{}: {}", template, message);
+        }
+    }
+
+    #[test]
+    fn unknown_tokens_fail_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        for (template, valid) in [
+            ("filename_template: '{filename}-{date}'", true),
+            ("filename_template: fixed", true),
+            ("filename_template: 'movie-{dat}'", false),
+            ("filename_template: x\n    subdir_template: '{year'", false),
+        ] {
+            std::fs::write(
+                &path,
+                format!(
+                    "inputs: []\noutputs:\n  o:\n    path: {}\n    container: mkv\n    {}\npresets: {{}}\n",
+                    dir.path().display(),
+                    template
+                ),
+            )
+            .unwrap();
+            let report = config::check_config(&path, false);
+            assert_eq!(!report.has_errors(), valid, "{}: {:?}", template, report);
+            if !valid {
+                assert!(report.findings[0].message.starts_with("Invalid "));
+            }
+        }
+    }
+
+    #[test]
+    fn source_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = InputConfig {
+            path: dir.path().join("camera"),
+            ..Default::default()
+        };
+        let source = input.path.join("2023/clip.final.MOV");
+        std::fs::create_dir_all(source.parent().unwrap()).unwrap();
+        std::fs::write(&source, b"").unwrap();
+        let vars = TemplateVars::for_source(&source, &input, "h265", None).unwrap();
+        assert_eq!(vars.filename, "clip.final");
+        assert_eq!(vars.input, "camera");
+        assert_eq!(vars.ext, "MOV");
+        assert_eq!(vars.preset, "h265");
+    }
+}
@@ -0,0 +1,227 @@
+//! Fixtures shared by the unit tests: presets and probes built from text, and a sandbox that
+//! runs real jobs against fake ffmpeg and ffprobe scripts.
+
+use crate::config::{self, Config, PresetConfig};
+use crate::ffprobe::ProbeResult;
+use crate::tools;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tempfile::TempDir;
+use tracing::field::Field;
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::DefaultGuard;
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+/// Stands in for ffprobe: a 10 second 1080p h264 video with one aac track, for any file that
+/// isn't empty. Files holding `CHAPTERS` have two chapters, and ones holding `TRUNCATED` are
+/// only 4 seconds long.
+const FAKE_FFPROBE: &str = r#"#!/bin/sh
+for a in "$@"; do f="$a"; done
+IFS="	"; printf '%s\n' "ffprobe	$*" >> "$CALLS"
+[ -s "$f" ] || { echo "invalid data" >&2; exit 1; }
+chapters=""
+grep -q CHAPTERS "$f" && chapters=',"chapters":[{"id":0,"start_time":"0.0","end_time":"5.0"},{"id":1,"start_time":"5.0","end_time":"10.0"}]'
+duration="10.0"
+grep -q TRUNCATED "$f" && duration="4.0"
+cat <<JSON
+{"format":{"duration":"$duration","filename":"$f","format_name":"mov","nb_streams":2,"bit_rate":"8000000"},"streams":[{"index":0,"codec_type":"video","codec_name":"h264","field_order":"progressive","width":1920,"height":1080,"r_frame_rate":"25/1","avg_frame_rate":"25/1","nb_frames":"250","duration":"10.0"},{"index":1,"codec_type":"audio","codec_name":"aac"}]$chapters}
+JSON
+"#;
+
+/// Stands in for ffmpeg: reports progress and writes a small output. Arguments naming a
+/// `*broken*` file fail the encode, the `h264_nvenc` encoder fails as on a machine without an
+/// NVIDIA GPU, `*slow*` ones take a second, `*hung*` ones write part of their output and then
+/// stall for a minute, `*large*` ones report growing twice the size of the sandbox's sources,
+/// `*blank*` ones "succeed" with an empty output and `*truncated*` ones with one that stops
+/// after 4 seconds. The chapters of the input are carried over with `-map_chapters 0`.
+const FAKE_FFMPEG: &str = r#"#!/bin/sh
+case "$1" in -version) echo "ffmpeg version 6.1-fake Copyright (c) fake"; exit 0;; esac
+case "$2" in -encoders) printf "Encoders:\n V..... = Video\n ------\n V....D libx264              H.264\n V....D libx265              H.265\n A....D aac                  AAC\n"; exit 0;; esac
+case "$2" in -hwaccels) printf "Hardware acceleration methods:\n\n"; exit 0;; esac
+prev=""; for a in "$@"; do [ "$prev" = "-i" ] && in="$a"; prev="$a"; out="$a"; done
+IFS="	"; printf '%s\n' "ffmpeg	$*" >> "$CALLS"
+case "$*" in *broken*) echo "broken: Invalid data found when processing input" >&2; exit 1;; esac
+case "$*" in *h264_nvenc*) echo "[h264_nvenc @ 0x5581a2c0] No capable devices found" >&2; echo "[vost#0:0/h264_nvenc @ 0x5581a1c0] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height." >&2; exit 1;; esac
+case "$*" in *slow*) sleep 1;; esac
+case "	$*	" in *"	-n	"*) [ -e "$out" ] && { echo "File '$out' already exists. Exiting." >&2; exit 1;};; esac
+case "$*" in *large*) printf 'total_size=1000\n';; esac
+printf 'frame=125\nfps=25\nout_time_us=5000000\nspeed=2.0x\nprogress=continue\n'
+case "$*" in *hung*) head -c 500 /dev/zero > "$out"; sleep 60;; esac
+case "$*" in *blank*) : > "$out"; exit 0;; esac
+case "$*" in *truncated*) echo TRUNCATED > "$out"; exit 0;; esac
+case "	$*	" in *"	-f	null	"*) ;; *) head -c 10 /dev/zero > "$out"
+  case "	$*	" in *"	-map_chapters	0	"*) grep -q CHAPTERS "$in" && echo CHAPTERS >> "$out";; esac;; esac
+printf 'frame=250\nfps=25\nout_time_us=10000000\ndup_frames=0\ndrop_frames=0\nspeed=2.0x\nprogress=end\n'
+"#;
+
+/// Directory of the fake tools, which every test in the process shares
+struct FakeTools {
+    _dir: TempDir,
+    calls: PathBuf,
+}
+
+/// Point sstc at the fake ffmpeg and ffprobe, once for the whole test run
+fn fake_tools() -> &'static FakeTools {
+    static TOOLS: OnceLock<FakeTools> = OnceLock::new();
+    TOOLS.get_or_init(|| {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls.log");
+        let install = |name: &str, script: &str| {
+            let path = dir.path().join(name);
+            let script = script.replacen(
+                "#!/bin/sh\n",
+                &format!("#!/bin/sh\nCALLS='{}'\n", calls.display()),
+                1,
+            );
+            std::fs::write(&path, script).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let ffmpeg = install("ffmpeg", FAKE_FFMPEG);
+        let ffprobe = install("ffprobe", FAKE_FFPROBE);
+        tools::configure(Some(&ffmpeg), Some(&ffprobe)).unwrap();
+        FakeTools { _dir: dir, calls }
+    })
+}
+
+/// A preset as it would be written in the config
+pub fn preset(yaml: &str) -> PresetConfig {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+/// What ffprobe reported, as its JSON
+pub fn probe(json: serde_json::Value) -> ProbeResult {
+    serde_json::from_value(json).unwrap()
+}
+
+/// A progressive 1080p h264 video of `duration` seconds at 25 fps with one aac track
+pub fn video_probe(duration: f32) -> ProbeResult {
+    probe(serde_json::json!({
+        "format": {
+            "filename": "in.mp4",
+            "nb_streams": 2,
+            "format_name": "mov",
+            "duration": duration.to_string(),
+        },
+        "streams": [
+            {
+                "index": 0,
+                "codec_type": "video",
+                "codec_name": "h264",
+                "width": 1920,
+                "height": 1080,
+                "field_order": "progressive",
+                "r_frame_rate": "25/1",
+                "avg_frame_rate": "25/1",
+            },
+            {"index": 1, "codec_type": "audio", "codec_name": "aac"},
+        ],
+    }))
+}
+
+/// Config of one `in` directory of mp4s transcoded by preset `p` into the `out` directory
+pub const BASIC_CONFIG: &str = "
+inputs:
+  - path: {dir}/in
+    extensions: [mp4]
+    preset: p
+    output: o
+outputs:
+  o:
+    path: {dir}/out
+    filename_template: '{filename}'
+    container: mkv
+presets:
+  p:
+    video_codec: libx264
+stability:
+  check_interval: 10ms
+  stable_for: 0s
+";
+
+/// A temporary directory to run jobs in, with the fake tools in place
+pub struct Sandbox {
+    dir: TempDir,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        fake_tools();
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Write `name` below the sandbox with some content, creating its directory
+    pub fn file(&self, name: &str) -> PathBuf {
+        let path = self.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"not really a video").unwrap();
+        path
+    }
+
+    /// Write `name` like [`Sandbox::file`], as a source with two chapters
+    pub fn chaptered_file(&self, name: &str) -> PathBuf {
+        let path = self.file(name);
+        std::fs::write(&path, b"not really a video with CHAPTERS").unwrap();
+        path
+    }
+
+    /// Load `yaml` as the config file, with `{dir}` standing for the sandbox
+    pub fn config(&self, yaml: &str) -> Arc<Config> {
+        let path = self.path().join("config.yaml");
+        std::fs::write(&path, yaml.replace("{dir}", &self.path().to_string_lossy())).unwrap();
+        Arc::new(config::load_config(&path, false).unwrap())
+    }
+
+    /// Arguments of each call of `tool` (`ffmpeg` or `ffprobe`) that involved the sandbox
+    pub fn calls(&self, tool: &str) -> Vec<Vec<String>> {
+        let dir = self.path().to_string_lossy();
+        std::fs::read_to_string(&fake_tools().calls)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains(dir.as_ref()))
+            .filter_map(|line| {
+                let mut args = line.split('\t');
+                (args.next() == Some(tool)).then(|| args.map(str::to_string).collect())
+            })
+            .collect()
+    }
+}
+
+/// Panic in the job of any source named `*poison*`, standing in for a bug in sstc. The job
+/// panics as its span opens, for as long as the guard is held on the current thread, which
+/// runs every task of a `#[tokio::test]`.
+pub fn poison_jobs() -> DefaultGuard {
+    struct Poison;
+
+    impl<S: Subscriber> Layer<S> for Poison {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            if attrs.metadata().name() != "job" {
+                return;
+            }
+            let mut poisoned = false;
+            attrs.record(&mut |field: &Field, value: &dyn fmt::Debug| {
+                poisoned |= field.name() == "source" && format!("{:?}", value).contains("poison");
+            });
+            if poisoned {
+                panic!("poisoned job");
+            }
+        }
+    }
+
+    tracing::subscriber::set_default(tracing_subscriber::registry().with(Poison))
+}
+
+/// Whether the tests run as root, who reads and writes past any file mode
+pub fn runs_as_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
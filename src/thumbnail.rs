@@ -0,0 +1,214 @@
+use crate::artifacts;
+use crate::config::{Config, ProcessPriority, ThumbnailConfig};
+use crate::priority;
+use crate::tools;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Poster of `output`: the same directory and stem, as `.jpg`
+pub fn path_for(output: &Path) -> PathBuf {
+    output.with_extension("jpg")
+}
+
+/// Whether `path` looks like the poster of an output: a `.jpg` sharing its stem with another
+/// file next to it, while some output of the config writes posters. Outputs may well be
+/// written into a watched tree, and their posters must not be queued as media.
+pub fn is_poster(config: &Config, path: &Path) -> bool {
+    let is_jpg = |path: &Path| path.extension().is_some_and(|ext| ext == "jpg");
+    if !is_jpg(path)
+        || !config
+            .outputs
+            .values()
+            .any(|output| output.thumbnail.is_some())
+    {
+        return false;
+    }
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return false;
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.filter_map(|entry| entry.ok()).any(|entry| {
+        let sibling = entry.path();
+        sibling.file_stem() == Some(stem) && !is_jpg(&sibling)
+    })
+}
+
+/// Extract one frame of a finished `output`, `duration` seconds long, into its poster.
+///
+/// The frame goes to a temporary file first and only takes the poster's name if nothing has
+/// it yet, so an existing `.jpg` next to the output, such as a camera's own, is never
+/// replaced. Returns the poster, which the job wrote itself.
+///
+/// Runs within the job that wrote the output, so it takes no job slot of its own.
+pub async fn generate(
+    output: &Path,
+    thumbnail: &ThumbnailConfig,
+    duration: Option<f64>,
+    priority: ProcessPriority,
+) -> Result<PathBuf> {
+    let poster = path_for(output);
+    if poster.exists() {
+        return Err(anyhow!(
+            "{} already exists, leaving it alone",
+            poster.display()
+        ));
+    }
+
+    let temp = TempPoster(artifacts::temp_path_for(&poster));
+    let mut cmd = Command::new(tools::ffmpeg());
+    priority::apply(&mut cmd, priority);
+    // The temporary file is sstc's own, so a leftover of an earlier attempt may be replaced
+    cmd.args(["-v", "error", "-y"])
+        // Seeking before -i jumps to the nearest keyframe instead of decoding up to the position
+        .args([
+            "-ss",
+            &format!("{:.3}", thumbnail.at.seconds_into(duration)),
+        ])
+        .arg("-i")
+        .arg(output)
+        .args(["-frames:v", "1", "-q:v", "2"]);
+    if let Some(width) = thumbnail.width {
+        cmd.args(["-vf", &format!("scale={}:-2", width)]);
+    }
+    // The image2 muxer picks the format by extension, which the temporary name keeps
+    let result = cmd
+        .arg(&temp.0)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to execute ffmpeg")?;
+
+    if !result.status.success() {
+        return Err(anyhow!(
+            "ffmpeg failed with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    // A position past the last frame leaves ffmpeg with nothing to write, which it doesn't
+    // count as a failure
+    if !temp.0.exists() {
+        return Err(anyhow!("ffmpeg found no frame at {}", thumbnail.at));
+    }
+
+    persist_new(&temp.0, &poster)?;
+    Ok(poster)
+}
+
+/// Give `temp` the name `path` unless a file has it already, which is left alone
+fn persist_new(temp: &Path, path: &Path) -> Result<()> {
+    match std::fs::hard_link(temp, path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(anyhow!(
+            "{} appeared meanwhile, leaving it alone",
+            path.display()
+        )),
+        // Filesystems without hard links, such as FAT, only get the check before the rename
+        Err(_) if !path.exists() => std::fs::rename(temp, path)
+            .with_context(|| format!("Failed to rename {} to {}", temp.display(), path.display())),
+        Err(_) => Err(anyhow!(
+            "{} appeared meanwhile, leaving it alone",
+            path.display()
+        )),
+    }
+}
+
+/// Temporary file a poster is written to, removed when dropped whether or not it became the
+/// poster, so a failed or cancelled extraction leaves nothing behind
+struct TempPoster(PathBuf);
+
+impl Drop for TempPoster {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Sandbox;
+
+    fn thumbnail() -> ThumbnailConfig {
+        serde_yaml::from_str("at: 10%").unwrap()
+    }
+
+    async fn generate_for(output: &Path) -> Result<PathBuf> {
+        generate(output, &thumbnail(), Some(10.0), ProcessPriority::default()).await
+    }
+
+    fn names_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn poster_is_written_next_to_the_output() {
+        let sandbox = Sandbox::new();
+        let output = sandbox.file("out/clip.mkv");
+
+        let poster = generate_for(&output).await.unwrap();
+
+        assert_eq!(poster, sandbox.path().join("out/clip.jpg"));
+        assert_eq!(
+            names_in(&sandbox.path().join("out")),
+            ["clip.jpg", "clip.mkv"]
+        );
+        // Extracted into a temporary file, a tenth into the video
+        let args = sandbox.calls("ffmpeg").pop().unwrap();
+        assert!(args.windows(2).any(|pair| pair == ["-ss", "1.000"]));
+        let temp = sandbox.path().join("out/clip.sstc.tmp.jpg");
+        assert_eq!(args.last().unwrap(), &*temp.to_string_lossy());
+    }
+
+    #[tokio::test]
+    async fn failed_extraction_leaves_nothing_behind() {
+        let sandbox = Sandbox::new();
+        let output = sandbox.file("out/broken.mkv");
+
+        let error = generate_for(&output).await.unwrap_err();
+
+        assert!(error.to_string().starts_with("ffmpeg failed"), "{}", error);
+        assert_eq!(names_in(&sandbox.path().join("out")), ["broken.mkv"]);
+    }
+
+    #[tokio::test]
+    async fn existing_jpg_is_never_replaced() {
+        let sandbox = Sandbox::new();
+        let output = sandbox.file("out/clip.mkv");
+        let camera = sandbox.path().join("out/clip.jpg");
+        std::fs::write(&camera, b"the camera's own poster").unwrap();
+
+        let error = generate_for(&output).await.unwrap_err();
+
+        assert!(error.to_string().contains("already exists"), "{}", error);
+        assert_eq!(std::fs::read(&camera).unwrap(), b"the camera's own poster");
+        assert_eq!(
+            names_in(&sandbox.path().join("out")),
+            ["clip.jpg", "clip.mkv"]
+        );
+        assert!(sandbox.calls("ffmpeg").is_empty());
+    }
+
+    #[test]
+    fn poster_appearing_during_extraction_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp = dir.path().join("clip.sstc.tmp.jpg");
+        let poster = dir.path().join("clip.jpg");
+        std::fs::write(&temp, b"frame").unwrap();
+        std::fs::write(&poster, b"someone else's").unwrap();
+
+        assert!(persist_new(&temp, &poster).is_err());
+        assert_eq!(std::fs::read(&poster).unwrap(), b"someone else's");
+
+        std::fs::remove_file(&poster).unwrap();
+        persist_new(&temp, &poster).unwrap();
+        assert_eq!(std::fs::read(&poster).unwrap(), b"frame");
+    }
+}
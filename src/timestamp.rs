@@ -0,0 +1,241 @@
+use crate::ffprobe::ProbeResult;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime};
+use std::fmt;
+use std::path::Path;
+
+/// Where a recording timestamp was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    Filename,
+    CreationTime,
+    Mtime,
+}
+
+impl fmt::Display for TimestampSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Filename => write!(f, "file name"),
+            Self::CreationTime => write!(f, "creation_time tag"),
+            Self::Mtime => write!(f, "modification time"),
+        }
+    }
+}
+
+/// When a clip was recorded, in local time: from the file name via `pattern`, then the
+/// container's `creation_time` tag, then the file's modification time
+pub fn recording_time(
+    path: &Path,
+    pattern: Option<&str>,
+    probe: Option<&ProbeResult>,
+) -> Option<(NaiveDateTime, TimestampSource)> {
+    if let Some(pattern) = pattern {
+        let stem = path.file_stem()?.to_string_lossy();
+        if let Some(time) = from_filename(&stem, pattern) {
+            return Some((time, TimestampSource::Filename));
+        }
+    }
+
+    if let Some(time) = probe.and_then(creation_time) {
+        return Some((time, TimestampSource::CreationTime));
+    }
+
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some((
+        DateTime::<Local>::from(modified).naive_local(),
+        TimestampSource::Mtime,
+    ))
+}
+
+/// Find the first place in `name` where the strftime-style `pattern` matches.
+///
+/// Camera names wrap the timestamp in prefixes and suffixes (`ch01_20231015213045`,
+/// `RecM01_20231015_213045_213145_6E36B00`), so the pattern may start anywhere and
+/// anything after it is ignored.
+pub fn from_filename(name: &str, pattern: &str) -> Option<NaiveDateTime> {
+    name.char_indices()
+        .map(|(i, _)| &name[i..])
+        .find_map(|rest| {
+            NaiveDateTime::parse_and_remainder(rest, pattern)
+                .ok()
+                .map(|(time, _)| time)
+        })
+}
+
+/// The container's `creation_time` tag, converted from UTC to local time
+fn creation_time(probe: &ProbeResult) -> Option<NaiveDateTime> {
+    let tag = probe.format.tags.as_ref()?.creation_time.as_deref()?;
+    let time = DateTime::parse_from_rfc3339(tag).ok()?;
+    Some(time.with_timezone(&Local).naive_local())
+}
+
+/// A daily time window such as `21:00-06:00`, which may wrap past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn parse(value: &str) -> Result<Self> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Expected HH:MM-HH:MM, got '{}'", value))?;
+        let parse = |s: &str| {
+            NaiveTime::parse_from_str(s.trim(), "%H:%M").context(format!(
+                "Invalid time '{}' in '{}'",
+                s.trim(),
+                value
+            ))
+        };
+
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+
+    /// Start inclusive, end exclusive; a window whose start equals its end covers the whole day
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else if self.start > self.end {
+            time >= self.start || time < self.end
+        } else {
+            true
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::probe;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d)
+            .unwrap()
+            .and_hms_opt(h, mi, s)
+            .unwrap()
+    }
+
+    #[test]
+    fn hikvision_names() {
+        let pattern = "%Y%m%d%H%M%S";
+        assert_eq!(
+            from_filename("ch01_20231015213045", pattern),
+            Some(at(2023, 10, 15, 21, 30, 45))
+        );
+        // NVR exports add the camera address and milliseconds
+        assert_eq!(
+            from_filename(
+                "192.168.1.64_01_20231015213045123_MOTION_DETECTION",
+                pattern
+            ),
+            Some(at(2023, 10, 15, 21, 30, 45))
+        );
+    }
+
+    #[test]
+    fn reolink_names() {
+        // The start of the clip comes first, then its end
+        assert_eq!(
+            from_filename("RecM01_20231015_213045_213145_6E36B00", "%Y%m%d_%H%M%S"),
+            Some(at(2023, 10, 15, 21, 30, 45))
+        );
+        assert_eq!(
+            from_filename("Front Door-00-20240102235959", "%Y%m%d%H%M%S"),
+            Some(at(2024, 1, 2, 23, 59, 59))
+        );
+    }
+
+    #[test]
+    fn gopro_names_carry_no_time() {
+        for name in ["GH010123", "GX020456", "GOPR0789"] {
+            assert_eq!(from_filename(name, "%Y%m%d%H%M%S"), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_without_a_match() {
+        assert_eq!(from_filename("", "%Y%m%d"), None);
+        assert_eq!(from_filename("clip", "%Y%m%d"), None);
+        // Not a real date
+        assert_eq!(from_filename("ch01_20231345213045", "%Y%m%d%H%M%S"), None);
+    }
+
+    #[test]
+    fn falls_back_to_creation_time_then_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let gopro = dir.path().join("GH010123.MP4");
+        std::fs::write(&gopro, b"").unwrap();
+        let format =
+            serde_json::json!({"filename": "GH010123.MP4", "nb_streams": 0, "format_name": "mov"});
+        let mut tagged_format = format.clone();
+        tagged_format["tags"] = serde_json::json!({"creation_time": "2023-10-15T21:30:45.000000Z"});
+        let tagged = probe(serde_json::json!({"format": tagged_format, "streams": []}));
+        let expected = DateTime::parse_from_rfc3339("2023-10-15T21:30:45Z")
+            .unwrap()
+            .with_timezone(&Local)
+            .naive_local();
+
+        assert_eq!(
+            recording_time(&gopro, Some("%Y%m%d%H%M%S"), Some(&tagged)),
+            Some((expected, TimestampSource::CreationTime))
+        );
+        let untagged = probe(serde_json::json!({"format": format, "streams": []}));
+        let (_, source) = recording_time(&gopro, None, Some(&untagged)).unwrap();
+        assert_eq!(source, TimestampSource::Mtime);
+        assert_eq!(
+            recording_time(&dir.path().join("gone.mp4"), None, None),
+            None
+        );
+
+        // The name wins over the tag
+        let hikvision = dir.path().join("ch01_20231016053000.mp4");
+        assert_eq!(
+            recording_time(&hikvision, Some("%Y%m%d%H%M%S"), Some(&tagged)),
+            Some((at(2023, 10, 16, 5, 30, 0), TimestampSource::Filename))
+        );
+    }
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let night = TimeWindow::parse("21:00-06:00").unwrap();
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert!(night.contains(time(21, 0)));
+        assert!(night.contains(time(23, 59)));
+        assert!(night.contains(time(0, 0)));
+        assert!(night.contains(time(5, 59)));
+        assert!(!night.contains(time(6, 0)));
+        assert!(!night.contains(time(12, 0)));
+        assert!(!night.contains(time(20, 59)));
+
+        let day = TimeWindow::parse(" 08:30 - 17:00 ").unwrap();
+        assert!(day.contains(time(8, 30)));
+        assert!(!day.contains(time(17, 0)));
+        assert_eq!(day.to_string(), "08:30-17:00");
+        assert!(TimeWindow::parse("06:00-06:00")
+            .unwrap()
+            .contains(time(3, 0)));
+    }
+
+    #[test]
+    fn invalid_windows() {
+        for window in ["", "21:00", "21:00-", "25:00-06:00", "9pm-6am"] {
+            assert!(TimeWindow::parse(window).is_err(), "{}", window);
+        }
+    }
+}
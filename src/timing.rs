@@ -0,0 +1,238 @@
+use crate::config::PresetConfig;
+use crate::ffprobe::ProbeResult;
+
+/// Parse an ffmpeg time duration: `[-][HH:]MM:SS[.m...]` or `[-]S+[.m...][s|ms|us]`
+pub fn parse_ffmpeg_time(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+
+    let seconds = if value.contains(':') {
+        let parts: Vec<&str> = value.split(':').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        parts.iter().try_fold(0.0, |acc, part| {
+            part.parse::<f64>().ok().map(|v| acc * 60.0 + v)
+        })?
+    } else if let Some(us) = value.strip_suffix("us") {
+        us.parse::<f64>().ok()? / 1_000_000.0
+    } else if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse::<f64>().ok()? / 1_000.0
+    } else {
+        value
+            .strip_suffix('s')
+            .unwrap_or(value)
+            .parse::<f64>()
+            .ok()?
+    };
+
+    if !seconds.is_finite() {
+        return None;
+    }
+
+    Some(if negative { -seconds } else { seconds })
+}
+
+/// How long the output will be once trims, sample limits and speed-changing filters apply.
+///
+/// ffmpeg's `out_time` counts from the trim start, so the progress bar, the ETA and
+/// the post-encode duration check must all be measured against this rather than
+/// the source duration. Returns `None` when the source duration is unknown.
+pub fn expected_output_duration(probe: &ProbeResult, preset: &PresetConfig) -> Option<f64> {
+    let source = probe.duration() as f64;
+    if source <= 0.0 {
+        return None;
+    }
+
+    let option = |name: &str| preset.extra_options.get(name).and_then(parse_ffmpeg_time);
+
+    // Output options trim what the filters turned out, so the speed change comes first
+    let filtered = source * speed_factor(preset);
+    let start = option("-ss").unwrap_or(0.0).clamp(0.0, filtered);
+    let end = option("-to").unwrap_or(filtered).clamp(start, filtered);
+    let mut duration = end - start;
+
+    if let Some(limit) = option("-t") {
+        duration = duration.min(limit.max(0.0));
+    }
+
+    Some(duration)
+}
+
+/// Duration multiplier introduced by `setpts` (video) or, failing that, `atempo` (audio)
+fn speed_factor(preset: &PresetConfig) -> f64 {
+    let complex = preset.extra_options.get("-filter_complex");
+    let video_filters = preset
+        .video_filters
+        .iter()
+        .map(String::as_str)
+        .chain(complex)
+        .flat_map(|chain| filter_args(chain, "setpts"))
+        .filter_map(|expr| setpts_factor(&expr))
+        .product::<f64>();
+
+    if video_filters != 1.0 {
+        return video_filters;
+    }
+
+    let tempo = preset
+        .audio_filters
+        .iter()
+        .map(String::as_str)
+        .chain(complex)
+        .flat_map(|chain| filter_args(chain, "atempo"))
+        .filter_map(|v| v.parse::<f64>().ok())
+        .filter(|v| *v > 0.0)
+        .product::<f64>();
+
+    1.0 / tempo
+}
+
+/// Arguments of every occurrence of `name` in a filter chain
+fn filter_args(chain: &str, name: &str) -> Vec<String> {
+    chain
+        .split([',', ';'])
+        .filter_map(|filter| {
+            let filter = without_pads(filter);
+            filter
+                .trim()
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(|args| args.trim_matches(|c| c == '\'' || c == '"').to_string())
+        })
+        .collect()
+}
+
+/// A filter without the `[label]` pads around it, like `[0:v]setpts=2*PTS[v]`
+fn without_pads(filter: &str) -> String {
+    let mut depth = 0usize;
+    filter
+        .chars()
+        .filter(|c| match c {
+            '[' => {
+                depth += 1;
+                false
+            }
+            ']' => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            _ => depth == 0,
+        })
+        .collect()
+}
+
+/// Multiplier of simple `setpts` expressions: `N*PTS`, `PTS*N`, `PTS/N`
+fn setpts_factor(expr: &str) -> Option<f64> {
+    let expr: String = expr.chars().filter(|c| !c.is_whitespace()).collect();
+    let expr = expr.to_uppercase();
+
+    if expr == "PTS" {
+        return Some(1.0);
+    }
+
+    let factor = if let Some(divisor) = expr.strip_prefix("PTS/") {
+        1.0 / divisor.parse::<f64>().ok()?
+    } else if let Some(factor) = expr.strip_prefix("PTS*") {
+        factor.parse::<f64>().ok()?
+    } else if let Some(factor) = expr.strip_suffix("*PTS") {
+        factor.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+
+    (factor.is_finite() && factor > 0.0).then_some(factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{preset, video_probe};
+
+    fn expected(preset_yaml: &str) -> Option<f64> {
+        expected_output_duration(&video_probe(120.0), &preset(preset_yaml))
+    }
+
+    #[test]
+    fn parses_ffmpeg_times() {
+        assert_eq!(parse_ffmpeg_time("90"), Some(90.0));
+        assert_eq!(parse_ffmpeg_time("1.5s"), Some(1.5));
+        assert_eq!(parse_ffmpeg_time("250ms"), Some(0.25));
+        assert_eq!(parse_ffmpeg_time("500000us"), Some(0.5));
+        assert_eq!(parse_ffmpeg_time("01:02:03.5"), Some(3723.5));
+        assert_eq!(parse_ffmpeg_time("02:30"), Some(150.0));
+        assert_eq!(parse_ffmpeg_time("-5"), Some(-5.0));
+        assert_eq!(parse_ffmpeg_time("1:2:3:4"), None);
+        assert_eq!(parse_ffmpeg_time("soon"), None);
+    }
+
+    #[test]
+    fn plain_encode_keeps_the_source_duration() {
+        assert_eq!(expected("video_codec: libx264"), Some(120.0));
+    }
+
+    #[test]
+    fn unknown_source_duration_has_no_expectation() {
+        let preset = preset("video_codec: libx264");
+        assert_eq!(expected_output_duration(&video_probe(0.0), &preset), None);
+    }
+
+    #[test]
+    fn trims_count_from_the_seek() {
+        assert_eq!(expected("extra_options:\n  - -ss: '20'"), Some(100.0));
+        assert_eq!(expected("extra_options:\n  - -to: '00:01:00'"), Some(60.0));
+        assert_eq!(
+            expected("extra_options:\n  - -ss: '20'\n  - -to: '50'"),
+            Some(30.0)
+        );
+        // Past the end leaves nothing, an end before the start too
+        assert_eq!(expected("extra_options:\n  - -ss: '200'"), Some(0.0));
+        assert_eq!(
+            expected("extra_options:\n  - -ss: '50'\n  - -to: '20'"),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn samples_are_limited_by_t() {
+        assert_eq!(expected("extra_options:\n  - -t: '30'"), Some(30.0));
+        assert_eq!(
+            expected("extra_options:\n  - -ss: '100'\n  - -t: '30'"),
+            Some(20.0)
+        );
+        assert_eq!(expected("extra_options:\n  - -t: '500'"), Some(120.0));
+    }
+
+    #[test]
+    fn frame_rate_changes_keep_the_duration() {
+        assert_eq!(expected("video_codec: libx264\nfps: 30"), Some(120.0));
+        assert_eq!(
+            expected("video_filters: ['fps=60']\naudio_filters: ['volume=2']"),
+            Some(120.0)
+        );
+    }
+
+    #[test]
+    fn speed_filters_scale_the_duration() {
+        assert_eq!(expected("video_filters: ['setpts=0.5*PTS']"), Some(60.0));
+        assert_eq!(expected("video_filters: ['setpts=PTS/4']"), Some(30.0));
+        assert_eq!(expected("audio_filters: ['atempo=2.0']"), Some(60.0));
+        assert_eq!(
+            expected("extra_options:\n  - -filter_complex: '[0:v]setpts=2*PTS[v]'"),
+            Some(240.0)
+        );
+        // The picture sets the pace when both are changed
+        assert_eq!(
+            expected("video_filters: ['setpts=2*PTS']\naudio_filters: ['atempo=0.5']"),
+            Some(240.0)
+        );
+        // Output options count in the time the filters turned out
+        assert_eq!(
+            expected("video_filters: ['setpts=0.5*PTS']\nextra_options:\n  - -t: '45'"),
+            Some(45.0)
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Result};
+use owo_colors::OwoColorize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+const FFMPEG_BIN_NAME: &str = "ffmpeg";
+const FFPROBE_BIN_NAME: &str = "ffprobe";
+
+static FFMPEG: OnceLock<PathBuf> = OnceLock::new();
+static FFPROBE: OnceLock<PathBuf> = OnceLock::new();
+
+/// The ffmpeg binary every encode runs; `ffmpeg` from PATH unless [`configure`] said otherwise
+pub fn ffmpeg() -> &'static Path {
+    FFMPEG.get_or_init(|| PathBuf::from(FFMPEG_BIN_NAME))
+}
+
+/// The ffprobe binary every probe runs; `ffprobe` from PATH unless [`configure`] said otherwise
+pub fn ffprobe() -> &'static Path {
+    FFPROBE.get_or_init(|| PathBuf::from(FFPROBE_BIN_NAME))
+}
+
+/// Pick the ffmpeg and ffprobe binaries for the rest of the process.
+///
+/// Configured paths must point at executables; without one the binary is looked up in PATH,
+/// and only a warning is logged when it's missing. Takes effect once, before the first job.
+pub fn configure(ffmpeg: Option<&Path>, ffprobe: Option<&Path>) -> Result<()> {
+    let ffmpeg = locate(FFMPEG_BIN_NAME, ffmpeg)?;
+    let ffprobe = locate(FFPROBE_BIN_NAME, ffprobe)?;
+
+    if FFMPEG.set(ffmpeg).is_err() || FFPROBE.set(ffprobe).is_err() {
+        warn!("ffmpeg and ffprobe were already in use, keeping the binaries picked before");
+    }
+    Ok(())
+}
+
+fn locate(name: &str, configured: Option<&Path>) -> Result<PathBuf> {
+    match configured {
+        Some(path) => {
+            let found = which::which(path).map_err(|e| {
+                anyhow!(
+                    "Configured {} {} is not an executable: {}",
+                    name,
+                    path.display(),
+                    e
+                )
+            })?;
+            info!("Using {} at: {}", name.green(), found.display().green());
+            Ok(found)
+        }
+        None => match which::which(name) {
+            Ok(found) => {
+                info!("Found {} at: {}", name.green(), found.display().green());
+                Ok(found)
+            }
+            Err(e) => {
+                warn!("{} not found in PATH: {}", name.red(), e.red());
+                Ok(PathBuf::from(name))
+            }
+        },
+    }
+}
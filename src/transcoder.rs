@@ -1,77 +1,516 @@
-use crate::config::{Config, InputConfig, OutputConfig, PresetConfig};
+use crate::artifacts;
+use crate::claim::{ClaimAttempt, SourceClaim};
+use crate::command::{self, build_ffmpeg_command, CommandOptions, Pass, MOV_TEXT_CONTAINERS};
+use crate::companion;
+use crate::compliance;
+use crate::config::{
+    container_holds_chapters, CodecMatchAction, Config, DeinterlaceMode, DroppedFramesAction,
+    ExistingOutputPolicy, FileAge, InputConfig, LargerOutputAction, OutputConfig, PresetConfig,
+    ProcessPriority, QueueFullPolicy, QueueOrder, Rotation, SameFilePolicy, SourceAction,
+    SourceNewerPolicy, StabilityConfig, SubtitlePolicy, TargetConfig, ThumbnailConfig, TonemapMode,
+};
+use crate::console::{self, JobBar};
+use crate::crop::{self, Crop};
+use crate::ffmpeg::{self, LogLevel};
 use crate::file_check;
+use crate::growth::OutputGrowthWatchdog;
+use crate::hash_store::{self, HashStore};
+use crate::history::History;
+use crate::history_db::HistoryDb;
+use crate::hooks::QueueHooks;
+use crate::in_place::{self, ReplacedFiles};
+use crate::job::{FfmpegFailure, FrameStats, JobError, JobOutcome, JobRecord, JobResult};
+use crate::job_log::{self, JobLog};
+use crate::loudnorm::{self, Measurement};
+use crate::marker::IgnoreMarkers;
+use crate::metrics;
+use crate::priority;
+use crate::progress::{short_duration, FFmpegProgress, JobProgress};
+use crate::queue::PriorityQueue;
+use crate::queue_file::QueueFile;
+use crate::rusage;
+use crate::scaling::ScaleDecision;
+use crate::shell;
+use crate::size_limit::OutputSizeLimit;
+use crate::source_action;
+use crate::summary::RunStats;
+use crate::telemetry;
+use crate::template::{self, TemplateVars};
+use crate::thumbnail;
+use crate::timestamp::{self, TimeWindow};
+use crate::timing;
+use crate::tools;
+use crate::units::{HumanDuration, Threads};
 use anyhow::{anyhow, Context, Result};
 use bytesize::ByteSize;
+use chrono::{Local, NaiveDateTime};
 use dashmap::DashMap;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::MultiProgress;
 use owo_colors::OwoColorize;
-use std::collections::{HashMap, VecDeque};
-use std::io::{BufRead, BufReader};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, Semaphore};
-use tracing::{debug, error, info, warn};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch, Mutex, Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::ffprobe;
+use crate::ffprobe::{self, ProbeResult, Stream};
+use crate::passlog::PassLogDir;
 
+/// One target of a source with the record of its job and how it ended
+type TargetJob = (JobRecord, Result<JobOutcome>);
+
+/// Number of trailing ffmpeg stderr lines kept for error classification: error lines, or
+/// untagged ones when ffmpeg logged no errors
+const STDERR_TAIL_LINES: usize = 50;
+/// Numbered names tried by `on_existing: rename` before giving up
+const MAX_RENAME_ATTEMPTS: u32 = 1000;
+/// How long a queue held by a failed active hook waits before trying again
+const HOOK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+/// How often a queue held outside the schedule window checks whether it opened
+const SCHEDULE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// Retries of a source that isn't ready when `max_retries` is unset
+const DEFAULT_MAX_RETRIES: u32 = 5;
+/// Wait before the first retry when `retry_delay` is unset
+const DEFAULT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+/// Longest wait between retries, however many there were
+const MAX_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(3600);
+/// Output duration difference allowed when `duration_tolerance_pct` is unset, in percent
+const DEFAULT_DURATION_TOLERANCE_PCT: f64 = 2.0;
+/// Output duration difference allowed when `duration_tolerance` is unset
+const DEFAULT_DURATION_TOLERANCE: HumanDuration = HumanDuration::from_secs(2);
+/// How long killed jobs get to clean up on shutdown before they are given up on
+const KILL_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+/// Finished express jobs remembered for [`Transcoder::express_status`]
+const EXPRESS_RESULTS_KEPT: usize = 100;
+/// How long changes to the queue gather before the queue file is written
+const QUEUE_SAVE_DELAY: std::time::Duration = std::time::Duration::from_millis(200);
+/// Times a queued file can be passed over by `queue_order` before it goes next regardless
+const MAX_QUEUE_SKIPS: u32 = 100;
+/// How much newer a source must be than its output for `if_source_newer`; filesystems like
+/// FAT only keep modification times to two seconds
+const SOURCE_NEWER_MARGIN: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Clones are cheap handles sharing the same queue, jobs and counters
+#[derive(Clone)]
 pub struct Transcoder {
-    config: Arc<Config>,
-    active_jobs: DashMap<PathBuf, ()>,
+    /// Swapped on reload; jobs take a snapshot when they start and keep it to the end
+    config: Arc<RwLock<Arc<Config>>>,
+    active_jobs: Arc<DashMap<PathBuf, ()>>,
     job_semaphore: Arc<Semaphore>,
-    file_queue: Arc<Mutex<VecDeque<PathBuf>>>,
+    file_queue: Arc<Mutex<PriorityQueue<QueuedFile>>>,
+    /// Where the queue is saved after changes, once [`Transcoder::restore_queue`] ran
+    queue_file: Arc<OnceLock<QueueFile>>,
+    /// Bumped on every change to the queue, for the writer of the queue file
+    queue_version: Arc<watch::Sender<u64>>,
+    /// The version of the queue last written to the queue file
+    queue_saved: Arc<watch::Sender<u64>>,
+    /// Dequeued sources whose jobs haven't come to an end yet, kept in the queue file so a
+    /// crash mid-encode restores them
+    unfinished: Arc<DashMap<PathBuf, ()>>,
     queue_tx: mpsc::Sender<()>,
     queue_rx: Arc<Mutex<mpsc::Receiver<()>>>,
+    hooks: Arc<QueueHooks>,
+    claimed_outputs: Arc<DashMap<PathBuf, PathBuf>>,
+    express_jobs: Arc<DashMap<PathBuf, ExpressEntry>>,
+    next_express_id: Arc<AtomicU64>,
+    /// The latest finished express jobs, for [`Transcoder::express_status`]
+    express_results: Arc<std::sync::Mutex<VecDeque<(u64, PathBuf, JobResult)>>>,
+    ignore_markers: IgnoreMarkers,
+    replaced_files: ReplacedFiles,
+    history: Option<Arc<History>>,
+    history_db: Option<Arc<HistoryDb>>,
+    /// Hashes of transcoded sources, with `dedup_by_hash`
+    hash_store: Option<Arc<HashStore>>,
+    stats: Arc<RunStats>,
+    idle_notify: Arc<Notify>,
+    /// Set while queued jobs are held outside the schedule window
+    schedule_paused: Arc<AtomicBool>,
+    schedule_clock: ScheduleClock,
+    /// Set between [`Transcoder::pause`] and [`Transcoder::resume`]
+    paused: Arc<AtomicBool>,
+    /// Notified whenever files leave the queue, for enqueues waiting for room
+    queue_space: Arc<Notify>,
+    /// Set when the watcher dropped a file because the queue was full, until the next rescan
+    dropped_while_full: Arc<AtomicBool>,
+    /// Files younger than the min_age of their input, offered again once old enough
+    aging_files: Arc<DashMap<PathBuf, ()>>,
+    /// Sources waiting for companion files outside an encode slot, with the extensions still
+    /// missing
+    waiting: Arc<DashMap<PathBuf, Vec<String>>>,
+    /// Stop switches of the jobs past their start checks, by source
+    job_controls: Arc<DashMap<PathBuf, Arc<JobControl>>>,
+    /// Set on shutdown, after which no more queued files are started
+    shutting_down: Arc<AtomicBool>,
+    /// Set for `--dry-run`, to print the ffmpeg commands of jobs instead of running them
+    dry_run: Arc<AtomicBool>,
+    /// Sources that weren't ready, with how often they were tried
+    retries: Arc<DashMap<PathBuf, RetryState>>,
+    /// Progress bars of the running encodes, drawn together
+    bars: MultiProgress,
+}
+
+/// What [`Transcoder::cancel`] did with a source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelResult {
+    /// The source was waiting in the queue and was taken out
+    Dequeued,
+    /// The source's job was running and its ffmpeg was killed
+    Stopped,
+    /// Nothing is queued or running for the source
+    NotFound,
+}
+
+/// An express job started or joined by [`Transcoder::express`]
+pub struct ExpressJob {
+    /// Shared by every caller coalesced onto the job, to ask after it with
+    /// [`Transcoder::express_status`]
+    pub id: u64,
+    result: watch::Receiver<Option<JobResult>>,
+}
+
+impl ExpressJob {
+    /// Wait for the job to end
+    pub async fn result(mut self) -> JobResult {
+        match self.result.wait_for(Option::is_some).await {
+            Ok(result) => result
+                .clone()
+                .expect("wait_for only returns once a result is set"),
+            Err(e) => Err(Arc::new(anyhow!(
+                "Express job ended without a result: {}",
+                e
+            ))),
+        }
+    }
+}
+
+/// What [`Transcoder::express_status`] knows of an express job
+#[derive(Debug)]
+pub enum ExpressStatus {
+    Running { source: PathBuf },
+    Finished { source: PathBuf, result: JobResult },
+}
+
+/// An express job in flight, for callers to join
+struct ExpressEntry {
+    id: u64,
+    /// The preset override the job runs with, which joining callers must ask for too
+    preset: Option<String>,
+    result: watch::Receiver<Option<JobResult>>,
+}
+
+/// Retries of a source that wasn't ready, counted for one version of the file
+#[derive(Debug, Clone)]
+struct RetryState {
+    attempts: u32,
+    size: Option<u64>,
+    modified: Option<std::time::SystemTime>,
+    /// Set while a retry is waiting for its delay
+    pending: bool,
 }
 
-#[derive(Debug, Default, Clone)]
-struct FFmpegProgress {
-    frame: Option<i64>,
-    fps: Option<f64>,
-    stream_0_0_q: Option<f64>,
-    bitrate: Option<String>,
-    total_size: Option<i64>,
-    out_time_us: Option<i64>,
-    out_time_ms: Option<i64>,
-    out_time: Option<String>,
-    dup_frames: Option<i64>,
-    drop_frames: Option<i64>,
-    speed: Option<String>,
-    progress: Option<String>,
+impl RetryState {
+    fn new(source: &Path) -> Self {
+        let metadata = source.metadata().ok();
+        Self {
+            attempts: 0,
+            size: metadata.as_ref().map(|m| m.len()),
+            modified: metadata.and_then(|m| m.modified().ok()),
+            pending: false,
+        }
+    }
+
+    /// Whether the source is the same file as when the retries were counted
+    fn is_unchanged(&self, current: &RetryState) -> bool {
+        self.size == current.size && self.modified == current.modified
+    }
+}
+
+/// A file waiting in the queue
+#[derive(Debug, Clone)]
+struct QueuedFile {
+    path: PathBuf,
+    /// Explicit input settings for files that don't belong to a configured input
+    input: Option<InputConfig>,
+    /// Priority of the input when the file was queued, kept when it's requeued
+    priority: u8,
+    /// Set once the wait for companion files is over, so a requeued file doesn't wait again
+    companions_settled: bool,
+    /// Companion extensions that never showed up
+    missing_companions: Vec<String>,
+    /// Size in bytes when the file was queued, for `queue_order`
+    size: u64,
+    /// Modification time when the file was queued, for `queue_order`
+    modified: Option<std::time::SystemTime>,
+    /// Times a file queued later started first, kept when it's requeued
+    skips: u32,
+    /// Transcode the file again even when its outputs exist, for `scan --force`
+    forced: bool,
+}
+
+impl QueuedFile {
+    /// Sort key of the file under `order`, lowest first. Files passed over
+    /// [`MAX_QUEUE_SKIPS`] times rank ahead of everything, in the order they were queued.
+    fn rank(&self, order: QueueOrder) -> (bool, i128) {
+        if self.skips >= MAX_QUEUE_SKIPS {
+            return (false, 0);
+        }
+        let rank = match order {
+            QueueOrder::Fifo => 0,
+            QueueOrder::SmallestFirst => i128::from(self.size),
+            QueueOrder::LargestFirst => -i128::from(self.size),
+            // Files without a modification time go last
+            QueueOrder::OldestFirst => self
+                .modified
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(i128::MAX, |since| since.as_nanos() as i128),
+        };
+        (true, rank)
+    }
 }
 
-impl FFmpegProgress {
-    fn from_key_values(key_values: &HashMap<String, String>) -> Self {
-        let mut progress = Self::default();
+/// Reservation of an output path by one job, released when dropped
+struct OutputClaim {
+    claims: Arc<DashMap<PathBuf, PathBuf>>,
+    output_path: PathBuf,
+}
 
-        for (key, value) in key_values {
-            match key.as_str() {
-                "frame" => progress.frame = value.parse().ok(),
-                "fps" => progress.fps = value.parse().ok(),
-                "stream_0_0_q" => progress.stream_0_0_q = value.parse().ok(),
-                "bitrate" => progress.bitrate = Some(value.clone()),
-                "total_size" => progress.total_size = value.parse().ok(),
-                "out_time_us" => progress.out_time_us = value.parse().ok(),
-                "out_time_ms" => progress.out_time_ms = value.parse().ok(),
-                "out_time" => progress.out_time = Some(value.clone()),
-                "dup_frames" => progress.dup_frames = value.parse().ok(),
-                "drop_frames" => progress.drop_frames = value.parse().ok(),
-                "speed" => progress.speed = Some(value.clone()),
-                "progress" => progress.progress = Some(value.clone()),
-                _ => {} // Ignore unknown fields
+impl OutputClaim {
+    fn acquire(
+        claims: &Arc<DashMap<PathBuf, PathBuf>>,
+        output_path: &Path,
+        source: &Path,
+    ) -> Result<Self> {
+        match claims.entry(output_path.to_path_buf()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => Err(JobError::OutputInUse {
+                output: output_path.to_path_buf(),
+                owner: entry.get().clone(),
             }
+            .into()),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(source.to_path_buf());
+                Ok(Self {
+                    claims: claims.clone(),
+                    output_path: output_path.to_path_buf(),
+                })
+            }
+        }
+    }
+}
+
+/// Stop switch of a running job, to kill its ffmpeg on shutdown, on `sstc cancel` or when it
+/// runs too long
+#[derive(Default)]
+struct JobControl {
+    /// The ffmpeg the job is running, if any
+    ffmpeg_pid: std::sync::Mutex<Option<u32>>,
+    killed: AtomicBool,
+    cancelled: AtomicBool,
+    timed_out: AtomicBool,
+    /// Set while the job's ffmpeg is stopped by a pause with `suspend_running`
+    suspended: AtomicBool,
+}
+
+impl JobControl {
+    /// Kill the running ffmpeg and keep the job from starting another
+    fn kill(&self) {
+        let pid = self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.killed.store(true, Ordering::SeqCst);
+        if let Some(pid) = *pid {
+            kill_process(pid);
+        }
+    }
+
+    /// Kill the running ffmpeg on request and keep the job from starting another
+    fn cancel(&self) {
+        let pid = self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(pid) = *pid {
+            kill_process(pid);
+        }
+    }
+
+    /// Kill the running ffmpeg for running past its time limit, and any the job starts later
+    fn time_out(&self) {
+        let pid = self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.timed_out.store(true, Ordering::SeqCst);
+        if let Some(pid) = *pid {
+            kill_process(pid);
+        }
+    }
+
+    /// Stop the running ffmpeg where it is, and any the job starts until it is resumed
+    #[cfg(unix)]
+    fn suspend(&self) {
+        let pid = self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.suspended.store(true, Ordering::SeqCst);
+        if let Some(pid) = *pid {
+            signal_process(pid, libc::SIGSTOP);
+        }
+    }
+
+    /// Let a suspended ffmpeg carry on; whether it was suspended
+    #[cfg(unix)]
+    fn resume(&self) -> bool {
+        let pid = self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let suspended = self.suspended.swap(false, Ordering::SeqCst);
+        if let (true, Some(pid)) = (suspended, *pid) {
+            signal_process(pid, libc::SIGCONT);
+        }
+        suspended
+    }
+
+    /// Note the ffmpeg the job started, killing it right away if the job already was, or
+    /// stopping it if the job is suspended
+    fn started(&self, pid: u32) {
+        let mut current = self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *current = Some(pid);
+        if self.is_killed() || self.is_cancelled() || self.is_timed_out() {
+            kill_process(pid);
+        }
+        #[cfg(unix)]
+        if self.suspended.load(Ordering::SeqCst) {
+            signal_process(pid, libc::SIGSTOP);
+        }
+    }
+
+    /// Forget the ffmpeg once it has exited and before it is reaped, so its pid isn't
+    /// signalled after reuse
+    fn finished(&self) {
+        *self
+            .ffmpeg_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn is_timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(unix)]
+fn kill_process(pid: u32) {
+    signal_process(pid, libc::SIGKILL);
+}
+
+#[cfg(unix)]
+fn signal_process(pid: u32, signal: libc::c_int) {
+    // ffmpeg leads its own process group, so anything it started goes with it
+    // SAFETY: kill only sends a signal; the pid is a child that has not been reaped yet
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), signal);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process(pid: u32) {
+    let _ = std::process::Command::new("taskkill")
+        .args(["/F", "/PID", &pid.to_string()])
+        .status();
+}
+
+/// One ffmpeg invocation of a job; two-pass encodes make two
+struct FfmpegRun<'a> {
+    args: Vec<std::ffi::OsString>,
+    input_path: &'a Path,
+    /// Output to check with `verify_output_growth`, if the invocation writes one
+    watched_output: Option<&'a Path>,
+    /// How often ffmpeg reports progress, unless it is off
+    progress_period: Option<std::time::Duration>,
+    expected_duration: Option<f64>,
+    /// Milliseconds of the progress bar taken up by earlier passes
+    position_offset: u64,
+    /// Projection of the output size for `if_larger_than_source`, if the invocation writes one
+    size_limit: Option<OutputSizeLimit>,
+    priority: ProcessPriority,
+    /// The config the job started with
+    config: &'a Config,
+}
+
+/// What the targets of a source's job share
+#[derive(Clone, Copy)]
+struct SourceJob<'a> {
+    /// The config the job started with, which a reload doesn't change
+    config: &'a Config,
+    source: &'a Path,
+    probe: &'a ProbeResult,
+    /// Transcode again even when the outputs exist, for `scan --force`
+    forced: bool,
+}
+
+/// Where the schedule window reads the local time, and how often a queue held outside it
+/// reads it again
+#[derive(Clone)]
+struct ScheduleClock {
+    now: Arc<dyn Fn() -> NaiveDateTime + Send + Sync>,
+    poll_interval: std::time::Duration,
+}
+
+impl Default for ScheduleClock {
+    fn default() -> Self {
+        Self {
+            now: Arc::new(|| Local::now().naive_local()),
+            poll_interval: SCHEDULE_POLL_INTERVAL,
         }
+    }
+}
+
+/// Registration of a job's [`JobControl`], removed when dropped
+struct RunningJob {
+    controls: Arc<DashMap<PathBuf, Arc<JobControl>>>,
+    source: PathBuf,
+    finished: Arc<Notify>,
+}
 
-        progress
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        self.controls.remove(&self.source);
+        self.finished.notify_waiters();
     }
+}
 
-    fn is_complete(&self) -> bool {
-        matches!(self.progress.as_deref(), Some("end"))
+impl Drop for OutputClaim {
+    fn drop(&mut self) {
+        self.claims.remove(&self.output_path);
     }
 }
 
 impl Transcoder {
     pub fn new(config: Arc<Config>) -> Self {
+        Self::with_clock(config, ScheduleClock::default())
+    }
+
+    fn with_clock(config: Arc<Config>, schedule_clock: ScheduleClock) -> Self {
         let max_jobs = config.max_parallel_jobs.unwrap_or(1);
         let (queue_tx, queue_rx) = mpsc::channel(100);
 
@@ -79,14 +518,56 @@ impl Transcoder {
             "Transcoder initialized with {} max parallel jobs",
             max_jobs.magenta()
         );
+        priority::set_default(config.process_priority.unwrap_or_default());
+        if let Some(period) = config.progress_interval.unwrap_or_default().period() {
+            console::set_refresh_interval(period);
+        }
 
         let transcoder = Self {
-            config,
-            active_jobs: DashMap::new(),
+            hooks: Arc::new(QueueHooks::new(&config)),
+            history: config
+                .history_file
+                .clone()
+                .map(|path| Arc::new(History::new(path))),
+            history_db: config
+                .history_db
+                .clone()
+                .map(|path| Arc::new(HistoryDb::new(path))),
+            hash_store: config
+                .hash_store
+                .as_ref()
+                .filter(|_| config.dedup_by_hash)
+                .map(|store| Arc::new(HashStore::open(store))),
+            config: Arc::new(RwLock::new(config)),
+            active_jobs: Arc::new(DashMap::new()),
             job_semaphore: Arc::new(Semaphore::new(max_jobs)),
-            file_queue: Arc::new(Mutex::new(VecDeque::new())),
+            file_queue: Arc::new(Mutex::new(PriorityQueue::new())),
+            queue_file: Arc::new(OnceLock::new()),
+            queue_version: Arc::new(watch::Sender::new(0)),
+            queue_saved: Arc::new(watch::Sender::new(0)),
+            unfinished: Arc::new(DashMap::new()),
             queue_tx,
             queue_rx: Arc::new(Mutex::new(queue_rx)),
+            claimed_outputs: Arc::new(DashMap::new()),
+            express_jobs: Arc::new(DashMap::new()),
+            next_express_id: Arc::new(AtomicU64::new(0)),
+            express_results: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            ignore_markers: IgnoreMarkers::new(),
+            replaced_files: ReplacedFiles::new(),
+            stats: Arc::new(RunStats::default()),
+            idle_notify: Arc::new(Notify::new()),
+            schedule_paused: Arc::new(AtomicBool::new(false)),
+            schedule_clock,
+            paused: Arc::new(AtomicBool::new(false)),
+            queue_space: Arc::new(Notify::new()),
+            dropped_while_full: Arc::new(AtomicBool::new(false)),
+            aging_files: Arc::new(DashMap::new()),
+            waiting: Arc::new(DashMap::new()),
+            job_controls: Arc::new(DashMap::new()),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            dry_run: Arc::new(AtomicBool::new(false)),
+            retries: Arc::new(DashMap::new()),
+            bars: console::bars().clone(),
         };
 
         transcoder.start_queue_processor();
@@ -113,489 +594,5455 @@ impl Transcoder {
 
     async fn process_queued_files(&self) {
         loop {
-            let file_path = {
-                let mut queue = self.file_queue.lock().await;
-                if queue.is_empty() {
+            // Pick the file only once a slot is free, so one queued in the meantime with a
+            // higher priority still goes first
+            let permit = match self.job_semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(e) => {
+                    error!("Failed to acquire semaphore: {}", e);
                     return;
                 }
-                queue.pop_front()
             };
+            if self.shutting_down.load(Ordering::SeqCst) || self.is_paused() {
+                return;
+            }
+            // Checked once a slot is free, as the window may have closed while waiting for it
+            if !self.schedule_open() {
+                self.pause_for_schedule();
+                return;
+            }
+
+            // Mark the file active while still holding the queue lock so the
+            // transcoder never looks idle between dequeue and job start
+            let item = {
+                let order = self.config().queue_order;
+                let mut queue = self.file_queue.lock().await;
+                let Some(item) = queue.pop_by(|item| item.rank(order), |item| item.skips += 1)
+                else {
+                    return;
+                };
+                self.queue_space.notify_waiters();
+                debug!(
+                    "Dequeued {} with priority {}, {} file(s) left in queue",
+                    item.path.display(),
+                    item.priority,
+                    queue.len()
+                );
 
-            if let Some(file_path) = file_path {
-                if self.active_jobs.contains_key(&file_path) {
-                    info!("Already processing file: {}", file_path.display());
+                if self.active_jobs.contains_key(&item.path) {
+                    info!("Already processing file: {}", item.path.display());
+                    self.save_queue();
                     continue;
                 }
 
-                self.spawn_file_processor(file_path).await;
+                self.active_jobs.insert(item.path.clone(), ());
+                if item.input.is_none() {
+                    self.unfinished.insert(item.path.clone(), ());
+                }
+                self.save_queue();
+                item
+            };
+
+            if !self.is_dry_run() && !self.hooks.activate().await {
+                self.hold_queue(item).await;
+                return;
             }
+
+            self.spawn_file_processor(item, permit).await;
+        }
+    }
+
+    /// Wake the queue processor; a full channel already has a wakeup pending
+    fn wake_queue_processor(&self) -> Result<()> {
+        match self.queue_tx.try_send(()) {
+            Ok(()) | Err(TrySendError::Full(())) => Ok(()),
+            Err(TrySendError::Closed(())) => Err(anyhow!("Queue processor has stopped")),
         }
     }
 
-    async fn spawn_file_processor(&self, file_path: PathBuf) {
-        self.active_jobs.insert(file_path.clone(), ());
+    /// Whether the schedule, if any, lets jobs start now
+    fn schedule_open(&self) -> bool {
+        self.config()
+            .schedule
+            .as_ref()
+            .is_none_or(|schedule| schedule.is_open((self.schedule_clock.now)()))
+    }
+
+    /// Hold the queue until the schedule window opens, checking every [`SCHEDULE_POLL_INTERVAL`]
+    fn pause_for_schedule(&self) {
+        if self.schedule_paused.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(schedule) = &self.config().schedule {
+            info!(
+                "Outside the schedule window {}, holding queued jobs until it opens",
+                schedule.cyan()
+            );
+        }
 
         let this = self.clone();
+        tokio::spawn(async move {
+            while !this.schedule_open() {
+                tokio::time::sleep(this.schedule_clock.poll_interval).await;
+            }
+            this.schedule_paused.store(false, Ordering::SeqCst);
+            info!("Schedule window is open, resuming queued jobs");
+            if let Err(e) = this.wake_queue_processor() {
+                error!("Failed to signal queue processor: {}", e);
+            }
+        });
+    }
+
+    /// Put the file back at the head of the queue and retry later
+    async fn hold_queue(&self, item: QueuedFile) {
+        let path = item.path.clone();
+        let mut queue = self.file_queue.lock().await;
+        queue.push_front(item.priority, item);
+        self.save_queue();
+        drop(queue);
+        self.active_jobs.remove(&path);
 
+        let queue_tx = self.queue_tx.clone();
         tokio::spawn(async move {
-            let permit = match this.job_semaphore.clone().acquire_owned().await {
-                Ok(permit) => permit,
-                Err(e) => {
-                    error!("Failed to acquire semaphore: {}", e);
-                    this.active_jobs.remove(&file_path);
-                    this.requeue_file(file_path).await;
-                    return;
-                }
-            };
+            tokio::time::sleep(HOOK_RETRY_DELAY).await;
+            if let Err(e) = queue_tx.send(()).await {
+                error!("Failed to signal queue processor: {}", e);
+            }
+        });
+    }
 
-            let output_path_result = this.get_output_path_for_file(&file_path);
+    async fn is_idle(&self) -> bool {
+        self.active_jobs.is_empty()
+            && self.aging_files.is_empty()
+            && !self.retries.iter().any(|entry| entry.pending)
+            && self.file_queue.lock().await.is_empty()
+    }
 
-            match this.process_file_internal(&file_path).await {
-                Ok(_) => {
-                    debug!(
-                        "Successfully processed file: {}",
-                        file_path.display().green()
-                    );
-                }
-                Err(e) => {
-                    error!(
-                        "Error processing file {}: {}",
-                        file_path.display().yellow(),
-                        e.red()
-                    );
+    /// Wait until nothing is queued or running
+    pub async fn wait_until_idle(&self) {
+        loop {
+            let notified = self.idle_notify.notified();
+            if self.is_idle().await {
+                return;
+            }
+            notified.await;
+        }
+    }
 
-                    if let Ok(output_path) = output_path_result {
-                        if output_path.exists() {
-                            match std::fs::remove_file(&output_path) {
-                                Ok(_) => info!(
-                                    "Removed incomplete output file: {}",
-                                    output_path.display()
-                                ),
-                                Err(err) => error!(
-                                    "Failed to remove incomplete output file {}: {}",
-                                    output_path.display(),
-                                    err
-                                ),
-                            }
-                        }
-                    }
+    /// Run the drained hook once the queue stayed empty for the settle period
+    fn schedule_drain_hook(&self) {
+        if self.is_dry_run() {
+            return;
+        }
+        let generation = self.hooks.generation();
+        let this = self.clone();
 
-                    if e.to_string().contains("not valid or still being copied") {
-                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                        this.requeue_file(file_path.clone()).await;
-                    }
-                }
+        tokio::spawn(async move {
+            tokio::time::sleep(this.hooks.settle()).await;
+            if this.is_idle().await {
+                this.hooks.drain(generation).await;
+            }
+        });
+    }
+
+    async fn spawn_file_processor(&self, item: QueuedFile, permit: OwnedSemaphorePermit) {
+        let this = self.clone();
+        let file_path = item.path.clone();
+
+        tokio::spawn(async move {
+            // Matching the input touches the filesystem, which can hang on a dead mount, so it
+            // is left to the job's task rather than holding up the dequeue loop
+            if !item.companions_settled && this.waits_for_companions(&item) {
+                drop(permit);
+                this.requeue_with_companions(item).await;
+                return;
+            }
+
+            // Run the job in its own task so a panic inside it is observed here
+            // instead of silently taking the bookkeeping below down with it
+            let job = tokio::spawn({
+                let this = this.clone();
+                async move { this.run_queued_job(item).await }
+            });
+
+            if let Err(e) = job.await {
+                error!(
+                    "Job for {} {}: {}",
+                    file_path.display().yellow(),
+                    if e.is_panic() {
+                        "panicked"
+                    } else {
+                        "was aborted"
+                    },
+                    e.red()
+                );
+                this.stats
+                    .record_failure(&JobRecord::new(file_path.clone()), "job panicked");
+                this.forget_unfinished(&file_path);
             }
 
             this.active_jobs.remove(&file_path);
 
             drop(permit);
+
+            if this.is_idle().await {
+                this.schedule_drain_hook();
+                this.idle_notify.notify_waiters();
+            }
         });
     }
 
-    async fn requeue_file(&self, file_path: PathBuf) {
+    fn waits_for_companions(&self, item: &QueuedFile) -> bool {
+        item.input
+            .clone()
+            .or_else(|| self.find_matching_input(&item.path))
+            .is_some_and(|input| !input.wait_for_companion.is_empty())
+    }
+
+    /// Wait for the companions outside of an encode slot, then put the file back at the head
+    /// of its priority
+    async fn requeue_with_companions(&self, item: QueuedFile) {
+        let item = self.settle_companions(item).await;
+        let path = item.path.clone();
         let mut queue = self.file_queue.lock().await;
-        queue.push_back(file_path.clone());
+        queue.push_front(item.priority, item);
+        self.save_queue();
         drop(queue);
-
-        if let Err(e) = self.queue_tx.send(()).await {
+        self.active_jobs.remove(&path);
+        if let Err(e) = self.wake_queue_processor() {
             error!("Failed to signal queue processor: {}", e);
-        } else {
-            info!(
-                "Requeued file for later processing: {}",
-                file_path.display()
-            );
         }
     }
 
-    pub async fn process_file(&self, file_path: &Path) -> Result<()> {
-        let Some(_) = self.find_matching_input(file_path) else {
-            debug!(
-                "No matching input configuration found for: {}",
-                file_path.display()
+    /// Wait for the companion files the item's input asks for, noting any that never arrived
+    async fn settle_companions(&self, mut item: QueuedFile) -> QueuedFile {
+        let input = item
+            .input
+            .clone()
+            .or_else(|| self.find_matching_input(&item.path));
+        if let Some(input) = input.filter(|i| !i.wait_for_companion.is_empty()) {
+            let timeout = input.companion_timeout.map_or(
+                std::time::Duration::from_secs(companion::DEFAULT_TIMEOUT_SECS),
+                Into::into,
             );
-            return Ok(());
-        };
-
-        if !self.active_jobs.contains_key(file_path) {
-            let mut queue = self.file_queue.lock().await;
-
-            let already_queued = queue.iter().any(|p| p == file_path);
-            if !already_queued {
-                debug!("Adding file to queue: {}", file_path.display());
-                queue.push_back(file_path.to_path_buf());
+            let stability = StabilityConfig::resolve(
+                input.stability.as_ref(),
+                self.config().stability.as_ref(),
+            );
+            item.missing_companions = companion::wait_for(
+                &item.path,
+                &input.wait_for_companion,
+                timeout,
+                stability,
+                |missing| {
+                    self.waiting.insert(item.path.clone(), missing.to_vec());
+                },
+            )
+            .await;
+            self.waiting.remove(&item.path);
 
-                drop(queue);
-                self.queue_tx
-                    .send(())
-                    .await
-                    .context("Failed to signal queue processor")?;
-                info!(
-                    "File queued for processing: {}",
-                    file_path.display().green()
+            if !item.missing_companions.is_empty() {
+                warn!(
+                    "Companion(s) {} of {} did not arrive in time, transcoding without them",
+                    item.missing_companions.join(", ").yellow(),
+                    item.path.display()
                 );
-            } else {
-                debug!("File already in queue: {}", file_path.display());
             }
-        } else {
-            info!("File already being processed: {}", file_path.display());
         }
 
-        Ok(())
+        item.companions_settled = true;
+        item
     }
 
-    async fn process_file_internal(&self, file_path: &Path) -> Result<()> {
-        if !file_check::is_file_valid(file_path).await? {
-            return Err(anyhow::anyhow!(
-                "File is not valid or still being copied: {}",
-                file_path.display()
-            ));
-        }
+    async fn run_queued_job(&self, item: QueuedFile) {
+        let file_path = item.path.clone();
+        let input_config = item
+            .input
+            .clone()
+            .or_else(|| self.find_matching_input(&file_path));
 
-        let Some(input_config) = self.find_matching_input(file_path) else {
-            return Err(anyhow::anyhow!("No matching input configuration found"));
+        let span = telemetry::job_span(&file_path);
+        let result = match &input_config {
+            Some(input_config) => {
+                self.process_file_internal(&file_path, input_config, item.forced)
+                    .instrument(span.clone())
+                    .await
+            }
+            None => Err(anyhow!("No matching input configuration found")),
+        };
+
+        if !matches!(&result, Err(e) if e.downcast_ref().is_some_and(JobError::is_retryable)) {
+            self.retries.remove(&file_path);
+        }
+        let jobs = match result {
+            Ok(jobs) => jobs,
+            Err(e) if matches!(e.downcast_ref(), Some(JobError::ClaimedElsewhere { .. })) => {
+                info!("Skipping: {}", e);
+                self.forget_unfinished(&file_path);
+                return;
+            }
+            Err(e) if e.downcast_ref().is_some_and(JobError::is_retryable) => {
+                if !self.retry_later(item, e).await {
+                    self.forget_unfinished(&file_path);
+                }
+                return;
+            }
+            // Left in the queue file, so the next start picks the source up again
+            Err(e) if matches!(e.downcast_ref(), Some(JobError::Interrupted)) => {
+                warn!("Interrupted {} before it started", file_path.display());
+                return;
+            }
+            Err(e) => vec![(JobRecord::new(file_path.clone()), Err(e))],
         };
 
-        let preset = self.get_preset(&input_config.preset)?;
-        let output = self.get_output(&input_config.output)?;
+        // Interrupted targets aren't failures; they are transcoded again on the next run
+        let (interrupted, jobs): (Vec<_>, Vec<_>) = jobs.into_iter().partition(|(_, result)| {
+            matches!(result, Err(e) if matches!(e.downcast_ref(), Some(JobError::Interrupted)))
+        });
 
-        let output_path = self.create_output_path(file_path, &output)?;
+        // Targets cut short by the schedule run again in the next window; those that finished
+        // are recorded now and skipped as existing then
+        let (stopped, jobs): (Vec<_>, Vec<_>) = jobs.into_iter().partition(|(_, result)| {
+            matches!(result, Err(e) if matches!(e.downcast_ref(), Some(JobError::StoppedBySchedule)))
+        });
 
-        if output_path.exists() {
+        if !item.missing_companions.is_empty() {
+            self.stats
+                .record_missing_companions(&file_path, &item.missing_companions);
+        }
+        let mut records = Vec::new();
+        for (mut record, result) in jobs {
+            if !item.missing_companions.is_empty() {
+                record.warnings.push(format!(
+                    "Transcoded without companion(s): {}",
+                    item.missing_companions.join(", ")
+                ));
+            }
+            self.finish_job(&mut record, result).await;
+            records.push(record);
+        }
+        telemetry::finish_job_span(&span, &records);
+
+        if !interrupted.is_empty() {
+            warn!("Interrupted {}", file_path.display().yellow());
+            return;
+        }
+        if !stopped.is_empty() {
             info!(
-                "Output file already exists, skipping: {}",
-                output_path.display()
+                "Stopped {} at the end of the schedule window, it will be transcoded in the next one",
+                file_path.display().yellow()
             );
-            return Ok(());
+            self.requeue_file(item).await;
+            self.forget_unfinished(&file_path);
+            return;
         }
 
-        if let Some(parent) = output_path.parent() {
-            std::fs::create_dir_all(parent).context("Failed to create output directory")?;
+        if let Some(input_config) = &input_config {
+            self.apply_source_action(&file_path, &input_config.source_action, &records)
+                .await;
         }
+        self.forget_unfinished(&file_path);
+    }
 
-        match self.transcode_file(file_path, &output_path, &preset).await {
-            Ok(_) => {
-                debug!(
-                    "Successfully transcoded: {} -> {}",
-                    file_path.display(),
-                    output_path.display()
-                );
-            }
-            Err(e) => {
-                error!(
-                    "Failed to transcode {}: {}",
-                    file_path.display().yellow(),
-                    e.red()
-                );
-                if output_path.exists() {
-                    if let Err(e) = std::fs::remove_file(&output_path) {
-                        error!("Failed to remove incomplete output file: {}", e);
-                    }
-                }
-                return Err(e);
-            }
+    /// Take a source whose job came to an end out of the queue file
+    fn forget_unfinished(&self, source: &Path) {
+        if self.unfinished.remove(source).is_some() {
+            self.save_queue();
         }
-
-        Ok(())
     }
 
-    fn find_matching_input(&self, file_path: &Path) -> Option<InputConfig> {
-        let extension = file_path.extension()?.to_str()?.to_lowercase();
-
-        let canonical_file_path = match std::fs::canonicalize(file_path) {
-            Ok(p) => p,
-            Err(e) => {
-                warn!("Failed to canonicalize path {}: {}", file_path.display(), e);
-                return None;
+    /// Queue a source that wasn't ready again after a growing delay, or give up on it once it
+    /// used up `max_retries`, returning whether it is retried. The count starts over when the
+    /// source changes.
+    async fn retry_later(&self, item: QueuedFile, error: anyhow::Error) -> bool {
+        let config = self.config();
+        let current = RetryState::new(&item.path);
+        let attempts = {
+            let mut state = self
+                .retries
+                .entry(item.path.clone())
+                .or_insert_with(|| current.clone());
+            if !state.is_unchanged(&current) {
+                *state = current;
             }
+            state.attempts += 1;
+            state.attempts
         };
 
-        debug!("Checking file: {}", canonical_file_path.display());
+        let max_retries = config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        if attempts > max_retries {
+            self.retries.remove(&item.path);
+            self.give_up(&item.path, max_retries, error).await;
+            return false;
+        }
 
-        for input in &self.config.inputs {
-            let canonical_input_path = match std::fs::canonicalize(&input.path) {
-                Ok(p) => p,
-                Err(e) => {
-                    warn!(
-                        "Failed to canonicalize input path {}: {}",
-                        input.path.display(),
-                        e
-                    );
-                    continue;
-                }
-            };
-
-            debug!(
-                "Comparing with input path: {}",
-                canonical_input_path.display()
-            );
+        let base = config.retry_delay.map_or(DEFAULT_RETRY_DELAY, Into::into);
+        let delay = retry_delay(base, attempts);
+        warn!(
+            "{}, retrying in {} ({} of {})",
+            error,
+            HumanDuration::from_secs(delay.as_secs()),
+            attempts,
+            max_retries
+        );
+        if let Some(mut state) = self.retries.get_mut(&item.path) {
+            state.pending = true;
+        }
 
-            if !canonical_file_path.starts_with(&canonical_input_path) {
-                debug!("Path doesn't match input directory");
-                continue;
+        let this = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            // Done with when the file was transcoded, given up on or failed again meanwhile
+            let due = this
+                .retries
+                .get_mut(&item.path)
+                .filter(|state| state.pending && state.attempts == attempts)
+                .map(|mut state| state.pending = false)
+                .is_some();
+            if !due {
+                return;
             }
-
-            if input
-                .extensions
+            // The watcher may have queued it again in the meantime
+            let queued = this
+                .file_queue
+                .lock()
+                .await
                 .iter()
-                .any(|ext| ext.to_lowercase() == extension)
-            {
-                debug!("Found matching input for file: {}", file_path.display());
-                return Some(input.clone());
+                .any(|queued| queued.path == item.path);
+            if queued || this.active_jobs.contains_key(&item.path) {
+                return;
             }
-        }
-
-        debug!("No matching input found for: {}", file_path.display());
-        None
+            if item.path.exists() {
+                this.requeue_file(item).await;
+            } else {
+                debug!("{} is gone, not retrying it", item.path.display());
+                this.retries.remove(&item.path);
+                this.forget_unfinished(&item.path);
+                if this.is_idle().await {
+                    this.idle_notify.notify_waiters();
+                }
+            }
+        });
+        true
     }
 
-    fn get_preset(&self, preset_name: &str) -> Result<PresetConfig> {
-        self.config
-            .presets
-            .get(preset_name)
-            .cloned()
-            .context(format!("Preset not found: {}", preset_name))
-    }
+    /// Report a source that never became ready as failed, and quarantine it if configured
+    async fn give_up(&self, source: &Path, retries: u32, error: anyhow::Error) {
+        let mut record = JobRecord::new(source.to_path_buf());
+        self.finish_job(
+            &mut record,
+            Err(anyhow!("{}, giving up after {} retries", error, retries)),
+        )
+        .await;
 
-    fn get_output(&self, output_name: &str) -> Result<OutputConfig> {
-        self.config
-            .outputs
-            .get(output_name)
-            .cloned()
-            .context(format!("Output not found: {}", output_name))
+        if let Some(dir) = self.config().quarantine_dir.clone() {
+            let action = SourceAction::Move(dir);
+            if let Err(e) = Self::run_source_action(action, source).await {
+                warn!("Failed to quarantine {}: {:#}", source.display(), e);
+            }
+        }
     }
 
-    fn create_output_path(
+    /// Delete, move or rename the source once every one of its targets produced an output
+    async fn apply_source_action(
         &self,
-        input_path: &Path,
-        output_config: &OutputConfig,
-    ) -> Result<PathBuf> {
-        let filename = input_path
-            .file_stem()
-            .context("Failed to get file stem")?
-            .to_str()
-            .context("Failed to convert file stem to string")?;
+        source: &Path,
+        action: &SourceAction,
+        records: &[JobRecord],
+    ) {
+        if *action == SourceAction::Keep {
+            return;
+        }
 
-        let output_filename = output_config
-            .filename_template
-            .replace("{filename}", filename);
-        let output_path = output_config
-            .path
-            .join(format!("{}.{}", output_filename, output_config.container));
+        let all_written = records.iter().all(|record| {
+            record.outcome == Some(JobOutcome::Transcoded)
+                && record.output.as_deref().is_some_and(|output| {
+                    // An in-place output is the source itself
+                    !in_place::is_same_file(source, output)
+                        && std::fs::metadata(output).is_ok_and(|m| m.len() > 0)
+                })
+        });
+        if !all_written {
+            debug!(
+                "Leaving source {} alone, not every target was transcoded",
+                source.display()
+            );
+            return;
+        }
 
-        Ok(output_path)
+        match Self::run_source_action(action.clone(), source).await {
+            Ok(Some(moved)) => self.replaced_files.record(&moved),
+            Ok(None) => {}
+            Err(e) => warn!("{:#}", e),
+        }
     }
 
-    async fn transcode_file(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-        preset: &PresetConfig,
-    ) -> Result<()> {
-        let ff = ffprobe::get_format_info(input_path);
-
-        let mut cmd = Command::new("ffmpeg");
-
-        cmd.arg("-v").arg("quiet");
-        cmd.arg("-progress").arg("pipe:1");
-        cmd.arg("-stats_period").arg("1.0");
+    /// Carry out a source action on the blocking pool, as a move to another filesystem copies
+    /// the whole source
+    async fn run_source_action(action: SourceAction, source: &Path) -> Result<Option<PathBuf>> {
+        let source = source.to_path_buf();
+        tokio::task::spawn_blocking(move || source_action::apply(&action, &source)).await?
+    }
 
-        cmd.arg("-i").arg(input_path);
-        cmd.arg("-y").arg(output_path);
+    /// Log the result of one target and count it in the stats and history
+    async fn finish_job(&self, record: &mut JobRecord, result: Result<JobOutcome>) {
+        match result {
+            Ok(JobOutcome::WouldTranscode) => {
+                info!(
+                    "Would transcode {} -> {}",
+                    record.source.display(),
+                    record
+                        .output
+                        .as_deref()
+                        .map_or_else(|| "-".into(), Path::to_string_lossy)
+                        .green()
+                );
+                record.outcome = Some(JobOutcome::WouldTranscode);
+                self.stats.record_would_transcode(record);
+            }
+            Ok(outcome) if outcome.is_skipped() => {
+                info!("Skipped {}: {}", record.source.display(), outcome.yellow());
+                record.outcome = Some(outcome);
+                self.stats.record_skipped(record);
+                self.record_history(record, None).await;
+            }
+            Ok(outcome) => {
+                record.outcome = Some(outcome);
+                record.log();
+                self.stats.record_success(record);
+                self.record_history(record, None).await;
+            }
+            Err(e) if Self::is_cancelled(&e) => {
+                warn!("Cancelled {}", record.source.display().yellow());
+                record.outcome = Some(JobOutcome::Cancelled);
+                self.stats.record_cancelled(record);
+                self.record_history(record, None).await;
+            }
+            Err(e) => {
+                record.outcome = Some(JobOutcome::Failed);
+                let message = match &record.log_file {
+                    Some(log) => format!("{} (ffmpeg log: {})", e, log.display()),
+                    None => e.to_string(),
+                };
+                error!(
+                    "Error processing file {}: {}",
+                    record.source.display().yellow(),
+                    message.red()
+                );
 
-        if let Some(video_codec) = &preset.video_codec {
-            cmd.arg("-c:v").arg(video_codec);
-        }
-        if let Some(audio_codec) = &preset.audio_codec {
-            cmd.arg("-c:a").arg(audio_codec);
+                match e.downcast_ref::<JobError>() {
+                    Some(JobError::PermissionDenied { .. }) => {
+                        self.stats.record_permission_denied(record, &message);
+                        self.record_history(record, Some(&e)).await;
+                    }
+                    _ => {
+                        self.stats.record_failure(record, &message);
+                        self.record_history(record, Some(&e)).await;
+                    }
+                }
+            }
         }
+        self.write_metrics();
+    }
 
-        if let Some(video_bitrate) = &preset.video_bitrate {
-            cmd.arg("-b:v").arg(video_bitrate);
+    /// Rewrite the `metrics_file`, if configured, with the totals of the jobs so far
+    fn write_metrics(&self) {
+        let Some(path) = self.config().metrics_file.clone() else {
+            return;
+        };
+        if self.is_dry_run() {
+            return;
         }
-        if let Some(audio_bitrate) = &preset.audio_bitrate {
-            cmd.arg("-b:a").arg(audio_bitrate);
+        if let Err(e) = metrics::write(&path, &self.stats.cpu_secs_by_preset()) {
+            warn!("{:#}", e);
         }
+    }
 
-        if let Some(pixel_format) = &preset.pixel_format {
-            cmd.arg("-pix_fmt").arg(pixel_format);
+    /// Append a finished job to the history file and database, those that are configured.
+    /// The database may wait on another sstc sharing it, so it's written on a blocking thread.
+    async fn record_history(&self, record: &JobRecord, error: Option<&anyhow::Error>) {
+        if self.is_dry_run() {
+            return;
         }
-
-        if let Some(scale) = &preset.scale {
-            cmd.arg("-vf").arg(format!("scale={}", scale));
+        let error = error.map(|e| format!("{:#}", e));
+        if let Some(history) = &self.history {
+            match history.append(record, error.clone()) {
+                Ok(id) => debug!("Recorded job {} for {}", id, record.source.display()),
+                Err(e) => warn!("Failed to record job history: {:#}", e),
+            }
         }
-
-        for (key, value) in &preset.extra_options {
-            cmd.arg(key).arg(value);
+        if let Some(history_db) = self.history_db.clone() {
+            let record = record.clone();
+            let written =
+                tokio::task::spawn_blocking(move || history_db.record(&record, error.as_deref()))
+                    .await
+                    .unwrap_or_else(|e| Err(anyhow!("History database write failed: {}", e)));
+            if let Err(e) = written {
+                warn!("Failed to record job history: {:#}", e);
+            }
         }
+    }
 
-        info!(
-            "Executing: {} {}",
-            cmd.get_program().to_str().unwrap().green(),
-            cmd.get_args()
-                .collect::<Vec<_>>()
-                .iter()
-                .map(|i| { String::from_utf8_lossy(i.as_encoded_bytes()) })
-                .collect::<Vec<_>>()
-                .join(" ")
-                .yellow()
-        );
+    async fn requeue_file(&self, item: QueuedFile) {
+        let file_path = item.path.clone();
+        let mut queue = self.file_queue.lock().await;
+        queue.push_back(item.priority, item);
+        self.save_queue();
+        drop(queue);
 
-        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or(anyhow!("Failed to open stdout"))?;
+        if let Err(e) = self.wake_queue_processor() {
+            error!("Failed to signal queue processor: {}", e);
+        } else {
+            info!(
+                "Requeued file for later processing: {}",
+                file_path.display()
+            );
+        }
+    }
 
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or(anyhow!("Failed to open stderr"))?;
+    /// Have the queue file rewritten, if one is in use. Only marks the queue as changed, which
+    /// is cheap enough to do under the queue lock; [`Transcoder::start_queue_writer`] does the
+    /// writing.
+    fn save_queue(&self) {
+        if self.queue_file.get().is_some() {
+            self.queue_version.send_modify(|version| *version += 1);
+        }
+    }
 
-        let stderr_reader = BufReader::new(stderr);
+    /// Write the queue file in the background whenever the queue changed, waiting
+    /// [`QUEUE_SAVE_DELAY`] first so a burst of changes, like a scan queueing thousands of
+    /// files, is written once rather than once each
+    fn start_queue_writer(&self, queue_file: QueueFile) {
+        let mut changes = self.queue_version.subscribe();
+        let this = self.clone();
         tokio::spawn(async move {
-            for line in stderr_reader.lines() {
-                if let Ok(line) = line {
-                    if !line.trim().is_empty() {
-                        error!("FFmpeg error: {}", line);
-                    }
+            while changes.changed().await.is_ok() {
+                tokio::time::sleep(QUEUE_SAVE_DELAY).await;
+                let version = *changes.borrow_and_update();
+                let files = this.queue_snapshot().await;
+                let queue_file = queue_file.clone();
+                let saved = tokio::task::spawn_blocking(move || {
+                    queue_file.save(files.iter().map(PathBuf::as_path))
+                })
+                .await
+                .unwrap_or_else(|e| Err(anyhow!("Queue file writer panicked: {}", e)));
+                if let Err(e) = saved {
+                    warn!("Failed to save the queue: {:#}", e);
                 }
+                this.queue_saved.send_replace(version);
             }
         });
+    }
 
-        let reader = BufReader::new(stdout);
-        let mut current_progress = HashMap::new();
+    /// The unfinished jobs and the queue, as they go into the queue file. Files queued with
+    /// explicit input settings are left out, as nothing would match them again on restore.
+    async fn queue_snapshot(&self) -> Vec<PathBuf> {
+        let queued: Vec<PathBuf> = self
+            .file_queue
+            .lock()
+            .await
+            .iter()
+            .filter(|item| item.input.is_none())
+            .map(|item| item.path.clone())
+            .collect();
+        let in_queue: HashSet<&PathBuf> = queued.iter().collect();
+        let unfinished: Vec<PathBuf> = self
+            .unfinished
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|path| !in_queue.contains(path))
+            .collect();
+        unfinished.into_iter().chain(queued).collect()
+    }
 
-        let bar = match ff {
-            Ok(format_info) => ProgressBar::new(format_info.duration as u64)
-                .with_style(
-                    ProgressStyle::with_template(
-                        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-                    )
-                    .unwrap(),
-                )
-                .with_message("video duration"),
+    /// Wait until every change to the queue so far is in the queue file, as before exiting
+    pub async fn flush_queue(&self) {
+        if self.queue_file.get().is_none() {
+            return;
+        }
+        let version = *self.queue_version.borrow();
+        let _ = self
+            .queue_saved
+            .subscribe()
+            .wait_for(|saved| *saved >= version)
+            .await;
+    }
+
+    /// Queue the files left in the queue file by the last run, then keep the file up to date
+    /// with the queue. Files that are gone or no longer belong to an input
+    /// are dropped; an unreadable queue file is logged and replaced.
+    ///
+    /// The files are queued in the background, ahead of the inputs' own scan where possible,
+    /// which skips those already queued.
+    pub fn restore_queue(&self) {
+        let Some(path) = self.config().queue_file.clone() else {
+            return;
+        };
+        let queue_file = QueueFile::new(path);
+        let files = match queue_file.load() {
+            Ok(files) => files,
             Err(e) => {
-                warn!("Could not get duration for {}: {}", input_path.display(), e);
-                ProgressBar::new_spinner()
-                    .with_style(
-                        ProgressStyle::with_template(
-                            "[{elapsed_precise}] {spinner} Processing... {msg}",
-                        )
-                        .unwrap(),
-                    )
-                    .with_message("transcoding")
+                warn!("Ignoring the saved queue: {:#}", e);
+                Vec::new()
             }
         };
+        if !files.is_empty() {
+            info!(
+                "Restoring {} queued file(s) from {}",
+                files.len().magenta(),
+                queue_file.path().display()
+            );
+        }
 
-        for line in reader.lines() {
-            let line = line?;
-            let line = line.trim();
-
-            if line.is_empty() {
-                continue;
+        let this = self.clone();
+        tokio::spawn(async move {
+            for file in files {
+                if !file.is_file() {
+                    info!("Not restoring {}, it is gone", file.display());
+                } else if !this.matches_input(&file) {
+                    info!(
+                        "Not restoring {}, it no longer matches any input",
+                        file.display()
+                    );
+                } else if let Err(e) = this.process_file(&file).await {
+                    error!("Failed to restore {}: {}", file.display(), e);
+                }
             }
+            // Saved from here on only, so a crash while restoring leaves the old queue intact
+            if this.queue_file.set(queue_file.clone()).is_ok() {
+                this.start_queue_writer(queue_file);
+                this.save_queue();
+            }
+        });
+    }
 
-            if let Some((key, value)) = line.split_once('=') {
-                current_progress.insert(key.to_string(), value.to_string());
-
-                if key == "progress" {
-                    let progress = FFmpegProgress::from_key_values(&current_progress);
+    /// The config new jobs start with
+    pub fn config(&self) -> Arc<Config> {
+        self.config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
 
-                    if let Some(ms) = progress.out_time_ms {
-                        let progress_t = (ms / 1_000_000) as u64;
-                        bar.set_position(progress_t);
-                    }
+    /// Swap in a reloaded config.
+    ///
+    /// Running jobs finish with the config they started with. Queued files are matched again
+    /// when they start; those no longer belonging to any input are dropped right away.
+    pub async fn reload(&self, config: Arc<Config>) {
+        priority::set_default(config.process_priority.unwrap_or_default());
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) = config;
 
-                    if progress.is_complete() {
-                        bar.finish();
-                        break;
-                    }
+        // Matching touches the filesystem, so it is done without holding up the queue
+        let queued: Vec<PathBuf> = self
+            .file_queue
+            .lock()
+            .await
+            .iter()
+            .filter(|item| item.input.is_none())
+            .map(|item| item.path.clone())
+            .collect();
+        let unmatched: HashSet<PathBuf> = queued
+            .into_iter()
+            .filter(|path| self.find_matching_input(path).is_none())
+            .collect();
 
-                    current_progress.clear();
+        let dropped = {
+            let mut queue = self.file_queue.lock().await;
+            let before = queue.len();
+            queue.retain(|item| {
+                let keep = item.input.is_some() || !unmatched.contains(&item.path);
+                if !keep {
+                    info!(
+                        "Dropping queued {}, it no longer matches any input",
+                        item.path.display()
+                    );
                 }
+                keep
+            });
+            self.save_queue();
+            before - queue.len()
+        };
+        // The queue may have shrunk or max_queue_size grown
+        self.queue_space.notify_waiters();
+
+        if dropped > 0 && self.is_idle().await {
+            self.schedule_drain_hook();
+            self.idle_notify.notify_waiters();
+        }
+    }
+
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    /// Called for every change to a `.sstc-ignore` marker file
+    pub fn invalidate_ignore_markers(&self, marker_path: &Path) {
+        self.ignore_markers.invalidate(marker_path);
+    }
+
+    /// Queue a file if it belongs to an input, waiting for room when the queue is full
+    pub async fn process_file(&self, file_path: &Path) -> Result<()> {
+        self.offer_file(file_path, QueueFullPolicy::Wait, false)
+            .await
+    }
+
+    /// Queue a file the watcher saw change, handling a full queue as `on_queue_full` says
+    pub async fn process_watched_file(&self, file_path: &Path) -> Result<()> {
+        let policy = self.config().on_queue_full;
+        self.offer_file(file_path, policy, false).await
+    }
+
+    /// Queue a file to be transcoded again even when its outputs exist or its content was seen
+    /// before, for `scan --force`; with `input`, using those settings instead of matching an
+    /// input. Existing outputs are only replaced once the new ones are verified.
+    pub async fn force_file(&self, file_path: &Path, input: Option<InputConfig>) -> Result<()> {
+        match input {
+            Some(input) => {
+                self.enqueue(file_path, Some(input), QueueFullPolicy::Wait, true)
+                    .await
+            }
+            None => {
+                self.offer_file(file_path, QueueFullPolicy::Wait, true)
+                    .await
             }
         }
+    }
 
-        let status = child.wait()?;
-        if !status.success() {
-            return Err(anyhow!("FFmpeg process failed with status: {}", status));
+    async fn offer_file(
+        &self,
+        file_path: &Path,
+        when_full: QueueFullPolicy,
+        forced: bool,
+    ) -> Result<()> {
+        if IgnoreMarkers::is_marker_file(file_path) {
+            self.invalidate_ignore_markers(file_path);
+            return Ok(());
         }
 
-        if !output_path.exists() {
-            return Err(anyhow!(
-                "Output file was not created: {}",
-                output_path.display()
-            ));
+        let Some(input_config) = self.find_matching_input(file_path) else {
+            debug!(
+                "No matching input configuration found for: {}",
+                file_path.display()
+            );
+            return Ok(());
+        };
+
+        if let Some(marker) = self.ignored_by_marker(file_path, &input_config) {
+            debug!(
+                "Ignoring {} because of marker {}",
+                file_path.display(),
+                marker
+            );
+            self.stats.record_ignored_by_marker();
+            return Ok(());
         }
 
-        let metadata = std::fs::metadata(output_path)?;
-        if metadata.len() == 0 {
-            return Err(anyhow!("Output file is empty: {}", output_path.display()));
+        // Checked again once the size has settled, before the job starts
+        if let Ok(metadata) = file_path.metadata() {
+            if let Some(reason) = input_config.outside_size_limits(metadata.len()) {
+                debug!("Skipping {}: {}", file_path.display(), reason);
+                return Ok(());
+            }
+            if let Ok(modified) = metadata.modified() {
+                match input_config.file_age(modified, std::time::SystemTime::now()) {
+                    FileAge::Ready => {}
+                    FileAge::TooOld(reason) => {
+                        debug!("Skipping {}: {}", file_path.display(), reason);
+                        return Ok(());
+                    }
+                    FileAge::TooNew(wait) => {
+                        self.offer_when_old_enough(file_path, wait, when_full, forced);
+                        return Ok(());
+                    }
+                }
+            }
         }
 
-        let input_size = match std::fs::metadata(input_path) {
-            Ok(metadata) => metadata.len(),
-            Err(e) => {
-                warn!("Could not get input file size: {}", e);
-                0
+        self.enqueue(file_path, None, when_full, forced).await
+    }
+
+    /// Offer a file that is younger than its input's min_age again once it has aged enough.
+    /// Checked afresh then, so a file modified in the meantime waits again.
+    fn offer_when_old_enough(
+        &self,
+        file_path: &Path,
+        wait: std::time::Duration,
+        when_full: QueueFullPolicy,
+        forced: bool,
+    ) {
+        if self
+            .aging_files
+            .insert(file_path.to_path_buf(), ())
+            .is_some()
+        {
+            return;
+        }
+        info!(
+            "Holding {} until it is old enough, checking again in {}",
+            file_path.display().cyan(),
+            HumanDuration::from_secs(wait.as_secs() + 1)
+        );
+
+        let this = self.clone();
+        let file_path = file_path.to_path_buf();
+        tokio::spawn(async move {
+            tokio::time::sleep(wait).await;
+            this.aging_files.remove(&file_path);
+            if !file_path.exists() {
+                debug!("{} is gone before it was old enough", file_path.display());
+            } else if let Err(e) = Box::pin(this.offer_file(&file_path, when_full, forced)).await {
+                error!("Failed to queue {}: {}", file_path.display(), e);
             }
-        };
+            // Nothing may have been queued, and a batch run waits for held files too
+            if this.is_idle().await {
+                this.idle_notify.notify_waiters();
+            }
+        });
+    }
 
-        if status.success() && output_path.exists() {
-            if let Ok(output_metadata) = std::fs::metadata(output_path) {
-                let output_size = output_metadata.len();
+    /// Whether the file belongs to one of the configured inputs
+    pub fn matches_input(&self, file_path: &Path) -> bool {
+        self.find_matching_input(file_path).is_some()
+    }
 
-                if input_size > 0 {
-                    let compression_ratio = input_size as f64 / output_size as f64;
-                    let size_reduction_percent =
-                        ((input_size - output_size) as f64 / input_size as f64) * 100.0;
+    /// The configured input the file belongs to, if any
+    pub fn matching_input(&self, file_path: &Path) -> Option<InputConfig> {
+        self.find_matching_input(file_path)
+    }
 
-                    info!(
-                        "Compression stats for {}:",
-                        output_path
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .green()
-                    );
-                    info!(
-                        "  Input size:  {}",
-                        ByteSize::b(input_size).display().si().to_string(),
-                    );
-                    info!(
-                        "  Output size: {}",
-                        ByteSize::b(output_size).display().si().to_string(),
-                    );
-                    info!(
-                        "  Ratio: {:.2}:1 ({:.1}% smaller)",
-                        compression_ratio.abs(),
-                        size_reduction_percent
-                    );
-                }
+    /// Queue a file with explicit input settings, skipping input matching entirely
+    pub async fn process_file_with_input(
+        &self,
+        file_path: &Path,
+        input_config: InputConfig,
+    ) -> Result<()> {
+        self.enqueue(file_path, Some(input_config), QueueFullPolicy::Wait, false)
+            .await
+    }
+
+    /// Number of files waiting in the queue
+    pub async fn queued_files(&self) -> usize {
+        self.file_queue.lock().await.len()
+    }
+
+    /// Number of files being worked on, including those waiting for companions
+    pub fn running_jobs(&self) -> usize {
+        self.active_jobs.len()
+    }
+
+    /// Sources waiting for companion files, with the extensions still missing
+    pub fn waiting_for_companions(&self) -> Vec<(PathBuf, Vec<String>)> {
+        let mut waiting: Vec<_> = self
+            .waiting
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        waiting.sort();
+        waiting
+    }
+
+    /// Print the ffmpeg commands of jobs instead of running them, for `--dry-run`. Jobs still
+    /// go through every check up to the encode, but write nothing: no outputs, directories,
+    /// claims, history or hashes, and queue hooks don't run.
+    pub fn enable_dry_run(&self) {
+        self.dry_run.store(true, Ordering::SeqCst);
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::SeqCst)
+    }
+
+    /// Stop starting queued files; running jobs carry on until they finish or are killed,
+    /// those a pause suspended included
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.resume_suspended_jobs();
+    }
+
+    /// Stop starting queued files until [`Transcoder::resume`], while new files are still found
+    /// and queued. With `suspend_running` the ffmpeg of running jobs is stopped in place too;
+    /// otherwise they run to their end. Returns false when already paused.
+    pub async fn pause(&self) -> bool {
+        if self.paused.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let suspended = match self.config().suspend_running {
+            true => self.suspend_running_jobs(),
+            false => 0,
+        };
+        info!(
+            "Paused, holding {} queued file(s){}",
+            self.queued_files().await.magenta(),
+            match suspended {
+                0 => String::new(),
+                n => format!(" and {} suspended job(s)", n),
+            }
+        );
+        true
+    }
+
+    /// Start queued files again after [`Transcoder::pause`], and let suspended jobs carry on.
+    /// Returns false when not paused.
+    pub async fn resume(&self) -> bool {
+        if !self.paused.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        let resumed = self.resume_suspended_jobs();
+        info!(
+            "Resumed, {} queued file(s) waiting{}",
+            self.queued_files().await.magenta(),
+            match resumed {
+                0 => String::new(),
+                n => format!(", {} suspended job(s) carry on", n),
             }
+        );
+        if let Err(e) = self.wake_queue_processor() {
+            error!("Failed to signal queue processor: {}", e);
         }
+        true
+    }
 
-        Ok(())
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
     }
 
-    fn get_output_path_for_file(&self, file_path: &Path) -> Result<PathBuf> {
-        let Some(input_config) = self.find_matching_input(file_path) else {
-            return Err(anyhow!("No matching input configuration found"));
-        };
+    /// Stop the ffmpeg of every running job, returning how many jobs there were
+    #[cfg(unix)]
+    fn suspend_running_jobs(&self) -> usize {
+        self.job_controls
+            .iter()
+            .map(|entry| entry.value().suspend())
+            .count()
+    }
 
-        let output = self.get_output(&input_config.output)?;
-        self.create_output_path(file_path, &output)
+    #[cfg(not(unix))]
+    fn suspend_running_jobs(&self) -> usize {
+        warn!("suspend_running is only supported on Unix, running jobs carry on");
+        0
     }
 
-    pub fn clone(&self) -> Self {
-        Self {
-            config: self.config.clone(),
-            active_jobs: self.active_jobs.clone(),
-            job_semaphore: self.job_semaphore.clone(),
-            file_queue: self.file_queue.clone(),
-            queue_tx: self.queue_tx.clone(),
-            queue_rx: self.queue_rx.clone(),
+    /// Continue the ffmpeg of every suspended job, returning how many there were
+    #[cfg(unix)]
+    fn resume_suspended_jobs(&self) -> usize {
+        self.job_controls
+            .iter()
+            .filter(|entry| entry.value().resume())
+            .count()
+    }
+
+    #[cfg(not(unix))]
+    fn resume_suspended_jobs(&self) -> usize {
+        0
+    }
+
+    /// Sources of the jobs that are running
+    pub fn running_sources(&self) -> Vec<PathBuf> {
+        self.job_controls
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Wait until every running job has ended
+    pub async fn wait_for_running_jobs(&self) {
+        loop {
+            let notified = self.idle_notify.notified();
+            if self.job_controls.is_empty() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Give the running jobs `grace` to finish after [`Transcoder::shut_down`], killing them
+    /// when it runs out or `stop_now` completes first. Returns the sources of the jobs killed,
+    /// once they cleaned up or [`KILL_WAIT`] ran out.
+    pub async fn drain_running_jobs(
+        &self,
+        grace: std::time::Duration,
+        stop_now: impl std::future::Future<Output = ()>,
+    ) -> Vec<PathBuf> {
+        let interrupted = tokio::select! {
+            _ = self.wait_for_running_jobs() => Vec::new(),
+            _ = tokio::time::sleep(grace) => {
+                warn!("Grace period is over, stopping the running jobs");
+                self.kill_running_jobs()
+            }
+            _ = stop_now => {
+                warn!("Stopping the running jobs");
+                self.kill_running_jobs()
+            }
+        };
+
+        // ffmpeg is gone, but a job may still be in a check that can't be cut short
+        if !interrupted.is_empty()
+            && tokio::time::timeout(KILL_WAIT, self.wait_for_running_jobs())
+                .await
+                .is_err()
+        {
+            warn!("Some jobs did not stop in time, their outputs may be left behind");
+        }
+        interrupted
+    }
+
+    /// Kill the ffmpeg of every running job, returning their sources. The jobs fail as
+    /// interrupted and clean up their incomplete outputs.
+    pub fn kill_running_jobs(&self) -> Vec<PathBuf> {
+        self.job_controls
+            .iter()
+            .map(|entry| {
+                entry.value().kill();
+                entry.key().clone()
+            })
+            .collect()
+    }
+
+    /// Cancel the job of a source: drop it from the queue while it is still waiting, or kill
+    /// its ffmpeg once it runs. A cancelled job removes its incomplete output and is recorded
+    /// as cancelled rather than failed; it isn't retried, but a rescan or a change to the
+    /// source queues it again.
+    pub async fn cancel(&self, source: &Path) -> CancelResult {
+        let is_source = |path: &Path| path == source || in_place::is_same_file(path, source);
+
+        let dequeued = {
+            let mut queue = self.file_queue.lock().await;
+            let before = queue.len();
+            queue.retain(|item| !is_source(&item.path));
+            if queue.len() < before {
+                self.save_queue();
+            }
+            queue.len() < before
+        };
+        // A retry waiting for its delay finds its state gone and gives up
+        let mut retrying = false;
+        self.retries.retain(|path, state| {
+            let matched = is_source(path);
+            retrying |= matched && state.pending;
+            !matched
+        });
+        if dequeued || retrying {
+            info!("Cancelled queued {}", source.display().yellow());
+            self.queue_space.notify_waiters();
+            if self.is_idle().await {
+                self.schedule_drain_hook();
+                self.idle_notify.notify_waiters();
+            }
+            return CancelResult::Dequeued;
+        }
+
+        let running = self
+            .job_controls
+            .iter()
+            .find(|entry| is_source(entry.key()))
+            .map(|entry| entry.value().clone());
+        match running {
+            Some(control) => {
+                info!(
+                    "Cancelling the running job of {}",
+                    source.display().yellow()
+                );
+                control.cancel();
+                CancelResult::Stopped
+            }
+            None => CancelResult::NotFound,
+        }
+    }
+
+    fn register_job(&self, source: &Path) -> RunningJob {
+        self.job_controls
+            .insert(source.to_path_buf(), Arc::new(JobControl::default()));
+        RunningJob {
+            controls: self.job_controls.clone(),
+            source: source.to_path_buf(),
+            finished: self.idle_notify.clone(),
+        }
+    }
+
+    /// Whether files were dropped because the queue was full and there is room for them now;
+    /// clears the mark so they are rescanned once
+    pub async fn take_dropped_files(&self) -> bool {
+        if self.queue_is_full(self.queued_files().await) {
+            return false;
+        }
+        self.dropped_while_full.swap(false, Ordering::SeqCst)
+    }
+
+    fn queue_is_full(&self, queued: usize) -> bool {
+        self.config()
+            .max_queue_size
+            .is_some_and(|max| queued >= max)
+    }
+
+    async fn enqueue(
+        &self,
+        file_path: &Path,
+        input: Option<InputConfig>,
+        when_full: QueueFullPolicy,
+        forced: bool,
+    ) -> Result<()> {
+        if self.replaced_files.is_unchanged(file_path) {
+            debug!(
+                "Skipping {}, it was just replaced in place",
+                file_path.display()
+            );
+            return Ok(());
+        }
+
+        if !self.active_jobs.contains_key(file_path) {
+            let mut queue = loop {
+                let space = self.queue_space.notified();
+                let queue = self.file_queue.lock().await;
+                if !self.queue_is_full(queue.len())
+                    || queue.iter().any(|item| item.path == file_path)
+                {
+                    break queue;
+                }
+                drop(queue);
+
+                match when_full {
+                    QueueFullPolicy::Wait => {
+                        debug!("Queue is full, waiting to queue {}", file_path.display());
+                        space.await;
+                    }
+                    QueueFullPolicy::Drop => {
+                        // Once per rescan; every file after the first only at debug level
+                        if !self.dropped_while_full.swap(true, Ordering::SeqCst) {
+                            warn!(
+                                "Queue is full, leaving {} and further new files for a rescan once there is room",
+                                file_path.display().yellow()
+                            );
+                        } else {
+                            debug!("Queue is full, dropped {}", file_path.display());
+                        }
+                        return Ok(());
+                    }
+                }
+            };
+
+            let already_queued = queue.iter().any(|item| item.path == file_path);
+            if !already_queued {
+                let priority = input
+                    .clone()
+                    .or_else(|| self.find_matching_input(file_path))
+                    .and_then(|input| input.priority)
+                    .unwrap_or_default();
+                debug!(
+                    "Adding file to queue with priority {}: {}",
+                    priority,
+                    file_path.display()
+                );
+                let metadata = std::fs::metadata(file_path).ok();
+                queue.push_back(
+                    priority,
+                    QueuedFile {
+                        path: file_path.to_path_buf(),
+                        input,
+                        priority,
+                        companions_settled: false,
+                        missing_companions: Vec::new(),
+                        size: metadata.as_ref().map_or(0, |m| m.len()),
+                        modified: metadata.and_then(|m| m.modified().ok()),
+                        skips: 0,
+                        forced,
+                    },
+                );
+                self.save_queue();
+
+                drop(queue);
+                self.wake_queue_processor()
+                    .context("Failed to signal queue processor")?;
+                info!(
+                    "File queued for processing: {}",
+                    file_path.display().green()
+                );
+            } else {
+                debug!("File already in queue: {}", file_path.display());
+            }
+        } else {
+            info!("File already being processed: {}", file_path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Transcode a file right away, bypassing the queue but not the job semaphore.
+    ///
+    /// Concurrent calls for the same path share a single job and all receive its result.
+    pub async fn transcode_now(
+        &self,
+        file_path: &Path,
+        preset_override: Option<&str>,
+    ) -> JobResult {
+        self.express(file_path, preset_override)
+            .map_err(Arc::new)?
+            .result()
+            .await
+    }
+
+    /// Start an express job for a file, or join the one in flight for it. Joining takes the
+    /// same preset override the job was started with, since the job runs only once.
+    pub fn express(&self, file_path: &Path, preset_override: Option<&str>) -> Result<ExpressJob> {
+        let file_path = file_path.to_path_buf();
+
+        let (tx, id, rx) = match self.express_jobs.entry(file_path.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => {
+                let running = entry.get();
+                if running.preset.as_deref() != preset_override {
+                    let describe = |preset: Option<&str>| match preset {
+                        Some(preset) => format!("preset {}", preset),
+                        None => "the input's presets".to_string(),
+                    };
+                    return Err(anyhow!(
+                        "{} is already being transcoded with {}, not {}",
+                        file_path.display(),
+                        describe(running.preset.as_deref()),
+                        describe(preset_override)
+                    ));
+                }
+                info!(
+                    "Joining in-flight express job for {}",
+                    file_path.display().green()
+                );
+                return Ok(ExpressJob {
+                    id: running.id,
+                    result: running.result.clone(),
+                });
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                let id = self.next_express_id.fetch_add(1, Ordering::SeqCst) + 1;
+                let (tx, rx) = watch::channel(None);
+                entry.insert(ExpressEntry {
+                    id,
+                    preset: preset_override.map(str::to_string),
+                    result: rx.clone(),
+                });
+                (tx, id, rx)
+            }
+        };
+
+        let this = self.clone();
+        let preset_override = preset_override.map(str::to_string);
+        // The job runs in its own task so it completes even if the caller goes away
+        tokio::spawn(async move {
+            let result = this
+                .run_express_job(&file_path, preset_override.as_deref())
+                .await
+                .map_err(Arc::new);
+            // Kept before the job is let go of, so asking after it never finds neither
+            {
+                let mut results = this
+                    .express_results
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                if results.len() == EXPRESS_RESULTS_KEPT {
+                    results.pop_front();
+                }
+                results.push_back((id, file_path.clone(), result.clone()));
+            }
+            this.express_jobs.remove(&file_path);
+            let _ = tx.send(Some(result));
+        });
+
+        Ok(ExpressJob { id, result: rx })
+    }
+
+    /// How the express job `id` is doing; nothing for an unknown job or one finished too long
+    /// ago
+    pub fn express_status(&self, id: u64) -> Option<ExpressStatus> {
+        let running = self
+            .express_jobs
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.key().clone());
+        if let Some(source) = running {
+            return Some(ExpressStatus::Running { source });
+        }
+        self.express_results
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|(finished, _, _)| *finished == id)
+            .map(|(_, source, result)| ExpressStatus::Finished {
+                source: source.clone(),
+                result: result.clone(),
+            })
+    }
+
+    async fn run_express_job(
+        &self,
+        file_path: &Path,
+        preset_override: Option<&str>,
+    ) -> Result<Vec<JobRecord>> {
+        let Some(mut input_config) = self.find_matching_input(file_path) else {
+            return Err(anyhow!(
+                "No matching input configuration found for: {}",
+                file_path.display()
+            ));
+        };
+
+        if let Some(preset) = preset_override {
+            Self::get_preset(&self.config(), preset)?;
+            // The override applies to every target of the input
+            input_config.targets = input_config
+                .targets()
+                .into_iter()
+                .map(|target| TargetConfig {
+                    preset: preset.to_string(),
+                    ..target
+                })
+                .collect();
+        }
+
+        if self
+            .active_jobs
+            .insert(file_path.to_path_buf(), ())
+            .is_some()
+        {
+            return Err(anyhow!(
+                "File already being processed: {}",
+                file_path.display()
+            ));
+        }
+        {
+            let mut queue = self.file_queue.lock().await;
+            queue.retain(|item| item.path != file_path);
+            self.save_queue();
+        }
+        self.queue_space.notify_waiters();
+
+        let result = self.run_express_job_locked(file_path, &input_config).await;
+
+        self.active_jobs.remove(file_path);
+        if self.is_idle().await {
+            self.schedule_drain_hook();
+        }
+
+        result
+    }
+
+    async fn run_express_job_locked(
+        &self,
+        file_path: &Path,
+        input_config: &InputConfig,
+    ) -> Result<Vec<JobRecord>> {
+        if !self.hooks.activate().await {
+            return Err(anyhow!("Queue is held by a failed active hook"));
+        }
+
+        let _permit = self
+            .job_semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .context("Failed to acquire semaphore")?;
+
+        let presets: Vec<String> = input_config
+            .targets()
+            .into_iter()
+            .map(|target| target.preset)
+            .collect();
+        info!(
+            "Express transcoding {} with preset {}",
+            file_path.display().green(),
+            presets.join(", ").cyan()
+        );
+
+        let span = telemetry::job_span(file_path);
+        let jobs = self
+            .process_file_internal(file_path, input_config, false)
+            .instrument(span.clone())
+            .await;
+        let jobs = match jobs {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                let mut record = JobRecord::new(file_path.to_path_buf());
+                record.outcome = Some(JobOutcome::Failed);
+                telemetry::finish_job_span(&span, std::slice::from_ref(&record));
+                self.record_history(&record, Some(&e)).await;
+                return Err(e);
+            }
+        };
+
+        let mut records = Vec::new();
+        let mut first_error = None;
+        for (mut record, result) in jobs {
+            record.outcome = Some(match &result {
+                Ok(outcome) => outcome.clone(),
+                Err(e) if Self::is_cancelled(e) => JobOutcome::Cancelled,
+                Err(_) => JobOutcome::Failed,
+            });
+            self.record_history(&record, result.as_ref().err()).await;
+            match result {
+                Ok(JobOutcome::Transcoded) => record.log(),
+                Ok(outcome) => info!("Skipped {}: {}", file_path.display(), outcome.yellow()),
+                // Every target runs; the caller gets the first failure
+                Err(e) if first_error.is_some() => error!(
+                    "Target {} failed: {}",
+                    record.preset.as_deref().unwrap_or("-").yellow(),
+                    e.red()
+                ),
+                Err(e) => first_error = Some(e),
+            }
+            records.push(record);
+        }
+        telemetry::finish_job_span(&span, &records);
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(records),
+        }
+    }
+
+    /// Check a source once, then transcode it for each target of its input. The outer error
+    /// stops the whole source; a failing target doesn't keep the others from running.
+    async fn process_file_internal(
+        &self,
+        file_path: &Path,
+        input_config: &InputConfig,
+        forced: bool,
+    ) -> Result<Vec<TargetJob>> {
+        let config = self.config();
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(JobError::Interrupted.into());
+        }
+        let _running = self.register_job(file_path);
+        file_check::check_readable(file_path)?;
+
+        // Held until the job ends so other instances sharing the inputs leave the source alone
+        let _source_claim = match &config.distributed {
+            Some(settings) if !self.is_dry_run() => {
+                match SourceClaim::acquire(settings, file_path, &input_config.path)? {
+                    ClaimAttempt::Claimed(claim) => Some(claim),
+                    ClaimAttempt::Taken(owner) => {
+                        return Err(JobError::ClaimedElsewhere {
+                            source: file_path.to_path_buf(),
+                            host: owner.host,
+                            pid: owner.pid,
+                        }
+                        .into());
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let stability =
+            StabilityConfig::resolve(input_config.stability.as_ref(), config.stability.as_ref());
+        // A dry run only looks at files, one still being copied is listed as it is now
+        let stable = self.is_dry_run()
+            || file_check::wait_for_stable_size(file_path, stability)
+                .instrument(info_span!("stability_wait"))
+                .await?;
+        if !stable {
+            return Err(JobError::NotReady(file_path.to_path_buf()).into());
+        }
+        // A source still being copied can take long to settle, and may be cancelled meanwhile
+        if self
+            .job_controls
+            .get(file_path)
+            .is_some_and(|control| control.is_cancelled())
+        {
+            return Err(JobError::Cancelled.into());
+        }
+
+        // Skips decided by the source alone are reported once rather than per target
+        let skipped = |outcome| Ok(vec![(JobRecord::new(file_path.to_path_buf()), Ok(outcome))]);
+
+        // The file may still have been growing when it was queued
+        let size = std::fs::metadata(file_path)
+            .context(format!("Failed to stat {}", file_path.display()))?
+            .len();
+        if let Some(reason) = input_config.outside_size_limits(size) {
+            return skipped(JobOutcome::SkippedFilter { reason });
+        }
+
+        // Only hashed once the size has settled, so a half-copied file is never fingerprinted
+        let content_hash = match &self.hash_store {
+            Some(store) => {
+                let path = file_path.to_path_buf();
+                let hash =
+                    tokio::task::spawn_blocking(move || hash_store::fingerprint(&path)).await??;
+                // A source matching its own earlier run is left to the output checks
+                if let Some(earlier) = store
+                    .lookup(&hash)
+                    .filter(|earlier| earlier.source != file_path)
+                    .filter(|_| !forced)
+                {
+                    return skipped(JobOutcome::SkippedDuplicate {
+                        original: earlier.source,
+                    });
+                }
+                Some(hash)
+            }
+            None => None,
+        };
+
+        let Some(probe) = file_check::probe_valid(file_path).await else {
+            return Err(JobError::NotReady(file_path.to_path_buf()).into());
+        };
+
+        if let Some(min) = input_config.min_duration {
+            let duration = probe.duration();
+            if duration < min.0.as_secs_f32() {
+                let reason = format!("{:.1}s is shorter than min_duration {}", duration, min);
+                return skipped(JobOutcome::SkippedFilter { reason });
+            }
+        }
+
+        if let Some(reason) = Self::outside_recording_window(file_path, input_config, &probe)? {
+            return skipped(JobOutcome::SkippedFilter { reason });
+        }
+
+        if let Some(codec) = Self::skipped_video_codec(input_config, &probe) {
+            match input_config.on_match {
+                CodecMatchAction::Skip => {
+                    info!(
+                        "Source {} is already {}, skipping it",
+                        file_path.display(),
+                        codec.cyan()
+                    );
+                    return skipped(JobOutcome::SkippedCodec { codec });
+                }
+                CodecMatchAction::Remux => info!(
+                    "Source {} is already {}, remuxing it without re-encoding",
+                    file_path.display(),
+                    codec.cyan()
+                ),
+            }
+        }
+
+        if let Some(rules) = &input_config.normalize {
+            let violations = compliance::violations(rules, &probe);
+            if violations.is_empty() {
+                return skipped(JobOutcome::SkippedCompliant);
+            }
+            info!(
+                "Normalizing {}: {}",
+                file_path.display(),
+                violations.join("; ")
+            );
+        }
+
+        if let Some(video) = probe.video_stream() {
+            debug!(
+                "Probed {}: {} {}x{}, {:.1}s",
+                file_path.display(),
+                video.codec_name.as_deref().unwrap_or("unknown"),
+                video.width.unwrap_or_default(),
+                video.height.unwrap_or_default(),
+                probe.duration()
+            );
+        }
+
+        let job = SourceJob {
+            config: &config,
+            source: file_path,
+            probe: &probe,
+            forced,
+        };
+        let targets = input_config.targets();
+        let mut jobs = Vec::new();
+        for (index, target) in targets.iter().enumerate() {
+            // Only inputs with several targets need telling them apart
+            let label = if targets.len() > 1 {
+                let label = format!("[{}/{} {}]", index + 1, targets.len(), target.label());
+                info!("Target {} for {}", label.cyan(), file_path.display());
+                label
+            } else {
+                String::new()
+            };
+
+            let mut record = JobRecord::new(file_path.to_path_buf());
+            let span = telemetry::target_span(target);
+            let result = self
+                .transcode_target(&job, input_config, target, &label, &mut record)
+                .instrument(span.clone())
+                .await;
+            telemetry::finish_target_span(&span, &record, &result);
+            jobs.push((record, result));
+        }
+
+        if let (Some(store), Some(hash)) = (&self.hash_store, content_hash) {
+            if !self.is_dry_run() && jobs.iter().all(|(_, result)| result.is_ok()) {
+                if let Err(e) = store.record(hash, file_path) {
+                    warn!(
+                        "Failed to record the content hash of {}: {:#}",
+                        file_path.display(),
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    /// Transcode a checked source for one target of its input
+    async fn transcode_target(
+        &self,
+        job: &SourceJob<'_>,
+        input_config: &InputConfig,
+        target: &TargetConfig,
+        label: &str,
+        record: &mut JobRecord,
+    ) -> Result<JobOutcome> {
+        let SourceJob {
+            config,
+            source: file_path,
+            probe,
+            forced,
+        } = *job;
+        record.preset = Some(target.preset.clone());
+        let mut preset = match Self::skipped_video_codec(input_config, probe) {
+            // Only reached with `on_match: remux`, skips end the job earlier
+            Some(_) => PresetConfig::remux(),
+            None => Self::get_preset(config, &target.preset)?,
+        };
+        let output = Self::get_output(config, &target.output)?;
+        // The configured preset picks the container even when remuxing or falling back, so the
+        // output path of a source stays the same
+        let container = Self::get_preset(config, &target.preset)?
+            .container_in(&output)
+            .to_string();
+
+        let mut output_path = self.create_output_path(
+            file_path,
+            input_config,
+            &target.preset,
+            &container,
+            &output,
+            probe,
+        )?;
+        let in_place = in_place::is_same_file(file_path, &output_path);
+        if let Some(transcoded_at) = self.transcoded_before(job, &output_path).await {
+            record.output = Some(output_path);
+            return Ok(JobOutcome::SkippedInHistory { transcoded_at });
+        }
+        // Set when an existing output is encoded anew next to itself and renamed over it
+        let mut replaces_existing = false;
+
+        // Claim before the exists check so two jobs resolving to the same output can't both pass it
+        let _claim = if output.on_existing == ExistingOutputPolicy::Rename && !in_place {
+            let (path, claim) = self.claim_unused_output(&output_path, file_path)?;
+            if path != output_path {
+                info!(
+                    "Output {} is taken, writing {} instead (on_existing: rename)",
+                    output_path.display(),
+                    path.display().cyan()
+                );
+                output_path = path;
+            }
+            claim
+        } else {
+            OutputClaim::acquire(&self.claimed_outputs, &output_path, file_path)?
+        };
+
+        // In-place jobs encode next to the source and only swap it in after verification
+        let encode_path = if in_place {
+            match output.on_same_file {
+                SameFilePolicy::Error => {
+                    return Err(anyhow!(
+                        "Output {} is the input file itself; set on_same_file: replace to transcode in place",
+                        output_path.display()
+                    ));
+                }
+                SameFilePolicy::Replace => self.replacement_path(&output_path),
+            }
+        } else if output_path.exists() && forced {
+            info!(
+                "Transcoding {} again over its existing output {}, which is replaced once the new one is verified (--force)",
+                file_path.display(),
+                output_path.display().yellow()
+            );
+            record.overwrote_existing = true;
+            replaces_existing = true;
+            self.replacement_path(&output_path)
+        } else if output_path.exists() {
+            match output.on_existing {
+                ExistingOutputPolicy::Skip
+                    if output.if_source_newer == SourceNewerPolicy::Retranscode
+                        && Self::is_source_newer(file_path, &output_path) =>
+                {
+                    record.overwrote_existing = true;
+                    replaces_existing = true;
+                    self.replacement_path(&output_path)
+                }
+                ExistingOutputPolicy::Skip | ExistingOutputPolicy::Rename => {
+                    record.output = Some(output_path);
+                    return Ok(JobOutcome::SkippedExisting);
+                }
+                ExistingOutputPolicy::Error => {
+                    return Err(anyhow!(
+                        "Output {} already exists (on_existing: error)",
+                        output_path.display()
+                    ));
+                }
+                ExistingOutputPolicy::Overwrite => {
+                    info!(
+                        "Overwriting existing output {} (on_existing: overwrite)",
+                        output_path.display().yellow()
+                    );
+                    record.overwrote_existing = true;
+                    output_path.clone()
+                }
+            }
+        } else {
+            output_path.clone()
+        };
+
+        if let Some(parent) = output_path.parent() {
+            if !output.create_subdirs && !parent.is_dir() {
+                return Err(anyhow!(
+                    "Output directory {} does not exist and create_subdirs is disabled",
+                    parent.display()
+                ));
+            }
+            if !self.is_dry_run() {
+                // Symlinks inside the output root can still lead out of it, so the check goes
+                // first and nothing gets created on their far side
+                Self::check_inside_output_root(&output.path, parent)?;
+                file_check::ensure_writable_dir(parent)?;
+            }
+        }
+
+        let input_size = std::fs::metadata(file_path).ok().map(|m| m.len());
+
+        record.output = Some(output_path.clone());
+
+        if !self.is_dry_run() {
+            record.log_file = Self::create_job_log(config, file_path);
+        }
+
+        let started = std::time::Instant::now();
+        let mut result = self
+            .transcode_file(job, &encode_path, &preset, label, record)
+            .instrument(info_span!("encode", preset = %target.preset))
+            .await;
+        if self.is_dry_run() {
+            return result.map(|_| JobOutcome::WouldTranscode);
+        }
+
+        let too_large = match &result {
+            Err(e) => match e.downcast_ref::<JobError>() {
+                Some(JobError::OutputTooLarge {
+                    projected,
+                    source_size,
+                }) => Some((*projected, *source_size)),
+                _ => None,
+            },
+            Ok(_) => None,
+        };
+        if let Some((projected_size, source_size)) = too_large {
+            self.remove_incomplete_output(&encode_path);
+            if preset.if_larger_than_source == Some(LargerOutputAction::Remux) {
+                warn!(
+                    "Output of {} is outgrowing the source, remuxing it instead (if_larger_than_source: remux)",
+                    file_path.display().yellow()
+                );
+                record.warnings.push(format!(
+                    "Remuxed, the encode was projected at {} bytes for a {} byte source",
+                    projected_size, source_size
+                ));
+                preset = PresetConfig::remux();
+                result = self
+                    .transcode_file(job, &encode_path, &preset, label, record)
+                    .instrument(info_span!("encode", preset = "remux"))
+                    .await;
+            } else {
+                info!(
+                    "Keeping the original {}, its output was outgrowing it (if_larger_than_source: keep_original)",
+                    file_path.display().yellow()
+                );
+                record.elapsed = Some(started.elapsed());
+                record.input_size = input_size;
+                record.output = None;
+                return Ok(JobOutcome::KeptOriginal {
+                    projected_size,
+                    source_size,
+                });
+            }
+        }
+
+        if let (Err(e), Some(fallback_name)) = (&result, &target.fallback_preset) {
+            if Self::should_use_fallback(e) {
+                warn!(
+                    "Preset {} failed for {}, retrying with fallback preset {}: {}",
+                    target.preset.yellow(),
+                    file_path.display(),
+                    fallback_name.cyan(),
+                    e
+                );
+                self.remove_incomplete_output(&encode_path);
+
+                let fallback = Self::get_preset(config, fallback_name)?;
+                record.preset = Some(fallback_name.clone());
+                record.used_fallback = true;
+
+                result = self
+                    .transcode_file(job, &encode_path, &fallback, label, record)
+                    .instrument(info_span!("encode", preset = %fallback_name))
+                    .await;
+                preset = fallback;
+            }
+        }
+
+        record.elapsed = Some(started.elapsed());
+
+        if let Ok(frames) = &result {
+            let verify = info_span!("verify");
+            record.frames = Some(frames.clone());
+            record.scale = Some(ScaleDecision::new(&preset, probe))
+                .filter(|decision| *decision != ScaleDecision::Uncapped);
+            if let Err(e) = verify.in_scope(|| Self::check_dropped_frames(frames, &preset, probe)) {
+                result = Err(e);
+            } else if let Err(e) = Self::check_output_duration(&encode_path, &preset, probe)
+                .instrument(verify.clone())
+                .await
+            {
+                result = Err(e);
+            } else if in_place {
+                if let Err(e) = in_place::verify_replacement(&encode_path)
+                    .instrument(verify)
+                    .await
+                {
+                    result = Err(e);
+                }
+            }
+        }
+
+        if in_place && result.is_ok() {
+            let _post_process = info_span!("post_process").entered();
+            match in_place::replace_source(file_path, &encode_path, output.trash_dir.as_deref()) {
+                Ok(()) => self.replaced_files.record(file_path),
+                Err(e) => result = Err(e),
+            }
+        } else if replaces_existing && result.is_ok() {
+            // The rename leaves the output newer than the source, so it isn't redone again
+            if let Err(e) = std::fs::rename(&encode_path, &output_path) {
+                result = Err(anyhow!(
+                    "Failed to replace {} with its new version: {}",
+                    output_path.display(),
+                    e
+                ));
+            }
+        }
+
+        match result {
+            Ok(_) => {
+                debug!(
+                    "Successfully transcoded: {} -> {}",
+                    file_path.display(),
+                    output_path.display()
+                );
+                record.input_size = input_size;
+                record.output_size = std::fs::metadata(&output_path).ok().map(|m| m.len());
+                if let Some(thumbnail) = output.thumbnail.as_ref().filter(|t| t.enabled) {
+                    self.write_thumbnail(config, &output_path, thumbnail, &preset, probe)
+                        .instrument(info_span!("post_process"))
+                        .await;
+                }
+            }
+            Err(e) => {
+                if !Self::is_cancelled(&e) {
+                    error!(
+                        "Failed to transcode {}: {}",
+                        file_path.display().yellow(),
+                        e.red()
+                    );
+                }
+                // With -n ffmpeg refuses to touch an output that appeared behind our back.
+                // Posters are only written once a job succeeded, so a `.jpg` next to the
+                // output isn't this job's and stays.
+                if !Self::is_output_collision(&e) {
+                    self.remove_incomplete_output(&encode_path);
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(JobOutcome::Transcoded)
+    }
+
+    /// Start the log of a job in `job_logs_dir`, pruning old logs first. A log that can't be
+    /// created only costs the log.
+    fn create_job_log(config: &Config, source: &Path) -> Option<PathBuf> {
+        let dir = config.job_logs_dir.as_ref()?;
+        let max_count = config
+            .job_logs_max_count
+            .unwrap_or(job_log::DEFAULT_MAX_COUNT);
+        let max_age = config.job_logs_max_age.map(Into::into);
+        // One fewer than the limit, making room for the new log
+        match job_log::prune(dir, max_count.saturating_sub(1), max_age) {
+            Ok(0) => {}
+            Ok(removed) => debug!("Removed {} old job log(s) from {}", removed, dir.display()),
+            Err(e) => warn!("Failed to prune job logs: {:#}", e),
+        }
+        match job_log::create(dir, source) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!("{:#}", e);
+                None
+            }
+        }
+    }
+
+    /// When `skip_if_in_history` finds the source, at its current size, transcoded to
+    /// `output_path` before. A database that can't be read only costs the check.
+    async fn transcoded_before(&self, job: &SourceJob<'_>, output_path: &Path) -> Option<u64> {
+        let SourceJob {
+            config,
+            source,
+            forced,
+            ..
+        } = *job;
+        if !config.skip_if_in_history || forced {
+            return None;
+        }
+        let history_db = self.history_db.clone()?;
+        let size = std::fs::metadata(source).ok()?.len();
+        let (source_path, output) = (source.to_path_buf(), output_path.to_path_buf());
+        let found =
+            tokio::task::spawn_blocking(move || history_db.transcoded(&source_path, size, &output))
+                .await
+                .unwrap_or_else(|e| Err(anyhow!("History database lookup failed: {}", e)));
+        match found {
+            Ok(past) => past.map(|past| past.finished_at),
+            Err(e) => {
+                warn!(
+                    "Failed to look up {} in the job history: {:#}",
+                    source.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Temp sibling a replacement of `output_path` is encoded to before it's swapped in
+    fn replacement_path(&self, output_path: &Path) -> PathBuf {
+        let temp_path = artifacts::temp_path_for(output_path);
+        // Leftover from an interrupted replace; it's ours and would make ffmpeg refuse
+        self.remove_incomplete_output(&temp_path);
+        temp_path
+    }
+
+    /// Whether the source was modified more than [`SOURCE_NEWER_MARGIN`] after the output,
+    /// logging both times when it was
+    fn is_source_newer(source: &Path, output: &Path) -> bool {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let (Some(source_time), Some(output_time)) = (modified(source), modified(output)) else {
+            return false;
+        };
+        let newer = source_time
+            .duration_since(output_time)
+            .is_ok_and(|by| by > SOURCE_NEWER_MARGIN);
+        if newer {
+            let format = |time| chrono::DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S");
+            info!(
+                "Source {} (modified {}) is newer than its output {} (modified {}), transcoding it again (if_source_newer: retranscode)",
+                source.display(),
+                format(source_time),
+                output.display().yellow(),
+                format(output_time)
+            );
+        }
+        newer
+    }
+
+    /// Black bars of the source for `auto_crop`. The analysis runs as part of the job, on its
+    /// job slot, and a failed one only costs the crop.
+    async fn detect_crop(
+        input_path: &Path,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+        priority: ProcessPriority,
+    ) -> Option<Crop> {
+        info!("Detecting black bars in {}", input_path.display());
+        let frame = probe.video_stream().and_then(|v| v.width.zip(v.height));
+        let duration = Some(probe.duration() as f64).filter(|d| *d > 0.0);
+        let detected = crop::detect(
+            input_path,
+            duration,
+            frame,
+            preset.crop_limit.unwrap_or(crop::DEFAULT_LIMIT),
+            preset
+                .crop_sample_duration
+                .map_or(crop::DEFAULT_SAMPLE_DURATION, |d| d.0),
+            preset.rotation != Some(Rotation::KeepMetadata),
+            priority,
+        )
+        .await;
+        match detected {
+            Ok(Some(crop)) => {
+                info!(
+                    "Cropping {} to {}x{} at {},{}",
+                    input_path.display(),
+                    crop.width.cyan(),
+                    crop.height.cyan(),
+                    crop.x,
+                    crop.y
+                );
+                Some(crop)
+            }
+            Ok(None) => {
+                debug!("No black bars to crop in {}", input_path.display());
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Crop detection failed for {}, encoding uncropped: {}",
+                    input_path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Write the poster of a finished output; a failure only costs the poster, not the job
+    async fn write_thumbnail(
+        &self,
+        config: &Config,
+        output_path: &Path,
+        thumbnail: &ThumbnailConfig,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+    ) {
+        if preset.is_audio_only() {
+            debug!(
+                "Not writing a thumbnail for {}, it has no video",
+                output_path.display()
+            );
+            return;
+        }
+        let duration = timing::expected_output_duration(probe, preset);
+        let priority = preset.process_priority_in(config);
+        match thumbnail::generate(output_path, thumbnail, duration, priority).await {
+            Ok(poster) => info!("Wrote thumbnail {}", poster.display()),
+            Err(e) => warn!(
+                "Failed to write a thumbnail for {}: {}",
+                output_path.display(),
+                e
+            ),
+        }
+    }
+
+    /// The source's video codec when the input's `skip_if_video_codec` lists it
+    fn skipped_video_codec(input_config: &InputConfig, probe: &ProbeResult) -> Option<String> {
+        let codec = probe.video_stream()?.codec_name.as_deref()?;
+        input_config
+            .skips_video_codec(codec)
+            .then(|| codec.to_string())
+    }
+
+    /// Why the source is excluded by the input's `recorded_between` window, if it is
+    fn outside_recording_window(
+        file_path: &Path,
+        input_config: &InputConfig,
+        probe: &ProbeResult,
+    ) -> Result<Option<String>> {
+        let Some(window) = &input_config.recorded_between else {
+            return Ok(None);
+        };
+        let window = TimeWindow::parse(window)?;
+
+        let Some((recorded, source)) = timestamp::recording_time(
+            file_path,
+            input_config.timestamp_pattern.as_deref(),
+            Some(probe),
+        ) else {
+            warn!(
+                "No recording time found for {}, transcoding it anyway",
+                file_path.display()
+            );
+            return Ok(None);
+        };
+
+        if window.contains(recorded.time()) {
+            return Ok(None);
+        }
+
+        Ok(Some(format!(
+            "recorded at {} (from {}), outside {}",
+            recorded.format("%Y-%m-%d %H:%M:%S"),
+            source,
+            window
+        )))
+    }
+
+    /// Enforce the preset's `max_dropped_frames_pct`, warning or failing when exceeded
+    fn check_dropped_frames(
+        frames: &FrameStats,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+    ) -> Result<()> {
+        // Audio-only encodes have no frames to drop
+        let Some(max_pct) = preset
+            .max_dropped_frames_pct
+            .filter(|_| !preset.is_audio_only())
+        else {
+            return Ok(());
+        };
+
+        let frame_rate = preset.fps.map(|fps| fps.value()).or(probe.frame_rate());
+        let expected = timing::expected_output_duration(probe, preset)
+            .zip(frame_rate)
+            .map(|(duration, fps)| (duration * fps).round() as i64)
+            .filter(|total| *total > 0)
+            .unwrap_or(frames.frames);
+
+        let Some(dropped_pct) = frames.dropped_pct(expected) else {
+            warn!("Cannot check dropped frames without a total frame count");
+            return Ok(());
+        };
+
+        if dropped_pct <= max_pct {
+            return Ok(());
+        }
+
+        let message = format!(
+            "Dropped {} of {} frames ({:.2}%), above the limit of {:.2}%",
+            frames.drop_frames, expected, dropped_pct, max_pct
+        );
+
+        match preset.on_dropped_frames.unwrap_or_default() {
+            DroppedFramesAction::Warn => {
+                warn!("{}", message.yellow());
+                Ok(())
+            }
+            DroppedFramesAction::Fail => Err(anyhow!(message)),
+        }
+    }
+
+    /// Fail when the output is shorter or longer than the trim-aware expected duration by more
+    /// than the preset's tolerance, as after a full disk or a wrong `-t`
+    async fn check_output_duration(
+        output_path: &Path,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+    ) -> Result<()> {
+        if preset.verify == Some(false) {
+            return Ok(());
+        }
+        let Some(expected) = timing::expected_output_duration(probe, preset) else {
+            debug!("Source duration is unknown, not checking the output duration");
+            return Ok(());
+        };
+
+        let output = ffprobe::probe(output_path)
+            .await
+            .context("Failed to probe the output")?;
+        let actual = output.duration() as f64;
+        let tolerance = Self::duration_tolerance(expected, preset);
+
+        if (actual - expected).abs() > tolerance {
+            return Err(anyhow!(
+                "Output is {:.2}s long but {:.2}s was expected, more than {:.2}s off",
+                actual,
+                expected,
+                tolerance
+            ));
+        }
+        debug!(
+            "Output duration {:.2}s matches the expected {:.2}s",
+            actual, expected
+        );
+        Ok(())
+    }
+
+    /// Seconds the output may be off an `expected` duration by: the preset's share of it or
+    /// its fixed allowance, whichever is larger
+    fn duration_tolerance(expected: f64, preset: &PresetConfig) -> f64 {
+        let share = expected
+            * preset
+                .duration_tolerance_pct
+                .unwrap_or(DEFAULT_DURATION_TOLERANCE_PCT)
+            / 100.0;
+        let allowance = preset
+            .duration_tolerance
+            .unwrap_or(DEFAULT_DURATION_TOLERANCE)
+            .0
+            .as_secs_f64();
+        share.max(allowance)
+    }
+
+    /// Say whether and how the source is deinterlaced, so misdetections are easy to spot
+    fn log_deinterlace(input_path: &Path, preset: &PresetConfig, probe: &ProbeResult) {
+        let mode = preset.deinterlace.unwrap_or_default();
+        if mode == DeinterlaceMode::Off || preset.is_audio_only() {
+            return;
+        }
+        let field_order = probe
+            .video_stream()
+            .and_then(|v| v.field_order.as_deref())
+            .unwrap_or("unknown");
+        match command::deinterlace_filter(preset, probe) {
+            Some(filter) => info!(
+                "Deinterlacing {} with {} (field order {})",
+                input_path.display(),
+                filter.cyan(),
+                field_order
+            ),
+            None => info!(
+                "Not deinterlacing {}, field order {} (deinterlace: auto)",
+                input_path.display(),
+                field_order
+            ),
+        }
+    }
+
+    /// Say whether the source is tone mapped to SDR, and what its transfer characteristics are
+    fn log_tonemap(input_path: &Path, preset: &PresetConfig, probe: &ProbeResult) {
+        let mode = preset.tonemap.unwrap_or_default();
+        if mode == TonemapMode::Off || preset.is_audio_only() {
+            return;
+        }
+        let video = probe.video_stream();
+        let transfer = video
+            .and_then(|v| v.color_transfer.as_deref())
+            .unwrap_or("unknown");
+        match (mode, video.is_some_and(Stream::is_hdr)) {
+            (TonemapMode::Auto, true) => info!(
+                "{} is HDR ({}), tone mapping it to SDR with {}",
+                input_path.display(),
+                transfer.cyan(),
+                mode.algorithm().unwrap_or_default().cyan()
+            ),
+            (TonemapMode::Auto, false) => debug!(
+                "Not tone mapping {}, transfer {} is not HDR (tonemap: auto)",
+                input_path.display(),
+                transfer
+            ),
+            (_, true) => info!(
+                "Tone mapping {} ({}) to SDR with {}",
+                input_path.display(),
+                transfer,
+                mode.algorithm().unwrap_or_default().cyan()
+            ),
+            (_, false) => warn!(
+                "Tone mapping {} with {}, but its transfer {} is not HDR",
+                input_path.display(),
+                mode.algorithm().unwrap_or_default(),
+                transfer
+            ),
+        }
+    }
+
+    /// Warn when `fps` is above the source's frame rate, which only repeats frames
+    fn check_frame_rate(input_path: &Path, preset: &PresetConfig, probe: &ProbeResult) {
+        let (Some(fps), Some(source)) = (preset.fps, probe.frame_rate()) else {
+            return;
+        };
+        if preset.is_audio_only() {
+            return;
+        }
+        if fps.value() > source + 0.01 {
+            warn!(
+                "{} runs at {:.3} fps, raising it to {} fps only repeats frames",
+                input_path.display(),
+                source,
+                fps
+            );
+        } else {
+            debug!(
+                "Converting {} from {:.3} fps to {} fps",
+                input_path.display(),
+                source,
+                fps
+            );
+        }
+    }
+
+    /// Say how a source recorded as turned is rotated, so sideways outputs are easy to trace
+    fn log_rotation(input_path: &Path, preset: &PresetConfig, probe: &ProbeResult) {
+        if preset.is_audio_only() || preset.is_remux() {
+            return;
+        }
+        let recorded = probe.video_stream().map_or(0, Stream::rotation);
+        match preset.rotation.unwrap_or_default() {
+            Rotation::Auto | Rotation::KeepMetadata if recorded == 0 => {}
+            Rotation::Auto => info!(
+                "{} is recorded as turned {}°, letting ffmpeg rotate it upright",
+                input_path.display(),
+                recorded.cyan()
+            ),
+            Rotation::KeepMetadata => info!(
+                "Encoding {} as stored, keeping its rotation of {}° for players",
+                input_path.display(),
+                recorded.cyan()
+            ),
+            Rotation::Degrees(degrees) if recorded != 0 => info!(
+                "Rotating {} upright from its recorded {}°, then another {}°",
+                input_path.display(),
+                recorded,
+                degrees.cyan()
+            ),
+            Rotation::Degrees(degrees) => {
+                info!("Rotating {} by {}°", input_path.display(), degrees.cyan())
+            }
+        }
+    }
+
+    /// Fail before encoding when `subtitles: copy` meets bitmap subtitles the output container
+    /// can't hold, and warn when there is nothing for `subtitles: burn`
+    fn check_subtitles(
+        input_path: &Path,
+        output_path: &Path,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+    ) -> Result<()> {
+        if preset.is_audio_only() {
+            return Ok(());
+        }
+        match preset.subtitle_policy() {
+            SubtitlePolicy::Drop => {}
+            SubtitlePolicy::Copy => {
+                let container = output_path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_default();
+                let bitmap = probe
+                    .subtitle_streams()
+                    .into_iter()
+                    .find(|stream| stream.is_bitmap_subtitle());
+                if let Some(stream) =
+                    bitmap.filter(|_| MOV_TEXT_CONTAINERS.contains(&container.as_str()))
+                {
+                    return Err(anyhow!(
+                        "{} has {} subtitles, which .{} can't hold; write mkv, or set subtitles to burn or drop",
+                        input_path.display(),
+                        stream.codec_name.as_deref().unwrap_or("bitmap"),
+                        container
+                    ));
+                }
+            }
+            SubtitlePolicy::Burn => {
+                if command::burned_subtitle(preset, probe).is_none() {
+                    warn!(
+                        "{} has no subtitle stream {} to burn in, encoding without subtitles",
+                        input_path.display(),
+                        preset.subtitle_stream.unwrap_or(0)
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Warn when the source's chapters are preserved into a container that drops them
+    fn check_chapters(
+        input_path: &Path,
+        output_path: &Path,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+    ) {
+        let container = output_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if preset.preserve_chapters.unwrap_or(true)
+            && !probe.chapters.is_empty()
+            && !container_holds_chapters(&container)
+        {
+            warn!(
+                "{} has {} chapters, which .{} can't hold, writing it without them",
+                input_path.display(),
+                probe.chapters.len(),
+                container
+            );
+        }
+    }
+
+    /// Warn about `audio_languages` the source has no audio stream in
+    fn check_audio_languages(input_path: &Path, preset: &PresetConfig, probe: &ProbeResult) {
+        let Some(streams) = preset.streams.as_ref().filter(|s| !s.map_all) else {
+            return;
+        };
+        let (present, missing): (Vec<&String>, Vec<&String>) = streams
+            .audio_languages
+            .iter()
+            .partition(|language| probe.has_audio_language(language));
+        if missing.is_empty() {
+            return;
+        }
+        let missing = missing
+            .iter()
+            .map(|language| language.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if present.is_empty() {
+            warn!(
+                "{} has no audio in {}, keeping its first audio stream",
+                input_path.display(),
+                missing
+            );
+        } else {
+            warn!("{} has no audio in {}", input_path.display(), missing);
+        }
+    }
+
+    /// Only encoder/parameter failures are worth retrying with a different preset
+    fn should_use_fallback(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<JobError>(),
+            Some(JobError::Ffmpeg {
+                kind: FfmpegFailure::Encoder,
+                ..
+            })
+        )
+    }
+
+    fn is_cancelled(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref::<JobError>(), Some(JobError::Cancelled))
+    }
+
+    fn is_output_collision(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<JobError>(),
+            Some(JobError::OutputInUse { .. })
+                | Some(JobError::Ffmpeg {
+                    kind: FfmpegFailure::OutputExists,
+                    ..
+                })
+        )
+    }
+
+    /// Claim `output_path`, or the first of `name (1).ext`, `name (2).ext`, ... that neither
+    /// exists nor is claimed by another job
+    fn claim_unused_output(
+        &self,
+        output_path: &Path,
+        source: &Path,
+    ) -> Result<(PathBuf, OutputClaim)> {
+        let stem = output_path
+            .file_stem()
+            .ok_or_else(|| anyhow!("Output {} has no file name", output_path.display()))?
+            .to_string_lossy();
+        let extension = output_path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+
+        for n in 0..MAX_RENAME_ATTEMPTS {
+            let candidate = if n == 0 {
+                output_path.to_path_buf()
+            } else {
+                output_path.with_file_name(format!("{} ({}){}", stem, n, extension))
+            };
+            if candidate.exists() {
+                continue;
+            }
+            if let Ok(claim) = OutputClaim::acquire(&self.claimed_outputs, &candidate, source) {
+                return Ok((candidate, claim));
+            }
+        }
+
+        Err(anyhow!(
+            "No free name for output {} after {} attempts",
+            output_path.display(),
+            MAX_RENAME_ATTEMPTS
+        ))
+    }
+
+    fn remove_incomplete_output(&self, output_path: &Path) {
+        if output_path.exists() {
+            match std::fs::remove_file(output_path) {
+                Ok(_) => info!("Removed incomplete output file: {}", output_path.display()),
+                Err(e) => error!(
+                    "Failed to remove incomplete output file {}: {}",
+                    output_path.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn ignored_by_marker(&self, file_path: &Path, input_config: &InputConfig) -> Option<String> {
+        let file_path = std::fs::canonicalize(file_path).ok()?;
+        let input_root = std::fs::canonicalize(&input_config.path).ok()?;
+        self.ignore_markers.ignored_by(&file_path, &input_root)
+    }
+
+    fn find_matching_input(&self, file_path: &Path) -> Option<InputConfig> {
+        if artifacts::is_sstc_artifact(file_path) {
+            debug!("Skipping sstc artifact: {}", file_path.display());
+            return None;
+        }
+
+        let canonical_file_path = match std::fs::canonicalize(file_path) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("Failed to canonicalize path {}: {}", file_path.display(), e);
+                return None;
+            }
+        };
+
+        debug!("Checking file: {}", canonical_file_path.display());
+
+        let config = self.config();
+        if artifacts::is_state_file(&config, &canonical_file_path)
+            || thumbnail::is_poster(&config, &canonical_file_path)
+        {
+            debug!(
+                "Skipping sstc state file or poster: {}",
+                file_path.display()
+            );
+            return None;
+        }
+        for input in &config.inputs {
+            let canonical_input_path = match std::fs::canonicalize(&input.path) {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!(
+                        "Failed to canonicalize input path {}: {}",
+                        input.path.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            debug!(
+                "Comparing with input path: {}",
+                canonical_input_path.display()
+            );
+
+            let Ok(relative) = canonical_file_path.strip_prefix(&canonical_input_path) else {
+                debug!("Path doesn't match input directory");
+                continue;
+            };
+
+            if let Some(pattern) = input.ignored_by(relative) {
+                debug!(
+                    "Ignored by pattern '{}' of input {}: {}",
+                    pattern,
+                    input.path.display(),
+                    file_path.display()
+                );
+                continue;
+            }
+
+            if let Some(pattern) = input.excluded_by(relative) {
+                debug!(
+                    "Excluded by pattern '{}' of input {}: {}",
+                    pattern,
+                    input.path.display(),
+                    file_path.display()
+                );
+                continue;
+            }
+
+            if input.matches_file(relative) {
+                debug!("Found matching input for file: {}", file_path.display());
+                return Some(input.clone());
+            }
+        }
+
+        debug!("No matching input found for: {}", file_path.display());
+        None
+    }
+
+    fn get_preset(config: &Config, preset_name: &str) -> Result<PresetConfig> {
+        config
+            .presets
+            .get(preset_name)
+            .cloned()
+            .context(format!("Preset not found: {}", preset_name))
+    }
+
+    fn get_output(config: &Config, output_name: &str) -> Result<OutputConfig> {
+        config
+            .outputs
+            .get(output_name)
+            .cloned()
+            .context(format!("Output not found: {}", output_name))
+    }
+
+    fn create_output_path(
+        &self,
+        input_path: &Path,
+        input_config: &InputConfig,
+        preset: &str,
+        container: &str,
+        output_config: &OutputConfig,
+        probe: &ProbeResult,
+    ) -> Result<PathBuf> {
+        let vars = TemplateVars::for_source(input_path, input_config, preset, Some(probe))?;
+        let output_filename = template::render(&output_config.filename_template, &vars)?;
+        let mut relative = PathBuf::new();
+        if let Some(subdir) = &output_config.subdir_template {
+            relative.push(template::render(subdir, &vars)?);
+        }
+        relative.push(format!("{}.{}", output_filename, container));
+
+        // Only plain names below the root: no `..`, `.`, or absolute paths from the template
+        let plain = relative
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if !plain {
+            return Err(anyhow!(
+                "Output path {} for {} escapes output directory {}",
+                relative.display(),
+                input_path.display(),
+                output_config.path.display()
+            ));
+        }
+
+        Ok(output_config.path.join(relative))
+    }
+
+    /// Fail unless `dir` resolves to the output root or a directory below it. Either may not
+    /// exist yet, so each resolves through its deepest existing ancestor.
+    fn check_inside_output_root(root: &Path, dir: &Path) -> Result<()> {
+        let root = resolve_through_existing(root)?;
+        let resolved = resolve_through_existing(dir)?;
+
+        if !resolved.starts_with(&root) {
+            return Err(anyhow!(
+                "Output directory {} resolves to {}, outside output root {}",
+                dir.display(),
+                resolved.display(),
+                root.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn transcode_file(
+        &self,
+        job: &SourceJob<'_>,
+        output_path: &Path,
+        preset: &PresetConfig,
+        label: &str,
+        record: &mut JobRecord,
+    ) -> Result<FrameStats> {
+        let SourceJob {
+            config,
+            source: input_path,
+            probe,
+            ..
+        } = *job;
+        let scale_decision = ScaleDecision::new(preset, probe);
+        if scale_decision != ScaleDecision::Uncapped {
+            info!(
+                "Resolution cap for {}: {}",
+                input_path.display(),
+                scale_decision.cyan()
+            );
+        }
+
+        Self::log_deinterlace(input_path, preset, probe);
+        Self::log_tonemap(input_path, preset, probe);
+        Self::log_rotation(input_path, preset, probe);
+        Self::check_frame_rate(input_path, preset, probe);
+        Self::check_subtitles(input_path, output_path, preset, probe)?;
+        Self::check_audio_languages(input_path, preset, probe);
+        Self::check_chapters(input_path, output_path, preset, probe);
+
+        let progress_period = preset.progress_interval_in(config).period();
+        let control = self
+            .job_controls
+            .get(input_path)
+            .map(|entry| entry.value().clone())
+            .unwrap_or_default();
+        if control.is_cancelled() {
+            return Err(JobError::Cancelled.into());
+        }
+        if control.is_killed() {
+            return Err(JobError::Interrupted.into());
+        }
+        if self.is_dry_run() {
+            self.print_commands(config, input_path, output_path, preset, probe, record);
+            return Ok(FrameStats::default());
+        }
+        let priority = preset.process_priority_in(config);
+        if !priority.is_normal() {
+            info!(
+                "Running ffmpeg for {} at {}",
+                input_path.display(),
+                priority.cyan()
+            );
+        }
+        let crop = match preset.auto_crop == Some(true) && !preset.is_audio_only() {
+            true => Self::detect_crop(input_path, preset, probe, priority).await,
+            false => None,
+        };
+
+        // Removed when dropped, however the job ends
+        let pass_log = preset.is_two_pass().then(PassLogDir::new).transpose()?;
+        let passes: Vec<Option<Pass>> = match &pass_log {
+            Some(log) => (1..=2)
+                .map(|number| {
+                    Some(Pass {
+                        number,
+                        log_file: log.prefix(),
+                    })
+                })
+                .collect(),
+            None => vec![None],
+        };
+
+        let expected_duration = timing::expected_output_duration(probe, preset);
+        let mut bar = match progress_period {
+            // Remuxes are over in seconds, a bar would only jump from empty to full
+            Some(_) if preset.is_remux() => {
+                Some(JobBar::spinner(&self.bars, input_path, label, "remuxing"))
+            }
+            Some(_) => Some(JobBar::new(
+                &self.bars,
+                expected_duration.map(|duration| duration * passes.len() as f64),
+                input_path,
+                label,
+            )),
+            None => {
+                info!(
+                    "Progress reporting is off, waiting for ffmpeg to finish {}",
+                    input_path.display()
+                );
+                None
+            }
+        };
+
+        // A hung ffmpeg writes no progress either, so the limit is kept by a timer of its own
+        let time_limit = preset.time_limit_in(config, probe.duration() as f64);
+        let timer = time_limit.map(|(limit, setting)| {
+            let control = control.clone();
+            let input_path = input_path.to_path_buf();
+            tokio::spawn(async move {
+                tokio::time::sleep(limit).await;
+                warn!(
+                    "Killing ffmpeg for {}, it ran longer than {} ({})",
+                    input_path.display(),
+                    short_duration(limit.as_secs()),
+                    setting
+                );
+                control.time_out();
+            })
+        });
+
+        // Measured under the same time limit and kill switch as the encode itself
+        let (mut result, audio_filter) =
+            match Self::loudness_filter(input_path, preset, probe, priority, &control, time_limit)
+                .await
+            {
+                Ok(filter) => (Ok(FrameStats::default()), filter),
+                Err(e) => (Err(e), None),
+            };
+        let threads = preset.threads_in(config);
+        if let Some(threads) = threads {
+            info!(
+                "Encoding {} with {} thread(s){}",
+                input_path.display(),
+                threads,
+                match preset.threads {
+                    Some(Threads::Auto) => " (auto)",
+                    _ => "",
+                }
+            );
+        }
+        let source_size = std::fs::metadata(input_path).map_or(0, |m| m.len());
+        let max_size_ratio = preset
+            .if_larger_than_source
+            .filter(|_| !preset.is_remux() && source_size > 0)
+            .map(|_| preset.max_size_ratio.unwrap_or(1.0));
+        for pass in passes {
+            if result.is_err() {
+                break;
+            }
+            let first_pass = matches!(&pass, Some(pass) if pass.number == 1);
+            if let (Some(bar), Some(pass)) = (&mut bar, &pass) {
+                bar.set_stage(&format!("pass {}/2", pass.number));
+            }
+            let position_offset = match &pass {
+                Some(pass) if pass.number == 2 => {
+                    expected_duration.map_or(0, |d| (d * 1000.0).ceil() as u64)
+                }
+                _ => 0,
+            };
+            let args = build_ffmpeg_command(
+                input_path,
+                output_path,
+                preset,
+                probe,
+                &CommandOptions {
+                    progress: progress_period,
+                    overwrite: record.overwrote_existing,
+                    input_options: config.input_options.clone(),
+                    pass,
+                    crop,
+                    audio_filter: audio_filter.clone(),
+                    threads,
+                },
+            );
+            let run = FfmpegRun {
+                args,
+                input_path,
+                // The first pass writes nothing to watch
+                watched_output: (!first_pass).then_some(output_path),
+                progress_period,
+                expected_duration,
+                position_offset,
+                size_limit: max_size_ratio
+                    .zip(expected_duration)
+                    .filter(|_| !first_pass)
+                    .map(|(ratio, duration)| OutputSizeLimit::new(source_size, duration, ratio)),
+                priority,
+                config,
+            };
+            result = self
+                .run_ffmpeg(run, bar.as_mut(), &control, time_limit, record)
+                .await;
+        }
+        if let Some(timer) = timer {
+            timer.abort();
+        }
+        let frame_stats = result?;
+        if let Some(bar) = &bar {
+            bar.finish();
+        }
+
+        if !output_path.exists() {
+            return Err(anyhow!(
+                "Output file was not created: {}",
+                output_path.display()
+            ));
+        }
+
+        let metadata = std::fs::metadata(output_path)?;
+        if metadata.len() == 0 {
+            return Err(anyhow!("Output file is empty: {}", output_path.display()));
+        }
+
+        let input_size = match std::fs::metadata(input_path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                warn!("Could not get input file size: {}", e);
+                0
+            }
+        };
+
+        if output_path.exists() {
+            if let Ok(output_metadata) = std::fs::metadata(output_path) {
+                let output_size = output_metadata.len();
+
+                if input_size > 0 {
+                    let compression_ratio = input_size as f64 / output_size as f64;
+                    let size_change_percent =
+                        (1.0 - output_size as f64 / input_size as f64) * 100.0;
+
+                    info!(
+                        "Compression stats for {}:",
+                        output_path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .green()
+                    );
+                    info!(
+                        "  Input size:  {}",
+                        ByteSize::b(input_size).display().si().to_string(),
+                    );
+                    info!(
+                        "  Output size: {}",
+                        ByteSize::b(output_size).display().si().to_string(),
+                    );
+                    info!(
+                        "  Ratio: {:.2}:1 ({:.1}% {})",
+                        compression_ratio.abs(),
+                        size_change_percent.abs(),
+                        if size_change_percent < 0.0 {
+                            "larger"
+                        } else {
+                            "smaller"
+                        }
+                    );
+                }
+            }
+        }
+
+        Ok(frame_stats)
+    }
+
+    /// Print the ffmpeg invocations of a job, one copy-pasteable line each, for `--dry-run`.
+    /// What auto_crop and normalize_audio would measure needs ffmpeg to read the whole
+    /// source, so their filters are left out.
+    fn print_commands(
+        &self,
+        config: &Config,
+        input_path: &Path,
+        output_path: &Path,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+        record: &JobRecord,
+    ) {
+        if preset.auto_crop == Some(true) && !preset.is_audio_only() {
+            info!(
+                "Leaving out the crop of {}, auto_crop detects it when the job runs",
+                input_path.display()
+            );
+        }
+        if preset.normalize_audio.is_some() && !preset.is_remux() {
+            info!(
+                "Leaving out the loudness filter of {}, normalize_audio measures it when the job runs",
+                input_path.display()
+            );
+        }
+
+        // The real pass log directory is made per job, so it is only named here
+        let passes: Vec<Option<Pass>> = match preset.is_two_pass() {
+            true => (1..=2)
+                .map(|number| {
+                    Some(Pass {
+                        number,
+                        log_file: std::env::temp_dir().join("sstc-passlog").join("pass"),
+                    })
+                })
+                .collect(),
+            false => vec![None],
+        };
+        for pass in passes {
+            let args = build_ffmpeg_command(
+                input_path,
+                output_path,
+                preset,
+                probe,
+                &CommandOptions {
+                    progress: preset.progress_interval_in(config).period(),
+                    overwrite: record.overwrote_existing,
+                    input_options: config.input_options.clone(),
+                    pass,
+                    crop: None,
+                    audio_filter: None,
+                    threads: preset.threads_in(config),
+                },
+            );
+            let argv = std::iter::once(tools::ffmpeg().as_os_str())
+                .chain(args.iter().map(|arg| arg.as_os_str()))
+                .map(|arg| arg.to_string_lossy().into_owned());
+            println!("{}", shell::join(argv));
+        }
+    }
+
+    /// The loudnorm filter of `normalize_audio`, fed with what an analysis pass over the
+    /// source's first audio stream measured. Silent audio is left as it is.
+    async fn loudness_filter(
+        input_path: &Path,
+        preset: &PresetConfig,
+        probe: &ProbeResult,
+        priority: ProcessPriority,
+        control: &Arc<JobControl>,
+        time_limit: Option<(std::time::Duration, &'static str)>,
+    ) -> Result<Option<String>> {
+        let Some(normalize) = preset.normalize_audio.filter(|_| !preset.is_remux()) else {
+            return Ok(None);
+        };
+        let Some(stream) = probe.audio_streams().first().copied() else {
+            debug!("No audio to normalize in {}", input_path.display());
+            return Ok(None);
+        };
+
+        info!("Measuring the loudness of {}", input_path.display());
+        let mut cmd = Command::new(tools::ffmpeg());
+        cmd.args(loudnorm::analysis_args(
+            input_path,
+            &preset.audio_filters,
+            &normalize,
+        ));
+        priority::apply(&mut cmd, priority);
+        #[cfg(unix)]
+        cmd.process_group(0);
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let mut child = rusage::Process::spawn(&mut cmd, Self::forget_on_exit(control))
+            .context("Failed to execute ffmpeg for loudness analysis")?;
+        control.started(child.id());
+        let mut stderr = Vec::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            pipe.read_to_end(&mut stderr).await?;
+        }
+        let (status, _) = child.wait().await?;
+        if control.is_cancelled() {
+            return Err(JobError::Cancelled.into());
+        }
+        if control.is_killed() {
+            return Err(JobError::Interrupted.into());
+        }
+        if let Some((limit, setting)) = time_limit.filter(|_| control.is_timed_out()) {
+            return Err(JobError::TimedOut { limit, setting }.into());
+        }
+
+        let stderr = String::from_utf8_lossy(&stderr);
+        if !status.success() {
+            let lines: Vec<String> = stderr
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(String::from)
+                .collect();
+            let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].to_vec();
+            return Err(JobError::Ffmpeg {
+                status: status.to_string(),
+                kind: FfmpegFailure::classify(&tail),
+                stderr: tail,
+            }
+            .into());
+        }
+
+        let measurement = Measurement::parse(&stderr).ok_or_else(|| {
+            anyhow!(
+                "Loudness analysis of {} reported no measurement",
+                input_path.display()
+            )
+        })?;
+        if !measurement.is_usable() {
+            warn!(
+                "Audio of {} is silent, leaving its loudness as it is",
+                input_path.display()
+            );
+            return Ok(None);
+        }
+        info!(
+            "Loudness of {} is {} LUFS, true peak {} dBTP; normalizing to {} LUFS",
+            input_path.display(),
+            measurement.input_i.cyan(),
+            measurement.input_tp,
+            normalize.target_lufs.cyan()
+        );
+        // loudnorm works at 192 kHz and hands that on unless resampled back
+        let sample_rate = stream
+            .sample_rate
+            .as_deref()
+            .and_then(|rate| rate.parse::<u32>().ok())
+            .unwrap_or(48_000);
+        Ok(Some(format!(
+            "{},aresample={}",
+            measurement.filter(&normalize),
+            sample_rate
+        )))
+    }
+
+    /// What a job's ffmpeg calls once it has exited, before it is reaped: letting go of its
+    /// pid, so a kill arriving later can't hit a process that reused it
+    fn forget_on_exit(control: &Arc<JobControl>) -> impl FnOnce() + Send + 'static {
+        let control = control.clone();
+        move || control.finished()
+    }
+
+    /// Run one ffmpeg invocation of a job to its end, reporting progress on `bar` and killing
+    /// it when a watchdog, the schedule or `control` says so
+    async fn run_ffmpeg(
+        &self,
+        run: FfmpegRun<'_>,
+        bar: Option<&mut JobBar>,
+        control: &Arc<JobControl>,
+        time_limit: Option<(std::time::Duration, &'static str)>,
+        record: &mut JobRecord,
+    ) -> Result<FrameStats> {
+        let FfmpegRun {
+            args,
+            input_path,
+            watched_output,
+            progress_period,
+            expected_duration,
+            position_offset,
+            mut size_limit,
+            priority,
+            config,
+        } = run;
+        let mut cmd = Command::new(tools::ffmpeg());
+        cmd.args(args);
+        priority::apply(&mut cmd, priority);
+
+        let argv: Vec<String> = std::iter::once(cmd.as_std().get_program())
+            .chain(cmd.as_std().get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        info!("Executing: {}", shell::join(&argv).yellow());
+
+        let mut job_log = record.log_file.as_deref().and_then(|path| {
+            JobLog::open(path, &shell::join(&argv))
+                .map_err(|e| warn!("{:#}", e))
+                .ok()
+        });
+        record.command = Some(argv);
+        record.ffmpeg_version = ffmpeg::version();
+        record.working_dir = std::env::current_dir().ok();
+
+        let stdout = match bar {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        };
+        // Out of the terminal's process group, so Ctrl-C reaches only sstc, which decides
+        // whether ffmpeg gets to finish
+        #[cfg(unix)]
+        cmd.process_group(0);
+        // Dropped along with a job that panics or is aborted, which then takes ffmpeg with it
+        cmd.stdout(stdout).stderr(Stdio::piped());
+        let mut child = rusage::Process::spawn(&mut cmd, Self::forget_on_exit(control))?;
+        control.started(child.id());
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or(anyhow!("Failed to open stderr"))?;
+
+        let stderr_task = tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            let mut errors = VecDeque::new();
+            // What a failure is explained with when ffmpeg tagged no line as an error
+            let mut untagged = VecDeque::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(log) = &mut job_log {
+                    log.line(&line);
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (level, message) = ffmpeg::parse_log_line(&line);
+                let tail = match level {
+                    Some(LogLevel::Error) => {
+                        error!("FFmpeg error: {}", message);
+                        &mut errors
+                    }
+                    Some(LogLevel::Warning) => {
+                        warn!("FFmpeg warning: {}", message);
+                        continue;
+                    }
+                    Some(LogLevel::Info | LogLevel::Debug) => {
+                        debug!("FFmpeg: {}", message);
+                        continue;
+                    }
+                    None => {
+                        debug!("FFmpeg: {}", message);
+                        &mut untagged
+                    }
+                };
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(message);
+            }
+            let tail = if errors.is_empty() { untagged } else { errors };
+            (Vec::from(tail), job_log)
+        });
+
+        let mut frame_stats = FrameStats::default();
+        // Set when a watchdog or the size limit killed ffmpeg
+        let mut aborted = None;
+        let mut stopped_by_schedule = false;
+
+        if let Some(bar) = bar {
+            let stdout = child
+                .stdout
+                .take()
+                .ok_or(anyhow!("Failed to open stdout"))?;
+            let mut lines = BufReader::new(stdout).lines();
+            let mut current_progress = HashMap::new();
+
+            let mut growth = watched_output
+                .zip(progress_period)
+                .filter(|_| config.verify_output_growth)
+                .map(|(output, period)| OutputGrowthWatchdog::new(output, period));
+            let schedule = config.schedule.clone().filter(|s| s.hard_stop);
+
+            while let Some(line) = lines.next_line().await? {
+                let line = line.trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                if let Some((key, value)) = line.split_once('=') {
+                    current_progress.insert(key.to_string(), value.to_string());
+
+                    if key == "progress" {
+                        let progress = FFmpegProgress::from_key_values(&current_progress);
+                        telemetry::progress_event(&progress);
+                        frame_stats = progress.frame_stats();
+                        bar.update(
+                            &JobProgress::new(&progress, expected_duration),
+                            progress
+                                .out_time_secs()
+                                .map(|secs| position_offset + (secs * 1000.0) as u64),
+                        );
+
+                        if let (Some(watchdog), Some(secs)) =
+                            (&mut growth, progress.out_time_secs())
+                        {
+                            if let Err(e) = watchdog.observe(secs) {
+                                error!("Killing ffmpeg for {}: {}", input_path.display(), e.red());
+                                child.start_kill();
+                                aborted = Some(e);
+                                break;
+                            }
+                        }
+
+                        if let (Some(limit), Some(size), Some(secs)) = (
+                            &mut size_limit,
+                            progress.total_size.filter(|size| *size >= 0),
+                            progress.out_time_secs(),
+                        ) {
+                            if let Err(e) = limit.observe(size as u64, secs) {
+                                warn!("Stopping ffmpeg for {}: {}", input_path.display(), e);
+                                child.start_kill();
+                                aborted = Some(e);
+                                break;
+                            }
+                        }
+
+                        let closed = schedule
+                            .as_ref()
+                            .is_some_and(|s| !s.is_open((self.schedule_clock.now)()));
+                        if closed {
+                            warn!(
+                                "Schedule window closed, killing ffmpeg for {} (hard_stop)",
+                                input_path.display()
+                            );
+                            child.start_kill();
+                            stopped_by_schedule = true;
+                            break;
+                        }
+
+                        if progress.is_complete() {
+                            break;
+                        }
+
+                        current_progress.clear();
+                    }
+                }
+            }
+        }
+
+        let (status, usage) = child.wait().await?;
+        if let Some(usage) = usage {
+            // Fallback attempts add to the CPU time of the job
+            record.resources = Some(match &record.resources {
+                Some(previous) => previous.add(&usage),
+                None => usage,
+            });
+        }
+        let (stderr, job_log) = stderr_task.await.unwrap_or_default();
+        if let Some(log) = job_log {
+            log.finish(&format!("ffmpeg {}", status));
+        }
+        if control.is_cancelled() {
+            return Err(JobError::Cancelled.into());
+        }
+        if control.is_killed() {
+            return Err(JobError::Interrupted.into());
+        }
+        if let Some((limit, setting)) = time_limit.filter(|_| control.is_timed_out()) {
+            return Err(JobError::TimedOut { limit, setting }.into());
+        }
+        if let Some(e) = aborted {
+            return Err(e.into());
+        }
+        if stopped_by_schedule {
+            return Err(JobError::StoppedBySchedule.into());
+        }
+        if !status.success() {
+            return Err(JobError::Ffmpeg {
+                status: status.to_string(),
+                kind: FfmpegFailure::classify(&stderr),
+                stderr,
+            }
+            .into());
+        }
+
+        Ok(frame_stats)
+    }
+}
+
+/// `path` with its deepest existing ancestor canonicalized and the rest joined back on
+fn resolve_through_existing(path: &Path) -> Result<PathBuf> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let resolved = std::fs::canonicalize(existing).context(format!(
+        "Failed to resolve output directory {}",
+        path.display()
+    ))?;
+    Ok(rest
+        .into_iter()
+        .rev()
+        .fold(resolved, |dir, name| dir.join(name)))
+}
+
+/// Delay before retry number `attempt`: `base` doubled for each retry before it, up to
+/// [`MAX_RETRY_DELAY`], plus up to a quarter more so retries of files that failed together
+/// spread out
+fn retry_delay(base: std::time::Duration, attempt: u32) -> std::time::Duration {
+    let delay = base
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY);
+    // Sub-second clock noise is random enough to spread a handful of retries
+    let noise = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    delay + delay.mul_f64(f64::from(noise % 1000) / 4000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{preset, runs_as_root, video_probe, Sandbox, BASIC_CONFIG};
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const TWO_TARGETS: &str = "
+inputs:
+  - path: {dir}/in
+    extensions: [mp4]
+    targets:
+      - preset: small
+        output: o
+      - preset: large
+        output: o
+outputs:
+  o:
+    path: {dir}/out
+    container: mkv
+    filename_template: '{filename}_{preset}'
+presets:
+  small:
+    video_codec: libx264
+    crf: 28
+  large:
+    video_codec: libx264
+    crf: 18
+stability:
+  check_interval: 10ms
+  stable_for: 0s
+";
+
+    #[tokio::test]
+    async fn probes_each_source_once_for_all_its_targets() {
+        let single = BASIC_CONFIG.replace("libx264", "libx264\n    verify: true");
+        let two = TWO_TARGETS.replace("crf:", "verify: true\n    crf:");
+        for (config, outputs) in [
+            (single.as_str(), vec!["out/clip.mkv"]),
+            (
+                two.as_str(),
+                vec!["out/clip_large.mkv", "out/clip_small.mkv"],
+            ),
+        ] {
+            let sandbox = Sandbox::new();
+            let transcoder = Transcoder::new(sandbox.config(config));
+            let source = sandbox.file("in/clip.mp4");
+
+            transcoder.process_file(&source).await.unwrap();
+            transcoder.wait_until_idle().await;
+            assert_eq!(transcoder.stats().failed(), 0);
+
+            // The source once, shared by the targets, and each output once to verify it
+            let mut probed: Vec<String> = sandbox
+                .calls("ffprobe")
+                .into_iter()
+                .map(|args| args.last().unwrap().clone())
+                .collect();
+            probed.sort();
+            let mut expected: Vec<String> = outputs
+                .iter()
+                .map(|output| sandbox.path().join(output).to_string_lossy().into_owned())
+                .collect();
+            expected.push(source.to_string_lossy().into_owned());
+            expected.sort();
+            assert_eq!(probed, expected);
+
+            let source = source.to_string_lossy();
+            let encodes = sandbox
+                .calls("ffmpeg")
+                .into_iter()
+                .filter(|args| args.iter().any(|arg| *arg == source))
+                .count();
+            assert_eq!(encodes, outputs.len());
+            for output in outputs {
+                assert!(sandbox.path().join(output).is_file());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn source_action_waits_for_every_target() {
+        for (action, second, left) in [
+            ("delete", "large", vec![]),
+            ("{move: {dir}/done}", "large", vec!["done/clip.mp4"]),
+            ("{suffix: .done}", "large", vec!["in/clip.mp4.done"]),
+            // A target that failed keeps the source where it is, whatever the action
+            ("delete", "broken", vec!["in/clip.mp4"]),
+            ("{move: {dir}/done}", "broken", vec!["in/clip.mp4"]),
+        ] {
+            let sandbox = Sandbox::new();
+            let config = sandbox.config(
+                &TWO_TARGETS
+                    .replace(
+                        "extensions: [mp4]",
+                        &format!("extensions: [mp4]\n    source_action: {}", action),
+                    )
+                    .replace("large", second),
+            );
+            let transcoder = Transcoder::new(config);
+            let source = sandbox.file("in/clip.mp4");
+
+            transcoder.process_file(&source).await.unwrap();
+            transcoder.wait_until_idle().await;
+
+            assert_eq!(
+                transcoder.stats().failed(),
+                usize::from(second == "broken"),
+                "{}",
+                action
+            );
+            let mut sources: Vec<PathBuf> = files_below(sandbox.path())
+                .into_iter()
+                .filter(|path| path.to_string_lossy().contains("clip.mp4"))
+                .collect();
+            sources.sort();
+            let left: Vec<PathBuf> = left.into_iter().map(PathBuf::from).collect();
+            assert_eq!(sources, left, "{} with {}", action, second);
+        }
+    }
+
+    #[test]
+    fn output_claim_is_exclusive_until_dropped() {
+        let claims = Arc::new(DashMap::new());
+        let output = Path::new("/out/clip.mkv");
+        let first = OutputClaim::acquire(&claims, output, Path::new("/in/a/clip.mp4")).unwrap();
+
+        let err = OutputClaim::acquire(&claims, output, Path::new("/in/b/clip.mp4"))
+            .err()
+            .unwrap();
+        match err.downcast_ref::<JobError>() {
+            Some(JobError::OutputInUse { owner, .. }) => {
+                assert_eq!(owner, Path::new("/in/a/clip.mp4"))
+            }
+            other => panic!("expected OutputInUse, got {:?}", other),
+        }
+
+        drop(first);
+        assert!(OutputClaim::acquire(&claims, output, Path::new("/in/b/clip.mp4")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn two_jobs_never_encode_to_the_same_output() {
+        let sandbox = Sandbox::new();
+        let config =
+            sandbox.config(&BASIC_CONFIG.replace("presets:", "max_parallel_jobs: 2\npresets:"));
+        let transcoder = Transcoder::new(config);
+        // Both land on out/slow.mkv, and the fake ffmpeg takes a second over either
+        let first = sandbox.file("in/a/slow.mp4");
+        let second = sandbox.file("in/b/slow.mp4");
+
+        transcoder.process_file(&first).await.unwrap();
+        transcoder.process_file(&second).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let output = sandbox.path().join("out/slow.mkv");
+        assert!(output.is_file());
+        let encodes = sandbox
+            .calls("ffmpeg")
+            .into_iter()
+            .filter(|args| args.iter().any(|arg| arg.contains("out/slow")))
+            .count();
+        assert_eq!(encodes, 1);
+        // The other job lost the claim, or found the output when it got there last
+        let mut outcomes: Vec<_> = transcoder
+            .stats()
+            .outcomes()
+            .into_iter()
+            .map(|(_, outcome)| outcome)
+            .collect();
+        outcomes.sort_by_key(|outcome| outcome != &Some(JobOutcome::Transcoded));
+        assert_eq!(outcomes[0], Some(JobOutcome::Transcoded));
+        assert!(
+            matches!(
+                outcomes[1],
+                Some(JobOutcome::Failed | JobOutcome::SkippedExisting)
+            ),
+            "{:?}",
+            outcomes
+        );
+    }
+
+    fn dropped(drop_frames: i64) -> FrameStats {
+        FrameStats {
+            frames: 250 - drop_frames,
+            dup_frames: 0,
+            drop_frames,
+        }
+    }
+
+    fn check_dropped(preset_yaml: &str, drop_frames: i64) -> Result<()> {
+        Transcoder::check_dropped_frames(
+            &dropped(drop_frames),
+            &preset(preset_yaml),
+            &video_probe(10.0),
+        )
+    }
+
+    #[test]
+    fn dropped_frames_limit_is_inclusive() {
+        // 10s at 25 fps is 250 frames, so 5 is 2%
+        let preset = "max_dropped_frames_pct: 2.0\non_dropped_frames: fail";
+        assert!(check_dropped(preset, 0).is_ok());
+        assert!(check_dropped(preset, 5).is_ok());
+        let err = check_dropped(preset, 6).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dropped 6 of 250 frames (2.40%), above the limit of 2.00%"
+        );
+    }
+
+    #[test]
+    fn dropped_frames_count_against_the_output_frames() {
+        // Trimmed to 4s, 100 frames are expected and 3 of them are 3%
+        let trimmed =
+            "max_dropped_frames_pct: 2.0\non_dropped_frames: fail\nextra_options:\n  - -t: '4'";
+        assert!(check_dropped(trimmed, 3).is_err());
+        // At 50 fps twice as many frames are expected, so 6 are 1.2%
+        let faster = "max_dropped_frames_pct: 2.0\non_dropped_frames: fail\nfps: 50";
+        assert!(check_dropped(faster, 6).is_ok());
+        assert!(check_dropped(faster, 11).is_err());
+    }
+
+    #[test]
+    fn dropped_frames_over_the_limit_only_warn_by_default() {
+        assert!(check_dropped("max_dropped_frames_pct: 0.5", 100).is_ok());
+        assert!(check_dropped("video_codec: libx264", 100).is_ok());
+        // Audio has no frames to drop
+        assert!(check_dropped(
+            "max_dropped_frames_pct: 0.5\non_dropped_frames: fail\naudio_only: true",
+            100
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn duration_tolerance_is_the_larger_of_the_share_and_the_allowance() {
+        for (expected, preset_yaml, tolerance) in [
+            // 2% or 2s by default
+            (10.0, "{}", 2.0),
+            (100.0, "{}", 2.0),
+            (3600.0, "{}", 72.0),
+            (3600.0, "duration_tolerance_pct: 0.5", 18.0),
+            (
+                3600.0,
+                "duration_tolerance_pct: 0.5\nduration_tolerance: 1m",
+                60.0,
+            ),
+            (10.0, "duration_tolerance: 500ms", 0.5),
+            (
+                10.0,
+                "duration_tolerance_pct: 50\nduration_tolerance: 0s",
+                5.0,
+            ),
+            (
+                10.0,
+                "duration_tolerance_pct: 0\nduration_tolerance: 0s",
+                0.0,
+            ),
+        ] {
+            assert_eq!(
+                Transcoder::duration_tolerance(expected, &preset(preset_yaml)),
+                tolerance,
+                "{} of {}s",
+                preset_yaml,
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn short_output_fails_the_job_unless_verify_is_off() {
+        for (preset_yaml, outcome) in [
+            ("    video_codec: libx264\n", JobOutcome::Failed),
+            (
+                "    video_codec: libx264\n    verify: false\n",
+                JobOutcome::Transcoded,
+            ),
+        ] {
+            let sandbox = Sandbox::new();
+            let config = sandbox.config(&BASIC_CONFIG.replace(PRESET, preset_yaml));
+            let transcoder = Transcoder::new(config);
+            let source = sandbox.file("in/truncated.mp4");
+
+            transcoder.process_file(&source).await.unwrap();
+            transcoder.wait_until_idle().await;
+
+            let failures = transcoder.stats().failures();
+            if outcome == JobOutcome::Failed {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(
+                    failures[0].1,
+                    "Output is 4.00s long but 10.00s was expected, more than 2.00s off"
+                );
+                // Only the output is probed, the source already was
+                assert_eq!(sandbox.calls("ffprobe").len(), 2);
+            } else {
+                assert_eq!(failures, []);
+                assert_eq!(sandbox.calls("ffprobe").len(), 1);
+            }
+            assert_eq!(transcoder.stats().outcomes(), [(source, Some(outcome))]);
+        }
+    }
+
+    /// Sources in `in` transcoded over themselves, with the originals moved to `trash`
+    const IN_PLACE: &str = "
+inputs:
+  - path: {dir}/in
+    extensions: [mkv]
+default_preset: p
+default_output: o
+outputs:
+  o:
+    path: {dir}/in
+    filename_template: '{filename}'
+    container: mkv
+    on_same_file: replace
+    trash_dir: {dir}/trash
+presets:
+  p:
+    video_codec: libx264
+stability:
+  check_interval: 10ms
+  stable_for: 0s
+";
+
+    #[tokio::test]
+    async fn replace_swaps_the_verified_encode_in_for_the_source() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(IN_PLACE));
+        let source = sandbox.file("in/clip.mkv");
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(std::fs::read(&source).unwrap(), vec![0; 10]);
+        assert_eq!(
+            std::fs::read(sandbox.path().join("trash/clip.mkv")).unwrap(),
+            b"not really a video"
+        );
+        // ffmpeg never wrote to the file it was reading
+        let encode = sandbox.calls("ffmpeg").pop().unwrap();
+        assert_ne!(encode.last(), Some(&source.to_string_lossy().into_owned()));
+        assert_eq!(
+            std::fs::read_dir(sandbox.path().join("in"))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        // Its own change events don't queue the replaced source again
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_verification_leaves_the_source_in_place() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(IN_PLACE));
+        let source = sandbox.file("in/blank.mkv");
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].1.starts_with("Output file is empty"),
+            "{}",
+            failures[0].1
+        );
+        assert_eq!(std::fs::read(&source).unwrap(), b"not really a video");
+        assert!(!sandbox.path().join("trash").exists());
+        assert_eq!(
+            std::fs::read_dir(sandbox.path().join("in"))
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn same_file_is_an_error_by_default() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&IN_PLACE.replace("    on_same_file: replace\n", ""));
+        let transcoder = Transcoder::new(config);
+        let source = sandbox.file("in/clip.mkv");
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].1.contains("is the input file itself"),
+            "{}",
+            failures[0].1
+        );
+        assert!(sandbox.calls("ffmpeg").is_empty());
+        assert_eq!(std::fs::read(&source).unwrap(), b"not really a video");
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        let base = Duration::from_secs(5);
+        let cases = [
+            (1, 5),
+            (2, 10),
+            (3, 20),
+            (5, 80),
+            (11, 3600),
+            (40, 3600),
+            (u32::MAX, 3600),
+        ];
+        for (attempt, secs) in cases {
+            let delay = retry_delay(base, attempt);
+            let least = Duration::from_secs(secs);
+            // Spread by up to a quarter on top
+            assert!(
+                delay >= least && delay <= least.mul_f64(1.25),
+                "attempt {}: {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn source_never_ready_is_given_up_on_and_quarantined() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "presets:",
+            "max_retries: 2\nretry_delay: 10ms\nquarantine_dir: {dir}/quarantine\npresets:",
+        ));
+        let transcoder = Transcoder::new(config);
+        // ffprobe can't make anything of an empty file, as of one still being copied
+        let source = sandbox.path().join("in/empty.mp4");
+        std::fs::create_dir_all(source.parent().unwrap()).unwrap();
+        std::fs::write(&source, b"").unwrap();
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        assert_eq!(failures[0].0, source);
+        assert!(
+            failures[0].1.contains("giving up after 2 retries"),
+            "{}",
+            failures[0].1
+        );
+        // The first try and both retries
+        assert_eq!(sandbox.calls("ffprobe").len(), 3);
+        assert!(!source.exists());
+        assert!(sandbox.path().join("quarantine/empty.mp4").is_file());
+        assert!(transcoder.retries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_start_over_when_the_source_changes() {
+        let sandbox = Sandbox::new();
+        let config = sandbox
+            .config(&BASIC_CONFIG.replace("presets:", "max_retries: 5\nretry_delay: 1h\npresets:"));
+        let transcoder = Transcoder::new(config);
+        let source = sandbox.file("in/clip.mp4");
+        let item = || QueuedFile {
+            path: source.clone(),
+            input: None,
+            priority: 0,
+            companions_settled: false,
+            missing_companions: Vec::new(),
+            size: 0,
+            modified: None,
+            skips: 0,
+            forced: false,
+        };
+        let not_ready = || anyhow::Error::from(JobError::NotReady(source.clone()));
+        let attempts = || transcoder.retries.get(&source).unwrap().attempts;
+
+        assert!(transcoder.retry_later(item(), not_ready()).await);
+        assert!(transcoder.retry_later(item(), not_ready()).await);
+        assert_eq!(attempts(), 2);
+
+        // More data arrived: a new modification time
+        let file = std::fs::File::options().write(true).open(&source).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(transcoder.retry_later(item(), not_ready()).await);
+        assert_eq!(attempts(), 1);
+
+        // And then a new size
+        std::fs::write(&source, b"a longer version of the same video").unwrap();
+        assert!(transcoder.retry_later(item(), not_ready()).await);
+        assert_eq!(attempts(), 1);
+        assert!(transcoder.retry_later(item(), not_ready()).await);
+        assert_eq!(attempts(), 2);
+    }
+
+    #[tokio::test]
+    async fn unreadable_source_fails_without_retries() {
+        if runs_as_root() {
+            return;
+        }
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let source = sandbox.file("in/clip.mp4");
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].1.starts_with("Permission denied reading"),
+            "{}",
+            failures[0].1
+        );
+        // Neither ffprobe nor ffmpeg got to fail on it, and it isn't waiting for another try
+        assert!(sandbox.calls("ffprobe").is_empty());
+        assert!(sandbox.calls("ffmpeg").is_empty());
+        assert!(transcoder.retries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn instances_sharing_an_input_encode_each_source_once() {
+        let sandbox = Sandbox::new();
+        let config = sandbox
+            .config(&BASIC_CONFIG.replace("presets:", "distributed:\n  lease: 1m\npresets:"));
+        let first = Transcoder::new(config.clone());
+        let second = Transcoder::new(config);
+        let sources = ["in/slow.mp4", "in/clip.mp4"].map(|name| sandbox.file(name));
+
+        // Both see the same events, the second a little late for one of them
+        for source in &sources {
+            first.process_file(source).await.unwrap();
+        }
+        second.process_file(&sources[0]).await.unwrap();
+        first.wait_until_idle().await;
+        second.process_file(&sources[1]).await.unwrap();
+        second.wait_until_idle().await;
+
+        for name in ["slow", "clip"] {
+            let encodes = sandbox
+                .calls("ffmpeg")
+                .into_iter()
+                .filter(|args| {
+                    args.iter()
+                        .any(|arg| arg.ends_with(&format!("in/{}.mp4", name)))
+                })
+                .count();
+            assert_eq!(encodes, 1, "{}", name);
+        }
+        assert_eq!(first.stats().failures(), []);
+        assert_eq!(second.stats().failures(), []);
+        // Finished jobs released their claims
+        let claims = std::fs::read_dir(sandbox.path().join("in"))
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(crate::claim::CLAIM_SUFFIX)
+            })
+            .count();
+        assert_eq!(claims, 0);
+    }
+
+    /// Transcode `name` with `output_lines` added to output `o`, returning the transcoder.
+    /// Hidden names like `...mp4` are let through, which the default `ignore` would skip.
+    async fn run_with_output(sandbox: &Sandbox, output_lines: &str, name: &str) -> Transcoder {
+        let config = sandbox.config(
+            &BASIC_CONFIG
+                .replace(
+                    "    container: mkv\n",
+                    &format!("    container: mkv\n{}", output_lines),
+                )
+                .replace("presets:", "ignore: []\npresets:"),
+        );
+        let transcoder = Transcoder::new(config);
+        transcoder.process_file(&sandbox.file(name)).await.unwrap();
+        transcoder.wait_until_idle().await;
+        transcoder
+    }
+
+    /// Every file below `dir`, relative to it
+    fn files_below(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(current) = dirs.pop() {
+            for entry in std::fs::read_dir(&current).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path.strip_prefix(dir).unwrap().to_path_buf());
+                }
+            }
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn encoded_traversal_in_a_name_stays_literal() {
+        for subdir in ["", "    subdir_template: '{filename}'\n"] {
+            let sandbox = Sandbox::new();
+            let transcoder = run_with_output(&sandbox, subdir, "in/..%2F..%2Fetc.mp4").await;
+
+            assert_eq!(transcoder.stats().failures(), []);
+            let expected = match subdir {
+                "" => PathBuf::from("..%2F..%2Fetc.mkv"),
+                _ => PathBuf::from("..%2F..%2Fetc/..%2F..%2Fetc.mkv"),
+            };
+            assert_eq!(files_below(&sandbox.path().join("out")), [expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn dot_dot_from_a_name_or_template_fails_the_job() {
+        let cases = [
+            // The stem of `...mp4` is `..`
+            ("    subdir_template: '{filename}'\n", "in/...mp4"),
+            ("    subdir_template: '{filename}/deeper'\n", "in/...mp4"),
+            ("    subdir_template: '../{preset}'\n", "in/clip.mp4"),
+            ("    subdir_template: '{preset}/../../x'\n", "in/clip.mp4"),
+            ("    subdir_template: '/tmp/{preset}'\n", "in/clip.mp4"),
+        ];
+        for (subdir, name) in cases {
+            let sandbox = Sandbox::new();
+            let transcoder = run_with_output(&sandbox, subdir, name).await;
+
+            let failures = transcoder.stats().failures();
+            assert_eq!(failures.len(), 1, "{}", subdir);
+            assert!(
+                failures[0].1.contains("escapes output directory"),
+                "{}",
+                failures[0].1
+            );
+            assert!(sandbox.calls("ffmpeg").is_empty());
+            let outputs = files_below(sandbox.path())
+                .into_iter()
+                .filter(|file| file.extension().is_some_and(|ext| ext == "mkv"))
+                .count();
+            assert_eq!(outputs, 0, "{}", subdir);
+        }
+    }
+
+    #[tokio::test]
+    async fn symlink_out_of_the_root_is_not_followed() {
+        let sandbox = Sandbox::new();
+        let outside = sandbox.path().join("outside");
+        std::fs::create_dir_all(sandbox.path().join("out")).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, sandbox.path().join("out/link")).unwrap();
+
+        let transcoder =
+            run_with_output(&sandbox, "    subdir_template: link\n", "in/clip.mp4").await;
+
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].1.contains("outside output root"),
+            "{}",
+            failures[0].1
+        );
+        // Nothing was created on the far side of the link
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn subdirectories_behind_a_symlink_are_not_created() {
+        let sandbox = Sandbox::new();
+        let outside = sandbox.path().join("outside");
+        std::fs::create_dir_all(sandbox.path().join("out")).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, sandbox.path().join("out/link")).unwrap();
+
+        let transcoder = run_with_output(
+            &sandbox,
+            "    subdir_template: link/deeper/still\n",
+            "in/clip.mp4",
+        )
+        .await;
+
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].1.contains("outside output root"),
+            "{}",
+            failures[0].1
+        );
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+        assert!(sandbox.calls("ffmpeg").is_empty());
+    }
+
+    #[tokio::test]
+    async fn deep_templates_create_each_level() {
+        let sandbox = Sandbox::new();
+        let deep = "    subdir_template: '{input}/{year}/{month}/{day}/{preset}/{ext}'\n";
+        let config = sandbox.config(
+            &BASIC_CONFIG
+                .replace(
+                    "    container: mkv\n",
+                    &format!("    container: mkv\n{}", deep),
+                )
+                .replace(
+                    "    extensions: [mp4]\n",
+                    "    extensions: [mp4]\n    timestamp_pattern: '%Y%m%d%H%M%S'\n",
+                ),
+        );
+        let transcoder = Transcoder::new(config.clone());
+        transcoder
+            .process_file(&sandbox.file("in/ch01_20231015213045.mp4"))
+            .await
+            .unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(
+            files_below(&sandbox.path().join("out")),
+            [PathBuf::from("in/2023/10/15/p/mp4/ch01_20231015213045.mkv")]
+        );
+
+        // Flat setups get an error rather than the nesting
+        let sandbox = Sandbox::new();
+        let transcoder = run_with_output(
+            &sandbox,
+            &format!("{}    create_subdirs: false\n", deep),
+            "in/clip.mp4",
+        )
+        .await;
+        let failures = transcoder.stats().failures();
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].1.contains("create_subdirs is disabled"),
+            "{}",
+            failures[0].1
+        );
+        assert!(!sandbox.path().join("out/in").exists());
+    }
+
+    #[tokio::test]
+    async fn clips_recorded_outside_the_window_are_skipped() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "    extensions: [mp4]\n",
+            "    extensions: [mp4]\n    recorded_between: 21:00-06:00\n    timestamp_pattern: '%Y%m%d_%H%M%S'\n",
+        ));
+        let transcoder = Transcoder::new(config);
+        let day = sandbox.file("in/RecM01_20231015_120000_120100_6E36B00.mp4");
+        let night = sandbox.file("in/RecM01_20231015_233000_233100_6E36B00.mp4");
+
+        for source in [&day, &night] {
+            transcoder.process_file(source).await.unwrap();
+        }
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(
+            transcoder.stats().outcomes(),
+            [
+                (
+                    day.clone(),
+                    Some(JobOutcome::SkippedFilter {
+                        reason:
+                            "recorded at 2023-10-15 12:00:00 (from file name), outside 21:00-06:00"
+                                .to_string()
+                    })
+                ),
+                (night.clone(), Some(JobOutcome::Transcoded)),
+            ]
+        );
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+    }
+
+    /// Outcome of the one job for `name` under BASIC_CONFIG with each `(from, to)` replaced
+    async fn outcome_of(edits: &[(&str, &str)], name: &str) -> Option<JobOutcome> {
+        let sandbox = Sandbox::new();
+        let yaml = edits
+            .iter()
+            .fold(BASIC_CONFIG.to_string(), |yaml, (from, to)| {
+                assert!(yaml.contains(from), "{}", from);
+                yaml.replace(from, to)
+            });
+        let transcoder = Transcoder::new(sandbox.config(&yaml));
+        let source = sandbox.file(name);
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let outcomes = transcoder.stats().outcomes();
+        assert_eq!(outcomes.len(), 1, "{:?}", outcomes);
+        outcomes[0].1.clone()
+    }
+
+    const INPUT: &str = "    extensions: [mp4]\n";
+    const PRESET: &str = "    video_codec: libx264\n";
+
+    #[tokio::test]
+    async fn each_skip_path_has_its_own_outcome() {
+        let cases = [
+            (vec![], Some(JobOutcome::Transcoded)),
+            (
+                vec![(INPUT, "    extensions: [mp4]\n    min_duration: 20s\n")],
+                Some(JobOutcome::SkippedFilter {
+                    reason: "10.0s is shorter than min_duration 20s".to_string(),
+                }),
+            ),
+            (
+                vec![(INPUT, "    extensions: [mp4]\n    skip_if_video_codec: [hevc, h264]\n")],
+                Some(JobOutcome::SkippedCodec {
+                    codec: "h264".to_string(),
+                }),
+            ),
+            (
+                vec![(
+                    INPUT,
+                    "    extensions: [mp4]\n    normalize:\n      video_codecs: [h264]\n      audio_codecs: [aac]\n",
+                )],
+                Some(JobOutcome::SkippedCompliant),
+            ),
+        ];
+        for (edits, expected) in cases {
+            assert_eq!(
+                outcome_of(&edits, "in/clip.mp4").await,
+                expected,
+                "{:?}",
+                edits
+            );
+        }
+        assert_eq!(
+            outcome_of(&[], "in/broken.mp4").await,
+            Some(JobOutcome::Failed)
+        );
+        // Half encoded at 1000 bytes projects 2000 for the 18 byte source
+        assert_eq!(
+            outcome_of(
+                &[(
+                    PRESET,
+                    "    video_codec: libx264\n    if_larger_than_source: keep_original\n",
+                )],
+                "in/large.mp4"
+            )
+            .await,
+            Some(JobOutcome::KeptOriginal {
+                projected_size: 2000,
+                source_size: 18,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn outgrowing_encode_is_remuxed_instead() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            PRESET,
+            "    video_codec: libx264\n    if_larger_than_source: remux\n",
+        ));
+        let transcoder = Transcoder::new(config);
+        let source = sandbox.file("in/large.mp4");
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(
+            transcoder.stats().outcomes(),
+            [(source, Some(JobOutcome::Transcoded))]
+        );
+        let encodes: Vec<_> = sandbox
+            .calls("ffmpeg")
+            .into_iter()
+            .filter(|args| args.last().is_some_and(|arg| arg.ends_with(".mkv")))
+            .collect();
+        assert_eq!(encodes.len(), 2, "{:?}", encodes);
+        assert!(encodes[0]
+            .windows(2)
+            .any(|pair| pair == ["-c:v", "libx264"]));
+        assert!(encodes[1].windows(2).any(|pair| pair == ["-c:v", "copy"]));
+        assert!(sandbox.path().join("out/large.mkv").is_file());
+    }
+
+    #[tokio::test]
+    async fn sources_outside_the_size_limits_are_never_queued() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            INPUT,
+            "    extensions: [mp4]\n    min_size: 10B\n    max_size: 20B\n",
+        ));
+        let transcoder = Transcoder::new(config);
+        let small = sandbox.path().join("in/small.mp4");
+        let large = sandbox.path().join("in/large.mp4");
+        std::fs::write(&small, b"tiny").unwrap();
+        std::fs::write(&large, [0; 100]).unwrap();
+        let fits = sandbox.file("in/fits.mp4");
+
+        for source in [&small, &large, &fits] {
+            transcoder.process_file(source).await.unwrap();
+        }
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(
+            transcoder.stats().outcomes(),
+            [(fits, Some(JobOutcome::Transcoded))]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_run_waits_for_files_to_age() {
+        let sandbox = Sandbox::new();
+        let config = sandbox
+            .config(&BASIC_CONFIG.replace(INPUT, "    extensions: [mp4]\n    min_age: 500ms\n"));
+        let transcoder = Transcoder::new(config);
+        let source = sandbox.file("in/clip.mp4");
+        let started = std::time::Instant::now();
+
+        transcoder.process_file(&source).await.unwrap();
+        assert_eq!(sandbox.calls("ffmpeg").len(), 0);
+        transcoder.wait_until_idle().await;
+
+        assert!(started.elapsed() >= std::time::Duration::from_millis(400));
+        assert_eq!(
+            transcoder.stats().outcomes(),
+            [(source, Some(JobOutcome::Transcoded))]
+        );
+    }
+
+    #[tokio::test]
+    async fn inputs_without_a_target_use_the_defaults() {
+        let sandbox = Sandbox::new();
+        let without_target = BASIC_CONFIG.replace("    preset: p\n    output: o\n", "");
+        let config = sandbox.config(
+            &without_target.replace("presets:", "default_preset: p\ndefault_output: o\npresets:"),
+        );
+        assert_eq!(config.inputs[0].preset, "p");
+        assert_eq!(config.inputs[0].output, "o");
+
+        let transcoder = Transcoder::new(config);
+        transcoder
+            .process_file(&sandbox.file("in/clip.mp4"))
+            .await
+            .unwrap();
+        transcoder.wait_until_idle().await;
+        assert!(sandbox.path().join("out/clip.mkv").is_file());
+
+        // Without the defaults there is nothing to go by
+        let path = sandbox.path().join("no-defaults.yaml");
+        std::fs::write(
+            &path,
+            without_target.replace("{dir}", &sandbox.path().to_string_lossy()),
+        )
+        .unwrap();
+        assert!(crate::config::load_config(&path, false).is_err());
+    }
+
+    #[tokio::test]
+    async fn existing_output_is_skipped() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let source = sandbox.file("in/clip.mp4");
+        sandbox.file("out/clip.mkv");
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(
+            transcoder.stats().outcomes(),
+            [(source, Some(JobOutcome::SkippedExisting))]
+        );
+        assert!(sandbox.calls("ffmpeg").is_empty());
+    }
+
+    #[tokio::test]
+    async fn outputs_older_than_their_source_are_transcoded_again() {
+        let config = BASIC_CONFIG.replace(
+            "    container: mkv\n",
+            "    container: mkv\n    if_source_newer: retranscode\n",
+        );
+        for (output_age, expected) in [
+            (10, JobOutcome::Transcoded),
+            // Within the margin, as a copy that kept its times may be
+            (1, JobOutcome::SkippedExisting),
+        ] {
+            let sandbox = Sandbox::new();
+            let transcoder = Transcoder::new(sandbox.config(&config));
+            let source = sandbox.file("in/clip.mp4");
+            let output = sandbox.file("out/clip.mkv");
+            std::fs::write(&output, b"old output").unwrap();
+            let source_time = source.metadata().unwrap().modified().unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&output)
+                .unwrap()
+                .set_modified(source_time - Duration::from_secs(output_age))
+                .unwrap();
+
+            transcoder.process_file(&source).await.unwrap();
+            transcoder.wait_until_idle().await;
+
+            let transcoded = expected == JobOutcome::Transcoded;
+            assert_eq!(
+                transcoder.stats().outcomes(),
+                [(source, Some(expected))],
+                "{}s",
+                output_age
+            );
+            let calls = sandbox.calls("ffmpeg");
+            if transcoded {
+                assert_eq!(std::fs::read(&output).unwrap(), [0; 10]);
+                // Encoded next to the old output, which is only replaced once verified
+                assert_ne!(
+                    calls[0].last().unwrap(),
+                    &*output.to_string_lossy(),
+                    "{}s",
+                    output_age
+                );
+            } else {
+                assert_eq!(std::fs::read(&output).unwrap(), b"old output");
+                assert!(calls.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_would_transcode() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        transcoder.enable_dry_run();
+        let source = sandbox.file("in/clip.mp4");
+
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(
+            transcoder.stats().outcomes(),
+            [(source, Some(JobOutcome::WouldTranscode))]
+        );
+        assert!(sandbox.calls("ffmpeg").is_empty());
+        assert!(!sandbox.path().join("out/clip.mkv").exists());
+    }
+
+    #[tokio::test]
+    async fn express_dry_run_would_transcode() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        transcoder.enable_dry_run();
+        let source = sandbox.file("in/clip.mp4");
+
+        let records = transcoder.transcode_now(&source, None).await.unwrap();
+
+        let outcomes: Vec<_> = records
+            .iter()
+            .map(|record| record.outcome.clone())
+            .collect();
+        assert_eq!(outcomes, [Some(JobOutcome::WouldTranscode)]);
+        assert!(sandbox.calls("ffmpeg").is_empty());
+        assert!(!sandbox.path().join("out/clip.mkv").exists());
+    }
+
+    #[tokio::test]
+    async fn sources_seen_before_are_skipped() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "presets:",
+            "history_file: {dir}/history.jsonl\nhistory_db: {dir}/history.db\nskip_if_in_history: true\ndedup_by_hash: true\nhash_store:\n  path: {dir}/hashes.json\npresets:",
+        ));
+        let first = sandbox.file("in/clip.mp4");
+        let transcoder = Transcoder::new(config.clone());
+        transcoder.process_file(&first).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        // The same content under another name, and the first source once its output is gone
+        let copy = sandbox.file("in/copy.mp4");
+        std::fs::remove_file(sandbox.path().join("out/clip.mkv")).unwrap();
+        let transcoder = Transcoder::new(config);
+        transcoder.process_file(&copy).await.unwrap();
+        transcoder.wait_until_idle().await;
+        transcoder.process_file(&first).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        let outcomes = transcoder.stats().outcomes();
+        assert_eq!(
+            outcomes[0],
+            (
+                copy,
+                Some(JobOutcome::SkippedDuplicate {
+                    original: first.clone()
+                })
+            )
+        );
+        assert!(
+            matches!(&outcomes[1], (source, Some(JobOutcome::SkippedInHistory { .. })) if *source == first),
+            "{:?}",
+            outcomes[1]
+        );
+        // The history carries each outcome as it was
+        let kinds: Vec<_> = crate::history::load(&sandbox.path().join("history.jsonl"))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.outcome)
+            .collect();
+        assert_eq!(kinds.len(), 3);
+        assert_eq!(kinds[0], Some(JobOutcome::Transcoded));
+        assert_eq!(kinds[1], outcomes[0].1);
+        assert_eq!(kinds[2], outcomes[1].1);
+    }
+
+    /// BASIC_CONFIG with the input waiting up to `timeout` for an srt next to each video
+    fn companion_config(sandbox: &Sandbox, timeout: &str) -> Arc<Config> {
+        sandbox.config(&BASIC_CONFIG.replace(
+            "    extensions: [mp4]\n",
+            &format!(
+                "    extensions: [mp4]\n    wait_for_companion: [srt]\n    companion_timeout: {}\n",
+                timeout
+            ),
+        ))
+    }
+
+    #[tokio::test]
+    async fn companion_arriving_late_is_waited_for() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(companion_config(&sandbox, "1m"));
+        let source = sandbox.file("in/clip.mp4");
+        transcoder.process_file(&source).await.unwrap();
+
+        while transcoder.waiting_for_companions().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            transcoder.waiting_for_companions(),
+            [(source.clone(), vec!["srt".to_string()])]
+        );
+        assert!(sandbox.calls("ffmpeg").is_empty());
+
+        sandbox.file("in/clip.srt");
+        transcoder.wait_until_idle().await;
+        assert!(transcoder.waiting_for_companions().is_empty());
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(transcoder.stats().missing_companions(), []);
+        assert!(sandbox.path().join("out/clip.mkv").is_file());
+    }
+
+    #[tokio::test]
+    async fn missing_companion_is_given_up_on_with_a_warning() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(companion_config(&sandbox, "1s"));
+        let source = sandbox.file("in/clip.mp4");
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert!(sandbox.path().join("out/clip.mkv").is_file());
+        assert_eq!(
+            transcoder.stats().missing_companions(),
+            [(source.clone(), vec!["srt".to_string()])]
+        );
+        let records = transcoder.stats().records();
+        assert_eq!(
+            records[0].warnings,
+            ["Transcoded without companion(s): srt"]
+        );
+        assert!(transcoder.waiting_for_companions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn metrics_file_totals_cpu_time_per_preset() {
+        let sandbox = Sandbox::new();
+        let config = sandbox
+            .config(&BASIC_CONFIG.replace("presets:", "metrics_file: {dir}/sstc.prom\npresets:"));
+        let transcoder = Transcoder::new(config);
+        transcoder
+            .process_file(&sandbox.file("in/clip.mp4"))
+            .await
+            .unwrap();
+        transcoder
+            .process_file(&sandbox.file("in/broken.mp4"))
+            .await
+            .unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures().len(), 1);
+        let cpu = transcoder.stats().cpu_secs_by_preset();
+        assert_eq!(cpu.keys().collect::<Vec<_>>(), ["p"]);
+        let text = std::fs::read_to_string(sandbox.path().join("sstc.prom")).unwrap();
+        assert_eq!(text, metrics::render(&cpu));
+        assert!(
+            text.contains("sstc_cpu_seconds_total{preset=\"p\"} "),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn panicking_job_fails_alone() {
+        let _poison = crate::test_support::poison_jobs();
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let sources = ["in/a.mp4", "in/poison.mp4", "in/z.mp4"].map(|name| sandbox.file(name));
+
+        for source in &sources {
+            transcoder.process_file(source).await.unwrap();
+        }
+        transcoder.wait_until_idle().await;
+
+        assert!(sandbox.path().join("out/a.mkv").is_file());
+        assert!(sandbox.path().join("out/z.mkv").is_file());
+        assert!(!sandbox.path().join("out/poison.mkv").exists());
+        assert_eq!(
+            transcoder.stats().failures(),
+            [(sources[1].clone(), "job panicked".to_string())]
+        );
+        // The panic left no bookkeeping behind, so the source can be queued again
+        assert_eq!(transcoder.running_jobs(), 0);
+        assert_eq!(transcoder.queued_files().await, 0);
+    }
+
+    #[tokio::test]
+    async fn input_options_reach_ffmpeg_before_the_input() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(
+            &BASIC_CONFIG
+                .replace("presets:", "input_options: [-fflags, +genpts]\npresets:")
+                .replace(
+                    PRESET,
+                    "    video_codec: libx264\n    input_options: [-probesize, 50M, -analyzeduration, 100M]\n",
+                ),
+        );
+        let transcoder = Transcoder::new(config);
+        let source = sandbox.file("in/clip.mp4");
+        transcoder.process_file(&source).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        let encode = sandbox.calls("ffmpeg").pop().unwrap();
+        let input = encode.iter().position(|arg| arg == "-i").unwrap();
+        assert_eq!(
+            encode[input - 6..=input + 1],
+            [
+                "-fflags",
+                "+genpts",
+                "-probesize",
+                "50M",
+                "-analyzeduration",
+                "100M",
+                "-i",
+                &source.to_string_lossy(),
+            ]
+        );
+    }
+
+    /// A transcoder allowing one job and two queued files, with `slow.mp4` running
+    async fn with_full_queue(sandbox: &Sandbox, policy: &str) -> Transcoder {
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "presets:",
+            &format!(
+                "max_parallel_jobs: 1\nmax_queue_size: 2\n{}presets:",
+                policy
+            ),
+        ));
+        let transcoder = Transcoder::new(config);
+        transcoder
+            .process_file(&sandbox.file("in/slow.mp4"))
+            .await
+            .unwrap();
+        while transcoder.running_jobs() == 0 || transcoder.queued_files().await > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for name in ["in/a.mp4", "in/b.mp4"] {
+            transcoder.process_file(&sandbox.file(name)).await.unwrap();
+        }
+        assert_eq!(transcoder.queued_files().await, 2);
+        transcoder
+    }
+
+    fn outputs_of(sandbox: &Sandbox) -> Vec<PathBuf> {
+        let mut outputs = files_below(&sandbox.path().join("out"));
+        outputs.sort();
+        outputs
+    }
+
+    #[tokio::test]
+    async fn full_queue_makes_new_files_wait() {
+        let sandbox = Sandbox::new();
+        let transcoder = with_full_queue(&sandbox, "").await;
+        let late = sandbox.file("in/c.mp4");
+
+        // Neither scans nor the watcher get past a full queue by default
+        let wait = Duration::from_millis(200);
+        assert!(timeout(wait, transcoder.process_file(&late)).await.is_err());
+        assert!(timeout(wait, transcoder.process_watched_file(&late))
+            .await
+            .is_err());
+        // Files already queued don't need the room
+        timeout(
+            wait,
+            transcoder.process_file(&sandbox.path().join("in/a.mp4")),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(transcoder.queued_files().await, 2);
+
+        // Queued once the running job lets the next file start
+        transcoder.process_file(&late).await.unwrap();
+        transcoder.wait_until_idle().await;
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(
+            outputs_of(&sandbox),
+            ["a.mkv", "b.mkv", "c.mkv", "slow.mkv"].map(PathBuf::from)
+        );
+        assert!(!transcoder.take_dropped_files().await);
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_watched_files_for_a_rescan() {
+        let sandbox = Sandbox::new();
+        let transcoder = with_full_queue(&sandbox, "on_queue_full: drop\n").await;
+        let late = sandbox.file("in/c.mp4");
+
+        timeout(
+            Duration::from_millis(200),
+            transcoder.process_watched_file(&late),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(transcoder.queued_files().await, 2);
+        // No rescan while there is still no room for it
+        assert!(!transcoder.take_dropped_files().await);
+
+        transcoder.wait_until_idle().await;
+        assert_eq!(
+            outputs_of(&sandbox),
+            ["a.mkv", "b.mkv", "slow.mkv"].map(PathBuf::from)
+        );
+        // Once there is room, one rescan picks the dropped file up
+        assert!(transcoder.take_dropped_files().await);
+        assert!(!transcoder.take_dropped_files().await);
+        transcoder.process_file(&late).await.unwrap();
+        transcoder.wait_until_idle().await;
+        assert_eq!(transcoder.stats().failures(), []);
+        assert!(sandbox.path().join("out/c.mkv").is_file());
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_are_neither_failed_nor_retried() {
+        let sandbox = Sandbox::new();
+        let transcoder = with_full_queue(&sandbox, "history_file: {dir}/history.jsonl\n").await;
+        let slow = sandbox.path().join("in/slow.mp4");
+        while transcoder.running_sources().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            transcoder.cancel(&sandbox.path().join("in/a.mp4")).await,
+            CancelResult::Dequeued
+        );
+        assert_eq!(transcoder.cancel(&slow).await, CancelResult::Stopped);
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(outputs_of(&sandbox), ["b.mkv"].map(PathBuf::from));
+        assert!(transcoder
+            .stats()
+            .outcomes()
+            .contains(&(slow.clone(), Some(JobOutcome::Cancelled))));
+        let history = crate::history::load(&sandbox.path().join("history.jsonl")).unwrap();
+        let entry = history.iter().find(|entry| entry.source == slow).unwrap();
+        assert_eq!(entry.outcome, Some(JobOutcome::Cancelled));
+        assert_eq!(entry.error, None);
+        assert_eq!(transcoder.cancel(&slow).await, CancelResult::NotFound);
+    }
+
+    #[tokio::test]
+    async fn paused_queue_keeps_files_until_resumed() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        assert!(transcoder.pause().await);
+        assert!(!transcoder.pause().await);
+
+        transcoder
+            .process_file(&sandbox.file("in/clip.mp4"))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(transcoder.queued_files().await, 1);
+        assert_eq!(transcoder.running_jobs(), 0);
+
+        assert!(transcoder.resume().await);
+        assert!(!transcoder.resume().await);
+        transcoder.wait_until_idle().await;
+        assert_eq!(transcoder.stats().failures(), []);
+        assert!(sandbox.path().join("out/clip.mkv").is_file());
+    }
+
+    #[tokio::test]
+    async fn pause_suspends_running_jobs_when_asked() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(
+            sandbox.config(&BASIC_CONFIG.replace("presets:", "suspend_running: true\npresets:")),
+        );
+        transcoder
+            .process_file(&sandbox.file("in/slow.mp4"))
+            .await
+            .unwrap();
+        while !transcoder
+            .job_controls
+            .iter()
+            .any(|entry| entry.value().ffmpeg_pid.lock().unwrap().is_some())
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        transcoder.pause().await;
+        // The encode would be done in a second if it weren't stopped
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!sandbox.path().join("out/slow.mkv").exists());
+        assert_eq!(transcoder.running_jobs(), 1);
+
+        transcoder.resume().await;
+        transcoder.wait_until_idle().await;
+        assert_eq!(transcoder.stats().failures(), []);
+        assert!(sandbox.path().join("out/slow.mkv").is_file());
+    }
+
+    #[tokio::test]
+    async fn jobs_past_their_time_limit_are_killed_and_not_retried() {
+        for (limit, source, message) in [
+            (
+                "max_job_duration: 300ms",
+                "hung",
+                "Killed ffmpeg after 0s (max_job_duration)",
+            ),
+            // Half a second for the fake's ten-second source, which takes a second
+            (
+                "max_speed_ratio: 0.05",
+                "slow",
+                "Killed ffmpeg after 0s (max_speed_ratio)",
+            ),
+        ] {
+            let sandbox = Sandbox::new();
+            let config = sandbox.config(&BASIC_CONFIG.replace(
+                "presets:",
+                &format!("{}\nmax_retries: 3\nretry_delay: 10ms\npresets:", limit),
+            ));
+            let transcoder = Transcoder::new(config);
+            let input = sandbox.file(&format!("in/{}.mp4", source));
+
+            let started = std::time::Instant::now();
+            transcoder.process_file(&input).await.unwrap();
+            transcoder.wait_until_idle().await;
+
+            assert!(started.elapsed() < Duration::from_secs(1), "{}", limit);
+            assert_eq!(
+                transcoder.stats().failures(),
+                [(input.clone(), message.to_string())]
+            );
+            // Nothing of the partial output is left, and it ran once
+            assert!(!sandbox.path().join(format!("out/{}.mkv", source)).exists());
+            assert_eq!(
+                std::fs::read_dir(sandbox.path().join("out"))
+                    .unwrap()
+                    .count(),
+                0
+            );
+            assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+            assert!(transcoder.retries.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn only_forced_files_are_transcoded_over_their_outputs() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        for name in ["a", "b"] {
+            std::fs::write(sandbox.file(&format!("out/{}.mkv", name)), b"old output").unwrap();
+        }
+
+        transcoder
+            .force_file(&sandbox.file("in/a.mp4"), None)
+            .await
+            .unwrap();
+        transcoder
+            .process_file(&sandbox.file("in/b.mp4"))
+            .await
+            .unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(
+            std::fs::read(sandbox.path().join("out/a.mkv")).unwrap(),
+            [0; 10]
+        );
+        assert_eq!(
+            std::fs::read(sandbox.path().join("out/b.mkv")).unwrap(),
+            b"old output"
+        );
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_forced_run_keeps_the_existing_output() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let output = sandbox.file("out/broken.mkv");
+        std::fs::write(&output, b"old output").unwrap();
+        let source = sandbox.file("in/broken.mp4");
+
+        transcoder.force_file(&source, None).await.unwrap();
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures().len(), 1);
+        assert_eq!(std::fs::read(&output).unwrap(), b"old output");
+        // The replacement it was encoding to is gone
+        assert_eq!(
+            std::fs::read_dir(sandbox.path().join("out"))
+                .unwrap()
+                .count(),
+            1
+        );
+        let args = sandbox.calls("ffmpeg").pop().unwrap();
+        assert_ne!(args.last().unwrap(), &*output.to_string_lossy());
+    }
+
+    /// A transcoder whose schedule window reads the time from `now`, checked every 10ms
+    fn scheduled_transcoder(
+        config: Arc<Config>,
+        now: &Arc<std::sync::Mutex<NaiveDateTime>>,
+    ) -> Transcoder {
+        let now = now.clone();
+        let clock = ScheduleClock {
+            now: Arc::new(move || *now.lock().unwrap()),
+            poll_interval: Duration::from_millis(10),
+        };
+        Transcoder::with_clock(config, clock)
+    }
+
+    fn at(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("2024-03-04 {}", time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[tokio::test]
+    async fn closed_schedule_window_holds_the_queue_until_it_opens() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "presets:",
+            "schedule:\n  start: '01:00'\n  end: '06:00'\npresets:",
+        ));
+        let now = Arc::new(std::sync::Mutex::new(at("12:00")));
+        let transcoder = scheduled_transcoder(config, &now);
+
+        transcoder
+            .process_file(&sandbox.file("in/clip.mp4"))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(transcoder.queued_files().await, 1);
+        assert!(transcoder.schedule_paused.load(Ordering::SeqCst));
+        assert!(sandbox.calls("ffmpeg").is_empty());
+
+        *now.lock().unwrap() = at("01:00");
+        timeout(Duration::from_secs(5), transcoder.wait_until_idle())
+            .await
+            .unwrap();
+        assert!(!transcoder.schedule_paused.load(Ordering::SeqCst));
+        assert!(sandbox.path().join("out/clip.mkv").is_file());
+        assert_eq!(transcoder.stats().failures(), []);
+    }
+
+    #[tokio::test]
+    async fn hard_stop_requeues_the_running_source_for_the_next_window() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "presets:",
+            "schedule:\n  start: '01:00'\n  end: '06:00'\n  hard_stop: true\npresets:",
+        ));
+        let now = Arc::new(std::sync::Mutex::new(at("05:59")));
+        let transcoder = scheduled_transcoder(config, &now);
+        let source = sandbox.file("in/slow.mp4");
+
+        transcoder.process_file(&source).await.unwrap();
+        while transcoder.running_jobs() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // The window closes before the encode reports its first progress
+        *now.lock().unwrap() = at("06:00");
+        while transcoder.queued_files().await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(transcoder.running_jobs(), 0);
+        assert!(transcoder.schedule_paused.load(Ordering::SeqCst));
+        assert!(!sandbox.path().join("out/slow.mkv").exists());
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+
+        *now.lock().unwrap() = at("01:00");
+        timeout(Duration::from_secs(5), transcoder.wait_until_idle())
+            .await
+            .unwrap();
+        assert!(sandbox.path().join("out/slow.mkv").is_file());
+        assert_eq!(sandbox.calls("ffmpeg").len(), 2);
+        assert_eq!(transcoder.stats().failures(), []);
+    }
+
+    #[tokio::test]
+    async fn higher_priority_input_jumps_the_queue() {
+        let sandbox = Sandbox::new();
+        let config = sandbox.config(&BASIC_CONFIG.replace(
+            "outputs:",
+            "  - path: {dir}/urgent\n    extensions: [mp4]\n    preset: p\n    output: o\n    priority: 5\noutputs:",
+        ).replace("presets:", "max_parallel_jobs: 1\npresets:"));
+        let transcoder = Transcoder::new(config);
+
+        transcoder.pause().await;
+        for name in ["in/a.mp4", "in/b.mp4", "urgent/c.mp4"] {
+            transcoder.process_file(&sandbox.file(name)).await.unwrap();
+        }
+        assert_eq!(transcoder.queued_files().await, 3);
+        transcoder.resume().await;
+        transcoder.wait_until_idle().await;
+
+        let started: Vec<String> = sandbox
+            .calls("ffmpeg")
+            .iter()
+            .map(|args| {
+                let input = args.iter().position(|arg| arg == "-i").unwrap();
+                args[input + 1].rsplit('/').next().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(started, ["c.mp4", "a.mp4", "b.mp4"]);
+    }
+
+    #[test]
+    fn queue_order_lets_no_file_starve() {
+        let file = |name: &str, size| QueuedFile {
+            path: PathBuf::from(name),
+            input: None,
+            priority: 0,
+            companions_settled: false,
+            missing_companions: Vec::new(),
+            size,
+            modified: None,
+            skips: 0,
+            forced: false,
+        };
+        let order = QueueOrder::SmallestFirst;
+        let mut queue = PriorityQueue::new();
+        queue.push_back(0, file("recording.mp4", 1 << 30));
+
+        // Small clips keep arriving, each going ahead of the recording
+        for clip in 0..MAX_QUEUE_SKIPS {
+            queue.push_back(0, file(&format!("clip{}.mp4", clip), 1 << 20));
+            let item = queue
+                .pop_by(|item| item.rank(order), |item| item.skips += 1)
+                .unwrap();
+            assert_eq!(item.path, PathBuf::from(format!("clip{}.mp4", clip)));
+        }
+
+        // Passed over often enough, it goes ahead of anything smaller
+        queue.push_back(0, file("last.mp4", 1));
+        let item = queue
+            .pop_by(|item| item.rank(order), |item| item.skips += 1)
+            .unwrap();
+        assert_eq!(item.path, PathBuf::from("recording.mp4"));
+        assert_eq!(item.skips, MAX_QUEUE_SKIPS);
+    }
+
+    /// BASIC_CONFIG with a queue file in the sandbox and one job at a time
+    fn queue_file_config(sandbox: &Sandbox) -> Arc<Config> {
+        sandbox.config(&BASIC_CONFIG.replace(
+            "presets:",
+            "max_parallel_jobs: 1\nqueue_file: {dir}/queue.json\npresets:",
+        ))
+    }
+
+    /// The files in the sandbox's queue file, once restoring is done with it and every change
+    /// to the queue so far is written
+    async fn saved_queue(transcoder: &Transcoder, sandbox: &Sandbox) -> Vec<PathBuf> {
+        while transcoder.queue_file.get().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        transcoder.flush_queue().await;
+        QueueFile::new(sandbox.path().join("queue.json"))
+            .load()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn running_jobs_stay_in_the_queue_file_until_they_finish() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(queue_file_config(&sandbox));
+        transcoder.restore_queue();
+        while transcoder.queue_file.get().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let slow = sandbox.file("in/slow.mp4");
+        let next = sandbox.file("in/next.mp4");
+        transcoder.process_file(&slow).await.unwrap();
+        while transcoder.running_jobs() == 0 || transcoder.queued_files().await > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        transcoder.process_file(&next).await.unwrap();
+        // A crash now would lose neither the encode nor the file waiting behind it
+        assert_eq!(saved_queue(&transcoder, &sandbox).await, [slow, next]);
+
+        transcoder.wait_until_idle().await;
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(
+            saved_queue(&transcoder, &sandbox).await,
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn restored_queue_skips_stale_entries_and_repeats_nothing() {
+        let sandbox = Sandbox::new();
+        let config = queue_file_config(&sandbox);
+        let kept = sandbox.file("in/kept.mp4");
+        let unmatched = sandbox.file("elsewhere/clip.mp4");
+        QueueFile::new(sandbox.path().join("queue.json"))
+            .save([
+                kept.as_path(),
+                &sandbox.path().join("in/gone.mp4"),
+                unmatched.as_path(),
+            ])
+            .unwrap();
+
+        let transcoder = Transcoder::new(config);
+        transcoder.restore_queue();
+        // The startup scan offers the same file while it is being restored
+        transcoder.process_file(&kept).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        let encodes: Vec<_> = sandbox
+            .calls("ffmpeg")
+            .into_iter()
+            .filter(|args| args.iter().any(|arg| arg.ends_with(".mkv")))
+            .collect();
+        assert_eq!(encodes.len(), 1, "{:?}", encodes);
+        assert!(sandbox.path().join("out/kept.mkv").is_file());
+        assert!(!sandbox.path().join("out/clip.mkv").exists());
+        assert_eq!(
+            saved_queue(&transcoder, &sandbox).await,
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_express_calls_share_one_ffmpeg_run() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let source = sandbox.file("in/slow.mp4");
+
+        let (first, second) = tokio::join!(
+            transcoder.transcode_now(&source, None),
+            transcoder.transcode_now(&source, None)
+        );
+
+        let first = first.unwrap();
+        let second = second.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].output, second[0].output);
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn joining_an_express_job_takes_its_preset_override() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let source = sandbox.file("in/slow.mp4");
+
+        let job = transcoder.express(&source, None).unwrap();
+        let error = transcoder.express(&source, Some("p")).err().unwrap();
+        assert!(
+            error.to_string().contains("already being transcoded"),
+            "{}",
+            error
+        );
+        let joined = transcoder.express(&source, None).unwrap();
+        assert_eq!(joined.id, job.id);
+
+        job.result().await.unwrap();
+        assert!(matches!(
+            transcoder.express_status(joined.id),
+            Some(ExpressStatus::Finished { result: Ok(_), .. })
+        ));
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn jobs_past_the_grace_period_are_killed_and_cleaned_up() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        let hung = sandbox.file("in/hung.mp4");
+        let output = sandbox.path().join("out/hung.mkv");
+        transcoder.process_file(&hung).await.unwrap();
+        while !output.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        transcoder.shut_down();
+        let started = std::time::Instant::now();
+        let interrupted = transcoder
+            .drain_running_jobs(Duration::from_millis(200), std::future::pending())
+            .await;
+
+        assert_eq!(interrupted, [hung]);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(transcoder.running_jobs(), 0);
+        assert!(!output.exists());
+        assert_eq!(transcoder.stats().failures(), []);
+    }
+
+    #[tokio::test]
+    async fn jobs_finishing_within_the_grace_period_are_left_alone() {
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(BASIC_CONFIG));
+        transcoder
+            .process_file(&sandbox.file("in/slow.mp4"))
+            .await
+            .unwrap();
+        while transcoder.running_jobs() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        transcoder.shut_down();
+        let interrupted = transcoder
+            .drain_running_jobs(Duration::from_secs(30), std::future::pending())
+            .await;
+
+        assert_eq!(interrupted, Vec::<PathBuf>::new());
+        assert!(sandbox.path().join("out/slow.mkv").is_file());
+    }
+
+    #[tokio::test]
+    async fn queue_busy_again_within_the_drain_settle_runs_no_drain_hook() {
+        const HOOKS: &str = "
+on_queue_active: echo active >> {dir}/hooks.log
+on_queue_drained: echo drained >> {dir}/hooks.log
+drain_settle: 300ms
+";
+        let sandbox = Sandbox::new();
+        let transcoder = Transcoder::new(sandbox.config(&format!("{}{}", HOOKS, BASIC_CONFIG)));
+        let log = sandbox.path().join("hooks.log");
+        let hook_log = || std::fs::read_to_string(&log).unwrap_or_default();
+
+        transcoder
+            .process_file(&sandbox.file("in/first.mp4"))
+            .await
+            .unwrap();
+        transcoder.wait_until_idle().await;
+        transcoder
+            .process_file(&sandbox.file("in/second.mp4"))
+            .await
+            .unwrap();
+        transcoder.wait_until_idle().await;
+
+        while !hook_log().contains("drained") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Past the settle period of the first drain too
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(hook_log(), "active\ndrained\n");
+    }
+
+    #[tokio::test]
+    async fn reload_drops_queued_files_no_input_takes_and_spares_running_jobs() {
+        let sandbox = Sandbox::new();
+        let config = BASIC_CONFIG
+            .replace("extensions: [mp4]", "extensions: [mp4, mov]")
+            .replace("presets:", "max_parallel_jobs: 1\npresets:");
+        let transcoder = Transcoder::new(sandbox.config(&config));
+        let slow = sandbox.file("in/slow.mp4");
+        transcoder.process_file(&slow).await.unwrap();
+        while transcoder.running_jobs() == 0 || transcoder.queued_files().await > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        transcoder
+            .process_file(&sandbox.file("in/clip.mp4"))
+            .await
+            .unwrap();
+        transcoder
+            .process_file(&sandbox.file("in/kept.mov"))
+            .await
+            .unwrap();
+
+        let reloaded = config
+            .replace("extensions: [mp4, mov]", "extensions: [mov]")
+            .replace("libx264", "libx265");
+        transcoder.reload(sandbox.config(&reloaded)).await;
+        assert_eq!(transcoder.queued_files().await, 1);
+        transcoder.wait_until_idle().await;
+
+        assert_eq!(transcoder.stats().failures(), []);
+        let codec_of = |name: &str| {
+            let args = sandbox
+                .calls("ffmpeg")
+                .into_iter()
+                .find(|args| args.iter().any(|arg| arg.ends_with(name)))
+                .unwrap();
+            let at = args.iter().position(|arg| arg == "-c:v").unwrap();
+            args[at + 1].clone()
+        };
+        // The running job finished with the preset it started with
+        assert_eq!(codec_of("slow.mp4"), "libx264");
+        assert_eq!(codec_of("kept.mov"), "libx265");
+        assert!(!sandbox.path().join("out/clip.mkv").exists());
+        assert_eq!(sandbox.calls("ffmpeg").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn encoder_failure_falls_back_to_the_fallback_preset() {
+        let sandbox = Sandbox::new();
+        let config = BASIC_CONFIG
+            .replace(
+                "    preset: p\n",
+                "    preset: gpu\n    fallback_preset: p\n",
+            )
+            .replace(
+                "presets:\n",
+                "presets:\n  gpu:\n    video_codec: h264_nvenc\n",
+            );
+        let transcoder = Transcoder::new(sandbox.config(&config));
+
+        let records = transcoder
+            .transcode_now(&sandbox.file("in/clip.mp4"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert!(records[0].used_fallback);
+        assert_eq!(records[0].preset.as_deref(), Some("p"));
+        assert!(sandbox.path().join("out/clip.mkv").is_file());
+        let codecs: Vec<String> = sandbox
+            .calls("ffmpeg")
+            .iter()
+            .map(|args| {
+                let at = args.iter().position(|arg| arg == "-c:v").unwrap();
+                args[at + 1].clone()
+            })
+            .collect();
+        assert_eq!(codecs, ["h264_nvenc", "libx264"]);
+    }
+
+    #[tokio::test]
+    async fn broken_source_gets_no_fallback() {
+        let sandbox = Sandbox::new();
+        let config = BASIC_CONFIG
+            .replace("    preset: p\n", "    preset: p\n    fallback_preset: q\n")
+            .replace("presets:\n", "presets:\n  q:\n    video_codec: libx265\n");
+        let transcoder = Transcoder::new(sandbox.config(&config));
+
+        let error = transcoder
+            .transcode_now(&sandbox.file("in/broken.mp4"), None)
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("Invalid data"), "{}", error);
+        assert_eq!(sandbox.calls("ffmpeg").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn corrupt_queue_file_is_ignored_and_replaced() {
+        let sandbox = Sandbox::new();
+        let config = queue_file_config(&sandbox);
+        std::fs::write(sandbox.path().join("queue.json"), "not json").unwrap();
+
+        let transcoder = Transcoder::new(config);
+        transcoder.restore_queue();
+        assert_eq!(
+            saved_queue(&transcoder, &sandbox).await,
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(transcoder.queued_files().await, 0);
+        assert!(sandbox.calls("ffmpeg").is_empty());
+    }
+
+    #[tokio::test]
+    async fn posters_are_written_only_where_no_jpg_is() {
+        const THUMBNAIL: &str = "    thumbnail: {at: 10%}\n";
+        let sandbox = Sandbox::new();
+        let transcoder = run_with_output(&sandbox, THUMBNAIL, "in/clip.mp4").await;
+        assert_eq!(transcoder.stats().failures(), []);
+        assert_eq!(
+            std::fs::read(sandbox.path().join("out/clip.jpg")).unwrap(),
+            [0; 10]
+        );
+
+        // A jpg of the user's next to the output is neither replaced nor removed, whether the
+        // job succeeds or fails
+        for name in ["clip", "broken"] {
+            let sandbox = Sandbox::new();
+            let poster = sandbox.path().join(format!("out/{}.jpg", name));
+            std::fs::create_dir_all(poster.parent().unwrap()).unwrap();
+            std::fs::write(&poster, b"the camera's own poster").unwrap();
+
+            let transcoder =
+                run_with_output(&sandbox, THUMBNAIL, &format!("in/{}.mp4", name)).await;
+
+            assert_eq!(transcoder.stats().failed(), usize::from(name == "broken"));
+            assert_eq!(std::fs::read(&poster).unwrap(), b"the camera's own poster");
+            let mut files = files_below(&sandbox.path().join("out"));
+            files.sort();
+            let expected: Vec<PathBuf> = match name {
+                "broken" => vec![format!("{}.jpg", name).into()],
+                _ => vec![
+                    format!("{}.jpg", name).into(),
+                    format!("{}.mkv", name).into(),
+                ],
+            };
+            assert_eq!(files, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn chapters_are_carried_over_unless_opted_out() {
+        for (preset_lines, container, chapters) in [
+            ("", "mkv", 2),
+            ("    preserve_chapters: false\n", "mkv", 0),
+            // A container without chapters gets the encode all the same
+            ("", "ts", 0),
+        ] {
+            let sandbox = Sandbox::new();
+            let config = sandbox.config(
+                &BASIC_CONFIG
+                    .replace(
+                        "    container: mkv\n",
+                        &format!("    container: {}\n", container),
+                    )
+                    .replace(PRESET, &format!("{}{}", PRESET, preset_lines)),
+            );
+            let source = sandbox.chaptered_file("in/movie.mp4");
+            assert_eq!(ffprobe::probe(&source).await.unwrap().chapters.len(), 2);
+
+            let transcoder = Transcoder::new(config);
+            transcoder.process_file(&source).await.unwrap();
+            transcoder.wait_until_idle().await;
+
+            assert_eq!(transcoder.stats().failures(), []);
+            let output = sandbox.path().join(format!("out/movie.{}", container));
+            assert_eq!(
+                ffprobe::probe(&output).await.unwrap().chapters.len(),
+                chapters,
+                "{}{}",
+                container,
+                preset_lines
+            );
         }
     }
 }
@@ -0,0 +1,913 @@
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A config duration given as seconds (`90`) or with units (`"90s"`, `"15m"`, `"1h30m"`).
+///
+/// Units are `ms`, `s`, `m`, `h` and `d`; an uppercase `M` is rejected since it could mean
+/// months as well as minutes. Serializes back in the largest unit that divides it exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct HumanDuration(pub Duration);
+
+impl HumanDuration {
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0.as_secs()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl From<HumanDuration> for Duration {
+    fn from(value: HumanDuration) -> Self {
+        value.0
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty duration".to_string());
+        }
+        if s.starts_with('-') {
+            return Err(format!("negative value '{}' is not allowed", s));
+        }
+        if let Ok(secs) = parse_number(s) {
+            return to_duration(secs, s);
+        }
+
+        let mut total = 0.0;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let (value, after) = split_number(rest)
+                .ok_or_else(|| format!("invalid duration '{}', expected e.g. 90s, 15m or 2h", s))?;
+            let unit_len = after
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(after.len());
+            let (unit, next) = after.split_at(unit_len);
+            let factor = match unit.trim() {
+                "ms" => 0.001,
+                "s" | "sec" | "secs" => 1.0,
+                "m" | "min" | "mins" => 60.0,
+                "h" | "hr" | "hrs" => 3600.0,
+                "d" | "day" | "days" => 86400.0,
+                "M" => {
+                    return Err(format!(
+                        "ambiguous unit 'M' in duration '{}'; use 'm' for minutes",
+                        s
+                    ))
+                }
+                "" => return Err(format!("missing unit after {} in duration '{}'", value, s)),
+                other => return Err(format!("unknown unit '{}' in duration '{}'", other, s)),
+            };
+            total += value * factor;
+            rest = next.trim_start();
+        }
+
+        to_duration(total, s)
+    }
+}
+
+fn to_duration(secs: f64, original: &str) -> Result<HumanDuration, String> {
+    Duration::try_from_secs_f64(secs)
+        .map(HumanDuration)
+        .map_err(|_| format!("duration '{}' is out of range", original))
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+        if !millis.is_multiple_of(1000) {
+            return write!(f, "{}ms", millis);
+        }
+
+        let secs = self.0.as_secs();
+        let (value, unit) = [(86400, "d"), (3600, "h"), (60, "m")]
+            .into_iter()
+            .find(|(unit_secs, _)| secs > 0 && secs.is_multiple_of(*unit_secs))
+            .map_or((secs, "s"), |(unit_secs, unit)| (secs / unit_secs, unit));
+        write!(f, "{}{}", value, unit)
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a duration like 90, \"90s\" or \"2h\"",
+        ))
+    }
+}
+
+/// How often ffmpeg reports progress: a duration like [`HumanDuration`], or `"off"` to not ask
+/// for progress at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressInterval {
+    Every(HumanDuration),
+    Off,
+}
+
+impl ProgressInterval {
+    /// Shortest interval the config accepts
+    pub const MIN: Duration = Duration::from_millis(100);
+    /// Longest interval the config accepts
+    pub const MAX: Duration = Duration::from_secs(60);
+
+    /// The interval, unless progress is off
+    pub fn period(&self) -> Option<Duration> {
+        match self {
+            Self::Every(interval) => Some(interval.0),
+            Self::Off => None,
+        }
+    }
+}
+
+impl Default for ProgressInterval {
+    fn default() -> Self {
+        Self::Every(HumanDuration::from_secs(1))
+    }
+}
+
+impl FromStr for ProgressInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("off") {
+            return Ok(Self::Off);
+        }
+        s.parse().map(Self::Every)
+    }
+}
+
+impl fmt::Display for ProgressInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Every(interval) => interval.fmt(f),
+            Self::Off => f.write_str("off"),
+        }
+    }
+}
+
+impl Serialize for ProgressInterval {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProgressInterval {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "an interval like 1, \"500ms\" or \"5s\", or \"off\"",
+        ))
+    }
+}
+
+/// Encoder threads of a job: a count, or `"auto"` to share the machine's cores out over the
+/// jobs running at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    Count(u32),
+    Auto,
+}
+
+impl Threads {
+    /// Threads of one job with up to `max_jobs` running at once; `auto` gives each at least one
+    pub fn per_job(&self, max_jobs: usize) -> u32 {
+        match self {
+            Self::Count(count) => *count,
+            Self::Auto => {
+                let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+                (cores / max_jobs.max(1)).max(1) as u32
+            }
+        }
+    }
+}
+
+impl FromStr for Threads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        s.parse()
+            .map(Self::Count)
+            .map_err(|_| format!("invalid thread count '{}', expected e.g. 8 or auto", s))
+    }
+}
+
+impl fmt::Display for Threads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count(count) => count.fmt(f),
+            Self::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+impl Serialize for Threads {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Count(count) => serializer.serialize_u32(*count),
+            Self::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Threads {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a thread count like 8, or \"auto\"",
+        ))
+    }
+}
+
+/// A point in a video: a time from the start like [`HumanDuration`], or a share of its
+/// length like `"10%"`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoPosition {
+    At(HumanDuration),
+    Percent(f64),
+}
+
+impl VideoPosition {
+    /// Seconds from the start of a video `duration` seconds long, kept inside it when the
+    /// duration is known
+    pub fn seconds_into(&self, duration: Option<f64>) -> f64 {
+        match (self, duration) {
+            (Self::At(at), Some(duration)) => at.0.as_secs_f64().min(duration * 0.99),
+            (Self::At(at), None) => at.0.as_secs_f64(),
+            (Self::Percent(pct), Some(duration)) => duration * pct / 100.0,
+            (Self::Percent(_), None) => 0.0,
+        }
+    }
+}
+
+impl FromStr for VideoPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(pct) = s.trim().strip_suffix('%') else {
+            return s.parse().map(Self::At);
+        };
+        let pct = parse_number(pct.trim())?;
+        if pct > 100.0 {
+            return Err(format!("position '{}' is past the end of the video", s));
+        }
+        Ok(Self::Percent(pct))
+    }
+}
+
+impl fmt::Display for VideoPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::At(at) => at.fmt(f),
+            Self::Percent(pct) => write!(f, "{}%", pct),
+        }
+    }
+}
+
+impl Serialize for VideoPosition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VideoPosition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a position like 30, \"1m30s\" or \"10%\"",
+        ))
+    }
+}
+
+/// A config size given in bytes (`1048576`) or with units (`"500M"`, `"1.5G"`).
+///
+/// Single-letter and `KiB`-style suffixes are binary (1024-based), `KB`-style ones decimal.
+/// Serializes back in the largest binary unit that divides it exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct HumanSize(pub u64);
+
+impl HumanSize {
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for HumanSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty size".to_string());
+        }
+        if s.starts_with('-') {
+            return Err(format!("negative value '{}' is not allowed", s));
+        }
+
+        let (value, unit) = split_number(s)
+            .ok_or_else(|| format!("invalid size '{}', expected e.g. 500M or 1.5G", s))?;
+        let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "t" | "tib" => 1 << 40,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            "tb" => 1_000_000_000_000,
+            _ => return Err(format!("unknown unit '{}' in size '{}'", unit.trim(), s)),
+        };
+
+        let bytes = value * factor as f64;
+        if bytes.fract() != 0.0 {
+            return Err(format!("size '{}' is not a whole number of bytes", s));
+        }
+        if bytes > u64::MAX as f64 {
+            return Err(format!("size '{}' is too large", s));
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl fmt::Display for HumanSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, unit) = [(40, "T"), (30, "G"), (20, "M"), (10, "K")]
+            .into_iter()
+            .find(|(shift, _)| self.0 > 0 && self.0.is_multiple_of(1u64 << shift))
+            .map_or((self.0, ""), |(shift, unit)| (self.0 >> shift, unit));
+        write!(f, "{}{}", value, unit)
+    }
+}
+
+impl Serialize for HumanSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a size like 1048576, \"500M\" or \"1.5G\"",
+        ))
+    }
+}
+
+/// Accepts bare numbers as well as strings for either humane type
+struct HumanVisitor<T> {
+    expecting: &'static str,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T> HumanVisitor<T> {
+    fn new(expecting: &'static str) -> Self {
+        Self {
+            expecting,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: FromStr<Err = String>> Visitor<'_> for HumanVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.expecting)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<T, E> {
+        v.to_string().parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<T, E> {
+        if v < 0 {
+            return Err(E::custom(format!("negative value {} is not allowed", v)));
+        }
+        self.visit_u64(v as u64)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<T, E> {
+        v.to_string().parse().map_err(E::custom)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+/// Parse a plain non-negative decimal number
+fn parse_number(s: &str) -> Result<f64, String> {
+    if s.starts_with('-') {
+        return Err(format!("negative value '{}' is not allowed", s));
+    }
+    let value: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !value.is_finite() {
+        return Err(format!("'{}' is not a finite number", s));
+    }
+    Ok(value)
+}
+
+/// Split a leading non-negative number off `s`, returning it and the rest
+fn split_number(s: &str) -> Option<(f64, &str)> {
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    if end == 0 {
+        return None;
+    }
+    let value = parse_number(&s[..end]).ok()?;
+    Some((value, &s[end..]))
+}
+
+/// A bitrate in bits per second, given bare (`12000000`) or with the decimal `k`/`M`/`G`
+/// suffixes ffmpeg uses (`"12M"`, `"800k"`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Bitrate(pub u64);
+
+impl Bitrate {
+    pub fn bits_per_sec(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Bitrate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty bitrate".to_string());
+        }
+        if s.starts_with('-') {
+            return Err(format!("negative value '{}' is not allowed", s));
+        }
+
+        let (value, unit) = split_number(s)
+            .ok_or_else(|| format!("invalid bitrate '{}', expected e.g. 800k or 12M", s))?;
+        let factor = match unit.trim().trim_end_matches("bps") {
+            "" => 1.0,
+            "k" | "K" => 1e3,
+            "M" => 1e6,
+            "G" => 1e9,
+            _ => return Err(format!("unknown unit '{}' in bitrate '{}'", unit.trim(), s)),
+        };
+        Ok(Self((value * factor).round() as u64))
+    }
+}
+
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, unit) = [(1_000_000_000, "G"), (1_000_000, "M"), (1_000, "k")]
+            .into_iter()
+            .find(|(factor, _)| self.0 > 0 && self.0.is_multiple_of(*factor))
+            .map_or((self.0, ""), |(factor, unit)| (self.0 / factor, unit));
+        write!(f, "{}{}", value, unit)
+    }
+}
+
+impl Serialize for Bitrate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bitrate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a bitrate like 12000000, \"800k\" or \"12M\"",
+        ))
+    }
+}
+
+/// A frame rate, given as a number (`30`, `29.97`) or as the fraction ffmpeg uses for NTSC
+/// rates (`"30000/1001"`). Kept as a fraction so it reaches ffmpeg without rounding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    num: u64,
+    den: u64,
+}
+
+impl FrameRate {
+    /// Frames per second
+    pub fn value(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+impl FromStr for FrameRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || {
+            format!(
+                "invalid frame rate '{}', expected e.g. 30, 29.97 or 30000/1001",
+                s
+            )
+        };
+        let (num, den) = match s.split_once('/') {
+            Some((num, den)) => (
+                num.trim().parse::<u64>().map_err(|_| invalid())?,
+                den.trim().parse::<u64>().map_err(|_| invalid())?,
+            ),
+            None => {
+                let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+                if fraction.len() > 6 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                let den = 10u64.pow(fraction.len() as u32);
+                let whole = whole.parse::<u64>().map_err(|_| invalid())?;
+                let fraction = fraction.parse::<u64>().unwrap_or(0);
+                (whole * den + fraction, den)
+            }
+        };
+        if num == 0 || den == 0 {
+            return Err(format!("frame rate '{}' must be more than 0", s));
+        }
+        Ok(Self { num, den })
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Rates given as decimals are written back as decimals
+        let decimals = (0..=6).find(|&digits| 10u64.pow(digits) == self.den);
+        match decimals {
+            Some(0) => write!(f, "{}", self.num),
+            Some(digits) => write!(
+                f,
+                "{}.{:0width$}",
+                self.num / self.den,
+                self.num % self.den,
+                width = digits as usize
+            ),
+            None => write!(f, "{}/{}", self.num, self.den),
+        }
+    }
+}
+
+impl Serialize for FrameRate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FrameRate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a frame rate like 30, 29.97 or \"30000/1001\"",
+        ))
+    }
+}
+
+/// A resolution tier named by its short side, `1080` or `"1080p"`. Serialized as a string so
+/// it can key maps in every config format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ResolutionTier(pub u32);
+
+impl FromStr for ResolutionTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.strip_suffix('p')
+            .unwrap_or(s)
+            .parse()
+            .map(Self)
+            .map_err(|_| {
+                format!(
+                    "invalid resolution tier '{}', expected e.g. 1080 or 1080p",
+                    s
+                )
+            })
+    }
+}
+
+impl fmt::Display for ResolutionTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}p", self.0)
+    }
+}
+
+impl Serialize for ResolutionTier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ResolutionTier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor::<Self>::new(
+            "a resolution tier like 1080 or \"1080p\"",
+        ))
+    }
+}
+
+/// Config schema of a humane type: a bare number, or a string with units
+macro_rules! human_schema {
+    ($ty:ty, $name:literal, $description:literal) => {
+        impl JsonSchema for $ty {
+            fn schema_name() -> Cow<'static, str> {
+                $name.into()
+            }
+
+            fn json_schema(_: &mut SchemaGenerator) -> Schema {
+                json_schema!({
+                    "type": ["number", "string"],
+                    "description": $description,
+                })
+            }
+        }
+    };
+}
+
+human_schema!(
+    HumanDuration,
+    "HumanDuration",
+    "Seconds, or a duration with units like \"90s\", \"15m\" or \"1h30m\""
+);
+human_schema!(
+    ProgressInterval,
+    "ProgressInterval",
+    "Seconds, a duration with units like \"500ms\" or \"5s\", or \"off\""
+);
+human_schema!(
+    VideoPosition,
+    "VideoPosition",
+    "Seconds, a duration with units like \"1m30s\", or a share of the length like \"10%\""
+);
+human_schema!(
+    HumanSize,
+    "HumanSize",
+    "Bytes, or a size with units like \"500M\", \"1.5G\" or \"10MB\""
+);
+human_schema!(
+    Bitrate,
+    "Bitrate",
+    "Bits per second, or a bitrate with units like \"800k\" or \"12M\""
+);
+human_schema!(
+    FrameRate,
+    "FrameRate",
+    "Frames per second, like 30 or 29.97, or a fraction like \"30000/1001\""
+);
+human_schema!(
+    Threads,
+    "Threads",
+    "A thread count, or \"auto\" to share the cores out over max_parallel_jobs"
+);
+human_schema!(
+    ResolutionTier,
+    "ResolutionTier",
+    "Short side of a resolution, like 1080 or \"1080p\""
+);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn duration(s: &str) -> Result<Duration, String> {
+        s.parse::<HumanDuration>().map(Duration::from)
+    }
+
+    fn size(s: &str) -> Result<u64, String> {
+        s.parse::<HumanSize>().map(|size| size.bytes())
+    }
+
+    #[test]
+    fn durations_with_and_without_units() {
+        let secs = Duration::from_secs;
+        for (text, expected) in [
+            ("90", secs(90)),
+            ("0", secs(0)),
+            ("2.5", Duration::from_millis(2500)),
+            ("90s", secs(90)),
+            ("15m", secs(900)),
+            ("2h", secs(7200)),
+            ("1d", secs(86400)),
+            ("250ms", Duration::from_millis(250)),
+            ("1h30m", secs(5400)),
+            ("1h 30m 15s", secs(5415)),
+            ("1.5h", secs(5400)),
+            ("10 mins", secs(600)),
+            (" 45 sec ", secs(45)),
+            ("3days", secs(259_200)),
+        ] {
+            assert_eq!(duration(text), Ok(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn durations_rejected() {
+        for (text, error) in [
+            ("", "empty duration"),
+            ("-5", "negative value '-5' is not allowed"),
+            ("-5s", "negative value '-5s' is not allowed"),
+            (
+                "5M",
+                "ambiguous unit 'M' in duration '5M'; use 'm' for minutes",
+            ),
+            ("1h30", "missing unit after 30 in duration '1h30'"),
+            ("5w", "unknown unit 'w' in duration '5w'"),
+            ("s", "invalid duration 's', expected e.g. 90s, 15m or 2h"),
+            (
+                "1.2.3s",
+                "invalid duration '1.2.3s', expected e.g. 90s, 15m or 2h",
+            ),
+            ("1e30d", "unknown unit 'e' in duration '1e30d'"),
+            (
+                "99999999999999999999d",
+                "duration '99999999999999999999d' is out of range",
+            ),
+        ] {
+            assert_eq!(duration(text), Err(error.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn durations_display_in_the_largest_exact_unit() {
+        for (text, shown) in [
+            ("90", "90s"),
+            ("120", "2m"),
+            ("5400", "90m"),
+            ("7200", "2h"),
+            ("86400", "1d"),
+            ("1.5", "1500ms"),
+            ("0", "0s"),
+        ] {
+            let parsed: HumanDuration = text.parse().unwrap();
+            assert_eq!(parsed.to_string(), shown, "{}", text);
+            assert_eq!(shown.parse::<HumanDuration>(), Ok(parsed), "{}", shown);
+        }
+    }
+
+    #[test]
+    fn sizes_binary_and_decimal() {
+        for (text, expected) in [
+            ("1048576", 1 << 20),
+            ("0", 0),
+            ("512B", 512),
+            ("500M", 500 << 20),
+            ("1.5G", 3 << 29),
+            ("2 GiB", 2 << 30),
+            ("1t", 1 << 40),
+            ("10MB", 10_000_000),
+            ("1.5kb", 1500),
+            ("3TB", 3_000_000_000_000),
+        ] {
+            assert_eq!(size(text), Ok(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn sizes_rejected() {
+        for (text, error) in [
+            ("", "empty size"),
+            ("-1G", "negative value '-1G' is not allowed"),
+            ("1.5", "size '1.5' is not a whole number of bytes"),
+            ("0.1K", "size '0.1K' is not a whole number of bytes"),
+            ("10 PB", "unknown unit 'PB' in size '10 PB'"),
+            ("G", "invalid size 'G', expected e.g. 500M or 1.5G"),
+            ("99999999T", "size '99999999T' is too large"),
+        ] {
+            assert_eq!(size(text), Err(error.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn sizes_display_in_the_largest_exact_binary_unit() {
+        for (text, shown) in [
+            ("1048576", "1M"),
+            ("1.5G", "1536M"),
+            ("10MB", "10000000"),
+            ("4096", "4K"),
+            ("1000", "1000"),
+            ("0", "0"),
+        ] {
+            let parsed: HumanSize = text.parse().unwrap();
+            assert_eq!(parsed.to_string(), shown, "{}", text);
+            assert_eq!(shown.parse::<HumanSize>(), Ok(parsed), "{}", shown);
+        }
+    }
+
+    #[test]
+    fn resolution_tiers_with_and_without_p() {
+        for (text, expected) in [("1080", 1080), ("1080p", 1080), (" 720p ", 720), ("0", 0)] {
+            assert_eq!(text.parse(), Ok(ResolutionTier(expected)), "{}", text);
+        }
+        for text in ["", "p", "1080i", "4k", "-720p", "1080pp"] {
+            assert_eq!(
+                text.parse::<ResolutionTier>(),
+                Err(format!(
+                    "invalid resolution tier '{}', expected e.g. 1080 or 1080p",
+                    text.trim()
+                )),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(ResolutionTier(2160).to_string(), "2160p");
+
+        // Keys of the bitrate map, bare or with the suffix, written back with it
+        let tiers: std::collections::BTreeMap<ResolutionTier, Bitrate> =
+            serde_yaml::from_str("720: 6M\n1080p: 12M\n").unwrap();
+        assert_eq!(
+            tiers.keys().copied().collect::<Vec<_>>(),
+            [ResolutionTier(720), ResolutionTier(1080)]
+        );
+        assert_eq!(
+            serde_yaml::to_string(&tiers).unwrap(),
+            "720p: 6M\n1080p: 12M\n"
+        );
+    }
+
+    #[test]
+    fn thread_counts_and_auto() {
+        assert_eq!("8".parse(), Ok(Threads::Count(8)));
+        assert_eq!(" Auto ".parse(), Ok(Threads::Auto));
+        assert!("-2".parse::<Threads>().is_err());
+        assert!("all".parse::<Threads>().is_err());
+
+        assert_eq!(Threads::Count(8).per_job(4), 8);
+        let cores = std::thread::available_parallelism().unwrap().get() as u32;
+        assert_eq!(Threads::Auto.per_job(1), cores);
+        assert_eq!(Threads::Auto.per_job(0), cores);
+        // Never less than one, however many jobs share the cores
+        assert_eq!(Threads::Auto.per_job(usize::MAX), 1);
+    }
+
+    /// Load a config with `extra` added at the top level, as YAML
+    fn config_with(extra: &str) -> Result<Config, String> {
+        let yaml = format!(
+            "inputs:\n  - path: /in\n    extensions: [mp4]\noutputs:\n  o:\n    path: /out\n    filename_template: '{{filename}}'\n    container: mkv\npresets:\n  p:\n    video_codec: libx264\n{}",
+            extra
+        );
+        serde_yaml::from_str(&yaml).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn config_fields_take_numbers_strings_and_old_names() {
+        let config = config_with(
+            "drain_settle_secs: 30\nstability:\n  check_interval: 2.5\n  stable_for: 1m\n",
+        )
+        .unwrap();
+        assert_eq!(config.drain_settle, Some(HumanDuration::from_secs(30)));
+        let stability = config.stability.unwrap();
+        assert_eq!(
+            stability.check_interval,
+            Some(HumanDuration(Duration::from_millis(2500)))
+        );
+        assert_eq!(stability.stable_for, Some(HumanDuration::from_secs(60)));
+
+        // Written back in the humane form, under the current names
+        let yaml =
+            serde_yaml::to_string(&config_with("drain_settle_secs: 7200\n").unwrap()).unwrap();
+        assert!(yaml.contains("drain_settle: 2h\n"), "{}", yaml);
+    }
+
+    #[test]
+    fn config_errors_name_the_field() {
+        for (extra, field, error) in [
+            (
+                "drain_settle: -5\n",
+                "drain_settle",
+                "negative value -5 is not allowed",
+            ),
+            (
+                "stability:\n  stable_for: 5M\n",
+                "stability.stable_for",
+                "ambiguous unit 'M'",
+            ),
+            (
+                "stability:\n  timeout: true\n",
+                "stability.timeout",
+                "expected a duration like 90, \"90s\" or \"2h\"",
+            ),
+        ] {
+            let message = config_with(extra).unwrap_err();
+            assert!(message.starts_with(field), "{}", message);
+            assert!(message.contains(error), "{}", message);
+        }
+    }
+}
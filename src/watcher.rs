@@ -1,6 +1,9 @@
-use crate::config::Config;
+use crate::artifacts;
+use crate::config::{Config, InputConfig};
+use crate::marker::IgnoreMarkers;
 use crate::transcoder::Transcoder;
 use anyhow::{Context, Result};
+use notify::event::ModifyKind;
 use notify::{EventKind, RecursiveMode, Watcher};
 use owo_colors::OwoColorize;
 use std::path::Path;
@@ -9,10 +12,13 @@ use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info};
 
+/// How often the queue is logged while there is work, and dropped files are looked for again
+const STATUS_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct DirectoryWatcher {
     config: Arc<Config>,
     transcoder: Arc<Transcoder>,
-    _watcher: Option<Box<dyn Watcher + Send>>,
+    watcher: Option<Box<dyn Watcher + Send>>,
 }
 
 impl DirectoryWatcher {
@@ -20,7 +26,7 @@ impl DirectoryWatcher {
         Self {
             config,
             transcoder,
-            _watcher: None,
+            watcher: None,
         }
     }
 
@@ -45,14 +51,36 @@ impl DirectoryWatcher {
                     input.path.display().green()
                 ))?;
 
-            self.process_existing_files(&input.path).await?;
+            Self::spawn_scan(&self.transcoder, input);
         }
+        Self::spawn_status_task(&self.transcoder);
 
         let transcoder = self.transcoder.clone();
 
         tokio::spawn(async move {
             while let Some(event) = rx.recv().await {
-                if let Some(path) = event.paths.first() {
+                // A rename lists the old name first; the file is at the new one, which may no
+                // longer be ignored
+                let path = match event.kind {
+                    EventKind::Modify(ModifyKind::Name(_)) => event.paths.last(),
+                    _ => event.paths.first(),
+                };
+                if let Some(path) = path {
+                    if IgnoreMarkers::is_marker_file(path) {
+                        debug!("Ignore marker changed: {}", path.display());
+                        transcoder.invalidate_ignore_markers(path);
+                        continue;
+                    }
+
+                    if artifacts::is_sstc_artifact(path) {
+                        continue;
+                    }
+
+                    if let Some(pattern) = Self::ignored_by(&transcoder.config(), path) {
+                        debug!("Ignored by pattern '{}': {}", pattern, path.display());
+                        continue;
+                    }
+
                     if Self::is_create_or_modify_event(&event.kind) && path.is_file() {
                         debug!("File event: {:?} at {}", event.kind, path.display());
 
@@ -60,7 +88,8 @@ impl DirectoryWatcher {
                         let transcoder_clone = transcoder.clone();
                         tokio::spawn(async move {
                             tokio::time::sleep(Duration::from_secs(1)).await;
-                            if let Err(e) = transcoder_clone.process_file(&path_clone).await {
+                            if let Err(e) = transcoder_clone.process_watched_file(&path_clone).await
+                            {
                                 error!("Failed to process file {}: {}", path_clone.display(), e);
                             }
                         });
@@ -70,13 +99,70 @@ impl DirectoryWatcher {
         });
 
         // Store the watcher in the struct so it doesn't get dropped
-        self._watcher = Some(Box::new(watcher));
+        self.watcher = Some(Box::new(watcher));
 
         info!("Directory watcher started");
         Ok(())
     }
 
-    async fn process_existing_files(&self, dir: &Path) -> Result<()> {
+    /// Follow a reloaded config: stop watching removed inputs and pick up added ones
+    pub async fn reload(&mut self, config: Arc<Config>) -> Result<()> {
+        let old = std::mem::replace(&mut self.config, config.clone());
+        let Some(watcher) = self.watcher.as_mut() else {
+            return Ok(());
+        };
+
+        for input in &old.inputs {
+            if !config.inputs.iter().any(|i| i.path == input.path) {
+                info!("No longer watching directory: {}", input.path.display());
+                if let Err(e) = watcher.unwatch(&input.path) {
+                    error!("Failed to unwatch {}: {}", input.path.display(), e);
+                }
+            }
+        }
+
+        for input in &config.inputs {
+            if !old.inputs.iter().any(|i| i.path == input.path) {
+                info!("Watching directory: {}", input.path.display().green());
+                watcher
+                    .watch(&input.path, RecursiveMode::Recursive)
+                    .context(format!(
+                        "Failed to watch directory: {}",
+                        input.path.display()
+                    ))?;
+            }
+        }
+
+        // Changed matching rules of a kept input can bring existing files into scope too
+        for input in &config.inputs {
+            if !old.inputs.contains(input) {
+                Self::spawn_scan(&self.transcoder, input);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue the files already in `input` in the background, waiting for room in the queue
+    fn spawn_scan(transcoder: &Arc<Transcoder>, input: &InputConfig) {
+        let transcoder = transcoder.clone();
+        let input = input.clone();
+        tokio::spawn(async move {
+            if let Err(e) = Self::process_existing_files(&transcoder, &input, &input.path).await {
+                error!(
+                    "Failed to scan existing files in {}: {:#}",
+                    input.path.display(),
+                    e
+                );
+            }
+        });
+    }
+
+    async fn process_existing_files(
+        transcoder: &Transcoder,
+        input: &InputConfig,
+        dir: &Path,
+    ) -> Result<()> {
         info!("Processing existing files in {}", dir.display());
 
         let mut entries = tokio::fs::read_dir(dir).await?;
@@ -84,34 +170,145 @@ impl DirectoryWatcher {
             let path = entry.path();
 
             if path.is_dir() {
-                Box::pin(self.process_existing_files(&path)).await?;
-            } else if path.is_file() {
+                Box::pin(Self::process_existing_files(transcoder, input, &path)).await?;
+            } else if path.is_file() && !artifacts::is_sstc_artifact(&path) {
+                let relative = path.strip_prefix(&input.path).unwrap_or(&path);
+                if let Some(pattern) = input.ignored_by(relative) {
+                    debug!("Ignored by pattern '{}': {}", pattern, path.display());
+                    continue;
+                }
+                if let Some(pattern) = input.excluded_by(relative) {
+                    debug!("Excluded by pattern '{}': {}", pattern, path.display());
+                    continue;
+                }
+
                 debug!("Found existing file: {}", path.display());
-                let transcoder = self.transcoder.clone();
-                let path_clone = path.clone();
-
-                tokio::spawn(async move {
-                    if let Err(e) = transcoder.process_file(&path_clone).await {
-                        error!(
-                            "Failed to process existing file {}: {}",
-                            path_clone.display(),
-                            e
-                        );
-                    }
-                });
+                // One at a time, so a bounded queue holds the scan back instead of piling up
+                // a task per file
+                if let Err(e) = transcoder.process_file(&path).await {
+                    error!("Failed to process existing file {}: {}", path.display(), e);
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Log the queue while there is work, and rescan the inputs for files the watcher dropped
+    /// while the queue was full
+    fn spawn_status_task(transcoder: &Arc<Transcoder>) {
+        let transcoder = transcoder.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STATUS_INTERVAL);
+            // The first tick is immediate
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+
+                let queued = transcoder.queued_files().await;
+                let waiting = transcoder.waiting_for_companions();
+                let running = transcoder.running_jobs().saturating_sub(waiting.len());
+                if queued > 0 || running > 0 || !waiting.is_empty() {
+                    let limit = transcoder
+                        .config()
+                        .max_queue_size
+                        .map(|max| format!(" of {}", max))
+                        .unwrap_or_default();
+                    info!(
+                        "Status: {}{} file(s) queued, {} running, {} waiting for companions",
+                        queued.magenta(),
+                        limit,
+                        running.magenta(),
+                        waiting.len().magenta()
+                    );
+                    for (source, missing) in &waiting {
+                        info!(
+                            "  {} is waiting for {}",
+                            source.display(),
+                            missing.join(", ").yellow()
+                        );
+                    }
+                }
+
+                if transcoder.take_dropped_files().await {
+                    info!("Rescanning inputs for files left out while the queue was full");
+                    for input in &transcoder.config().inputs {
+                        Self::spawn_scan(&transcoder, input);
+                    }
+                }
+            }
+        });
+    }
+
     fn is_create_or_modify_event(kind: &EventKind) -> bool {
-        use notify::event::ModifyKind;
         matches!(
             kind,
             EventKind::Create(_)
                 | EventKind::Modify(ModifyKind::Data(_))
                 | EventKind::Modify(ModifyKind::Metadata(_))
+                | EventKind::Modify(ModifyKind::Name(_))
         )
     }
+
+    /// The `ignore` pattern of the input `path` is in that matches it, if any
+    fn ignored_by(config: &Config, path: &Path) -> Option<String> {
+        let path = std::fs::canonicalize(path).ok()?;
+        config.inputs.iter().find_map(|input| {
+            let root = std::fs::canonicalize(&input.path).ok()?;
+            let relative = path.strip_prefix(&root).ok()?;
+            input.ignored_by(relative).map(str::to_string)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Sandbox, BASIC_CONFIG};
+
+    #[tokio::test]
+    async fn reload_watches_added_inputs_and_drops_removed_ones() {
+        let sandbox = Sandbox::new();
+        let input = |dir: &str| {
+            format!(
+                "  - path: {{dir}}/{}\n    extensions: [mp4]\n    preset: p\n    output: o\n",
+                dir
+            )
+        };
+        let with_inputs = |dirs: &[&str]| {
+            let inputs: String = dirs.iter().map(|dir| input(dir)).collect();
+            BASIC_CONFIG.replace(&input("in"), &inputs)
+        };
+        for dir in ["old", "new"] {
+            std::fs::create_dir_all(sandbox.path().join(dir)).unwrap();
+        }
+        // The transcoder takes files from both, so only the watches decide what is seen
+        let transcoder = Arc::new(Transcoder::new(
+            sandbox.config(&with_inputs(&["old", "new"])),
+        ));
+        let mut watcher =
+            DirectoryWatcher::new(sandbox.config(&with_inputs(&["old"])), transcoder.clone());
+        watcher.start_watching().await.unwrap();
+
+        watcher
+            .reload(sandbox.config(&with_inputs(&["new"])))
+            .await
+            .unwrap();
+        // Past the scan of the added input, so only its watch can find what comes next
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        sandbox.file("new/added.mp4");
+        sandbox.file("old/removed.mp4");
+
+        let added = sandbox.path().join("out/added.mkv");
+        for _ in 0..500 {
+            if added.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(added.is_file());
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        transcoder.wait_until_idle().await;
+        assert!(!sandbox.path().join("out/removed.mkv").exists());
+    }
 }